@@ -3,9 +3,11 @@ use bluebook_app::{
 };
 
 use bluebook_core::{
-    buffer::peritext_buffer::{buffer_impl::Peritext, cursor_impl::CursorRange},
+    buffer::peritext_buffer::buffer_impl::Peritext,
     ctx::TextEditorContext,
+    cursor::CursorRange,
     editor::TextEditor,
+    keymap::Keymap,
 };
 use eframe::{self, egui};
 use egui::{Align2, Id, ScrollArea, Vec2, Widget};
@@ -15,7 +17,7 @@ use egui::{Align2, Id, ScrollArea, Vec2, Widget};
 use tracing_subscriber::{filter, layer::SubscriberExt, util::SubscriberInitExt};
 
 
-use tracing::{Level};
+use tracing::{warn, Level};
 use tracing_subscriber::{prelude::*};
 
 // #[derive(serde::Deserialize, serde::Serialize)]
@@ -37,12 +39,24 @@ impl TextEditApp {
         let edit_ctx = TextEditorContext::new(buf, cursor_range);
         let view_ctx = EguiViewCtx::new(Id::new("text_editor"), Vec2::ZERO, Align2::CENTER_CENTER);
 
-        let editor = TextEditor::<Peritext, egui::Event, EguiViewCtx>::new(
+        let mut editor = TextEditor::<Peritext, egui::Event, EguiViewCtx>::new(
             edit_ctx,
             egui_transact_fn,
             view_ctx,
         );
 
+        // User bindings, from the keymap file BLUEBOOK_KEYMAP names, override
+        // the default ones.
+        if let Ok(path) = std::env::var("BLUEBOOK_KEYMAP") {
+            match Keymap::load(&path) {
+                Ok(overrides) => editor.keymap.extend(overrides),
+                Err(err) => warn!("Could not load the keymap at {path}: {err}"),
+            }
+        }
+        for conflict in editor.keymap.conflicts() {
+            warn!("Conflicting key bindings: {conflict:?}");
+        }
+
         Self {
             editor: EguiTextEditor(editor),
         }
@@ -18,6 +18,13 @@ impl<'painter> Draw<'painter> {
 
         self.painter.line_segment([top, bottom], (1., Color32::RED));
     }
+
+    /// Shade a bracket or quote whose partner is highlighted with it.
+    pub fn draw_pair_highlight(&self, rect: Rect) {
+        self.painter
+            .rect_filled(rect, 2., Color32::from_rgba_unmultiplied(120, 120, 255, 60));
+    }
+
     pub fn draw_text(&self, pos: Pos2, galley: Arc<Galley>) {
         self.painter.galley(pos, galley);
     }
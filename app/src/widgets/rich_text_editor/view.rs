@@ -1,11 +1,20 @@
 use std::{
-    ops::{Deref, DerefMut},
+    ops::{Deref, DerefMut, Range},
     sync::Arc,
 };
 
 use bluebook_core::{
-    buffer::peritext_buffer::cursor_impl::CursorRange, command::Transaction,
-    ctx::TextEditorContext, editor::TextEditor, span::Span, text_buffer::TextBuffer,
+    command::Transaction,
+    coordinates::{ColPosition, RowPosition},
+    ctx::TextEditorContext,
+    cursor::CursorRange,
+    editor::TextEditor,
+    keymap::{Input, KeyPress, Modifiers},
+    mode::Mode,
+    offset::ByteOffset,
+    search::Searchable,
+    span::Span,
+    text_buffer::TextBuffer,
     text_buffer_cursor::CursorDocCoords,
 };
 use egui::{
@@ -52,43 +61,40 @@ impl<Buf: TextBuffer> DerefMut for EguiTextEditor<Buf> {
     }
 }
 
-pub fn editor_ui<'ctx, Buffer: TextBuffer + 'ctx>(
+pub fn editor_ui<'ctx, Buffer: TextBuffer + Searchable + 'ctx>(
     text_edtitor: &'ctx mut EguiTextEditor<Buffer>,
 ) -> impl egui::Widget + 'ctx {
     move |ui: &mut egui::Ui| text_edtitor.editor_ui(ui)
 }
 
+/// Read `event` as a key to look up in the editor's keymap, or as a
+/// transaction. Typed text is inserted in insert mode, and read as keys in
+/// the other modes.
 pub fn egui_transact_fn<Buf: TextBuffer>(
-    _ctx: &TextEditorContext<Buf>,
+    ctx: &TextEditorContext<Buf>,
     event: &Event,
-) -> Option<Transaction> {
+) -> Option<Input> {
     match event {
-        Event::Copy => None,
+        Event::Copy => Some(Input::Transaction(Transaction::ClipboardCopy)),
         Event::CompositionEnd(_c) => None,
         Event::CompositionUpdate(_c) => None,
         Event::CompositionStart => None,
-        Event::Cut => None,
+        Event::Cut => Some(Input::Transaction(Transaction::ClipboardCut)),
         Event::Key {
             key,
-            pressed,
+            pressed: true,
             repeat: _,
-            modifiers: _,
-        } => match (key, pressed) {
-            (Key::Backspace, true) => Some(Transaction::DeleteBackward),
-            (Key::Enter, true) => Some(Transaction::InsertNewLine),
-            (Key::ArrowLeft, true) => Some(Transaction::MoveCursorLeft { grapheme_count: 1 }),
-            (Key::ArrowRight, true) => Some(Transaction::MoveCursorRight { grapheme_count: 1 }),
-
-            _ => None,
-        },
+            modifiers,
+        } => egui_key_press(key, modifiers).map(Input::Key),
+        Event::Key { .. } => None,
         Event::MouseWheel {
             unit: _,
             delta: _,
             modifiers: _,
         } => None,
-        Event::Paste(s) => Some(Transaction::Paste {
+        Event::Paste(s) => Some(Input::Transaction(Transaction::Paste {
             clipboard: s.clone(),
-        }),
+        })),
         Event::PointerButton {
             pos: _,
             button: _,
@@ -97,15 +103,68 @@ pub fn egui_transact_fn<Buf: TextBuffer>(
         } => None,
         Event::PointerGone => None,
         Event::PointerMoved(_c) => None,
-        Event::Text(s) => Some(Transaction::InsertAtCursorHead { value: s.into() }),
+        Event::Text(s) => match ctx.modal.mode {
+            Mode::Insert | Mode::Terminal => {
+                Some(Input::Transaction(Transaction::InsertAtCursorHead {
+                    value: s.into(),
+                }))
+            }
+            // Space is read from its key event, as a named key.
+            _ => {
+                let mut chars = s.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(ch), None) if ch != ' ' => Some(Input::Key(KeyPress::char(ch))),
+                    _ => None,
+                }
+            }
+        },
 
         _ => None,
     }
 }
 
+/// The key egui reports, as keymaps name it. Keys typing text are read from
+/// their [`Event::Text`] instead, unless pressed with ctrl, alt or command.
+fn egui_key_press(key: &Key, modifiers: &egui::Modifiers) -> Option<KeyPress> {
+    let mut pressed = Modifiers::empty();
+    pressed.set(Modifiers::CTRL, modifiers.ctrl);
+    pressed.set(Modifiers::ALT, modifiers.alt);
+    pressed.set(Modifiers::SHIFT, modifiers.shift);
+    pressed.set(Modifiers::META, modifiers.mac_cmd);
+
+    let name = match key {
+        Key::Escape => "esc",
+        Key::Enter => "enter",
+        Key::Tab => "tab",
+        Key::Space => "space",
+        Key::Backspace => "backspace",
+        Key::Delete => "delete",
+        Key::Insert => "insert",
+        Key::ArrowLeft => "left",
+        Key::ArrowRight => "right",
+        Key::ArrowUp => "up",
+        Key::ArrowDown => "down",
+        Key::Home => "home",
+        Key::End => "end",
+        Key::PageUp => "pageup",
+        Key::PageDown => "pagedown",
+        _ if !pressed.intersects(Modifiers::CTRL | Modifiers::ALT | Modifiers::META) => {
+            return None
+        }
+        // Letters and digits, which egui names "A" and "Num0".
+        _ => {
+            let name = format!("{key:?}");
+            let name = name.strip_prefix("Num").unwrap_or(&name).to_lowercase();
+            return (name.chars().count() == 1).then(|| KeyPress::new(name, pressed));
+        }
+    };
+
+    Some(KeyPress::new(name, pressed))
+}
+
 impl<'ctx, Buffer> EguiTextEditor<Buffer>
 where
-    Buffer: TextBuffer,
+    Buffer: TextBuffer + Searchable,
 {
     fn editor_ui(&mut self, ui: &mut egui::Ui) -> egui::Response {
         let font_id = FontSelection::default().resolve(ui.style());
@@ -126,13 +185,28 @@ where
         let mut response = ui.interact(rect, auto_id, Sense::click_and_drag());
         let events = ui.input(|i| i.events.clone());
 
-        let requires_change = events.iter().any(|event| {
-            self.emit_transcation(event).map_or(false, |t| {
-                self.edit_ctx()
-                    .consume_transaction::<Buffer>(t)
-                    .unwrap_or(true)
-            })
-        });
+        // Every event is handled, even after one has changed the text.
+        let mut requires_change = false;
+        for event in &events {
+            let Some(t) = self.emit_transcation(event) else {
+                continue;
+            };
+            let changed = self
+                .edit_ctx()
+                .consume_transaction::<Buffer>(t)
+                .unwrap_or(true);
+
+            // Copied and cut text goes to the system clipboard too, as
+            // plain text.
+            if changed && matches!(event, Event::Copy | Event::Cut) {
+                if let Some(fragment) = &self.edit_ctx().clipboard {
+                    let text = fragment.plain_text();
+                    ui.output_mut(|o| o.copied_text = text);
+                }
+            }
+
+            requires_change |= changed && !matches!(event, Event::Copy);
+        }
 
         if requires_change {
             response.mark_changed();
@@ -147,6 +221,13 @@ where
             let painter = ui.painter_at(rect.expand(1.0));
             let draw = Draw::new(&painter);
 
+            let pair_highlights = self.edit_ctx().pair_highlights().unwrap_or_default();
+            for range in pair_highlights {
+                if let Ok(rect) = self.range_rect(&galley, draw_position, range) {
+                    draw.draw_pair_highlight(rect);
+                }
+            }
+
             if let Ok(cursor_rect) = cursor_rect {
                 draw.draw_cursor(cursor_rect);
             }
@@ -252,13 +333,7 @@ where
 
         // tracing::info!("{:?}", prev_wrapped_row_count);
 
-        let galley_row = match &galley.rows.get(row) {
-            Some(row) => row,
-            None => match galley.rows.last() {
-                Some(last) => last,
-                None => &galley.rows[0],
-            },
-        };
+        let galley_row = Self::galley_row(galley, row);
 
         let screen_x = galley_row.x_offset(col);
 
@@ -276,6 +351,53 @@ where
 
         Ok(cursor_rect)
     }
+
+    /// The rect covering the text of `range`, which lies on a single row.
+    fn range_rect(
+        &mut self,
+        galley: &Galley,
+        draw_position: Pos2,
+        range: Range<usize>,
+    ) -> Result<Rect, TextEditorError> {
+        let text_buffer = &mut self.0.edit_ctx.text_buffer;
+        let (start_row, start_col) = row_col(text_buffer.cursor_coords(ByteOffset(range.start))?);
+        let (end_row, end_col) = row_col(text_buffer.cursor_coords(ByteOffset(range.end))?);
+
+        let galley_row = Self::galley_row(galley, start_row);
+        let end_col = match end_row == start_row {
+            true => end_col,
+            false => start_col + 1,
+        };
+
+        Ok(Rect::from_min_max(
+            draw_position + vec2(galley_row.x_offset(start_col), galley_row.min_y()),
+            draw_position + vec2(galley_row.x_offset(end_col), galley_row.max_y()),
+        ))
+    }
+
+    fn galley_row(galley: &Galley, row: usize) -> &Row {
+        match galley.rows.get(row) {
+            Some(row) => row,
+            None => match galley.rows.last() {
+                Some(last) => last,
+                None => &galley.rows[0],
+            },
+        }
+    }
+}
+
+/// The row and column of `coords`, as [`TextBuffer::cursor_coords`] gives them.
+fn row_col(coords: (RowPosition, ColPosition)) -> (usize, usize) {
+    let row = match coords.0 {
+        RowPosition::Row(row) => row,
+        _ => 0,
+    };
+    let col = match coords.1 {
+        ColPosition::Col(col) => col,
+        _ => 0,
+    };
+
+    (row, col)
 }
 
 // impl<'ctx, Buffer: TextBuffer> egui::Widget for EguiTextEditor<Buffer> {
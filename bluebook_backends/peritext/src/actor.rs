@@ -75,7 +75,7 @@ impl Actor {
     }
 
     fn merge(&mut self, other: &Self) {
-        self.text.merge(&other.text)
+        self.text.merge(&other.text);
     }
 }
 
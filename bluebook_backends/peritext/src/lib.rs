@@ -110,7 +110,7 @@ pub enum Behavior {
 }
 
 /// If both `move_start_to` and `move_end_to` equal to None, the target range will be deleted
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Patch {
     pub id: OpID,
     pub target_range_id: OpID,
@@ -213,6 +213,17 @@ impl Expand {
         }
     }
 
+    /// The expand type of a range whose anchors have the given types, the
+    /// inverse of [`Expand::start_type`] and [`Expand::end_type`].
+    pub fn from_anchor_types(start: AnchorType, end: AnchorType) -> Self {
+        match (start, end) {
+            (AnchorType::Before, AnchorType::After) => Self::None,
+            (AnchorType::After, AnchorType::After) => Self::Before,
+            (AnchorType::Before, AnchorType::Before) => Self::After,
+            (AnchorType::After, AnchorType::Before) => Self::Both,
+        }
+    }
+
     pub fn infer_delete_expand(type_: &str) -> Self {
         Self::infer_insert_expand(type_).toggle()
     }
@@ -16,6 +16,9 @@ pub type AnnIdx = i32;
 pub struct AnnManager {
     idx_to_ann: Vec<Arc<Annotation>>,
     id_to_idx: FxHashMap<OpID, AnnIdx>,
+    /// Annotations removed by a patch. A patch may arrive before the
+    /// annotation it removes, so this is keyed by id rather than by index.
+    removed: FxHashSet<OpID>,
 }
 
 impl AnnManager {
@@ -54,6 +57,24 @@ impl AnnManager {
     pub fn get_idx_by_id(&self, id: OpID) -> Option<AnnIdx> {
         self.id_to_idx.get(&id).copied()
     }
+
+    pub fn remove(&mut self, id: OpID) {
+        self.removed.insert(id);
+    }
+
+    #[inline(always)]
+    pub fn is_removed(&self, id: OpID) -> bool {
+        self.removed.contains(&id)
+    }
+
+    /// Every annotation that hasn't been removed.
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Annotation>> {
+        // The zero pos repeats the first annotation.
+        self.idx_to_ann
+            .iter()
+            .skip(1)
+            .filter(|ann| !self.is_removed(ann.id))
+    }
 }
 
 /// The annotated text span.
@@ -409,6 +430,9 @@ impl StyleCalculator {
         let mut style_map = FxHashMap::default();
         for ann in self.inner.iter() {
             let ann = manager.get_ann_by_idx(*ann).unwrap();
+            if manager.is_removed(ann.id) {
+                continue;
+            }
             let suffix_to_make_inclusive_work = if ann.behavior == Behavior::AllowMultiple {
                 Some(ann.id)
             } else {
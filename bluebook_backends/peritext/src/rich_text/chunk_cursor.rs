@@ -0,0 +1,289 @@
+//! Cursors that walk the alive text of a [`RichText`] chunk by chunk, reading
+//! straight out of the content B-tree, so that navigating the document never
+//! has to copy it into a `String`.
+//!
+//! A chunk is the string of one alive [`Elem`]. All offsets are utf8 offsets
+//! into the alive text.
+
+use generic_btree::QueryResult;
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
+
+use super::{
+    rich_tree::{query::IndexFinder, utf16::bytes_to_str, Elem},
+    IndexType, RichText,
+};
+
+pub struct ChunkCursor<'a> {
+    text: &'a RichText,
+    /// The element holding the current chunk. It is alive unless the document is empty.
+    path: QueryResult,
+    /// Offset of the start of the current chunk.
+    chunk_start: usize,
+    pos: usize,
+}
+
+impl<'a> ChunkCursor<'a> {
+    pub fn new(text: &'a RichText, pos: usize) -> Self {
+        let mut cursor = Self {
+            text,
+            path: QueryResult {
+                leaf: text.content.first_leaf(),
+                elem_index: 0,
+                offset: 0,
+                found: true,
+            },
+            chunk_start: 0,
+            pos: 0,
+        };
+        cursor.set(pos);
+        cursor
+    }
+
+    #[inline]
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// Seek to `pos`, clamped to the length of the text.
+    pub fn set(&mut self, pos: usize) {
+        let pos = pos.min(self.text.len());
+        let path = self
+            .text
+            .content
+            .query::<IndexFinder>(&(pos, IndexType::Utf8));
+
+        self.pos = pos;
+        if self.is_alive(path) {
+            self.path = path;
+            self.chunk_start = pos - path.offset;
+        } else if let Some(prev) = self.prev_alive(path) {
+            // Only happens at the end of the text, when the last elements are tombstones.
+            self.path = prev;
+            self.chunk_start = pos - self.str_of(prev).len();
+        } else {
+            self.path = path;
+            self.chunk_start = pos;
+        }
+    }
+
+    /// The chunk the cursor is in, and the offset it starts at.
+    pub fn chunk(&self) -> (&'a str, usize) {
+        (self.str_of(self.path), self.chunk_start)
+    }
+
+    /// Whether the cursor sits between two codepoints.
+    pub fn is_char_boundary(&self) -> bool {
+        let (chunk, chunk_start) = self.chunk();
+        chunk.is_char_boundary(self.pos - chunk_start)
+    }
+
+    pub fn prev_codepoint(&mut self) -> Option<char> {
+        if self.pos == self.chunk_start {
+            let prev = self.prev_alive(self.path)?;
+            self.chunk_start -= self.str_of(prev).len();
+            self.path = prev;
+        }
+
+        let (chunk, chunk_start) = self.chunk();
+        let ch = chunk[..self.pos - chunk_start].chars().next_back()?;
+        self.pos -= ch.len_utf8();
+        Some(ch)
+    }
+
+    pub fn next_codepoint(&mut self) -> Option<char> {
+        let (chunk, chunk_start) = self.chunk();
+        if self.pos == chunk_start + chunk.len() {
+            let next = self.next_alive(self.path)?;
+            self.chunk_start = chunk_start + chunk.len();
+            self.path = next;
+        }
+
+        let (chunk, chunk_start) = self.chunk();
+        let ch = chunk[self.pos - chunk_start..].chars().next()?;
+        self.pos += ch.len_utf8();
+        Some(ch)
+    }
+
+    pub fn peek_next_codepoint(&self) -> Option<char> {
+        let (_, chunk, chunk_start) = self.chunk_after()?;
+        chunk[self.pos - chunk_start..].chars().next()
+    }
+
+    /// Whether the cursor sits on an extended grapheme cluster boundary.
+    pub fn is_grapheme_boundary(&self) -> bool {
+        if !self.is_char_boundary() {
+            return false;
+        }
+        let (chunk, chunk_start) = match self.chunk_after() {
+            Some((_, chunk, chunk_start)) => (chunk, chunk_start),
+            // The end of the text is always a boundary.
+            None => return true,
+        };
+
+        let mut gc = GraphemeCursor::new(self.pos, self.text.len(), true);
+        loop {
+            match gc.is_boundary(chunk, chunk_start) {
+                Ok(is_boundary) => return is_boundary,
+                Err(GraphemeIncomplete::PreContext(end)) => match self.chunk_ending_at(end) {
+                    Some((ctx, ctx_start)) => gc.provide_context(ctx, ctx_start),
+                    None => return true,
+                },
+                Err(_) => return false,
+            }
+        }
+    }
+
+    /// The offset of the next grapheme cluster boundary, without moving the cursor.
+    pub fn next_grapheme_boundary(&self) -> Option<usize> {
+        let (mut path, mut chunk, mut chunk_start) = self.chunk_after()?;
+
+        let mut gc = GraphemeCursor::new(self.pos, self.text.len(), true);
+        loop {
+            match gc.next_boundary(chunk, chunk_start) {
+                Ok(boundary) => return boundary,
+                Err(GraphemeIncomplete::NextChunk) => {
+                    chunk_start += chunk.len();
+                    path = self.next_alive(path)?;
+                    chunk = self.str_of(path);
+                }
+                Err(GraphemeIncomplete::PreContext(end)) => {
+                    let (ctx, ctx_start) = self.chunk_ending_at(end)?;
+                    gc.provide_context(ctx, ctx_start);
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// The offset of the previous grapheme cluster boundary, without moving the cursor.
+    pub fn prev_grapheme_boundary(&self) -> Option<usize> {
+        let (mut path, mut chunk, mut chunk_start) = self.chunk_before()?;
+
+        let mut gc = GraphemeCursor::new(self.pos, self.text.len(), true);
+        loop {
+            match gc.prev_boundary(chunk, chunk_start) {
+                Ok(boundary) => return boundary,
+                Err(GraphemeIncomplete::PrevChunk) => {
+                    path = self.prev_alive(path)?;
+                    chunk = self.str_of(path);
+                    chunk_start -= chunk.len();
+                }
+                Err(GraphemeIncomplete::PreContext(end)) => {
+                    let (ctx, ctx_start) = self.chunk_ending_at(end)?;
+                    gc.provide_context(ctx, ctx_start);
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// Move to the next grapheme cluster boundary and return it.
+    pub fn next_grapheme(&mut self) -> Option<usize> {
+        let boundary = self.next_grapheme_boundary()?;
+        while self.pos < boundary {
+            self.next_codepoint()?;
+        }
+        Some(boundary)
+    }
+
+    /// Move to the previous grapheme cluster boundary and return it.
+    pub fn prev_grapheme(&mut self) -> Option<usize> {
+        let boundary = self.prev_grapheme_boundary()?;
+        while self.pos > boundary {
+            self.prev_codepoint()?;
+        }
+        Some(boundary)
+    }
+
+    /// The chunk holding the codepoint right after the cursor.
+    fn chunk_after(&self) -> Option<(QueryResult, &'a str, usize)> {
+        let (chunk, chunk_start) = self.chunk();
+        if self.pos < chunk_start + chunk.len() {
+            return Some((self.path, chunk, chunk_start));
+        }
+
+        let next = self.next_alive(self.path)?;
+        Some((next, self.str_of(next), chunk_start + chunk.len()))
+    }
+
+    /// The chunk holding the codepoint right before the cursor.
+    fn chunk_before(&self) -> Option<(QueryResult, &'a str, usize)> {
+        let (chunk, chunk_start) = self.chunk();
+        if self.pos > chunk_start {
+            return Some((self.path, chunk, chunk_start));
+        }
+
+        let prev = self.prev_alive(self.path)?;
+        let prev_chunk = self.str_of(prev);
+        Some((prev, prev_chunk, chunk_start - prev_chunk.len()))
+    }
+
+    /// The text ending at `end`, up to the start of the chunk holding the codepoint
+    /// before it. This is what `GraphemeCursor` asks for as pre-context, which may
+    /// lie behind chunks that were already handed out.
+    fn chunk_ending_at(&self, end: usize) -> Option<(&'a str, usize)> {
+        if end == 0 {
+            return None;
+        }
+
+        let path = self
+            .text
+            .content
+            .query::<IndexFinder>(&(end - 1, IndexType::Utf8));
+        if !self.is_alive(path) {
+            return None;
+        }
+
+        let chunk = &self.str_of(path)[..path.offset + 1];
+        Some((chunk, end - chunk.len()))
+    }
+
+    fn elem(&self, path: QueryResult) -> Option<&'a Elem> {
+        let text = self.text;
+        text.content
+            .get_node(path.leaf)
+            .elements()
+            .get(path.elem_index)
+    }
+
+    fn is_alive(&self, path: QueryResult) -> bool {
+        self.elem(path).is_some_and(|elem| elem.content_len() > 0)
+    }
+
+    fn str_of(&self, path: QueryResult) -> &'a str {
+        match self.elem(path) {
+            Some(elem) if !elem.is_dead() => bytes_to_str(&elem.string[..]),
+            _ => "",
+        }
+    }
+
+    fn next_alive(&self, mut path: QueryResult) -> Option<QueryResult> {
+        let content = &self.text.content;
+        loop {
+            path.elem_index += 1;
+            while path.elem_index >= content.get_node(path.leaf).elements().len() {
+                path.leaf = content.next_same_level_node(path.leaf)?;
+                path.elem_index = 0;
+            }
+
+            if self.is_alive(path) {
+                return Some(path);
+            }
+        }
+    }
+
+    fn prev_alive(&self, mut path: QueryResult) -> Option<QueryResult> {
+        let content = &self.text.content;
+        loop {
+            while path.elem_index == 0 {
+                path.leaf = content.prev_same_level_node(path.leaf)?;
+                path.elem_index = content.get_node(path.leaf).elements().len();
+            }
+            path.elem_index -= 1;
+
+            if self.is_alive(path) {
+                return Some(path);
+            }
+        }
+    }
+}
@@ -8,13 +8,16 @@ use flate2::{read::GzDecoder, Compression};
 use fxhash::FxHashMap;
 use generic_btree::rle::HasLength;
 use serde::{Deserialize, Serialize};
-use serde_columnar::{columnar, from_bytes, to_vec};
+use serde_columnar::{columnar, ColumnarDecoder, ColumnarEncoder};
 
 use crate::{
-    Anchor, AnchorRange, AnchorType, Annotation, Behavior, ClientID, InternalString, OpID,
+    Anchor, AnchorRange, AnchorType, Annotation, Behavior, ClientID, InternalString, OpID, Patch,
 };
 
-use super::op::{DeleteOp, Op, OpContent, TextInsertOp};
+use super::{
+    error::Error,
+    op::{DeleteOp, Op, OpContent, TextInsertOp},
+};
 const COMPRESS_THRESHOLD: usize = 1024;
 
 #[columnar(vec, ser, de)]
@@ -66,6 +69,14 @@ pub(super) struct AnnEncoding {
     value: u32,
 }
 
+#[columnar(vec, ser, de)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct PatchEncoding {
+    target_range_id: OpID,
+    move_start_to: Option<OpID>,
+    move_end_to: Option<OpID>,
+}
+
 #[columnar(ser, de)]
 #[derive(Debug, Serialize, Deserialize)]
 struct DocEncoding {
@@ -86,11 +97,22 @@ struct DocEncoding {
     start_counters: Vec<u32>,
 }
 
+/// The patches of a document, encoded after the rest of it, and only when
+/// there are any: documents without patches, saved before there were patches
+/// or not, encode as they always have.
+#[columnar(ser, de)]
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PatchesEncoding {
+    #[columnar(type = "vec")]
+    patches: Vec<PatchEncoding>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum OpContentType {
     Insert = 0,
     Delete = 1,
     Ann = 2,
+    Patch = 3,
 }
 
 impl From<OpContentType> for u8 {
@@ -99,13 +121,16 @@ impl From<OpContentType> for u8 {
     }
 }
 
-impl From<u8> for OpContentType {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for OpContentType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            0 => OpContentType::Insert,
-            1 => OpContentType::Delete,
-            2 => OpContentType::Ann,
-            _ => unreachable!(),
+            0 => Ok(OpContentType::Insert),
+            1 => Ok(OpContentType::Delete),
+            2 => Ok(OpContentType::Ann),
+            3 => Ok(OpContentType::Patch),
+            _ => Err(Error::DecodeError),
         }
     }
 }
@@ -113,19 +138,29 @@ impl From<u8> for OpContentType {
 type InnerUpdates = FxHashMap<ClientID, Vec<Op>>;
 
 pub fn encode(exported: InnerUpdates) -> Vec<u8> {
-    let data = to_doc_encoding(exported);
-    to_vec(&data).unwrap()
+    let (data, patches) = to_doc_encoding(exported);
+    let mut encoder = ColumnarEncoder::new();
+    data.serialize(&mut *encoder).unwrap();
+    if !patches.patches.is_empty() {
+        patches.serialize(&mut *encoder).unwrap();
+    }
+    encoder.into_bytes()
 }
 
-pub fn decode(encoded: &[u8]) -> InnerUpdates {
-    from_doc_encoding(from_bytes(encoded).unwrap())
+pub fn decode(encoded: &[u8]) -> Result<InnerUpdates, Error> {
+    let mut decoder = ColumnarDecoder::new(encoded);
+    let data = DocEncoding::deserialize(&mut *decoder).map_err(|_| Error::DecodeError)?;
+    // A truncated column leaves patch ops without a patch, which fails below.
+    let patches = PatchesEncoding::deserialize(&mut *decoder).unwrap_or_default();
+    from_doc_encoding(data, patches)
 }
 
-fn to_doc_encoding(mut exported_map: InnerUpdates) -> DocEncoding {
+fn to_doc_encoding(mut exported_map: InnerUpdates) -> (DocEncoding, PatchesEncoding) {
     exported_map.retain(|_, v| !v.is_empty());
     let mut inserts = Vec::new();
     let mut deletes = Vec::new();
     let mut annotations = Vec::new();
+    let mut patches = Vec::new();
     let mut client_mapping = VecMapping::new();
     for client in exported_map.keys() {
         client_mapping.get_or_insert(*client);
@@ -185,6 +220,14 @@ fn to_doc_encoding(mut exported_map: InnerUpdates) -> DocEncoding {
                     });
                     OpContentType::Ann
                 }
+                crate::rich_text::op::OpContent::Patch(patch) => {
+                    patches.push(PatchEncoding {
+                        target_range_id: patch.target_range_id,
+                        move_start_to: patch.move_start_to,
+                        move_end_to: patch.move_end_to,
+                    });
+                    OpContentType::Patch
+                }
             };
 
             ops.push(OpEncoding {
@@ -209,7 +252,7 @@ fn to_doc_encoding(mut exported_map: InnerUpdates) -> DocEncoding {
         str = e.finish().unwrap();
     }
 
-    DocEncoding {
+    let data = DocEncoding {
         ops,
         inserts,
         deletes,
@@ -220,16 +263,33 @@ fn to_doc_encoding(mut exported_map: InnerUpdates) -> DocEncoding {
         op_len,
         start_counters,
         str,
-    }
+    };
+
+    (data, PatchesEncoding { patches })
 }
 
-fn from_doc_encoding(exported: DocEncoding) -> InnerUpdates {
+fn from_doc_encoding(
+    exported: DocEncoding,
+    patches: PatchesEncoding,
+) -> Result<InnerUpdates, Error> {
     let clients = &exported.clients;
+    let client_at = |index: u32| {
+        clients
+            .get(index as usize)
+            .copied()
+            .ok_or(Error::DecodeError)
+    };
+    let ann_str = |index: u32| {
+        exported
+            .ann_types_and_values
+            .get(index as usize)
+            .ok_or(Error::DecodeError)
+    };
     let mut str = AppendOnlyBytes::new();
     if exported.compressed_str {
         let mut d = GzDecoder::new(exported.str.deref());
         let mut ans = vec![];
-        d.read_to_end(&mut ans).unwrap();
+        d.read_to_end(&mut ans).map_err(|_| Error::DecodeError)?;
         str.push_slice(&ans);
     } else {
         str.push_slice(&exported.str);
@@ -239,6 +299,7 @@ fn from_doc_encoding(exported: DocEncoding) -> InnerUpdates {
     let mut insert_iter = exported.inserts.iter();
     let mut delete_iter = exported.deletes.iter();
     let mut ann_iter = exported.annotations.iter();
+    let mut patch_iter = patches.patches.iter();
     let mut op_iter = exported.ops.iter();
     for ((client, op_len), counter) in exported
         .clients
@@ -249,17 +310,17 @@ fn from_doc_encoding(exported: DocEncoding) -> InnerUpdates {
         let mut counter = *counter;
         let mut arr = Vec::with_capacity((*op_len) as usize);
         for _ in 0..*op_len {
-            let op = op_iter.next().unwrap();
+            let op = op_iter.next().ok_or(Error::DecodeError)?;
             let id = OpID {
                 client: *client,
                 counter,
             };
-            let content = match op.type_.into() {
+            let content = match op.type_.try_into()? {
                 OpContentType::Insert => {
-                    let insert = insert_iter.next().unwrap();
+                    let insert = insert_iter.next().ok_or(Error::DecodeError)?;
                     let left = if insert.left_client != u32::MAX {
                         Some(OpID {
-                            client: clients[insert.left_client as usize],
+                            client: client_at(insert.left_client)?,
                             counter: insert.left_counter,
                         })
                     } else {
@@ -267,29 +328,32 @@ fn from_doc_encoding(exported: DocEncoding) -> InnerUpdates {
                     };
                     let right = if insert.right_client != u32::MAX {
                         Some(OpID {
-                            client: clients[insert.right_client as usize],
+                            client: client_at(insert.right_client)?,
                             counter: insert.right_counter,
                         })
                     } else {
                         None
                     };
                     let end = str_index + insert.len as usize;
+                    if end > str.len() {
+                        return Err(Error::DecodeError);
+                    }
                     let text = str.slice(str_index..end);
                     str_index = end;
                     OpContent::Text(TextInsertOp { left, right, text })
                 }
                 OpContentType::Delete => {
-                    let delete = delete_iter.next().unwrap();
+                    let delete = delete_iter.next().ok_or(Error::DecodeError)?;
                     OpContent::Del(DeleteOp {
                         start: OpID {
-                            client: clients[delete.start_client as usize],
+                            client: client_at(delete.start_client)?,
                             counter: delete.start_counter,
                         },
                         len: delete.len,
                     })
                 }
                 OpContentType::Ann => {
-                    let ann = ann_iter.next().unwrap();
+                    let ann = ann_iter.next().ok_or(Error::DecodeError)?;
                     let range = AnchorRange {
                         start: Anchor {
                             id: ann.start,
@@ -312,15 +376,28 @@ fn from_doc_encoding(exported: DocEncoding) -> InnerUpdates {
                     OpContent::Ann(Arc::new(Annotation {
                         range,
                         behavior: ann.behavior,
-                        type_: exported.ann_types_and_values[ann.type_ as usize].clone(),
+                        type_: ann_str(ann.type_)?.clone(),
                         id,
                         range_lamport: (op.lamport, id),
-                        value: serde_json::from_str(
-                            &exported.ann_types_and_values[ann.value as usize],
-                        )
-                        .unwrap(),
+                        value: serde_json::from_str(ann_str(ann.value)?)
+                            .map_err(|_| Error::DecodeError)?,
                     }))
                 }
+                OpContentType::Patch => {
+                    let patch = patch_iter.next().ok_or(Error::DecodeError)?;
+                    // Only removals are ever made; moving a range is not
+                    // supported.
+                    if patch.move_start_to.is_some() || patch.move_end_to.is_some() {
+                        return Err(Error::UnsupportedPatch);
+                    }
+                    OpContent::Patch(Patch {
+                        id,
+                        target_range_id: patch.target_range_id,
+                        move_start_to: patch.move_start_to,
+                        move_end_to: patch.move_end_to,
+                        lamport: op.lamport,
+                    })
+                }
             };
 
             let op = Op {
@@ -335,7 +412,7 @@ fn from_doc_encoding(exported: DocEncoding) -> InnerUpdates {
         ans.insert(*client, arr);
     }
 
-    ans
+    Ok(ans)
 }
 
 struct VecMapping<T> {
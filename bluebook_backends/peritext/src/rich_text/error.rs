@@ -4,4 +4,6 @@ pub enum Error {
     DecodeError,
     #[error("Invalid expand")]
     InvalidExpand,
+    #[error("Moving an annotation's range is not supported")]
+    UnsupportedPatch,
 }
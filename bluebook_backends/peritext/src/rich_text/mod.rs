@@ -1,7 +1,7 @@
 use std::{
     cmp::Ordering,
     fmt::Display,
-    ops::{Bound, RangeBounds},
+    ops::{Bound, Range, RangeBounds},
     sync::Arc,
 };
 
@@ -21,8 +21,8 @@ use crate::{
         op::OpContent,
         rich_tree::utf16::{bytes_to_str, get_utf16_len_and_line_breaks, Utf16LenAndLineBreaks},
     },
-    Anchor, AnchorType, Annotation, Behavior, ClientID, Counter, Expand, IdSpan, InternalString,
-    OpID, Style,
+    Anchor, AnchorRange, AnchorType, Annotation, Behavior, ClientID, Counter, Expand, IdSpan,
+    InternalString, OpID, Patch, Style,
 };
 
 use self::{
@@ -34,7 +34,7 @@ use self::{
     rich_tree::{
         query::{IndexFinder, IndexFinderWithStyles, LineStartFinder},
         rich_tree_btree_impl::RichTreeTrait,
-        utf16::{get_utf16_len, utf16_to_utf8},
+        utf16::get_utf16_len,
         CacheDiff, Elem,
     },
     vv::VersionVector,
@@ -47,6 +47,7 @@ pub use event::Event;
 pub use rich_tree::query::IndexType;
 
 mod ann;
+pub mod chunk_cursor;
 pub mod cursor;
 mod delta;
 mod encoding;
@@ -64,6 +65,26 @@ pub mod vv;
 
 type Listener = Box<dyn FnMut(&Event)>;
 
+/// How [`RichText::remove_annotation`] removed an annotation.
+#[derive(Debug, Clone)]
+pub enum Removal {
+    /// Removed for good by a patch.
+    Patched,
+    /// Erased by this [`Behavior::Delete`] annotation over its range.
+    Erased(Arc<Annotation>),
+}
+
+/// What [`RichText::import`] or [`RichText::merge`] changed.
+#[derive(Debug, Clone, Default)]
+pub struct Imported {
+    /// The change to the text, in the event index type, as listeners get it.
+    pub delta: Vec<DeltaItem>,
+    /// The annotations added.
+    pub added: Vec<Arc<Annotation>>,
+    /// The annotations removed by a patch.
+    pub removed: Vec<OpID>,
+}
+
 pub struct RichText {
     bytes: AppendOnlyBytes,
     content: BTree<RichTreeTrait>,
@@ -633,6 +654,17 @@ impl RichText {
         style: Style,
         index_type: IndexType,
     ) {
+        self.add_annotation(range, style, index_type);
+    }
+
+    /// Annotate the given range with style, as [`RichText::annotate`] does, and
+    /// return the annotation made, or None if the range is empty.
+    pub fn add_annotation(
+        &mut self,
+        range: impl RangeBounds<usize>,
+        style: Style,
+        index_type: IndexType,
+    ) -> Option<Arc<Annotation>> {
         let start = match range.start_bound() {
             Bound::Included(start) => *start,
             Bound::Excluded(start) => *start + 1,
@@ -645,7 +677,7 @@ impl RichText {
         };
 
         if inclusive_end < start {
-            return;
+            return None;
         }
 
         let event = if self.has_listener() {
@@ -757,10 +789,12 @@ impl RichText {
         }
 
         // register op to store
-        self.store.insert_local(OpContent::new_ann(ann));
+        self.store.insert_local(OpContent::new_ann(ann.clone()));
         if let Some(event) = event {
             self.emit(event)
         }
+
+        Some(ann)
     }
 
     fn annotate_given_range(
@@ -886,27 +920,25 @@ impl RichText {
         encode(self.store.export(vv))
     }
 
-    pub fn import(&mut self, data: &[u8]) {
-        self.import_inner(decode(data));
+    pub fn import(&mut self, data: &[u8]) -> Result<Imported, Error> {
+        Ok(self.import_inner(decode(data)?))
     }
 
-    fn apply(&mut self, op: Op) -> Vec<DeltaItem> {
+    /// Apply `op`, returning the change it made to the text and recording
+    /// the annotations it adds or removes in `imported`.
+    fn apply(&mut self, op: Op, imported: &mut Imported) -> Vec<DeltaItem> {
         debug_log::group!("apply op");
         let mut ans = Vec::new();
-        let has_listener = self.has_listener();
         'apply: {
             match &op.content {
                 OpContent::Ann(ann) => {
                     let ann_idx = self.ann.register(ann.clone());
+                    imported.added.push(ann.clone());
                     let mut start = 0;
                     match ann.range.start.id {
                         Some(start_id) => {
                             let cursor = self.find_cursor(start_id);
-                            start = if has_listener {
-                                self.get_index_from_path(cursor, self.event_index_type)
-                            } else {
-                                0
-                            };
+                            start = self.get_index_from_path(cursor, self.event_index_type);
                             self.content.update_leaf(cursor.leaf, |elements| {
                                 let index = cursor.elem_index;
                                 let offset = cursor.offset;
@@ -920,9 +952,7 @@ impl RichText {
                                     Some(AnchorSetDiff::from_ann(ann_idx, is_start).into()),
                                 )
                             });
-                            if has_listener {
-                                ans.push(DeltaItem::retain(start));
-                            }
+                            ans.push(DeltaItem::retain(start));
                         }
                         None => {
                             self.init_styles.insert_start(ann_idx);
@@ -932,9 +962,7 @@ impl RichText {
                     let mut end = self.len_with(self.event_index_type);
                     if let Some(end_id) = ann.range.end.id {
                         let cursor = self.find_cursor(end_id);
-                        if has_listener {
-                            end = self.get_index_from_path(cursor, self.event_index_type);
-                        }
+                        end = self.get_index_from_path(cursor, self.event_index_type);
                         self.content.update_leaf(cursor.leaf, |elements| {
                             let index = cursor.elem_index;
                             let offset = cursor.offset;
@@ -949,11 +977,9 @@ impl RichText {
                             )
                         });
                     }
-                    if has_listener {
-                        let mut attributes: FxHashMap<_, _> = Default::default();
-                        attributes.insert(ann.type_.to_string(), ann.value.clone());
-                        ans.push(DeltaItem::retain_with_attributes(end - start, attributes));
-                    }
+                    let mut attributes: FxHashMap<_, _> = Default::default();
+                    attributes.insert(ann.type_.to_string(), ann.value.clone());
+                    ans.push(DeltaItem::retain_with_attributes(end - start, attributes));
                 }
                 OpContent::Text(text) => {
                     let right = match self.find_right(text, &op) {
@@ -967,60 +993,67 @@ impl RichText {
                                 text.right,
                                 text.text.clone(),
                             ));
-                            if has_listener {
-                                let annotations = self
-                                    .get_style_at_position(index, self.event_index_type)
-                                    .map(|(k, v)| (k.to_string(), v))
-                                    .collect();
-                                ans.push(DeltaItem::retain(index));
-                                ans.push(DeltaItem::insert_with_attributes(
-                                    bytes_to_str(&text.text).to_owned(),
-                                    self.event_index_type,
-                                    annotations,
-                                ));
-                            }
+                            let annotations = self
+                                .get_style_at_position(index, self.event_index_type)
+                                .map(|(k, v)| (k.to_string(), v))
+                                .collect();
+                            ans.push(DeltaItem::retain(index));
+                            ans.push(DeltaItem::insert_with_attributes(
+                                bytes_to_str(&text.text).to_owned(),
+                                self.event_index_type,
+                                annotations,
+                            ));
                             break 'apply;
                         }
                     };
 
-                    let mut index = 0;
-                    if let Some(right) = right {
-                        if has_listener {
-                            index = self.get_index_from_path(right, self.event_index_type);
-                        }
+                    let index = if let Some(right) = right {
+                        let index = self.get_index_from_path(right, self.event_index_type);
                         self.content.insert_by_query_result(
                             right,
                             Elem::new(op.id, text.left, text.right, text.text.clone()),
                         );
+                        index
                     } else {
-                        if has_listener {
-                            index = self.len_with(self.event_index_type);
-                        }
+                        let index = self.len_with(self.event_index_type);
                         self.content.push(Elem::new(
                             op.id,
                             text.left,
                             text.right,
                             text.text.clone(),
                         ));
-                    }
+                        index
+                    };
 
-                    if has_listener {
-                        let annotations = self
-                            .get_style_at_position(index, self.event_index_type)
-                            .map(|(k, v)| (k.to_string(), v))
-                            .collect();
-                        ans.push(DeltaItem::retain(index));
-                        ans.push(DeltaItem::insert_with_attributes(
-                            bytes_to_str(&text.text).to_owned(),
-                            self.event_index_type,
-                            annotations,
-                        ));
-                    }
+                    let annotations = self
+                        .get_style_at_position(index, self.event_index_type)
+                        .map(|(k, v)| (k.to_string(), v))
+                        .collect();
+                    ans.push(DeltaItem::retain(index));
+                    ans.push(DeltaItem::insert_with_attributes(
+                        bytes_to_str(&text.text).to_owned(),
+                        self.event_index_type,
+                        annotations,
+                    ));
                 }
                 OpContent::Del(del) => {
                     let del = del.positive();
                     self.delete_in_id_range(del.start, del.len as usize, &mut ans)
                 }
+                OpContent::Patch(patch) => {
+                    // Patches moving a range are rejected when decoding, so
+                    // this is a removal.
+                    let removed = self.ann.get_ann_by_id(patch.target_range_id).cloned();
+                    self.ann.remove(patch.target_range_id);
+                    if let Some(ann) = removed {
+                        imported.removed.push(ann.id);
+                        let range = self.resolve_range(&ann.range, self.event_index_type);
+                        ans.push(DeltaItem::retain(range.start));
+                        let mut attributes: FxHashMap<_, _> = Default::default();
+                        attributes.insert(ann.type_.to_string(), Value::Null);
+                        ans.push(DeltaItem::retain_with_attributes(range.len(), attributes));
+                    }
+                }
             }
         }
 
@@ -1148,19 +1181,19 @@ impl RichText {
     }
 
     /// Merge data from other data into self
-    pub fn merge(&mut self, other: &Self) {
+    pub fn merge(&mut self, other: &Self) -> Imported {
         let vv = self.store.vv();
         let exported = other.export(&vv);
-        let exported = decode(&exported);
+        let exported = decode(&exported).expect("a replica's own export decodes");
         if cfg!(debug_assertions) || cfg!(feature = "test") {
             let expected = other.store.export(&vv);
             assert_eq!(exported, expected);
         }
 
-        self.import_inner(exported);
+        self.import_inner(exported)
     }
 
-    fn import_inner(&mut self, exported: FxHashMap<ClientID, Vec<Op>>) {
+    fn import_inner(&mut self, exported: FxHashMap<ClientID, Vec<Op>>) -> Imported {
         let mut all_ops = Vec::new();
         for (_, ops) in exported {
             for mut op in ops {
@@ -1189,31 +1222,34 @@ impl RichText {
         // because of the merges of delete ops.
         let mut deletions = Vec::new();
         let mut delta = Vec::new();
+        let mut imported = Imported::default();
         for op in all_ops.iter() {
             if let OpContent::Del(_) = &op.content {
                 deletions.push(op.clone());
             } else {
-                let new_delta = self.apply(op.clone());
-                if self.has_listener() {
-                    delta = compose(delta, new_delta);
-                }
+                let new_delta = self.apply(op.clone(), &mut imported);
+                delta = compose(delta, new_delta);
             }
         }
 
         for op in deletions {
-            let new_delta = self.apply(op);
-            if self.has_listener() {
-                delta = compose(delta, new_delta);
-            }
+            let new_delta = self.apply(op, &mut imported);
+            delta = compose(delta, new_delta);
         }
 
+        delta.retain(|x| !x.should_remove());
+        imported.delta = delta;
+        // An annotation patched away before it arrived was never added.
+        imported.added.retain(|ann| !self.ann.is_removed(ann.id));
         if self.has_listener() {
             self.emit(Event {
-                ops: delta,
+                ops: imported.delta.clone(),
                 is_local: false,
                 index_type: self.event_index_type,
             })
         }
+
+        imported
     }
 
     pub fn version(&self) -> VersionVector {
@@ -1226,24 +1262,19 @@ impl RichText {
         // debug_log::debug_dbg!(&self.content);
         // debug_log::debug_dbg!(&self.cursor_map);
         // debug_log::group_end!();
-        let has_listener = self.has_listener();
         while len > 0 {
             let (insert_leaf, mut leaf_del_len) = self.cursor_map.get_insert(id).unwrap();
             leaf_del_len = leaf_del_len.min(len);
             // next record retain value
-            let mut retain = if has_listener {
-                self.get_index_from_path(
-                    QueryResult {
-                        leaf: insert_leaf,
-                        elem_index: 0,
-                        offset: 0,
-                        found: true,
-                    },
-                    self.event_index_type,
-                )
-            } else {
-                0
-            };
+            let mut retain = self.get_index_from_path(
+                QueryResult {
+                    leaf: insert_leaf,
+                    elem_index: 0,
+                    offset: 0,
+                    found: true,
+                },
+                self.event_index_type,
+            );
             let leaf_del_len = leaf_del_len;
             let mut left_len = leaf_del_len;
             let mut new_delta = Vec::new();
@@ -1269,17 +1300,12 @@ impl RichText {
                     let end = elem
                         .rle_len()
                         .min((id.counter + leaf_del_len as Counter - elem.id.counter) as usize);
-                    if has_listener {
-                        let start =
-                            elements[index].slice_len_with(self.event_index_type, 0..offset);
-                        retain += start;
-                        let del_len =
-                            elements[index].slice_len_with(self.event_index_type, offset..end);
-                        let end = elements[index].slice_len_with(self.event_index_type, end..);
-                        new_delta.push(DeltaItem::retain(retain));
-                        new_delta.push(DeltaItem::delete(del_len));
-                        retain = end;
-                    }
+                    retain += elements[index].slice_len_with(self.event_index_type, 0..offset);
+                    let del_len =
+                        elements[index].slice_len_with(self.event_index_type, offset..end);
+                    new_delta.push(DeltaItem::retain(retain));
+                    new_delta.push(DeltaItem::delete(del_len));
+                    retain = elements[index].slice_len_with(self.event_index_type, end..);
 
                     let (new, _) =
                         elements[index].update(offset, end, &mut |elem| elem.apply_remote_delete());
@@ -1456,6 +1482,10 @@ impl RichText {
         let start = self.content.query::<IndexFinder>(&(start, index_type));
         let end = self.content.query::<IndexFinder>(&(end, index_type));
         for span in self.content.iter_range(start..end) {
+            if span.elem.is_dead() {
+                continue;
+            }
+
             let s = &span.elem.string;
             ans.push_str(bytes_to_str(
                 &s[span.start.unwrap_or(0)..span.end.unwrap_or(s.len())],
@@ -1465,6 +1495,105 @@ impl RichText {
         ans
     }
 
+    /// The ids of the bytes in `range`, in document order, as runs of
+    /// consecutive ids: the id of the first byte of each run, and its length.
+    pub fn id_runs(
+        &self,
+        range: impl RangeBounds<usize>,
+        index_type: IndexType,
+    ) -> Vec<(OpID, usize)> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len_with(index_type),
+        };
+
+        let mut ans: Vec<(OpID, usize)> = Vec::new();
+        if start >= end {
+            return ans;
+        }
+
+        let start = self.content.query::<IndexFinder>(&(start, index_type));
+        let end = self.content.query::<IndexFinder>(&(end, index_type));
+        for span in self.content.iter_range(start..end) {
+            if span.elem.is_dead() {
+                continue;
+            }
+
+            let from = span.start.unwrap_or(0);
+            let len = span.end.unwrap_or(span.elem.rle_len()) - from;
+            if len == 0 {
+                continue;
+            }
+            let id = span.elem.id.inc(from as Counter);
+            match ans.last_mut() {
+                Some((last, last_len)) if last.inc(*last_len as Counter) == id => *last_len += len,
+                _ => ans.push((id, len)),
+            }
+        }
+
+        ans
+    }
+
+    /// The ranges covered by those of the bytes in the id runs `ids` that
+    /// haven't been deleted, in document order, with adjacent ranges joined.
+    ///
+    /// Each run is looked up leaf by leaf, as deleting it would be.
+    pub fn live_ranges(
+        &self,
+        ids: impl IntoIterator<Item = (OpID, usize)>,
+        index_type: IndexType,
+    ) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        for (mut id, mut len) in ids {
+            while len > 0 {
+                let Some((leaf, leaf_len)) = self.cursor_map.get_insert(id) else {
+                    break;
+                };
+                let leaf_len = leaf_len.min(len);
+                let mut index = self.get_index_from_path(
+                    QueryResult {
+                        leaf,
+                        elem_index: 0,
+                        offset: 0,
+                        found: true,
+                    },
+                    index_type,
+                );
+                for elem in self.content.get_node(leaf).elements() {
+                    if !elem.is_dead() && elem.overlap(id, leaf_len) {
+                        let from = id.counter.saturating_sub(elem.id.counter) as usize;
+                        let to = elem
+                            .rle_len()
+                            .min((id.counter + leaf_len as Counter - elem.id.counter) as usize);
+                        let start = index + elem.slice_len_with(index_type, 0..from);
+                        ranges.push(start..start + elem.slice_len_with(index_type, from..to));
+                    }
+                    index += elem.content_len_with(index_type);
+                }
+
+                id.counter += leaf_len as Counter;
+                len -= leaf_len;
+            }
+        }
+
+        ranges.sort_unstable_by_key(|range| range.start);
+        let mut ans: Vec<Range<usize>> = Vec::new();
+        for range in ranges {
+            match ans.last_mut() {
+                Some(last) if last.end == range.start => last.end = range.end,
+                _ => ans.push(range),
+            }
+        }
+
+        ans
+    }
+
     pub fn slice(&self, range: impl RangeBounds<usize>, index_type: IndexType) -> Vec<Span> {
         let start = match range.start_bound() {
             Bound::Included(&start) => start,
@@ -1509,6 +1638,202 @@ impl RichText {
         self.content.root_cache().line_breaks as usize + 1
     }
 
+    /// Get the line the given index is on, i.e. the number of `\n` before it.
+    pub fn line_of_offset(&self, index: usize, index_type: IndexType) -> usize {
+        let path = self.content.query::<IndexFinder>(&(index, index_type));
+        let mut line = 0;
+        self.content.visit_previous_caches(path, |v| match v {
+            generic_btree::PreviousCache::NodeCache(cache) => {
+                line += cache.line_breaks as usize;
+            }
+            generic_btree::PreviousCache::PrevSiblingElem(elem) => {
+                if !elem.is_dead() {
+                    line += elem.line_breaks as usize;
+                }
+            }
+            generic_btree::PreviousCache::ThisElemAndOffset { elem, offset } => {
+                if !elem.is_dead() {
+                    line += elem.string[..offset].iter().filter(|&&b| b == b'\n').count();
+                }
+            }
+        });
+        line
+    }
+
+    /// Get the utf8 index at which `line` starts, or `None` if there is no such line.
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        if line >= self.lines() {
+            return None;
+        }
+
+        let path = self.content.query::<LineStartFinder>(&line);
+        Some(self.get_index_from_path(path, IndexType::Utf8))
+    }
+
+    /// A cursor walking the text from the utf8 index `pos`, without copying it.
+    pub fn chunk_cursor(&self, pos: usize) -> chunk_cursor::ChunkCursor<'_> {
+        chunk_cursor::ChunkCursor::new(self, pos)
+    }
+
+    /// A position that sticks to the text around `index` rather than to the index
+    /// itself, so that it can be found again after concurrent edits.
+    ///
+    /// With [`AnchorType::Before`] the anchor sits right before the character at
+    /// `index`, and text inserted at `index` ends up before it. With
+    /// [`AnchorType::After`] it sits right after the character before `index`, and
+    /// inserted text ends up after it.
+    pub fn anchor_at(&self, index: usize, type_: AnchorType, index_type: IndexType) -> Anchor {
+        let index = self.convert_index(index, index_type, IndexType::Utf8);
+        let target = match type_ {
+            AnchorType::Before if index < self.len() => Some(index),
+            // Ids are per byte, so this is the last byte of the character before.
+            AnchorType::After if index > 0 => Some(index - 1),
+            _ => None,
+        };
+
+        Anchor {
+            id: target.map(|target| {
+                self.get_id_at_pos(
+                    self.content
+                        .query::<IndexFinder>(&(target, IndexType::Utf8)),
+                )
+            }),
+            type_,
+        }
+    }
+
+    /// The index `anchor` currently resolves to. An anchor whose character has
+    /// been deleted resolves to where the character used to be.
+    ///
+    /// # Panics
+    ///
+    /// If the anchor was not created on this document or on one of its replicas.
+    pub fn resolve_anchor(&self, anchor: Anchor, index_type: IndexType) -> usize {
+        let index = match (anchor.id, anchor.type_) {
+            (None, AnchorType::Before) => self.len(),
+            (None, AnchorType::After) => 0,
+            (Some(id), type_) => {
+                let path = self.find_cursor(id);
+                let index = self.get_index_from_path(path, IndexType::Utf8);
+                let is_dead =
+                    self.content.get_node(path.leaf).elements()[path.elem_index].is_dead();
+                match type_ {
+                    AnchorType::After if !is_dead => index + 1,
+                    _ => index,
+                }
+            }
+        };
+
+        self.convert_index(index, IndexType::Utf8, index_type)
+    }
+
+    /// The indexes `range` currently covers.
+    fn resolve_range(&self, range: &AnchorRange, index_type: IndexType) -> Range<usize> {
+        let start = self.resolve_anchor(range.start, index_type);
+        let end = self.resolve_anchor(range.end, index_type);
+        start..end.max(start)
+    }
+
+    /// Every annotation that hasn't been removed, erasing ones included, each
+    /// with the range it currently covers.
+    pub fn annotations(&self, index_type: IndexType) -> Vec<(Arc<Annotation>, Range<usize>)> {
+        self.ann
+            .iter()
+            .map(|ann| (ann.clone(), self.resolve_range(&ann.range, index_type)))
+            .collect()
+    }
+
+    /// The annotations in effect on at least one character of `range`, each
+    /// with the range it covers.
+    ///
+    /// Erasing annotations ([`Behavior::Delete`]) are left out, and so are the
+    /// annotations that newer annotations of the same type override on every
+    /// character of `range`.
+    pub fn annotations_in(
+        &self,
+        range: impl RangeBounds<usize>,
+        index_type: IndexType,
+    ) -> Vec<(Arc<Annotation>, Range<usize>)> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len_with(index_type),
+        };
+
+        annotations_in_effect(&self.annotations(index_type), start..end)
+    }
+
+    /// Remove the annotation `id`, returning how it was removed, or None if
+    /// there is no such annotation.
+    ///
+    /// [`Behavior::AllowMultiple`] annotations, such as comments, are removed for
+    /// good by a patch, and other annotations of the same type are left alone.
+    /// Any other annotation is erased the way a [`Behavior::Delete`] annotation
+    /// over its range does, which erases every annotation of its type there.
+    pub fn remove_annotation(&mut self, id: OpID) -> Option<Removal> {
+        let ann = self.ann.get_ann_by_id(id).cloned()?;
+        if self.ann.is_removed(id) || ann.behavior == Behavior::Delete {
+            return None;
+        }
+
+        if ann.behavior != Behavior::AllowMultiple {
+            let range = self.resolve_range(&ann.range, IndexType::Utf8);
+            if range.is_empty() {
+                return None;
+            }
+
+            let expand = Expand::from_anchor_types(ann.range.start.type_, ann.range.end.type_);
+            return self
+                .add_annotation(
+                    range,
+                    Style {
+                        expand: expand.toggle(),
+                        behavior: Behavior::Delete,
+                        type_: ann.type_.clone(),
+                        value: Value::Null,
+                    },
+                    IndexType::Utf8,
+                )
+                .map(Removal::Erased);
+        }
+
+        let event = if self.has_listener() {
+            let range = self.resolve_range(&ann.range, self.event_index_type);
+            let mut attributes: FxHashMap<_, _> = Default::default();
+            attributes.insert(ann.type_.to_string(), Value::Null);
+            Some(Event {
+                ops: vec![
+                    DeltaItem::retain(range.start),
+                    DeltaItem::retain_with_attributes(range.len(), attributes),
+                ],
+                is_local: true,
+                index_type: self.event_index_type,
+            })
+        } else {
+            None
+        };
+
+        let patch = Patch {
+            id: self.next_id(),
+            target_range_id: id,
+            move_start_to: None,
+            move_end_to: None,
+            lamport: self.next_lamport(),
+        };
+        self.store.insert_local(OpContent::new_patch(patch));
+        self.ann.remove(id);
+        if let Some(event) = event {
+            self.emit(event)
+        }
+
+        Some(Removal::Patched)
+    }
+
     pub fn apply_delta(&mut self, delta: impl Iterator<Item = DeltaItem>, index_type: IndexType) {
         let mut index = 0;
         for delta_item in delta {
@@ -1643,7 +1968,8 @@ impl RichText {
             generic_btree::PreviousCache::ThisElemAndOffset { elem, offset } => {
                 if !elem.is_dead() {
                     match index_type {
-                        IndexType::Utf8 => count += utf16_to_utf8(&elem.string, offset),
+                        // the offset of a query result is always a utf8 offset
+                        IndexType::Utf8 => count += offset,
                         IndexType::Utf16 => {
                             count += get_utf16_len_and_line_breaks(&elem.string[..offset]).utf16
                                 as usize;
@@ -1669,3 +1995,56 @@ impl Display for RichText {
         Ok(())
     }
 }
+
+/// The annotations out of `resolved` in effect on at least one character of
+/// `range`, as [`RichText::annotations_in`] defines them.
+///
+/// Annotations overriding one another on `range` overlap it, so `resolved`
+/// only needs to hold the annotations overlapping `range` for the result to
+/// be the same as with all of them.
+pub fn annotations_in_effect(
+    resolved: &[(Arc<Annotation>, Range<usize>)],
+    range: Range<usize>,
+) -> Vec<(Arc<Annotation>, Range<usize>)> {
+    resolved
+        .iter()
+        .filter(|(ann, ann_range)| {
+            if ann.behavior == Behavior::Delete {
+                return false;
+            }
+
+            let overlap = ann_range.start.max(range.start)..ann_range.end.min(range.end);
+            let mut visible: Vec<Range<usize>> = std::iter::once(overlap).collect();
+            // `AllowMultiple` annotations never override each other, see
+            // `StyleCalculator::calc_styles`.
+            if ann.behavior != Behavior::AllowMultiple {
+                for (other, other_range) in resolved.iter() {
+                    if other.type_ == ann.type_
+                        && other.behavior != Behavior::AllowMultiple
+                        && other.range_lamport > ann.range_lamport
+                    {
+                        visible = visible
+                            .into_iter()
+                            .flat_map(|piece| range_difference(piece, other_range))
+                            .collect();
+                    }
+                }
+            }
+
+            visible.iter().any(|piece| piece.start < piece.end)
+        })
+        .cloned()
+        .collect()
+}
+
+/// The parts of `range` outside of `other`.
+fn range_difference(
+    range: Range<usize>,
+    other: &Range<usize>,
+) -> impl Iterator<Item = Range<usize>> {
+    let before = range.start..range.end.min(other.start);
+    let after = range.start.max(other.end)..range.end;
+    [before, after]
+        .into_iter()
+        .filter(|piece| piece.start < piece.end)
+}
@@ -4,7 +4,7 @@ use append_only_bytes::BytesSlice;
 use fxhash::FxHashMap;
 use generic_btree::rle::{HasLength, Mergeable, Sliceable};
 
-use crate::{Annotation, ClientID, Counter, Lamport, OpID};
+use crate::{Annotation, ClientID, Counter, Lamport, OpID, Patch};
 
 use super::vv::VersionVector;
 
@@ -20,6 +20,7 @@ pub enum OpContent {
     Ann(Arc<Annotation>),
     Text(TextInsertOp),
     Del(DeleteOp),
+    Patch(Patch),
 }
 
 impl OpContent {
@@ -43,6 +44,10 @@ impl OpContent {
     pub fn new_ann(ann: Arc<Annotation>) -> Self {
         OpContent::Ann(ann)
     }
+
+    pub fn new_patch(patch: Patch) -> Self {
+        OpContent::Patch(patch)
+    }
 }
 
 #[derive(Clone)]
@@ -205,6 +210,7 @@ impl HasLength for Op {
     fn rle_len(&self) -> usize {
         match &self.content {
             OpContent::Ann(_) => 1,
+            OpContent::Patch(_) => 1,
             OpContent::Text(text) => text.text.len(),
             OpContent::Del(del) => del.len.unsigned_abs() as usize,
         }
@@ -260,6 +266,11 @@ impl Sliceable for Op {
                 lamport: self.lamport + (start as Lamport),
                 content: OpContent::Ann(a.clone()),
             },
+            OpContent::Patch(p) => Op {
+                id: self.id.inc(start as Counter),
+                lamport: self.lamport + (start as Lamport),
+                content: OpContent::Patch(*p),
+            },
             OpContent::Text(text) => Op {
                 id: self.id.inc(start as Counter),
                 lamport: self.lamport + (start as Lamport),
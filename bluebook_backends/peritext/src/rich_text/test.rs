@@ -44,6 +44,16 @@ mod delete {
         assert_eq!(text.to_string().as_str(), "");
     }
 
+    #[test]
+    fn slice_skips_deleted_text() {
+        let mut text = RichText::new(1);
+        text.insert(0, "aé😀b");
+        text.delete(1..3);
+        assert_eq!(text.slice_str(.., IndexType::Utf8), "a😀b");
+        assert_eq!(text.slice_str(1..5, IndexType::Utf8), "😀");
+        assert_eq!(text.slice_str(1..3, IndexType::Utf16), "😀");
+    }
+
     #[test]
     fn delete_across_leaf() {
         let mut text = RichText::new(1);
@@ -744,6 +754,292 @@ mod get_line {
     }
 }
 
+mod chunk_cursor {
+    use super::*;
+
+    /// Build a text out of many small inserts, so that it spans many elements and leaves.
+    fn fragmented(s: &str) -> RichText {
+        let mut text = RichText::new(1);
+        for (i, ch) in s.char_indices().collect::<Vec<_>>().into_iter().rev() {
+            text.insert(0, &s[i..i + ch.len_utf8()]);
+        }
+        text
+    }
+
+    #[test]
+    fn walk_codepoints() {
+        let text = fragmented("aé😀\nb");
+        let mut cursor = text.chunk_cursor(0);
+        let mut chars = Vec::new();
+        while let Some(ch) = cursor.next_codepoint() {
+            chars.push(ch);
+        }
+        assert_eq!(chars, vec!['a', 'é', '😀', '\n', 'b']);
+        assert_eq!(cursor.pos(), text.len());
+        assert_eq!(cursor.prev_codepoint(), Some('b'));
+        assert_eq!(cursor.prev_codepoint(), Some('\n'));
+        assert_eq!(cursor.peek_next_codepoint(), Some('\n'));
+        assert_eq!(cursor.pos(), 7);
+    }
+
+    #[test]
+    fn skip_deleted_text() {
+        let mut text = RichText::new(1);
+        text.insert(0, "hello");
+        text.insert(5, " world");
+        text.delete(2..8);
+        assert_eq!(text.to_string(), "herld");
+
+        let mut cursor = text.chunk_cursor(1);
+        assert_eq!(cursor.next_codepoint(), Some('e'));
+        assert_eq!(cursor.next_codepoint(), Some('r'));
+        cursor.set(5);
+        assert_eq!(cursor.prev_codepoint(), Some('d'));
+    }
+
+    #[test]
+    fn grapheme_across_chunks() {
+        let family = "👨‍👩‍👧‍👦";
+        let text = fragmented(&format!("a{family}b"));
+        let end = 1 + family.len();
+
+        let mut cursor = text.chunk_cursor(1);
+        assert_eq!(cursor.next_grapheme_boundary(), Some(end));
+        assert_eq!(cursor.next_grapheme(), Some(end));
+        assert!(cursor.is_grapheme_boundary());
+        assert_eq!(cursor.prev_grapheme(), Some(1));
+        assert!(!text.chunk_cursor(5).is_grapheme_boundary());
+    }
+
+    #[test]
+    fn line_starts() {
+        let text = fragmented("one\n你好\n\nfour");
+        assert_eq!(text.line_start(0), Some(0));
+        assert_eq!(text.line_start(1), Some(4));
+        assert_eq!(text.line_start(2), Some(11));
+        assert_eq!(text.line_start(3), Some(12));
+        assert_eq!(text.line_start(4), None);
+        assert_eq!(text.line_of_offset(0, IndexType::Utf8), 0);
+        assert_eq!(text.line_of_offset(4, IndexType::Utf8), 1);
+        assert_eq!(text.line_of_offset(10, IndexType::Utf8), 1);
+        assert_eq!(text.line_of_offset(14, IndexType::Utf8), 3);
+    }
+}
+
+mod anchor {
+    use super::*;
+
+    #[test]
+    fn anchors_follow_concurrent_inserts() {
+        let mut a = RichText::new(1);
+        a.insert(0, "Hello world");
+        let mut b = RichText::new(2);
+        b.merge(&a);
+
+        let before = a.anchor_at(6, AnchorType::Before, IndexType::Utf8);
+        let after = a.anchor_at(6, AnchorType::After, IndexType::Utf8);
+        b.insert(0, ">> ");
+        b.insert(9, "big ");
+        a.merge(&b);
+
+        assert_eq!(a.to_string(), ">> Hello big world");
+        assert_eq!(a.resolve_anchor(before, IndexType::Utf8), 13);
+        assert_eq!(a.resolve_anchor(after, IndexType::Utf8), 9);
+    }
+
+    #[test]
+    fn anchors_at_the_edges() {
+        let mut text = RichText::new(1);
+        text.insert(0, "abc");
+        let start = text.anchor_at(0, AnchorType::After, IndexType::Utf8);
+        let end = text.anchor_at(3, AnchorType::Before, IndexType::Utf8);
+        assert_eq!(start.id, None);
+        assert_eq!(end.id, None);
+
+        text.insert(0, "x");
+        text.insert(4, "y");
+        assert_eq!(text.resolve_anchor(start, IndexType::Utf8), 0);
+        assert_eq!(text.resolve_anchor(end, IndexType::Utf8), 5);
+    }
+
+    #[test]
+    fn anchor_on_deleted_text() {
+        let mut text = RichText::new(1);
+        text.insert(0, "aé😀b");
+        let anchor = text.anchor_at(3, AnchorType::After, IndexType::Utf8);
+        assert_eq!(text.resolve_anchor(anchor, IndexType::Utf8), 3);
+        assert_eq!(text.resolve_anchor(anchor, IndexType::Utf16), 2);
+
+        text.delete(1..3);
+        assert_eq!(text.resolve_anchor(anchor, IndexType::Utf8), 1);
+    }
+
+    #[test]
+    fn id_runs_skip_deleted_text() {
+        let mut text = RichText::new(1);
+        text.insert(0, "abcdef");
+        text.delete(2..4);
+        text.insert(1, "x");
+        let id = |offset| {
+            text.anchor_at(offset, AnchorType::Before, IndexType::Utf8)
+                .id
+                .unwrap()
+        };
+
+        // "axbef": "a", "x", then "b" and "ef" around the deleted "cd".
+        assert_eq!(
+            text.id_runs(0..5, IndexType::Utf8),
+            [(id(0), 1), (id(1), 1), (id(2), 1), (id(3), 2)]
+        );
+        assert_eq!(text.id_runs(3..5, IndexType::Utf8), [(id(3), 2)]);
+        assert_eq!(text.id_runs(2..2, IndexType::Utf8), []);
+    }
+
+    #[test]
+    fn live_ranges_skip_deleted_text() {
+        let mut text = RichText::new(1);
+        text.insert(0, "abcdef");
+        let ids = text.id_runs(.., IndexType::Utf8);
+        text.delete(2..4);
+        text.insert(1, "x");
+
+        // "axbef": "a" and "bef" are what is left of "abcdef".
+        assert_eq!(text.live_ranges(ids, IndexType::Utf8), [0..1, 2..5]);
+        assert_eq!(text.live_ranges([], IndexType::Utf8), []);
+    }
+}
+
+mod annotations {
+    use serde_json::Value;
+
+    use super::*;
+
+    fn types_at(text: &RichText, index: usize) -> Vec<String> {
+        let mut types: Vec<_> = text
+            .annotations_in(index..index + 1, IndexType::Utf8)
+            .into_iter()
+            .map(|(ann, _)| ann.type_.to_string())
+            .collect();
+        types.sort();
+        types
+    }
+
+    #[test]
+    fn query_annotations_with_their_ranges() {
+        let mut text = RichText::new(1);
+        text.insert(0, "Roe v. Wade");
+        text.annotate(0..3, Style::new_bold_like("bold".into(), Value::Bool(true)));
+        text.annotate(
+            2..6,
+            Style::new_comment_like("comment".into(), "cite".into()),
+        );
+
+        let found = text.annotations_in(.., IndexType::Utf8);
+        assert_eq!(found.len(), 2);
+        assert!(found
+            .iter()
+            .any(|(ann, range)| ann.type_ == *"bold" && *range == (0..3)));
+        assert!(found
+            .iter()
+            .any(|(ann, range)| ann.type_ == *"comment" && *range == (2..6)));
+        assert_eq!(types_at(&text, 2), ["bold", "comment"]);
+        assert!(types_at(&text, 8).is_empty());
+
+        // Erased on every character, so no longer in effect.
+        text.annotate(0..3, Style::new_erase_bold_like("bold".into()));
+        assert_eq!(types_at(&text, 1), Vec::<String>::new());
+    }
+
+    #[test]
+    fn remove_one_comment_of_several() {
+        let mut text = RichText::new(1);
+        text.insert(0, "Roe v. Wade");
+        text.annotate(0..3, Style::new_comment_like("comment".into(), "a".into()));
+        text.annotate(0..6, Style::new_comment_like("comment".into(), "b".into()));
+        let mut other = RichText::new(2);
+        other.merge(&text);
+
+        let (first, _) = text
+            .annotations_in(0..1, IndexType::Utf8)
+            .into_iter()
+            .find(|(ann, _)| ann.value == "a")
+            .unwrap();
+        assert!(matches!(
+            text.remove_annotation(first.id),
+            Some(Removal::Patched)
+        ));
+        assert!(text.remove_annotation(first.id).is_none());
+
+        let remaining = text.annotations_in(.., IndexType::Utf8);
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].0.value, "b");
+
+        other.import(&text.export(&other.version())).unwrap();
+        assert_eq!(other.annotations_in(.., IndexType::Utf8).len(), 1);
+    }
+
+    #[test]
+    fn remove_merged_style() {
+        let mut text = RichText::new(1);
+        text.insert(0, "Roe v. Wade");
+        text.annotate(0..6, Style::new_bold_like("bold".into(), Value::Bool(true)));
+        let (bold, _) = text.annotations_in(0..1, IndexType::Utf8).remove(0);
+
+        assert!(matches!(
+            text.remove_annotation(bold.id),
+            Some(Removal::Erased(_))
+        ));
+        assert!(text.annotations_in(.., IndexType::Utf8).is_empty());
+        assert!(text.get_spans()[0].attributes.is_empty());
+    }
+}
+
+mod encoding {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::rich_text::{
+        encoding::{decode, encode},
+        op::OpContent,
+        Error,
+    };
+
+    #[test]
+    fn undecodable_updates_are_errors() {
+        let mut text = RichText::new(1);
+        text.insert(0, "Roe v. Wade");
+        let data = text.export(&Default::default());
+
+        let mut other = RichText::new(2);
+        assert!(other.import(&data[..data.len() - 1]).is_err());
+        assert!(other.import(&[]).is_err());
+        other.import(&data).unwrap();
+        assert_eq!(other.to_string(), "Roe v. Wade");
+    }
+
+    #[test]
+    fn moving_patches_are_rejected() {
+        let mut text = RichText::new(1);
+        text.insert(0, "Roe v. Wade");
+        text.annotate(0..3, Style::new_comment_like("comment".into(), Value::Null));
+        let (ann, _) = text.annotations_in(.., IndexType::Utf8).remove(0);
+        text.remove_annotation(ann.id);
+
+        let mut updates = decode(&text.export(&Default::default())).unwrap();
+        for op in updates.values_mut().flatten() {
+            if let OpContent::Patch(patch) = &mut op.content {
+                patch.move_start_to = Some(ann.id);
+            }
+        }
+
+        let mut other = RichText::new(2);
+        assert!(matches!(
+            other.import(&encode(updates)),
+            Err(Error::UnsupportedPatch)
+        ));
+    }
+}
+
 mod delta {
     use std::{
         rc::Rc,
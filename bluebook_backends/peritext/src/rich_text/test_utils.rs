@@ -451,7 +451,7 @@ impl Actor {
     }
 
     fn merge(&mut self, other: &Self) {
-        self.text.merge(&other.text)
+        self.text.merge(&other.text);
     }
 
     #[allow(unused)]
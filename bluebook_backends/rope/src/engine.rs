@@ -38,7 +38,7 @@ use crate::multiset::{CountMatcher, Subset};
 use crate::rope::{Rope, RopeInfo};
 
 /// Represents the current state of a document and all of its history
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Engine {
     /// The session ID used to create new `RevId`s for edits made on this device
@@ -89,7 +89,7 @@ pub struct RevId {
     num: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct Revision {
     /// This uniquely represents the identity of this revision and it stays
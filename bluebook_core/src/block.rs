@@ -1,4 +1,9 @@
-use crate::text_buffer::TextBuffer;
+use std::ops::Range;
+
+use serde_json::Value;
+use string_cache::DefaultAtom;
+
+use crate::{span::FxHashMap, text_buffer::TextBuffer};
 
 #[derive(thiserror::Error, Debug)]
 pub enum BlockCursorError {
@@ -7,7 +12,190 @@ pub enum BlockCursorError {
     // Add more error variants as needed
 }
 
+/// The annotation types that apply to a whole block rather than to a run of
+/// text inside it.
+pub const BLOCK_ATTRIBUTES: [&str; 4] = ["header", "list", "code-block", "align"];
+
+/// A block: the text between two line feeds, together with its block
+/// attributes.
+///
+/// The attributes of a block are the block annotations on its first
+/// character, so annotating any range that starts at the beginning of a line
+/// turns that line into a header, a list item, etc. An empty block is just its
+/// line feed, which carries its attributes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Block {
+    /// The block, including its trailing line feed if it has one.
+    pub range: Range<usize>,
+    pub attributes: FxHashMap<DefaultAtom, Value>,
+}
+
+impl Block {
+    /// Build a block from the attributes of its first character, keeping only
+    /// the [`BLOCK_ATTRIBUTES`].
+    pub fn new(
+        range: Range<usize>,
+        attributes: impl IntoIterator<Item = (DefaultAtom, Value)>,
+    ) -> Self {
+        Self {
+            range,
+            attributes: attributes
+                .into_iter()
+                .filter(|(type_, value)| {
+                    !value.is_null() && BLOCK_ATTRIBUTES.contains(&type_.as_ref())
+                })
+                .collect(),
+        }
+    }
+
+    fn attribute(&self, type_: &str) -> Option<&Value> {
+        self.attributes.get(&DefaultAtom::from(type_))
+    }
+
+    /// The header level, if the block is a header. Both `1` and `"1"` are
+    /// accepted as values.
+    pub fn header_level(&self) -> Option<u64> {
+        match self.attribute("header")? {
+            Value::Number(level) => level.as_u64(),
+            Value::String(level) => level.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn is_header(&self) -> bool {
+        self.header_level().is_some()
+    }
+
+    /// The list type, e.g. `"bullet"` or `"ordered"`, if the block is a list item.
+    pub fn list(&self) -> Option<&Value> {
+        self.attribute("list")
+    }
+
+    pub fn is_code_block(&self) -> bool {
+        self.attribute("code-block")
+            .is_some_and(|value| value != &Value::Bool(false))
+    }
+
+    pub fn align(&self) -> Option<&str> {
+        self.attribute("align")?.as_str()
+    }
+}
+
+/// Navigation between blocks.
+///
+/// Backends only have to say which block an offset is in; jumping between
+/// blocks and headers is built on top of that.
 pub trait BlockCursor<'buffer> {
     type Buffer: TextBuffer;
     fn new(text: &'buffer Self::Buffer, pos: usize) -> Self;
+    fn pos(&self) -> usize;
+    fn set(&mut self, pos: usize);
+    /// The block holding `pos`. The end of the text is in the last block.
+    fn block_at(&self, pos: usize) -> Block;
+
+    /// The block the cursor is in.
+    fn block(&self) -> Block {
+        self.block_at(self.pos())
+    }
+
+    /// Move to the start of the block before the cursor, or to the start of
+    /// the current block if the cursor is inside it.
+    fn prev_boundary(&mut self) -> Option<usize> {
+        let pos = self.pos();
+        let start = self.block_at(pos).range.start;
+        let start = if start < pos {
+            start
+        } else if pos == 0 {
+            return None;
+        } else {
+            // `pos - 1` is the line feed ending the previous block.
+            self.block_at(pos - 1).range.start
+        };
+
+        self.set(start);
+        Some(start)
+    }
+
+    /// Move to the start of the next block.
+    fn next_boundary(&mut self) -> Option<usize> {
+        let pos = self.pos();
+        let end = self.block_at(pos).range.end;
+        // The last block ends at the end of the text without starting another
+        // one, unless the text ends with a line feed.
+        if end == pos || self.block_at(end).range.start != end {
+            return None;
+        }
+
+        self.set(end);
+        Some(end)
+    }
+
+    /// Move to the start of the next header of `level`, or of any level if
+    /// `level` is `None`. The cursor doesn't move if there is none.
+    fn next_header(&mut self, level: Option<u64>) -> Option<usize> {
+        let origin = self.pos();
+        while let Some(start) = self.next_boundary() {
+            if is_header_of(&self.block(), level) {
+                return Some(start);
+            }
+        }
+
+        self.set(origin);
+        None
+    }
+
+    /// Move to the start of the previous header of `level`, or of any level if
+    /// `level` is `None`. The cursor doesn't move if there is none.
+    fn prev_header(&mut self, level: Option<u64>) -> Option<usize> {
+        let origin = self.pos();
+        while let Some(start) = self.prev_boundary() {
+            if is_header_of(&self.block(), level) {
+                return Some(start);
+            }
+        }
+
+        self.set(origin);
+        None
+    }
+}
+
+fn is_header_of(block: &Block, level: Option<u64>) -> bool {
+    match (block.header_level(), level) {
+        (Some(found), Some(level)) => found == level,
+        (found, None) => found.is_some(),
+        (None, _) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_block_attributes_are_kept() {
+        let block = Block::new(
+            0..4,
+            [
+                (DefaultAtom::from("header"), Value::from(2)),
+                (DefaultAtom::from("bold"), Value::Bool(true)),
+                (DefaultAtom::from("align"), Value::from("center")),
+                (DefaultAtom::from("list"), Value::Null),
+            ],
+        );
+
+        assert_eq!(block.attributes.len(), 2);
+        assert_eq!(block.header_level(), Some(2));
+        assert_eq!(block.align(), Some("center"));
+        assert_eq!(block.list(), None);
+        assert!(!block.is_code_block());
+    }
+
+    #[test]
+    fn header_levels_may_be_strings() {
+        let block = Block::new(0..4, [(DefaultAtom::from("header"), Value::from("3"))]);
+
+        assert_eq!(block.header_level(), Some(3));
+        assert!(is_header_of(&block, None));
+        assert!(!is_header_of(&block, Some(1)));
+    }
 }
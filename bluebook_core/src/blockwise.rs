@@ -0,0 +1,244 @@
+//! Blockwise visual mode: selecting a rectangle of text, the same columns of
+//! a run of lines.
+//!
+//! Each cursor keeps its range as in the other visual modes, and the block is
+//! the rectangle with the anchor and the head at opposite corners. Columns
+//! count graphemes, as [`ColPosition::Col`] does, so a grapheme of several
+//! characters, like a flag or a CJK syllable, is one column and never split.
+//! Lines too short to reach the block have nothing in it.
+
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    coordinates::ColPosition, ctx::TextEditorContext, cursor::CursorRange,
+    error::BluebookCoreError, mode::Mode, offset::ByteOffset, search::Searchable,
+    selection::Selection, text_buffer::TextBuffer,
+};
+
+/// A rectangle of text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockSelection {
+    /// From the start of the first line to the end of the last one, its line
+    /// break included.
+    pub lines: Range<usize>,
+    /// The grapheme columns, the end excluded.
+    pub columns: Range<usize>,
+}
+
+/// The offset in `content` of grapheme column `column`, or by how many
+/// columns `content` falls short of it.
+fn column_offset(content: &str, column: usize) -> Result<usize, usize> {
+    let mut columns = 0;
+    for (offset, _) in content.grapheme_indices(true) {
+        if columns == column {
+            return Ok(offset);
+        }
+        columns += 1;
+    }
+
+    match column - columns {
+        0 => Ok(content.len()),
+        short => Err(short),
+    }
+}
+
+impl<Buffer> TextEditorContext<Buffer>
+where
+    Buffer: TextBuffer + Searchable,
+{
+    fn column(&mut self, offset: usize) -> Result<usize, BluebookCoreError> {
+        Ok(match self.text_buffer.cursor_coords(ByteOffset(offset))? {
+            (_, ColPosition::Col(col)) => col,
+            _ => 0,
+        })
+    }
+
+    /// The block with the anchor and the head of `range` at its corners.
+    pub fn block_selection(
+        &mut self,
+        range: CursorRange,
+    ) -> Result<BlockSelection, BluebookCoreError> {
+        let anchor = self.column(range.anchor)?;
+        let head = self.column(range.head)?;
+
+        Ok(BlockSelection {
+            lines: self.line_at(range.from())?.start..self.line_at(range.to())?.end,
+            columns: anchor.min(head)..anchor.max(head),
+        })
+    }
+
+    /// Where each line of `block` starts, in order.
+    fn block_lines(&mut self, block: &BlockSelection) -> Result<Vec<usize>, BluebookCoreError> {
+        let mut starts = Vec::new();
+        let mut offset = block.lines.start;
+        loop {
+            starts.push(offset);
+            let line = self.line_at(offset)?;
+            if line.end >= block.lines.end || line.end <= offset {
+                break;
+            }
+            offset = line.end;
+        }
+
+        Ok(starts)
+    }
+
+    /// The text of `block` on each of its lines, empty at the end of the
+    /// lines too short to reach it.
+    pub fn block_ranges(
+        &mut self,
+        block: &BlockSelection,
+    ) -> Result<Vec<Range<usize>>, BluebookCoreError> {
+        let mut ranges = Vec::new();
+        for start in self.block_lines(block)? {
+            let (_, content) = self.line_text(start)?;
+            let left = column_offset(&content, block.columns.start).unwrap_or(content.len());
+            let right = column_offset(&content, block.columns.end).unwrap_or(content.len());
+            ranges.push(start + left..start + right);
+        }
+
+        Ok(ranges)
+    }
+
+    /// Insert on every line of each cursor's block, with a cursor before the
+    /// block on each line, or after it if `append`. Lines ending before the
+    /// block are skipped when inserting, and padded with spaces to reach it
+    /// when appending.
+    pub(crate) fn block_insert(&mut self, append: bool) -> Result<bool, BluebookCoreError> {
+        let mut carets = Vec::new();
+        let mut shift = 0isize;
+        for range in self.selection.ranges().to_vec() {
+            let range = CursorRange::new(
+                range.anchor.saturating_add_signed(shift),
+                range.head.saturating_add_signed(shift),
+            );
+            let block = self.block_selection(range)?;
+            let column = match append {
+                true => block.columns.end,
+                false => block.columns.start,
+            };
+
+            let len = self.text_buffer.len();
+            for start in self.block_lines(&block)? {
+                // Padding moves the lines after it along.
+                let start =
+                    start.saturating_add_signed(self.text_buffer.len() as isize - len as isize);
+                let (_, content) = self.line_text(start)?;
+                match column_offset(&content, column) {
+                    // A line ending before the block has nothing to insert
+                    // before.
+                    Ok(offset) if !append && column > 0 && offset == content.len() => {}
+                    Ok(offset) => carets.push(start + offset),
+                    Err(short) if append => {
+                        let end = start + content.len();
+                        self.edit(end..end, &" ".repeat(short))?;
+                        carets.push(end + short);
+                    }
+                    Err(_) => {}
+                }
+            }
+            shift += self.text_buffer.len() as isize - len as isize;
+        }
+
+        if carets.is_empty() {
+            return Ok(false);
+        }
+        let carets = carets
+            .into_iter()
+            .map(|caret| CursorRange::new(caret, caret));
+        self.selection = Selection::new(carets, 0);
+        self.modal.mode = Mode::Insert;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        buffer::rope::buffer_impl::RopeBuffer,
+        command::Transaction,
+        register::UNNAMED,
+        test_utils::{normal_ctx, run},
+    };
+
+    /// A context with a block selected from `anchor` to `head`.
+    fn ctx(s: &str, anchor: usize, head: usize) -> TextEditorContext<RopeBuffer> {
+        let mut ctx = normal_ctx(s, head);
+        run(&mut ctx, [Transaction::ToggleBlockwiseVisualMode]).unwrap();
+        ctx.selection = Selection::single(CursorRange::new(anchor, head));
+        ctx
+    }
+
+    fn insert(value: &str) -> Transaction {
+        Transaction::InsertAtCursorHead {
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn blocks_count_graphemes() -> Result<(), BluebookCoreError> {
+        // "日本" is two columns of three bytes each.
+        let mut ctx = ctx("ab日本cd\nx\n日本語ef", 1, 22);
+
+        let block = ctx.block_selection(CursorRange::new(1, 22))?;
+        assert_eq!(
+            block,
+            BlockSelection {
+                lines: 0..24,
+                columns: 1..3,
+            }
+        );
+        assert_eq!(ctx.block_ranges(&block)?, vec![1..5, 12..12, 16..22]);
+
+        // The corners may be either way round.
+        assert_eq!(ctx.block_selection(CursorRange::new(22, 1))?, block);
+        assert_eq!(ctx.block_selection(CursorRange::new(5, 16))?, block);
+
+        Ok(())
+    }
+
+    #[test]
+    fn operators_apply_to_the_block() -> Result<(), BluebookCoreError> {
+        let mut ctx = ctx("ab日本cd\nx\n日本語ef", 1, 22);
+
+        run(&mut ctx, [Transaction::YankOperator])?;
+        assert_eq!(ctx.text_buffer.take(), "ab日本cd\nx\n日本語ef");
+        let yanked = ctx.registers.get(UNNAMED).map(|r| r.fragment.plain_text());
+        assert_eq!(yanked.as_deref(), Some("b日\n本語"));
+        assert_eq!(ctx.selection, Selection::single(CursorRange::new(1, 1)));
+        assert_eq!(ctx.modal.mode, Mode::Normal);
+
+        let mut ctx = self::ctx("ab日本cd\nx\n日本語ef", 1, 22);
+        run(&mut ctx, [Transaction::DeleteOperator])?;
+        assert_eq!(ctx.text_buffer.take(), "a本cd\nx\n日ef");
+        assert_eq!(ctx.selection, Selection::single(CursorRange::new(1, 1)));
+
+        // A change goes on with a cursor on every line the block reaches.
+        let mut ctx = self::ctx("abc\nd\nefg", 1, 8);
+        run(&mut ctx, [Transaction::ChangeOperator, insert("X")])?;
+        assert_eq!(ctx.text_buffer.take(), "aXc\nd\neXg");
+        assert_eq!(ctx.modal.mode, Mode::Insert);
+
+        Ok(())
+    }
+
+    #[test]
+    fn inserts_and_appends_on_every_line() -> Result<(), BluebookCoreError> {
+        let mut ctx = ctx("abc\nd\nefg", 1, 8);
+
+        run(&mut ctx, [Transaction::InsertFirstNonBlank, insert("X")])?;
+        assert_eq!(ctx.text_buffer.take(), "aXbc\nd\neXfg");
+        assert_eq!(ctx.modal.mode, Mode::Insert);
+
+        // Appending pads the lines too short to reach the block.
+        let mut ctx = self::ctx("abc\nd\nefg", 1, 8);
+        run(&mut ctx, [Transaction::AppendEndOfLine, insert("Y")])?;
+        assert_eq!(ctx.text_buffer.take(), "abYc\nd Y\nefYg");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+//! Cursor ranges pinned to the characters around them rather than to byte
+//! offsets, so that local cursors stay where they are while remote edits are
+//! merged in.
+
+use peritext::{rich_text::IndexType, Anchor, AnchorType};
+
+use super::buffer_impl::Peritext;
+use crate::{
+    ctx::TextEditorContext, cursor::CursorRange, error::BluebookCoreError, offset::ByteOffset,
+    selection::Selection,
+};
+
+/// A [`CursorRange`] whose ends are peritext anchors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnchoredRange {
+    pub anchor: Anchor,
+    pub head: Anchor,
+}
+
+impl Peritext {
+    /// Pin `offset` to the text around it. See [`peritext::RichText::anchor_at`]
+    /// for what `bias` means.
+    pub fn anchor_at(&self, ByteOffset(offset): ByteOffset, bias: AnchorType) -> Anchor {
+        self.rich_text().anchor_at(offset, bias, IndexType::Utf8)
+    }
+
+    pub fn resolve_anchor(&self, anchor: Anchor) -> ByteOffset {
+        ByteOffset(self.rich_text().resolve_anchor(anchor, IndexType::Utf8))
+    }
+
+    /// Pin both ends of `range`.
+    ///
+    /// A caret sticks to the character before it, so that text someone else
+    /// inserts at the caret ends up after it. The ends of a selection stick to
+    /// the selected text, so that text inserted right outside the selection
+    /// stays outside of it.
+    pub fn anchor_range(&self, range: CursorRange) -> AnchoredRange {
+        let (from_bias, to_bias) = match range.is_empty() {
+            true => (AnchorType::After, AnchorType::After),
+            false => (AnchorType::Before, AnchorType::After),
+        };
+        let bias = |offset: usize| match offset == range.from() {
+            true => from_bias,
+            false => to_bias,
+        };
+
+        AnchoredRange {
+            anchor: self.anchor_at(ByteOffset(range.anchor), bias(range.anchor)),
+            head: self.anchor_at(ByteOffset(range.head), bias(range.head)),
+        }
+    }
+
+    pub fn resolve_range(&self, range: AnchoredRange) -> CursorRange {
+        let ByteOffset(anchor) = self.resolve_anchor(range.anchor);
+        let ByteOffset(head) = self.resolve_anchor(range.head);
+
+        CursorRange::new(anchor, head)
+    }
+}
+
+impl TextEditorContext<Peritext> {
+    /// Apply updates exported by another replica, keeping the cursors on the
+    /// text they were on.
+    pub fn import(&mut self, data: &[u8]) -> Result<(), BluebookCoreError> {
+        self.keeping_selection(|text| text.import(data))?;
+        Ok(())
+    }
+
+    /// Merge another replica into this one, keeping the cursors on the text
+    /// they were on.
+    pub fn merge(&mut self, other: &Peritext) {
+        self.keeping_selection(|text| text.merge(other));
+    }
+
+    fn keeping_selection<T>(&mut self, edit: impl FnOnce(&mut Peritext) -> T) -> T {
+        let anchored: Vec<AnchoredRange> = self
+            .selection
+            .ranges()
+            .iter()
+            .map(|&range| self.text_buffer.anchor_range(range))
+            .collect();
+
+        let edited = edit(&mut self.text_buffer);
+
+        let ranges = anchored
+            .into_iter()
+            .map(|range| self.text_buffer.resolve_range(range));
+        self.selection = Selection::new(ranges, self.selection.primary_index());
+        self.cursor_range = self.selection.primary();
+
+        edited
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text_buffer::TextBuffer;
+
+    fn replicas(s: &str) -> (Peritext, Peritext) {
+        let mut local = Peritext::new(1);
+        let _ = local.write(ByteOffset(0), s);
+        let mut remote = Peritext::new(2);
+        remote.merge(&local);
+
+        (local, remote)
+    }
+
+    #[test]
+    fn cursors_stay_put_across_remote_edits() -> Result<(), BluebookCoreError> {
+        let (local, mut remote) = replicas("Hello world");
+        let mut ctx = TextEditorContext::new(local, CursorRange::new(6, 11));
+        ctx.selection.push(CursorRange::new(5, 5));
+
+        remote.write(ByteOffset(0), ">> ")?;
+        remote.write(ByteOffset(8), ",")?;
+        ctx.merge(&remote);
+
+        assert_eq!(ctx.text_buffer.take(), ">> Hello, world");
+        assert_eq!(
+            ctx.selection.ranges(),
+            [CursorRange::new(8, 8), CursorRange::new(10, 15)]
+        );
+        assert_eq!(ctx.cursor_range, CursorRange::new(8, 8));
+
+        Ok(())
+    }
+
+    #[test]
+    fn selection_does_not_grow_on_remote_inserts_at_its_ends() -> Result<(), BluebookCoreError> {
+        let (local, mut remote) = replicas("one two three");
+        let mut ctx = TextEditorContext::new(local, CursorRange::new(7, 4));
+
+        remote.write(ByteOffset(7), "!")?;
+        remote.write(ByteOffset(4), "[")?;
+        let changes = remote.export(&ctx.text_buffer.version());
+        ctx.import(&changes)?;
+
+        assert_eq!(ctx.text_buffer.take(), "one [two! three");
+        assert_eq!(ctx.cursor_range, CursorRange::new(8, 5));
+
+        Ok(())
+    }
+}
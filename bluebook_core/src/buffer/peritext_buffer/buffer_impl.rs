@@ -1,16 +1,49 @@
-use super::cursor_impl::{CursorRange, PeritextCursor};
-use crate::error::TextBufferWithCursorError;
-use crate::text_buffer_cursor::{CursorDocCoords, TextBufferCursor};
-use crate::{span::Span, text_buffer::TextBuffer};
+use super::{
+    builder::{loaded_style, PeritextBuilder},
+    cursor_impl::{
+        PeritextBlockCursor, PeritextCharCursor, PeritextGraphemeClusterCursor, PeritextLineCursor,
+    },
+    history::{History, IdSpan, LocalEdit},
+};
+use crate::{
+    block::{BlockCursor, BlockCursorError},
+    change::{ChangeEvent, ChangeOp, ChangeSet, Subscribers},
+    char::{CharCursor, CharCursorError},
+    coordinates::{ColPosition, RowPosition},
+    cursor::CursorRange,
+    graphemes::{GraphemeClusterCursor, GraphemeClusterCursorError},
+    line::{LineCursor, LineCursorError},
+    offset::{ByteOffset, CharOffset, GraphemeIndex, Utf16Offset},
+    paragraph::{CharParagraphCursor, ParagraphCursor, ParagraphCursorError},
+    search::Searchable,
+    sentence::{CharSentenceCursor, SentenceCursor, SentenceCursorError},
+    span::{
+        attribute_runs,
+        interval_tree::{Element, Growth, IntervalId, MutableIntervalTree},
+        Annotation, Behavior, FxHashMap, Span,
+    },
+    text_buffer::{ConversionError, CursorError, TextBuffer},
+    word::{CharWordCursor, WordCursor, WordCursorError},
+};
 use std::ops::Bound;
 use std::{
     borrow::Cow,
+    cell::RefCell,
     ops::{Range, RangeBounds},
+    rc::Rc,
+    sync::{mpsc::Receiver, Arc},
 };
 
-use peritext::rich_text::{self, IndexType, RichText as RichTextInner};
-
-use unicode_segmentation::UnicodeSegmentation;
+use peritext::{
+    rich_text::{
+        self, annotations_in_effect, DeltaItem, Event, Imported, IndexType, Removal,
+        RichText as RichTextInner,
+    },
+    Anchor, AnchorRange, AnchorType, InternalString, OpID, Style, VersionVector,
+};
+use serde_json::Value;
+use string_cache::DefaultAtom;
+use xi_rope::Rope;
 
 impl From<peritext::rich_text::Span> for Span {
     fn from(val: peritext::rich_text::Span) -> Self {
@@ -21,189 +54,848 @@ impl From<peritext::rich_text::Span> for Span {
     }
 }
 
+impl From<&Event> for ChangeEvent {
+    fn from(event: &Event) -> Self {
+        debug_assert_eq!(event.index_type, IndexType::Utf8);
+
+        let attributes = |attributes: &Option<FxHashMap<String, serde_json::Value>>| {
+            attributes
+                .iter()
+                .flatten()
+                .map(|(type_, value)| (type_.as_str().into(), value.clone()))
+                .collect()
+        };
+        let ops = event
+            .ops
+            .iter()
+            .map(|item| match item {
+                DeltaItem::Retain {
+                    retain,
+                    attributes: attrs,
+                } => ChangeOp::Retain {
+                    len: *retain,
+                    attributes: attributes(attrs),
+                },
+                DeltaItem::Insert {
+                    insert,
+                    attributes: attrs,
+                    ..
+                } => ChangeOp::Insert {
+                    text: insert.clone(),
+                    attributes: attributes(attrs),
+                },
+                DeltaItem::Delete { delete } => ChangeOp::delete(*delete),
+            })
+            .collect();
+
+        ChangeEvent::new(ops, event.is_local)
+    }
+}
+
 pub struct Peritext {
     inner: RichTextInner,
+    /// Every annotation, at the range it covers. Edits, local or merged in,
+    /// shift it along with the text, and annotating adds to it.
+    annotation_index: MutableIntervalTree<Arc<peritext::Annotation>>,
+    /// Where each annotation is in `annotation_index`.
+    annotation_intervals: FxHashMap<OpID, IntervalId>,
+    /// This client's own edits, for undo.
+    history: History,
+    /// The text as last searched, and the version it was taken at.
+    search_rope: RefCell<Option<(VersionVector, Rope)>>,
+    /// Those told about changes, through a listener on the rich text added
+    /// along with the first of them.
+    subscribers: Rc<RefCell<Subscribers>>,
+}
+
+impl From<peritext::Behavior> for Behavior {
+    fn from(val: peritext::Behavior) -> Self {
+        match val {
+            peritext::Behavior::AllowMultiple => Behavior::AllowMultiple,
+            peritext::Behavior::Merge => Behavior::Merge,
+            peritext::Behavior::Delete => Behavior::Delete,
+        }
+    }
+}
+
+/// Text inserted at an end of an annotation lands inside it when that end is
+/// anchored to the character outside.
+fn growth(ann: &peritext::Annotation) -> Growth {
+    Growth {
+        start: ann.range.start.type_ == AnchorType::After,
+        end: ann.range.end.type_ == AnchorType::Before,
+    }
+}
+
+impl From<RichTextInner> for Peritext {
+    fn from(inner: RichTextInner) -> Self {
+        let mut text = Self {
+            inner,
+            annotation_index: MutableIntervalTree::new(),
+            annotation_intervals: FxHashMap::default(),
+            history: History::default(),
+            search_rope: RefCell::default(),
+            subscribers: Rc::default(),
+        };
+        text.reindex();
+        text
+    }
 }
 
 impl Peritext {
     pub fn new(client_id: u64) -> Self {
         Self {
             inner: RichTextInner::new(client_id),
+            annotation_index: MutableIntervalTree::new(),
+            annotation_intervals: FxHashMap::default(),
+            history: History::default(),
+            search_rope: RefCell::default(),
+            subscribers: Rc::default(),
+        }
+    }
+
+    pub fn rich_text(&self) -> &RichTextInner {
+        &self.inner
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// The undo history, e.g. to change how edits are grouped.
+    pub fn history_mut(&mut self) -> &mut History {
+        &mut self.history
+    }
+
+    pub fn version(&self) -> VersionVector {
+        self.inner.version()
+    }
+
+    /// Export the changes made since `version`, for another replica to import.
+    pub fn export(&self, version: &VersionVector) -> Vec<u8> {
+        self.inner.export(version)
+    }
+
+    pub fn import(&mut self, data: &[u8]) -> Result<(), peritext::rich_text::Error> {
+        let imported = self.inner.import(data)?;
+        self.index_imported(imported);
+        Ok(())
+    }
+
+    pub fn merge(&mut self, other: &Peritext) {
+        let imported = self.inner.merge(&other.inner);
+        self.index_imported(imported);
+    }
+
+    /// Rebuild the annotation index from scratch.
+    fn reindex(&mut self) {
+        self.annotation_index.clear();
+        self.annotation_intervals.clear();
+        for (ann, range) in self.inner.annotations(IndexType::Utf8) {
+            self.index_annotation(range, ann);
+        }
+    }
+
+    /// Add `ann`, covering `range`, to the annotation index.
+    fn index_annotation(&mut self, range: Range<usize>, ann: Arc<peritext::Annotation>) {
+        let id = ann.id;
+        let interval = self.annotation_index.insert(range, growth(&ann), ann);
+        self.annotation_intervals.insert(id, interval);
+    }
+
+    /// The range `ann` covers now.
+    fn annotation_range(&self, ann: &peritext::Annotation) -> Range<usize> {
+        let start = self.inner.resolve_anchor(ann.range.start, IndexType::Utf8);
+        let end = self.inner.resolve_anchor(ann.range.end, IndexType::Utf8);
+        start..end.max(start)
+    }
+
+    /// Update the annotation index for `len` bytes inserted at `offset`.
+    fn index_insert(&mut self, offset: usize, len: usize) {
+        self.annotation_index.on_insert(offset, len);
+        self.resolve_touching(offset, len);
+    }
+
+    /// Resolve again the annotations starting or ending at either end of the
+    /// `len` bytes inserted at `offset`.
+    ///
+    /// Where an annotation ending right at the insertion ends up depends on
+    /// the deleted characters around it, not only on its anchors, so those
+    /// annotations are resolved again rather than guessed.
+    fn resolve_touching(&mut self, offset: usize, len: usize) {
+        let settled = offset..=offset + len;
+        let touching: Vec<_> = self
+            .annotation_index
+            .query(offset.saturating_sub(1)..offset + len + 1)
+            .filter(|(_, Element { range, .. })| {
+                settled.contains(&range.start) || settled.contains(&range.end)
+            })
+            .map(|(id, Element { range, value })| (id, range, value.clone()))
+            .collect();
+        for (id, range, ann) in touching {
+            let resolved = self.annotation_range(&ann);
+            if resolved != range {
+                self.annotation_index.remove(id);
+                self.index_annotation(resolved, ann);
+            }
+        }
+    }
+
+    /// Update the annotation index for the remote changes `imported` made,
+    /// through the same paths local edits take.
+    fn index_imported(&mut self, imported: Imported) {
+        // The delta goes through the text once, front to back, so the offsets
+        // before the one it's at are already those of the merged text.
+        let mut offset = 0;
+        let mut inserted = Vec::new();
+        for item in imported.delta {
+            match item {
+                DeltaItem::Retain { retain, .. } => offset += retain,
+                DeltaItem::Insert { insert, .. } => {
+                    self.annotation_index.on_insert(offset, insert.len());
+                    inserted.push((offset, insert.len()));
+                    offset += insert.len();
+                }
+                DeltaItem::Delete { delete } => {
+                    self.annotation_index.on_delete(offset..offset + delete)
+                }
+            }
+        }
+
+        for ann in imported.added {
+            self.index_annotation(self.annotation_range(&ann), ann);
+        }
+        for id in imported.removed {
+            if let Some(interval) = self.annotation_intervals.remove(&id) {
+                self.annotation_index.remove(interval);
+            }
+        }
+        // Only now that every offset is final can annotations be resolved.
+        for (offset, len) in inserted {
+            self.resolve_touching(offset, len);
+        }
+    }
+
+    fn insert_text(&mut self, offset: usize, s: &str) {
+        self.inner.insert(offset, s);
+        self.index_insert(offset, s.len());
+    }
+
+    fn delete_text(&mut self, range: Range<usize>) {
+        self.inner.delete(range.clone());
+        self.annotation_index.on_delete(range);
+    }
+
+    /// Annotate `range` with `style`, returning the annotation that made.
+    fn annotate_text(
+        &mut self,
+        range: Range<usize>,
+        style: Style,
+    ) -> Option<Arc<peritext::Annotation>> {
+        let ann = self.inner.add_annotation(range, style, IndexType::Utf8)?;
+        self.index_annotation(self.annotation_range(&ann), ann.clone());
+
+        Some(ann)
+    }
+
+    /// Remove the annotation `id`, returning false if there is no such
+    /// annotation.
+    fn unannotate_text(&mut self, id: OpID) -> bool {
+        match self.inner.remove_annotation(id) {
+            Some(Removal::Patched) => {
+                if let Some(interval) = self.annotation_intervals.remove(&id) {
+                    self.annotation_index.remove(interval);
+                }
+                true
+            }
+            Some(Removal::Erased(eraser)) => {
+                self.index_annotation(self.annotation_range(&eraser), eraser);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The ids of the bytes in `range`, in document order.
+    fn ids_in(&self, range: Range<usize>) -> Vec<IdSpan> {
+        self.inner
+            .id_runs(range, IndexType::Utf8)
+            .into_iter()
+            .map(|(start, len)| IdSpan {
+                start,
+                len: len as u32,
+            })
+            .collect()
+    }
+
+    /// The ranges covered by those of the bytes `ids` that haven't been
+    /// deleted, in document order.
+    fn live_ranges(&self, ids: &[IdSpan]) -> Vec<Range<usize>> {
+        self.inner.live_ranges(
+            ids.iter().map(|span| (span.start, span.len as usize)),
+            IndexType::Utf8,
+        )
+    }
+
+    /// Pin `range` to the text it covers.
+    fn pin(&self, range: Range<usize>) -> AnchorRange {
+        AnchorRange {
+            start: self
+                .inner
+                .anchor_at(range.start, AnchorType::Before, IndexType::Utf8),
+            end: self
+                .inner
+                .anchor_at(range.end, AnchorType::After, IndexType::Utf8),
+        }
+    }
+
+    fn resolve(&self, range: &AnchorRange) -> Range<usize> {
+        let start = self.inner.resolve_anchor(range.start, IndexType::Utf8);
+        let end = self.inner.resolve_anchor(range.end, IndexType::Utf8);
+        start..end.max(start)
+    }
+
+    fn spans_in(&self, range: Range<usize>) -> Vec<Span> {
+        match range.is_empty() {
+            true => Vec::new(),
+            false => self
+                .inner
+                .slice(range, IndexType::Utf8)
+                .into_iter()
+                .map(Span::from)
+                .collect(),
+        }
+    }
+
+    /// The edit inserting the text now in `range`.
+    fn inserted(&self, range: Range<usize>) -> Option<LocalEdit> {
+        (!range.is_empty()).then(|| LocalEdit::Insert {
+            ids: self.ids_in(range),
+        })
+    }
+
+    /// The edit deleting the text now in `range`.
+    fn deleting(&self, range: Range<usize>) -> Option<LocalEdit> {
+        (!range.is_empty()).then(|| LocalEdit::Delete {
+            ids: self.ids_in(range.clone()),
+            spans: self.spans_in(range),
+        })
+    }
+
+    /// The runs of `type_` in `range`, pinned to the text they cover.
+    fn runs_of(&self, range: Range<usize>, type_: &InternalString) -> Vec<(AnchorRange, Value)> {
+        attribute_runs(&self.spans_in(range.clone()))
+            .into_iter()
+            .filter(|(_, run_type, value)| run_type == type_ && !value.is_null())
+            .map(|(run, _, value)| {
+                (
+                    self.pin(range.start + run.start..range.start + run.end),
+                    value,
+                )
+            })
+            .collect()
+    }
+
+    /// Annotate the runs `runs` of `type_` with their values again.
+    fn restore_runs(
+        &mut self,
+        type_: &InternalString,
+        runs: &[(AnchorRange, Value)],
+        change: &mut ChangeSet,
+    ) {
+        for (range, value) in runs {
+            let range = self.resolve(range);
+            if !range.is_empty() {
+                self.annotate_text(range.clone(), loaded_style(type_.clone(), value.clone()));
+                change.set_attribute(range, type_.clone(), value.clone());
+            }
         }
     }
 
-    fn convert_range<R: RangeBounds<usize>>(&self, range: R) -> (usize, usize) {
+    /// Undo `edits`, last first, returning the edits that made, in the order
+    /// they were made, along with the change to the text.
+    ///
+    /// Inserted text that is still there is deleted, one edit per run left of
+    /// it. Deleted text is written back where its first byte used to be, with
+    /// its annotations, and every edit in the history follows it to its new
+    /// ids. Annotations are removed, and the values they replaced restored.
+    fn revert(&mut self, mut edits: Vec<LocalEdit>) -> (Vec<LocalEdit>, ChangeEvent) {
+        let mut change = ChangeSet::new(self.inner.len());
+        let mut reverted = Vec::new();
+
+        while let Some(edit) = edits.pop() {
+            match edit {
+                LocalEdit::Insert { ids } => {
+                    // Back to front, so that the ranges still to delete don't move.
+                    for range in self.live_ranges(&ids).into_iter().rev() {
+                        reverted.extend(self.deleting(range.clone()));
+                        self.delete_text(range.clone());
+                        change.replace(range, "");
+                    }
+                }
+                LocalEdit::Delete { ids, spans } => {
+                    let Some(first) = ids.first() else {
+                        continue;
+                    };
+                    let offset = self.inner.resolve_anchor(
+                        Anchor {
+                            id: Some(first.start),
+                            type_: AnchorType::Before,
+                        },
+                        IndexType::Utf8,
+                    );
+                    let text: String = spans.iter().map(|span| span.insert.as_str()).collect();
+                    self.insert_text(offset, &text);
+                    change.replace(offset..offset, &text);
+                    for (run, type_, value) in attribute_runs(&spans) {
+                        if !value.is_null() {
+                            let range = offset + run.start..offset + run.end;
+                            self.annotate_text(
+                                range.clone(),
+                                loaded_style(type_.clone(), value.clone()),
+                            );
+                            change.set_attribute(range, type_, value);
+                        }
+                    }
+                    let written = self.ids_in(offset..offset + text.len());
+                    let ids_map: FxHashMap<OpID, OpID> = ids
+                        .iter()
+                        .flat_map(IdSpan::ids)
+                        .zip(written.iter().flat_map(IdSpan::ids))
+                        .collect();
+                    for edit in &mut edits {
+                        edit.remap(&ids_map);
+                    }
+                    self.history.remap(&ids_map);
+                    reverted.push(LocalEdit::Insert { ids: written });
+                }
+                LocalEdit::Annotate {
+                    range,
+                    style,
+                    id,
+                    replaced,
+                } => {
+                    self.unannotate_text(id);
+                    change.set_attribute(self.resolve(&range), style.type_.clone(), Value::Null);
+                    self.restore_runs(&style.type_, &replaced, &mut change);
+                    reverted.push(LocalEdit::Unannotate {
+                        range,
+                        style,
+                        replaced,
+                    });
+                }
+                LocalEdit::Unannotate {
+                    range,
+                    style,
+                    replaced,
+                } => {
+                    let resolved = self.resolve(&range);
+                    if let Some(ann) = self.annotate_text(resolved.clone(), style.clone()) {
+                        let value = match style.behavior {
+                            peritext::Behavior::Delete => Value::Null,
+                            _ => style.value.clone(),
+                        };
+                        change.set_attribute(resolved, style.type_.clone(), value);
+                        reverted.push(LocalEdit::Annotate {
+                            range: ann.range.clone(),
+                            style,
+                            id: ann.id,
+                            replaced,
+                        });
+                    }
+                }
+            }
+        }
+
+        (reverted, change.to_event(true))
+    }
+
+    fn convert_range<R: RangeBounds<ByteOffset>>(&self, range: R) -> (usize, usize) {
         let start = match range.start_bound() {
-            Bound::Included(&start) => start,
-            Bound::Excluded(&start) => start + 1,
+            Bound::Included(&ByteOffset(start)) => start,
+            Bound::Excluded(&ByteOffset(start)) => start + 1,
             Bound::Unbounded => 0,
         };
 
         let end = match range.end_bound() {
-            Bound::Included(&end) => end + 1,
-            Bound::Excluded(&end) => end,
+            Bound::Included(&ByteOffset(end)) => end + 1,
+            Bound::Excluded(&ByteOffset(end)) => end,
             Bound::Unbounded => self.inner.len(),
         };
 
         (start, end)
     }
+
+    fn is_valid_offset(&self, offset: usize) -> bool {
+        offset <= self.inner.len() && self.inner.chunk_cursor(offset).is_char_boundary()
+    }
+
+    fn is_valid_range(&self, start: usize, end: usize) -> bool {
+        start <= end && self.is_valid_offset(start) && self.is_valid_offset(end)
+    }
+
+    fn annotations(&self, range: Range<usize>) -> Vec<Annotation> {
+        if range.is_empty() {
+            return Vec::new();
+        }
+
+        let overlapping: Vec<_> = self
+            .annotation_index
+            .query(range.clone())
+            .map(|(_, Element { range, value })| (value.clone(), range))
+            .collect();
+
+        annotations_in_effect(&overlapping, range)
+            .into_iter()
+            .map(|(ann, range)| Annotation {
+                id: ann.id,
+                type_: ann.type_.clone(),
+                value: ann.value.clone(),
+                behavior: ann.behavior.into(),
+                range,
+            })
+            .collect()
+    }
+
+    fn check_offset(&self, offset: usize) -> Result<(), ConversionError> {
+        if offset > self.inner.len() {
+            return Err(ConversionError::OutOfBounds(offset));
+        }
+        if !self.inner.chunk_cursor(offset).is_char_boundary() {
+            return Err(ConversionError::NotACodepointBoundary(offset));
+        }
+
+        Ok(())
+    }
 }
 
 impl TextBuffer for Peritext {
-    type Cursor<'cursor> = PeritextCursor<'cursor> where Self:'cursor;
+    type CharCursor<'cursor>
+        = PeritextCharCursor<'cursor>
+    where
+        Self: 'cursor;
+    type GraphemeClusterCursor<'cursor>
+        = PeritextGraphemeClusterCursor<'cursor>
+    where
+        Self: 'cursor;
+    type WordCursor<'cursor>
+        = CharWordCursor<PeritextCharCursor<'cursor>>
+    where
+        Self: 'cursor;
+    type SentenceCursor<'cursor>
+        = CharSentenceCursor<PeritextCharCursor<'cursor>>
+    where
+        Self: 'cursor;
+    type ParagraphCursor<'cursor>
+        = CharParagraphCursor<PeritextCharCursor<'cursor>>
+    where
+        Self: 'cursor;
+    type LineCursor<'cursor>
+        = PeritextLineCursor<'cursor>
+    where
+        Self: 'cursor;
+    type BlockCursor<'cursor>
+        = PeritextBlockCursor<'cursor>
+    where
+        Self: 'cursor;
+
     type SpanItem = rich_text::Span;
-    type SpanIter<'spans> = rich_text::iter::Iter<'spans> where Self: 'spans;
+    type SpanIter<'spans>
+        = rich_text::iter::Iter<'spans>
+    where
+        Self: 'spans;
 
-    fn cursor(
+    fn char_cursor(
         &mut self,
-        cursor_range: CursorRange,
-    ) -> Result<Self::Cursor<'_>, TextBufferWithCursorError> {
-        let new_cursor = PeritextCursor {
-            buffer: self.inner.to_string().into(),
-            cursor_range,
-        };
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Self::CharCursor<'_>, CharCursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(CharCursorError::InvalidCharacter);
+        }
 
-        let is_boundary = new_cursor.is_grapheme_boundary()?;
-        Ok(new_cursor)
+        Ok(PeritextCharCursor::new(self, offset))
     }
 
-    /**
-     * Our cursor
-     */
-
-    fn cursor_coords(
+    fn grapheme_cluster_cursor(
         &mut self,
-        cursor_range: CursorRange,
-    ) -> Result<CursorDocCoords, TextBufferWithCursorError> {
-        let buf = &self.inner.slice_str(0..cursor_range.head, IndexType::Utf8);
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Self::GraphemeClusterCursor<'_>, GraphemeClusterCursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(GraphemeClusterCursorError::InvalidCharacter);
+        }
 
-        let mut row = buf.chars().filter(|&c| c == '\n').count();
-        let mut col: usize = 0;
+        Ok(PeritextGraphemeClusterCursor::new(
+            self,
+            CursorRange::new(offset, offset),
+        ))
+    }
 
-        fn find_last_newline_position(s: &str) -> Option<usize> {
-            for (i, c) in s.char_indices().rev() {
-                if c == '\n' {
-                    return Some(i);
-                }
-            }
-            None
+    fn word_cursor(
+        &mut self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Self::WordCursor<'_>, WordCursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(WordCursorError::InvalidCharacter);
         }
 
-        let newline_idx = find_last_newline_position(&buf);
-
-        match newline_idx {
-            Some(newline_idx) => {
-                let g = UnicodeSegmentation::graphemes(&buf[newline_idx..cursor_range.head], true);
+        Ok(CharWordCursor::new(self, offset))
+    }
 
-                col = g.collect::<Vec<&str>>().len();
-            }
-            None => {}
+    fn sentence_cursor(
+        &mut self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Self::SentenceCursor<'_>, SentenceCursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(SentenceCursorError::InvalidSentence);
         }
 
-        tracing::info!("{:?}", buf);
+        Ok(CharSentenceCursor::new(self, offset))
+    }
 
-        // // If the cursor's position exceeds the buffer, return an error.
-        // if cursor_range.head > buf.len() {
-        //     return Err(TextBufferWithCursorError::OutOfBounds); // Assuming you have such an error variant
-        // }
+    fn paragraph_cursor(
+        &mut self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Self::ParagraphCursor<'_>, ParagraphCursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(ParagraphCursorError::InvalidParagraph);
+        }
 
-        // let mut line_start_byte_offset: usize = 0;
-        // let mut row: usize = 0;
-        // let mut col: usize = 0;
+        Ok(CharParagraphCursor::new(self, offset))
+    }
 
-        // for (line_idx, line) in buf.lines().enumerate() {
-        //     let newline_char = '\n'.to_string();
-        //     tracing::info!(
-        //         "{:?} <  {:?} < {:?} ",
-        //         line_start_byte_offset,
-        //         cursor_range.head,
-        //         line_start_byte_offset + line.len() + line_idx * &newline_char.len()
-        //     );
+    fn line_cursor(
+        &mut self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Self::LineCursor<'_>, LineCursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(LineCursorError::InvalidCharacter);
+        }
 
-        //     if line_start_byte_offset <= cursor_range.head
-        //         && cursor_range.head
-        //             <= line_start_byte_offset + line.len() + line_idx * &newline_char.len()
-        //     {
-        //         let g = UnicodeSegmentation::graphemes(
-        //             &buf[line_start_byte_offset..cursor_range.head],
-        //             true,
-        //         );
+        Ok(PeritextLineCursor::new(self, offset))
+    }
 
-        //         col = g.collect::<Vec<&str>>().len();
-        //     }
-        //     row = line_idx;
-        //     line_start_byte_offset += line.len();
-        // }
+    fn block_cursor(
+        &mut self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Self::BlockCursor<'_>, BlockCursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(BlockCursorError::InvalidCharacter);
+        }
+
+        Ok(PeritextBlockCursor::new(self, offset))
+    }
 
-        if buf.ends_with("\n") {
-            row += 1;
+    /// The row is the number of line feeds before `offset`, and the column the
+    /// number of grapheme clusters between the start of that line and `offset`.
+    fn cursor_coords(
+        &mut self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<(RowPosition, ColPosition), CursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(CharCursorError::InvalidCharacter.into());
         }
 
-        let coords = CursorDocCoords::new(row, col);
-        tracing::info!("coords: {:?}", coords);
+        let row = self.inner.line_of_offset(offset, IndexType::Utf8);
+        let line_start = self.inner.line_start(row).unwrap_or(0);
 
-        Ok(coords)
+        let mut col = 0;
+        let mut cursor = self.inner.chunk_cursor(line_start);
+        while cursor.pos() < offset {
+            if cursor.next_grapheme().is_none() {
+                break;
+            }
+            col += 1;
+        }
+
+        Ok((RowPosition::Row(row), ColPosition::Col(col)))
     }
 
-    // pub fn iter(&self) -> impl Iterator<Item = Span> + '_ {
     fn annotate<R>(&mut self, range: R, annotation: peritext::Style)
     where
-        R: RangeBounds<usize>,
+        R: RangeBounds<ByteOffset>,
     {
-        self.inner.annotate(range, annotation)
+        let (start, end) = self.convert_range(range);
+        let replaced = match annotation.behavior {
+            peritext::Behavior::AllowMultiple => Vec::new(),
+            _ => self.runs_of(start..end, &annotation.type_),
+        };
+
+        if let Some(ann) = self.annotate_text(start..end, annotation.clone()) {
+            self.history.record(vec![LocalEdit::Annotate {
+                range: ann.range.clone(),
+                style: annotation,
+                id: ann.id,
+                replaced,
+            }]);
+        }
+    }
+
+    fn set_attribute(&mut self, range: Range<ByteOffset>, type_: DefaultAtom, value: Value) {
+        self.annotate(range, loaded_style(type_, value));
     }
 
     fn span_iter<'spans, 'buffer: 'spans>(&'buffer self) -> Self::SpanIter<'spans> {
         rich_text::iter::Iter::new(&self.inner)
     }
 
-    fn write(&mut self, offset: usize, s: &str) -> Result<usize, TextBufferWithCursorError> {
-        self.inner.insert(offset, s);
+    fn slice_spans(&self, range: Range<ByteOffset>) -> Result<Vec<Span>, ConversionError> {
+        let Range {
+            start: ByteOffset(start),
+            end: ByteOffset(end),
+        } = range;
+        if !self.is_valid_range(start, end) {
+            return Err(ConversionError::SomeError);
+        }
+        if start == end {
+            return Ok(Vec::new());
+        }
 
-        Ok(offset + s.len())
+        Ok(self
+            .inner
+            .slice(start..end, IndexType::Utf8)
+            .into_iter()
+            .map(Span::from)
+            .collect())
     }
 
-    fn drain<R>(&mut self, range: R) -> Result<Cow<str>, TextBufferWithCursorError>
-    where
-        R: RangeBounds<usize>,
-    {
-        let (start, end) = self.convert_range(range);
+    fn write(
+        &mut self,
+        ByteOffset(offset): ByteOffset,
+        s: &str,
+    ) -> Result<ByteOffset, CursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(CharCursorError::InvalidCharacter.into());
+        }
 
-        self.inner.delete(start..end);
+        self.insert_text(offset, s);
+        let inserted = self.inserted(offset..offset + s.len());
+        self.history.record(inserted.into_iter().collect());
 
-        let slice = self.slice(start..end);
+        Ok(ByteOffset(offset).after(s))
+    }
+
+    fn drain(&mut self, range: Range<ByteOffset>) -> Result<Cow<'_, str>, CursorError> {
+        let Range {
+            start: ByteOffset(start),
+            end: ByteOffset(end),
+        } = range;
+        if !self.is_valid_range(start, end) {
+            return Err(CharCursorError::InvalidCharacter.into());
+        }
 
-        slice
+        let drained = self.inner.slice_str(start..end, IndexType::Utf8);
+        let deleted = self.deleting(start..end);
+        self.delete_text(start..end);
+        self.history.record(deleted.into_iter().collect());
+
+        Ok(Cow::Owned(drained))
     }
 
     fn replace_range<R>(
         &mut self,
         range: R,
         replace_with: &str,
-    ) -> Result<Range<usize>, TextBufferWithCursorError>
+    ) -> Result<Range<ByteOffset>, CursorError>
     where
-        R: RangeBounds<usize>,
+        R: RangeBounds<ByteOffset>,
     {
         let (start, end) = self.convert_range(range);
+        if !self.is_valid_range(start, end) {
+            return Err(CharCursorError::InvalidCharacter.into());
+        }
 
-        self.inner.delete(start..end);
-        self.inner.insert(start, replace_with);
+        let deleted = self.deleting(start..end);
+        self.delete_text(start..end);
+        self.insert_text(start, replace_with);
+        let inserted = self.inserted(start..start + replace_with.len());
+        self.history
+            .record(deleted.into_iter().chain(inserted).collect());
 
         Ok(Range {
-            start,
-            end: start + replace_with.len(),
+            start: ByteOffset(start),
+            end: ByteOffset(start).after(replace_with),
         })
     }
-    // fn edit(&mut self, range: Range<usize>, new: impl Into<String>) {
-    //     self.replace_range(range, &new.into());
-    // }
 
-    fn take(&self) -> Cow<str> {
+    fn annotations_at(
+        &self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Vec<Annotation>, ConversionError> {
+        self.check_offset(offset)?;
+
+        // Ids are per byte, and annotations start and end on character
+        // boundaries, so the first byte of the character stands for all of it.
+        Ok(self.annotations(offset..(offset + 1).min(self.inner.len())))
+    }
+
+    fn annotations_in(&self, range: Range<ByteOffset>) -> Result<Vec<Annotation>, ConversionError> {
+        let Range {
+            start: ByteOffset(start),
+            end: ByteOffset(end),
+        } = range;
+        if !self.is_valid_range(start, end) {
+            return Err(ConversionError::SomeError);
+        }
+
+        Ok(self.annotations(start..end))
+    }
+
+    fn remove_annotation(&mut self, id: peritext::OpID) -> bool {
+        self.unannotate_text(id)
+    }
+
+    fn subscribe(&mut self) -> Receiver<ChangeEvent> {
+        // Only the listener holds on to the subscribers besides this buffer,
+        // so it's added once, and the rich text builds no events before.
+        if Rc::strong_count(&self.subscribers) == 1 {
+            let subscribers = Rc::clone(&self.subscribers);
+            self.inner.observe(Box::new(move |event| {
+                subscribers.borrow_mut().emit(ChangeEvent::from(event));
+            }));
+        }
+        self.subscribers.borrow_mut().subscribe()
+    }
+
+    /// Undo this client's last group of edits, as they are now: text others
+    /// deleted since stays deleted, and text they inserted stays.
+    fn undo(&mut self) -> Option<ChangeEvent> {
+        let group = self.history.pop_undo()?;
+        let (reverted, change) = self.revert(group.edits);
+        self.history.push_redo(reverted);
+
+        Some(change)
+    }
+
+    fn redo(&mut self) -> Option<ChangeEvent> {
+        let group = self.history.pop_redo()?;
+        let (reverted, change) = self.revert(group.edits);
+        self.history.push_undo(reverted);
+
+        Some(change)
+    }
+
+    fn begin_undo_group(&mut self) {
+        self.history.begin_group();
+    }
+
+    fn end_undo_group(&mut self, keep: bool) {
+        self.history.end_group(keep);
+    }
+
+    fn take(&self) -> Cow<'_, str> {
         self.inner.to_string().into()
     }
 
-    fn slice(&self, range: Range<usize>) -> Result<Cow<str>, TextBufferWithCursorError> {
-        let str = self.inner.slice_str(range, IndexType::Utf16);
+    fn slice(&self, range: Range<ByteOffset>) -> Result<Cow<'_, str>, ConversionError> {
+        let Range {
+            start: ByteOffset(start),
+            end: ByteOffset(end),
+        } = range;
+        if !self.is_valid_range(start, end) {
+            return Err(ConversionError::SomeError);
+        }
+
+        let str = self.inner.slice_str(start..end, IndexType::Utf8);
 
         Ok(str.into())
     }
@@ -216,8 +908,116 @@ impl TextBuffer for Peritext {
         self.inner.is_empty()
     }
 
-    fn from_str(_s: &str) -> Self {
-        todo!()
+    /// Load `s` with a single insert, under a random client id. Use a
+    /// [`PeritextBuilder`] to choose the client id or to load styled text.
+    fn from_str(s: &str) -> Self {
+        let mut builder = PeritextBuilder::with_random_client_id();
+        builder.push_str(s);
+        builder.build()
+    }
+
+    fn byte_to_utf16(
+        &self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Utf16Offset, ConversionError> {
+        self.check_offset(offset)?;
+
+        Ok(Utf16Offset(self.inner.convert_index(
+            offset,
+            IndexType::Utf8,
+            IndexType::Utf16,
+        )))
+    }
+
+    fn utf16_to_byte(
+        &self,
+        Utf16Offset(offset): Utf16Offset,
+    ) -> Result<ByteOffset, ConversionError> {
+        if offset > self.inner.len_utf16() {
+            return Err(ConversionError::OutOfBounds(offset));
+        }
+
+        Ok(ByteOffset(self.inner.convert_index(
+            offset,
+            IndexType::Utf16,
+            IndexType::Utf8,
+        )))
+    }
+
+    fn byte_to_char(&self, ByteOffset(offset): ByteOffset) -> Result<CharOffset, ConversionError> {
+        self.check_offset(offset)?;
+
+        let mut cursor = self.inner.chunk_cursor(0);
+        let mut count = 0;
+        while cursor.pos() < offset {
+            cursor.next_codepoint();
+            count += 1;
+        }
+
+        Ok(CharOffset(count))
+    }
+
+    fn char_to_byte(&self, CharOffset(offset): CharOffset) -> Result<ByteOffset, ConversionError> {
+        let mut cursor = self.inner.chunk_cursor(0);
+        for _ in 0..offset {
+            if cursor.next_codepoint().is_none() {
+                return Err(ConversionError::OutOfBounds(offset));
+            }
+        }
+
+        Ok(ByteOffset(cursor.pos()))
+    }
+
+    fn byte_to_grapheme(
+        &self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<GraphemeIndex, ConversionError> {
+        self.check_offset(offset)?;
+
+        let mut cursor = self.inner.chunk_cursor(0);
+        let mut index = 0;
+        while let Some(next) = cursor.next_grapheme_boundary() {
+            if next > offset {
+                break;
+            }
+            cursor.set(next);
+            index += 1;
+        }
+
+        Ok(GraphemeIndex(index))
+    }
+
+    fn grapheme_to_byte(
+        &self,
+        GraphemeIndex(index): GraphemeIndex,
+    ) -> Result<ByteOffset, ConversionError> {
+        let mut cursor = self.inner.chunk_cursor(0);
+        for _ in 0..index {
+            cursor
+                .next_grapheme()
+                .ok_or(ConversionError::OutOfBounds(index))?;
+        }
+
+        Ok(ByteOffset(cursor.pos()))
+    }
+}
+
+impl Searchable for Peritext {
+    /// The text is copied into a rope once per version, and shared by the
+    /// searches until it changes.
+    fn search_rope(&self) -> Cow<'_, Rope> {
+        let version = self.inner.version();
+        let mut cached = self.search_rope.borrow_mut();
+        match &*cached {
+            Some((cached_version, rope)) if cached_version.vv == version.vv => {
+                Cow::Owned(rope.clone())
+            }
+            _ => {
+                let rope = Rope::from(self.take().as_ref());
+                *cached = Some((version, rope.clone()));
+                Cow::Owned(rope)
+            }
+        }
     }
 }
 
@@ -225,6 +1025,16 @@ impl TextBuffer for Peritext {
 mod tests {
 
     use super::*;
+    use crate::{compound::CompoundTransaction, ctx::TextEditorContext, error::BluebookCoreError};
+
+    const TEXT: &str = "Hello \nworld\n\n";
+
+    fn peritext_buffer(s: &str) -> Peritext {
+        let mut buffer = Peritext::new(1);
+        let _ = buffer.write(ByteOffset(0), s);
+
+        buffer
+    }
 
     #[test]
     fn replace() {
@@ -234,4 +1044,465 @@ mod tests {
 
         assert_eq!("herald", buf);
     }
+
+    #[test]
+    fn drain_returns_the_removed_text() -> Result<(), CursorError> {
+        let mut buf = peritext_buffer("hello, world");
+
+        let drained = buf.drain(ByteOffset(5)..ByteOffset(6))?.into_owned();
+
+        assert_eq!(drained, ",");
+        assert_eq!(buf.take(), "hello world");
+
+        Ok(())
+    }
+
+    #[test]
+    fn cursor_coords() -> Result<(), CursorError> {
+        let mut buf = peritext_buffer(TEXT);
+
+        assert_eq!(
+            buf.cursor_coords(ByteOffset(6))?,
+            (RowPosition::Row(0), ColPosition::Col(6))
+        );
+        assert_eq!(
+            buf.cursor_coords(ByteOffset(9))?,
+            (RowPosition::Row(1), ColPosition::Col(2))
+        );
+        assert_eq!(
+            buf.cursor_coords(ByteOffset(14))?,
+            (RowPosition::Row(3), ColPosition::Col(0))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn slice_takes_byte_offsets() -> Result<(), ConversionError> {
+        let buf = peritext_buffer("né😀 ok");
+
+        assert_eq!(buf.slice(ByteOffset(1)..ByteOffset(7))?, "é😀");
+        assert!(buf.slice(ByteOffset(2)..ByteOffset(7)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn converts_between_units() -> Result<(), ConversionError> {
+        let buf = peritext_buffer("aé😀b");
+
+        assert_eq!(buf.byte_to_utf16(ByteOffset(7))?, Utf16Offset(4));
+        assert_eq!(buf.utf16_to_byte(Utf16Offset(4))?, ByteOffset(7));
+        assert_eq!(buf.byte_to_char(ByteOffset(7))?, CharOffset(3));
+        assert_eq!(buf.char_to_byte(CharOffset(2))?, ByteOffset(3));
+        assert_eq!(buf.byte_to_grapheme(ByteOffset(8))?, GraphemeIndex(4));
+        assert_eq!(buf.grapheme_to_byte(GraphemeIndex(3))?, ByteOffset(7));
+        assert!(buf.grapheme_to_byte(GraphemeIndex(5)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn pasted_fragments_keep_their_annotations() -> Result<(), crate::error::BluebookCoreError> {
+        use crate::{
+            command::Transaction, ctx::TextEditorContext, cursor::CursorRange, selection::Selection,
+        };
+
+        let mut buf = peritext_buffer("See Roe v. Wade. ");
+        buf.annotate(
+            ByteOffset(4)..ByteOffset(15),
+            peritext::Style::new_bold_like("Italic".into(), serde_json::Value::Bool(true)),
+        );
+        let mut ctx = TextEditorContext::new(buf, CursorRange::new(4, 15));
+
+        assert!(ctx.consume_transaction::<Peritext>(Transaction::ClipboardCopy)?);
+        ctx.selection = Selection::single(CursorRange::new(17, 17));
+        ctx.consume_transaction::<Peritext>(Transaction::Paste {
+            clipboard: "Roe v. Wade".to_string(),
+        })?;
+
+        let italic = ctx
+            .text_buffer
+            .slice_spans(ByteOffset(17)..ByteOffset(28))?;
+        assert_eq!(italic.len(), 1);
+        assert!(italic[0].attributes.contains_key(&"Italic".into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn searches_the_rich_text() -> Result<(), BluebookCoreError> {
+        use crate::search::SearchQuery;
+
+        let buf = peritext_buffer("né Roe, roe");
+
+        assert_eq!(
+            buf.find_all(&SearchQuery::literal("roe").case_insensitive())?,
+            [4..7, 9..12]
+        );
+        assert_eq!(
+            buf.find_next(&SearchQuery::literal("Roe").whole_word(), 5)?,
+            Some(4..7)
+        );
+
+        // Edits are searched once the text has changed.
+        let mut buf = buf;
+        buf.write(ByteOffset(0), "Roe ")?;
+        assert_eq!(
+            buf.find_all(&SearchQuery::literal("roe").case_insensitive())?,
+            [0..3, 8..11, 13..16]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn replace_all_undoes_at_once() -> Result<(), BluebookCoreError> {
+        use crate::{command::Transaction, cursor::CursorRange, search::SearchQuery};
+
+        let buf = peritext_buffer("Roe v. Wade; roe v. wade");
+        let mut ctx = TextEditorContext::new(buf, CursorRange::new(0, 0));
+        ctx.consume_transaction::<Peritext>(Transaction::ReplaceAll {
+            query: SearchQuery::literal("wade").case_insensitive(),
+            replacement: "Bolton".to_string(),
+        })?;
+        assert_eq!(ctx.text_buffer.take(), "Roe v. Bolton; roe v. Bolton");
+
+        ctx.consume_transaction::<Peritext>(Transaction::Undo)?;
+        assert_eq!(ctx.text_buffer.take(), "Roe v. Wade; roe v. wade");
+
+        Ok(())
+    }
+
+    #[test]
+    fn query_and_remove_annotations() -> Result<(), crate::error::BluebookCoreError> {
+        use crate::{command::Transaction, ctx::TextEditorContext, cursor::CursorRange};
+
+        let mut buf = peritext_buffer("See Roe v. Wade.");
+        buf.annotate(
+            ByteOffset(4)..ByteOffset(15),
+            peritext::Style::new_bold_like("Bold".into(), serde_json::Value::Bool(true)),
+        );
+        for note in ["check cite", "pin cite"] {
+            buf.annotate(
+                ByteOffset(4)..ByteOffset(7),
+                peritext::Style::new_comment_like("Comment".into(), note.into()),
+            );
+        }
+
+        let at = buf.annotations_at(ByteOffset(5))?;
+        assert_eq!(at.len(), 3);
+        assert!(at
+            .iter()
+            .all(|ann| ann.range == (4..7) || ann.range == (4..15)));
+        assert!(buf.annotations_at(ByteOffset(1))?.is_empty());
+        assert_eq!(buf.annotations_in(ByteOffset(8)..ByteOffset(16))?.len(), 1);
+
+        let comment = at
+            .iter()
+            .find(|ann| ann.value == "check cite")
+            .map(|ann| ann.id)
+            .unwrap();
+        let mut ctx = TextEditorContext::new(buf, CursorRange::new(0, 0));
+        assert!(ctx.consume_transaction::<Peritext>(Transaction::RemoveAnnotation { id: comment })?);
+
+        let at = ctx.text_buffer.annotations_at(ByteOffset(5))?;
+        assert_eq!(at.len(), 2);
+        assert!(at.iter().any(|ann| ann.value == "pin cite"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn annotated_text_objects() -> Result<(), crate::error::BluebookCoreError> {
+        use crate::{
+            command::Transaction, ctx::TextEditorContext, cursor::CursorRange, mode::Mode,
+            text_object::TextObject,
+        };
+
+        let mut buf = peritext_buffer("See Roe v. Wade, 410 U.S. 113.");
+        for range in [4..7, 7..15] {
+            buf.annotate(
+                ByteOffset(range.start)..ByteOffset(range.end),
+                peritext::Style::new_bold_like("Bold".into(), serde_json::Value::Bool(true)),
+            );
+        }
+        buf.annotate(
+            ByteOffset(8)..ByteOffset(10),
+            peritext::Style::new_comment_like("Comment".into(), "check cite".into()),
+        );
+
+        let mut ctx = TextEditorContext::new(buf, CursorRange::new(9, 9));
+        ctx.modal.mode = Mode::Normal;
+        let bold = TextObject::Annotation("bold".into());
+        let comment = TextObject::Annotation("comment".into());
+
+        // Bold runs together, however it was applied.
+        assert_eq!(ctx.text_object_range(5, &bold, false)?, Some(4..15));
+        assert_eq!(ctx.text_object_range(8, &comment, false)?, Some(8..10));
+        assert_eq!(ctx.text_object_range(5, &comment, false)?, None);
+
+        for transaction in [
+            Transaction::DeleteOperator,
+            Transaction::SelectTextObject {
+                object: comment,
+                around: true,
+            },
+        ] {
+            ctx.consume_transaction::<Peritext>(transaction)?;
+        }
+        assert_eq!(ctx.text_buffer.take(), "See Roe Wade, 410 U.S. 113.");
+
+        Ok(())
+    }
+
+    /// The indexed annotations, checked against those resolved from scratch.
+    fn indexed(buf: &Peritext) -> Vec<(OpID, Range<usize>)> {
+        let mut indexed: Vec<_> = buf
+            .annotations(0..buf.len())
+            .into_iter()
+            .map(|ann| (ann.id, ann.range))
+            .collect();
+        let mut resolved: Vec<_> = buf
+            .inner
+            .annotations_in(.., IndexType::Utf8)
+            .into_iter()
+            .map(|(ann, range)| (ann.id, range))
+            .collect();
+        indexed.sort_by_key(|(id, _)| *id);
+        resolved.sort_by_key(|(id, _)| *id);
+        assert_eq!(indexed, resolved);
+        indexed
+    }
+
+    #[test]
+    fn annotation_index_follows_local_edits() -> Result<(), CursorError> {
+        let mut buf = peritext_buffer("See Roe v. Wade, 410 U.S. 113.");
+        buf.annotate(
+            ByteOffset(4)..ByteOffset(15),
+            peritext::Style::new_bold_like("Bold".into(), serde_json::Value::Bool(true)),
+        );
+        buf.annotate(
+            ByteOffset(17)..ByteOffset(29),
+            peritext::Style::new_link_like("Link".into(), "https://example.com".into()),
+        );
+        buf.annotate(
+            ByteOffset(8)..ByteOffset(20),
+            peritext::Style::new_comment_like("Comment".into(), "check cite".into()),
+        );
+
+        // Edits at, around and inside the ends of every annotation.
+        buf.write(ByteOffset(15), "!")?;
+        buf.write(ByteOffset(18), "(")?;
+        buf.write(ByteOffset(4), "> ")?;
+        buf.drain(ByteOffset(12)..ByteOffset(14))?;
+        buf.replace_range(ByteOffset(20)..ByteOffset(25), "4")?;
+
+        let annotations = indexed(&buf);
+        assert_eq!(annotations.len(), 3);
+
+        // Removing a comment drops it from the index, and removing bold text
+        // indexes the annotation erasing it.
+        assert!(buf.remove_annotation(annotations[2].0));
+        assert!(buf.remove_annotation(annotations[0].0));
+        assert_eq!(indexed(&buf), [annotations[1].clone()]);
+        assert_eq!(buf.annotation_index.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn annotation_index_follows_merged_edits() -> Result<(), BluebookCoreError> {
+        let mut local = peritext_buffer("See Roe v. Wade, 410 U.S. 113.");
+        local.annotate(
+            ByteOffset(4)..ByteOffset(15),
+            peritext::Style::new_bold_like("Bold".into(), serde_json::Value::Bool(true)),
+        );
+        local.annotate(
+            ByteOffset(8)..ByteOffset(20),
+            peritext::Style::new_comment_like("Comment".into(), "check cite".into()),
+        );
+        let mut remote = Peritext::new(2);
+        remote.merge(&local);
+        let comment = indexed(&remote)[1].0;
+
+        // Concurrent edits at and inside the ends of the annotations.
+        local.write(ByteOffset(4), "> ")?;
+        local.drain(ByteOffset(12)..ByteOffset(14))?;
+        remote.write(ByteOffset(15), "!")?;
+        remote.drain(ByteOffset(0)..ByteOffset(4))?;
+        remote.annotate(
+            ByteOffset(13)..ByteOffset(25),
+            peritext::Style::new_link_like("Link".into(), "https://example.com".into()),
+        );
+        assert!(remote.remove_annotation(comment));
+
+        local.import(&remote.export(&local.version()))?;
+        remote.merge(&local);
+        assert_eq!(local.take(), remote.take());
+        assert_eq!(indexed(&local), indexed(&remote));
+        assert_eq!(indexed(&local).len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn local_and_remote_changes_are_reported() -> Result<(), CursorError> {
+        let mut local = Peritext::new(1);
+        let events = local.subscribe();
+
+        local.write(ByteOffset(0), "Roe v. Wade")?;
+        local.annotate(
+            ByteOffset(0)..ByteOffset(3),
+            peritext::Style::new_bold_like("Bold".into(), serde_json::Value::Bool(true)),
+        );
+        let mut remote = Peritext::new(2);
+        remote.merge(&local);
+        remote.write(ByteOffset(0), "See ")?;
+        local.merge(&remote);
+
+        let events: Vec<_> = events.try_iter().collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].ops, [ChangeOp::insert("Roe v. Wade")]);
+        assert_eq!(
+            events[1].attribute_changes(),
+            [(0..3, "Bold".into(), serde_json::Value::Bool(true))]
+        );
+        assert!(events[..2].iter().all(|event| event.is_local));
+        assert!(!events[2].is_local);
+        assert_eq!(events[2].ops, [ChangeOp::insert("See ")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn subscribers_share_one_listener() -> Result<(), CursorError> {
+        let mut buf = Peritext::new(1);
+        let gone = buf.subscribe();
+        let events = buf.subscribe();
+        drop(gone);
+
+        buf.write(ByteOffset(0), "Roe")?;
+        buf.write(ByteOffset(3), " v. Wade")?;
+        assert_eq!(events.try_iter().count(), 2);
+        assert_eq!(Rc::strong_count(&buf.subscribers), 2);
+
+        drop(events);
+        buf.write(ByteOffset(0), "See ")?;
+        assert!(buf.subscribers.borrow().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn rolled_back_compounds_keep_annotations() -> Result<(), BluebookCoreError> {
+        let mut buf = peritext_buffer("See Roe v. Wade.");
+        buf.annotate(
+            ByteOffset(4)..ByteOffset(15),
+            peritext::Style::new_bold_like("Bold".into(), serde_json::Value::Bool(true)),
+        );
+        let spans = buf.slice_spans(ByteOffset(0)..ByteOffset(buf.len()))?;
+        let mut ctx = TextEditorContext::new(buf, CursorRange::new(0, 0));
+
+        let compound = CompoundTransaction::new()
+            .delete(4..11)
+            .annotate(0..4, "Bold", false)
+            .insert(100, "out of bounds");
+
+        assert!(ctx.apply_compound(&compound).is_err());
+        assert_eq!(ctx.text_buffer.take(), "See Roe v. Wade.");
+        assert_eq!(
+            ctx.text_buffer
+                .slice_spans(ByteOffset(0)..ByteOffset(ctx.text_buffer.len()))?,
+            spans
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn undo_groups_keystrokes() -> Result<(), CursorError> {
+        let mut buf = Peritext::new(1);
+        for (offset, ch) in "Roe".char_indices() {
+            buf.write(ByteOffset(offset), &ch.to_string())?;
+        }
+        buf.history_mut().group_interval = std::time::Duration::ZERO;
+        buf.write(ByteOffset(3), " v. Wade")?;
+        buf.drain(ByteOffset(0)..ByteOffset(4))?;
+
+        assert!(buf.undo().is_some());
+        assert_eq!(buf.take(), "Roe v. Wade");
+        // The space written back is deleted along with the text typed after it.
+        assert!(buf.undo().is_some());
+        assert_eq!(buf.take(), "Roe");
+
+        assert!(buf.redo().is_some());
+        assert_eq!(buf.take(), "Roe v. Wade");
+        assert!(buf.redo().is_some());
+        assert_eq!(buf.take(), "v. Wade");
+
+        for _ in 0..3 {
+            assert!(buf.undo().is_some());
+        }
+        assert_eq!(buf.take(), "");
+        assert!(buf.undo().is_none());
+
+        buf.write(ByteOffset(0), "See ")?;
+        assert!(buf.redo().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn undo_leaves_remote_edits_alone() -> Result<(), CursorError> {
+        let mut local = Peritext::new(1);
+        local.history_mut().group_interval = std::time::Duration::ZERO;
+        local.write(ByteOffset(0), "Roe v. Wade")?;
+        local.drain(ByteOffset(3)..ByteOffset(6))?;
+
+        let mut remote = Peritext::new(2);
+        remote.history_mut().group_interval = std::time::Duration::ZERO;
+        remote.merge(&local);
+        remote.write(ByteOffset(0), "See ")?;
+        remote.write(ByteOffset(12), ", 410 U.S. 113")?;
+        local.merge(&remote);
+        assert_eq!(local.take(), "See Roe Wade, 410 U.S. 113");
+
+        let inserted = local.undo().map(|change| change.inserted());
+        assert_eq!(local.take(), "See Roe v. Wade, 410 U.S. 113");
+        assert_eq!(inserted.as_deref().and_then(<[_]>::first), Some(&(7..10)));
+        assert_eq!(inserted.map(|inserted| inserted.len()), Some(1));
+
+        local.undo();
+        assert_eq!(local.take(), "See , 410 U.S. 113");
+        assert!(remote.undo().is_some());
+        remote.merge(&local);
+        assert_eq!(remote.take(), "See ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn undo_restores_replaced_annotations() -> Result<(), ConversionError> {
+        let mut buf = peritext_buffer("Roe v. Wade");
+        let bold = |value: bool| {
+            peritext::Style::new_bold_like("bold".into(), serde_json::Value::Bool(value))
+        };
+        let in_effect = |buf: &Peritext| -> Result<Vec<_>, ConversionError> {
+            Ok(buf
+                .annotations_in(ByteOffset(0)..ByteOffset(buf.len()))?
+                .into_iter()
+                .map(|ann| (ann.value, ann.range))
+                .collect())
+        };
+        buf.annotate(ByteOffset(0)..ByteOffset(3), bold(true));
+        buf.annotate(ByteOffset(0)..ByteOffset(11), bold(false));
+
+        assert!(buf.undo().is_some());
+        assert_eq!(in_effect(&buf)?, [(serde_json::Value::Bool(true), 0..3)]);
+        assert!(buf.redo().is_some());
+        assert_eq!(in_effect(&buf)?, [(serde_json::Value::Bool(false), 0..11)]);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,149 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    ops::Range,
+};
+
+use peritext::{rich_text::RichText as RichTextInner, Behavior, Expand, Style};
+use serde_json::Value;
+use string_cache::DefaultAtom;
+
+use super::buffer_impl::Peritext;
+use crate::span::{attribute_runs, Span};
+
+/// Builds a [`Peritext`] document in bulk.
+///
+/// Text is accumulated and written with a single insert, then every run of
+/// an attribute is annotated once, however many spans it covers. Loading a
+/// large document this way produces a handful of ops, where writing it span
+/// by span, let alone keystroke by keystroke, would produce thousands.
+pub struct PeritextBuilder {
+    client_id: u64,
+    text: String,
+    spans: Vec<Span>,
+    annotations: Vec<(Range<usize>, Style)>,
+}
+
+impl PeritextBuilder {
+    pub fn new(client_id: u64) -> Self {
+        Self {
+            client_id,
+            text: String::new(),
+            spans: Vec::new(),
+            annotations: Vec::new(),
+        }
+    }
+
+    /// A builder with a random client id, for documents that aren't shared
+    /// with anyone yet.
+    pub fn with_random_client_id() -> Self {
+        Self::new(RandomState::new().build_hasher().finish())
+    }
+
+    /// Append unstyled text.
+    pub fn push_str(&mut self, s: &str) -> &mut Self {
+        self.push_span(Span {
+            insert: s.to_string(),
+            attributes: Default::default(),
+        })
+    }
+
+    /// Append text styled with the attributes of `span`.
+    ///
+    /// Comments are kept as comments, which may overlap; every other attribute
+    /// is merged with the same attribute around it, expanding the way Quill
+    /// expects for its type.
+    pub fn push_span(&mut self, span: Span) -> &mut Self {
+        self.text.push_str(&span.insert);
+        self.spans.push(span);
+        self
+    }
+
+    pub fn extend_spans(&mut self, spans: impl IntoIterator<Item = Span>) -> &mut Self {
+        for span in spans {
+            self.push_span(span);
+        }
+        self
+    }
+
+    /// Annotate `range`, in bytes from the start of the document, with `style`.
+    /// The range may cover text that hasn't been pushed yet.
+    pub fn annotate(&mut self, range: Range<usize>, style: Style) -> &mut Self {
+        self.annotations.push((range, style));
+        self
+    }
+
+    pub fn build(self) -> Peritext {
+        let mut inner = RichTextInner::new(self.client_id);
+        inner.insert(0, &self.text);
+
+        for (range, type_, value) in attribute_runs(&self.spans) {
+            if !value.is_null() {
+                inner.annotate(range, loaded_style(type_, value));
+            }
+        }
+        for (range, style) in self.annotations {
+            inner.annotate(range, style);
+        }
+
+        Peritext::from(inner)
+    }
+}
+
+/// The style an attribute read from a span is loaded with.
+pub(crate) fn loaded_style(type_: DefaultAtom, value: Value) -> Style {
+    let lowercase = type_.to_ascii_lowercase();
+    if &*lowercase == "comment" {
+        return Style::new_comment_like(type_, value);
+    }
+
+    Style {
+        expand: Expand::infer_insert_expand(&lowercase),
+        behavior: Behavior::Merge,
+        type_,
+        value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{offset::ByteOffset, text_buffer::TextBuffer};
+
+    #[test]
+    fn builds_styled_text() -> Result<(), crate::text_buffer::ConversionError> {
+        let bold = [(DefaultAtom::from("Bold"), Value::Bool(true))];
+        let mut builder = PeritextBuilder::new(7);
+        builder
+            .push_str("See ")
+            .push_span(Span {
+                insert: "Roe".to_string(),
+                attributes: bold.iter().cloned().collect(),
+            })
+            .push_span(Span {
+                insert: " v. Wade".to_string(),
+                attributes: bold.iter().cloned().collect(),
+            })
+            .push_str(".\n");
+        let buf = builder.build();
+
+        assert_eq!(buf.rich_text().id(), 7);
+        assert_eq!(buf.take(), "See Roe v. Wade.\n");
+
+        let spans = buf.slice_spans(ByteOffset(0)..ByteOffset(buf.len()))?;
+        assert_eq!(spans.len(), 3);
+        assert_eq!(spans[1].insert, "Roe v. Wade");
+        assert_eq!(spans[1].attributes, bold.into_iter().collect());
+
+        Ok(())
+    }
+
+    #[test]
+    fn loads_large_text() {
+        let text = "line\n".repeat(10_000);
+        let buf = Peritext::from_str(&text);
+
+        assert_eq!(buf.take(), text);
+        assert_eq!(buf.rich_text().lines(), 10_001);
+    }
+}
@@ -1,243 +1,341 @@
-use std::borrow::Cow;
-
-// use unicode_segmentation::Graphemes;
-
-// use super::grapheme::Graphemes;
-use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete, UnicodeSegmentation};
-
-use crate::text_buffer_cursor::TextBufferCursor;
-
-use crate::movement::Direction;
-
-use crate::graphemes::UnicodeSegmentationError;
-
-/// A single selection range.
-///
-/// A range consists of an "anchor" and "head" position in
-/// the text.  The head is the part that the user moves when
-/// directly extending a selection.  The head and anchor
-/// can be in any order, or even share the same position.
-///
-/// The anchor and head positions use gap indexing, meaning
-/// that their indices represent the gaps *between* `char`s
-/// rather than the `char`s themselves. For example, 1
-/// represents the position between the first and second `char`.
-///
-/// Below are some examples of `Range` configurations.
-/// The anchor and head indices are shown as "(anchor, head)"
-/// tuples, followed by example text with "[" and "]" symbols
-/// representing the anchor and head positions:
-///
-/// - (0, 3): `[Som]e text`.
-/// - (3, 0): `]Som[e text`.
-/// - (2, 7): `So[me te]xt`.
-/// - (1, 1): `S[]ome text`.
-///
-/// Ranges are considered to be inclusive on the left and
-/// exclusive on the right, regardless of anchor-head ordering.
-/// This means, for example, that non-zero-width ranges that
-/// are directly adjacent, sharing an edge, do not overlap.
-/// However, a zero-width range will overlap with the shared
-/// left-edge of another range.
-///
-/// By convention, user-facing ranges are considered to have
-/// a block cursor on the head-side of the range that spans a
-/// single grapheme inward from the range's edge.  There are a
-/// variety of helper methods on `Range` for working in terms of
-/// that block cursor, all of which have `cursor` in their name.
-///
-///
-
-enum CursorEnd {
-    Head,
-    Tail,
+use peritext::rich_text::{chunk_cursor::ChunkCursor, IndexType, RichText};
+
+use super::buffer_impl::Peritext;
+use crate::{
+    block::{Block, BlockCursor},
+    char::CharCursor,
+    cursor::CursorRange,
+    graphemes::{GraphemeClusterCursor, GraphemeClusterCursorError},
+    line::LineCursor,
+};
+
+/// Walks the alive text of the document one codepoint at a time, reading the
+/// elements of the content tree in place rather than copying the document.
+pub struct PeritextCharCursor<'a> {
+    inner: ChunkCursor<'a>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Eq)]
-pub struct PeritextCursor<'a> {
-    pub buffer: Cow<'a, str>,
-    pub cursor_range: CursorRange,
-}
-
-// #[derive(Debug)]
-// pub struct StringCursor<'a> {
-//     text: &'a mut String,
-//     position: usize,
-// }
+impl<'a> CharCursor<'a> for PeritextCharCursor<'a> {
+    type Buffer = Peritext;
 
-impl<'a> PeritextCursor<'a> {
-    /// Create a new cursor.
-    pub fn new(buffer: Cow<'a, str>) -> Self {
+    fn new(text: &'a Self::Buffer, pos: usize) -> Self {
         Self {
-            buffer,
-            cursor_range: CursorRange::new(0, 0),
+            inner: text.rich_text().chunk_cursor(pos),
         }
     }
-}
 
-impl<'cursor> TextBufferCursor<'cursor> for PeritextCursor<'cursor> {
-    fn anchor(&self) -> usize {
-        self.cursor_range.anchor
-    }
-    fn head(&self) -> usize {
-        self.cursor_range.head
-    }
-    fn set_anchor(mut self, byte_offset: usize) -> Self {
-        self.cursor_range.anchor = byte_offset;
-        self
+    fn pos(&self) -> usize {
+        self.inner.pos()
     }
-    fn set_head(mut self, byte_offset: usize) -> Self {
-        self.cursor_range.head = byte_offset;
-        self
-    }
-
-    fn set_point(mut self, byte_offset: usize) -> Self {
-        self.cursor_range.head = byte_offset;
-        self.cursor_range.anchor = byte_offset;
 
-        self
+    fn set(&mut self, pos: usize) {
+        self.inner.set(pos)
     }
 
-    fn range(&self) -> CursorRange {
-        self.cursor_range
+    fn prev_codepoint(&mut self) -> Option<char> {
+        self.inner.prev_codepoint()
     }
 
-    fn is_grapheme_boundary(&self) -> Result<bool, UnicodeSegmentationError> {
-        // let graphemes = Graphemes::new(&self.buffer, false);
+    fn next_codepoint(&mut self) -> Option<char> {
+        self.inner.next_codepoint()
+    }
 
-        let s = self.buffer.as_ref();
+    fn peek_next_codepoint(&self) -> Option<char> {
+        self.inner.peek_next_codepoint()
+    }
+}
 
-        let mut gc = GraphemeCursor::new(self.head(), s.len(), false);
+/// Grapheme cluster navigation over the content tree. Clusters that straddle
+/// several elements are handled by feeding the segmenter chunk by chunk.
+pub struct PeritextGraphemeClusterCursor<'a> {
+    text: &'a RichText,
+    range: CursorRange,
+}
 
-        gc.is_boundary(s, 0)
-            .map_err(UnicodeSegmentationError::GraphemeIncompleteError)
+impl<'a> PeritextGraphemeClusterCursor<'a> {
+    pub fn range(&self) -> CursorRange {
+        self.range
     }
+}
 
-    fn next_grapheme_boundary(&self) -> Result<Option<usize>, UnicodeSegmentationError> {
-        let s = self.buffer.as_ref();
-
-        let mut gc = GraphemeCursor::new(self.head(), s.len(), false);
+impl<'a> GraphemeClusterCursor<'a> for PeritextGraphemeClusterCursor<'a> {
+    type Buffer = Peritext;
 
-        gc.next_boundary(s, 0)
-            .map_err(UnicodeSegmentationError::GraphemeIncompleteError)
+    fn new(text: &'a Self::Buffer, range: CursorRange) -> Self {
+        Self {
+            text: text.rich_text(),
+            range,
+        }
     }
 
-    fn prev_grapheme_boundary(&self) -> Result<Option<usize>, UnicodeSegmentationError> {
-        let s = self.buffer.as_ref();
-
-        let mut gc = GraphemeCursor::new(self.head(), s.len(), false);
+    fn prev_grapheme_cluster_boundary(&self) -> Result<Option<usize>, GraphemeClusterCursorError> {
+        Ok(self
+            .text
+            .chunk_cursor(self.range.head)
+            .prev_grapheme_boundary())
+    }
 
-        gc.prev_boundary(s, 0)
-            .map_err(UnicodeSegmentationError::GraphemeIncompleteError)
+    fn next_grapheme_cluster_boundary(&self) -> Result<Option<usize>, GraphemeClusterCursorError> {
+        Ok(self
+            .text
+            .chunk_cursor(self.range.head)
+            .next_grapheme_boundary())
     }
 
-    fn nth_next_grapheme_boundary(
+    fn nth_next_grapheme_cluster_boundary(
         &self,
         n: usize,
-    ) -> Result<Option<usize>, UnicodeSegmentationError> {
-        let s = self.buffer.as_ref();
-
-        let mut gc = GraphemeCursor::new(self.head(), s.len(), false);
+    ) -> Result<Option<usize>, GraphemeClusterCursorError> {
+        let mut cursor = self.text.chunk_cursor(self.range.head);
 
         for _ in 0..n {
-            let next_byte_offset = gc
-                .next_boundary(s, 0)
-                .map_err(UnicodeSegmentationError::GraphemeIncompleteError)?;
-
-            match next_byte_offset {
-                Some(next_byte_offset) => {
-                    gc.set_cursor(next_byte_offset);
-
-                    continue;
-                }
-                None => {
-                    return Ok(None);
-                }
+            if cursor.next_grapheme().is_none() {
+                return Ok(None);
             }
         }
 
-        Ok(Some(gc.cur_cursor()))
+        Ok(Some(cursor.pos()))
     }
-    fn nth_prev_grapheme_boundary(
+
+    fn nth_prev_grapheme_cluster_boundary(
         &self,
         n: usize,
-    ) -> Result<Option<usize>, UnicodeSegmentationError> {
-        let s = self.buffer.as_ref();
-
-        let mut gc = GraphemeCursor::new(self.head(), s.len(), false);
+    ) -> Result<Option<usize>, GraphemeClusterCursorError> {
+        let mut cursor = self.text.chunk_cursor(self.range.head);
 
         for _ in 0..n {
-            let prev_byte_offset = gc
-                .prev_boundary(s, 0)
-                .map_err(UnicodeSegmentationError::GraphemeIncompleteError)?;
-
-            match prev_byte_offset {
-                Some(prev_byte_offset) => {
-                    gc.set_cursor(prev_byte_offset);
-                    continue;
-                }
-                None => {
-                    return Ok(None);
-                }
+            if cursor.prev_grapheme().is_none() {
+                return Ok(None);
             }
         }
 
-        Ok(Some(gc.cur_cursor()))
+        Ok(Some(cursor.pos()))
+    }
+
+    fn is_grapheme_boundary(&self) -> Result<bool, GraphemeClusterCursorError> {
+        Ok(self
+            .text
+            .chunk_cursor(self.range.head)
+            .is_grapheme_boundary())
+    }
+}
+
+/// Jumps between line starts using the line break counts cached in the tree.
+pub struct PeritextLineCursor<'a> {
+    text: &'a RichText,
+    pos: usize,
+}
+
+impl<'a> LineCursor<'a> for PeritextLineCursor<'a> {
+    type Buffer = Peritext;
+
+    fn new(text: &'a Self::Buffer, pos: usize) -> Self {
+        Self {
+            text: text.rich_text(),
+            pos,
+        }
+    }
+
+    fn prev_boundary(&mut self) -> Option<usize> {
+        let line = self.text.line_of_offset(self.pos, IndexType::Utf8);
+        let line_start = self.text.line_start(line)?;
+
+        let line_start = if line_start < self.pos {
+            line_start
+        } else {
+            // Already at the start of a line: go to the start of the one above.
+            self.text.line_start(line.checked_sub(1)?)?
+        };
+
+        self.pos = line_start;
+        Some(line_start)
+    }
+
+    fn next_boundary(&mut self) -> Option<usize> {
+        let line = self.text.line_of_offset(self.pos, IndexType::Utf8);
+        let line_start = self.text.line_start(line + 1)?;
+
+        self.pos = line_start;
+        Some(line_start)
     }
 }
 
-pub fn len_utf8_from_first_byte(b: u8) -> usize {
-    match b {
-        b if b < 0x80 => 1,
-        b if b < 0xe0 => 2,
-        b if b < 0xf0 => 3,
-        _ => 4,
+/// Blocks of rich text: lines, carrying the block annotations (headers, lists,
+/// code blocks, alignment) on their first character.
+pub struct PeritextBlockCursor<'a> {
+    text: &'a RichText,
+    pos: usize,
+}
+
+impl<'a> BlockCursor<'a> for PeritextBlockCursor<'a> {
+    type Buffer = Peritext;
+
+    fn new(text: &'a Self::Buffer, pos: usize) -> Self {
+        Self {
+            text: text.rich_text(),
+            pos,
+        }
+    }
+
+    fn pos(&self) -> usize {
+        self.pos
+    }
+
+    fn set(&mut self, pos: usize) {
+        self.pos = pos.min(self.text.len());
+    }
+
+    fn block_at(&self, pos: usize) -> Block {
+        let pos = pos.min(self.text.len());
+        let line = self.text.line_of_offset(pos, IndexType::Utf8);
+        let start = self.text.line_start(line).unwrap_or(0);
+        let end = self
+            .text
+            .line_start(line + 1)
+            .unwrap_or_else(|| self.text.len());
+
+        let attributes = match self.text.chunk_cursor(start).peek_next_codepoint() {
+            Some(first) => self
+                .text
+                .slice(start..start + first.len_utf8(), IndexType::Utf8)
+                .into_iter()
+                .next()
+                .map(|span| span.attributes)
+                .unwrap_or_default(),
+            // The empty last block has no character to carry attributes.
+            None => Default::default(),
+        };
+
+        Block::new(start..end, attributes)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        buffer::peritext_buffer::buffer_impl::Peritext, error::TextBufferWithCursorError,
-        text_buffer::TextBuffer, text_buffer_cursor::CursorDocCoords,
+        offset::ByteOffset, sentence::SentenceCursor, text_buffer::TextBuffer, word::WordCursor,
     };
 
     use super::*;
 
-    const TEXT: &str = &"Hello \nworld\n\n";
-    const EMOJI: &str = "😀👋🌍";
     const COMPLEX_EMOJI: &str = "👨‍👩‍👧‍👦";
 
     fn peritext_buffer(s: &str) -> Peritext {
         let mut buffer = Peritext::new(1);
-        let _ = buffer.write(0, s);
+        let _ = buffer.write(ByteOffset(0), s);
 
         buffer
     }
 
-    #[test]
-    fn test_cursor_coords_single_line() -> Result<(), TextBufferWithCursorError> {
-        let mut buf = peritext_buffer(TEXT);
+    /// Builds the text one char at a time, back to front, so that every char
+    /// ends up in an element of its own.
+    fn fragmented_buffer(s: &str) -> Peritext {
+        let mut buffer = Peritext::new(1);
+        for ch in s.chars().rev() {
+            let _ = buffer.write(ByteOffset(0), ch.encode_utf8(&mut [0; 4]));
+        }
 
-        let c = CursorRange { anchor: 7, head: 7 };
-        let doc_coords = buf.cursor_coords(c)?;
+        buffer
+    }
 
-        assert_eq!(doc_coords, CursorDocCoords::new(0, 7));
+    fn cursor_at(buf: &Peritext, offset: usize) -> PeritextGraphemeClusterCursor<'_> {
+        PeritextGraphemeClusterCursor::new(buf, CursorRange::new(offset, offset))
+    }
+
+    #[test]
+    fn char_cursor_walks_across_elements() {
+        let buf = fragmented_buffer("aé😀");
+        let mut cursor = PeritextCharCursor::new(&buf, 0);
+
+        assert_eq!(cursor.prev_codepoint(), None);
+        assert_eq!(cursor.next_codepoint(), Some('a'));
+        assert_eq!(cursor.next_codepoint(), Some('é'));
+        assert_eq!(cursor.peek_next_codepoint(), Some('😀'));
+        assert_eq!(cursor.next_codepoint(), Some('😀'));
+        assert_eq!(cursor.next_codepoint(), None);
+        assert_eq!(cursor.prev_codepoint(), Some('😀'));
+        assert_eq!(cursor.pos(), 3);
+    }
+
+    #[test]
+    fn zwj_sequence_split_across_elements() -> Result<(), GraphemeClusterCursorError> {
+        let buf = fragmented_buffer(&format!("a{COMPLEX_EMOJI}b"));
+        let end_of_emoji = 1 + COMPLEX_EMOJI.len();
+
+        assert_eq!(
+            cursor_at(&buf, 1).next_grapheme_cluster_boundary()?,
+            Some(end_of_emoji)
+        );
+        assert_eq!(
+            cursor_at(&buf, end_of_emoji).prev_grapheme_cluster_boundary()?,
+            Some(1)
+        );
+        assert_eq!(
+            cursor_at(&buf, 0).nth_next_grapheme_cluster_boundary(3)?,
+            Some(end_of_emoji + 1)
+        );
+        assert!(cursor_at(&buf, end_of_emoji).is_grapheme_boundary()?);
+        assert!(!cursor_at(&buf, 5).is_grapheme_boundary()?);
 
         Ok(())
     }
 
     #[test]
-    fn test_cursor_coords_multiple_lines_middle() {}
+    fn line_cursor() {
+        let buf = peritext_buffer("one\ntwo\nthree");
+        let mut cursor = PeritextLineCursor::new(&buf, 5);
+
+        assert_eq!(cursor.next_boundary(), Some(8));
+        assert_eq!(cursor.next_boundary(), None);
+        assert_eq!(cursor.prev_boundary(), Some(4));
+        assert_eq!(cursor.prev_boundary(), Some(0));
+        assert_eq!(cursor.prev_boundary(), None);
+    }
 
     #[test]
-    fn test_cursor_coords_multiple_lines_end() {}
+    fn word_cursor() -> Result<(), crate::text_buffer::CursorError> {
+        let mut buf = fragmented_buffer("hello brave world");
+
+        let mut cursor = buf.word_cursor(ByteOffset(0))?;
+        assert_eq!(cursor.next_boundary(), Some(6));
+        assert_eq!(cursor.next_boundary(), Some(12));
+
+        Ok(())
+    }
 
     #[test]
-    fn test_cursor_coords_at_start() {}
+    fn sentence_cursor() -> Result<(), crate::text_buffer::CursorError> {
+        let mut buf = fragmented_buffer("See Roe v. Wade. Id. at 153.\nNext.");
+
+        let mut cursor = buf.sentence_cursor(ByteOffset(0))?;
+        assert_eq!(cursor.next_boundary(), Some(17));
+        assert_eq!(cursor.next_boundary(), Some(29));
+        assert_eq!(cursor.next_boundary(), Some(34));
+        assert_eq!(cursor.next_boundary(), None);
+        assert_eq!(cursor.prev_boundary(), Some(29));
+        assert_eq!(cursor.prev_boundary(), Some(17));
+
+        Ok(())
+    }
 
     #[test]
-    fn test_cursor_coords_past_end() {}
+    fn block_cursor_reads_block_annotations() -> Result<(), crate::text_buffer::CursorError> {
+        let mut buf = fragmented_buffer("Title\nbody\nSub\nmore");
+        let header = |level: u64| peritext::Style::new_bold_like("header".into(), level.into());
+        buf.annotate(ByteOffset(0)..ByteOffset(5), header(1));
+        buf.annotate(ByteOffset(11)..ByteOffset(14), header(2));
+
+        let mut cursor = buf.block_cursor(ByteOffset(7))?;
+        assert_eq!(cursor.block().range, 6..11);
+        assert_eq!(cursor.block().header_level(), None);
+
+        assert_eq!(cursor.next_header(None), Some(11));
+        assert_eq!(cursor.block().header_level(), Some(2));
+        assert_eq!(cursor.next_header(Some(1)), None);
+        assert_eq!(cursor.pos(), 11);
+        assert_eq!(cursor.prev_header(Some(1)), Some(0));
+        assert_eq!(cursor.block().range, 0..6);
 
-    // Add more test cases as needed
+        Ok(())
+    }
 }
@@ -0,0 +1,287 @@
+//! Local undo for a shared document: each client undoes its own edits, and
+//! only those, however the document changed around them since.
+//!
+//! Edits are remembered by the ids of the bytes and annotations they made or
+//! removed, rather than by offsets, which remote edits invalidate. Undoing an
+//! edit resolves those ids to wherever they are now: inserted text that is
+//! still there is deleted, deleted text is written back where it used to be,
+//! and annotations are removed or restored. Undoing is itself an edit, which
+//! is what redo undoes.
+
+use std::time::{Duration, Instant};
+
+use peritext::{Anchor, AnchorRange, OpID, Style};
+use serde_json::Value;
+
+use crate::{
+    span::{FxHashMap, Span},
+    text_buffer::{EditKind, DEFAULT_UNDO_GROUP_INTERVAL},
+};
+
+/// Consecutive bytes of the document, by id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IdSpan {
+    pub start: OpID,
+    pub len: u32,
+}
+
+impl IdSpan {
+    pub fn ids(&self) -> impl Iterator<Item = OpID> {
+        let start = self.start;
+        (0..self.len).map(move |i| start.inc(i))
+    }
+
+    /// Extend the span with `id`, if it comes right after it.
+    pub fn push(&mut self, id: OpID) -> bool {
+        let next = self.start.inc(self.len) == id;
+        if next {
+            self.len += 1;
+        }
+        next
+    }
+}
+
+/// Map every id of `ids` through `ids_map`.
+fn remap_spans(ids: &mut Vec<IdSpan>, ids_map: &FxHashMap<OpID, OpID>) {
+    if !ids
+        .iter()
+        .flat_map(IdSpan::ids)
+        .any(|id| ids_map.contains_key(&id))
+    {
+        return;
+    }
+
+    let mut remapped: Vec<IdSpan> = Vec::new();
+    for id in ids.iter().flat_map(IdSpan::ids) {
+        let id = ids_map.get(&id).copied().unwrap_or(id);
+        if !remapped.last_mut().is_some_and(|span| span.push(id)) {
+            remapped.push(IdSpan { start: id, len: 1 });
+        }
+    }
+    *ids = remapped;
+}
+
+fn remap_range(range: &mut AnchorRange, ids_map: &FxHashMap<OpID, OpID>) {
+    for Anchor { id, .. } in [&mut range.start, &mut range.end] {
+        if let Some(new) = id.and_then(|old| ids_map.get(&old)) {
+            *id = Some(*new);
+        }
+    }
+}
+
+/// An edit made by this client, as it can be undone.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LocalEdit {
+    /// The bytes `ids` were inserted.
+    Insert { ids: Vec<IdSpan> },
+    /// The bytes `ids`, in document order, were deleted. They held `spans`.
+    Delete { ids: Vec<IdSpan>, spans: Vec<Span> },
+    /// `range` was annotated with `style`, as the annotation `id`. It
+    /// replaced the values of the same type in `replaced`.
+    Annotate {
+        range: AnchorRange,
+        style: Style,
+        id: OpID,
+        replaced: Vec<(AnchorRange, Value)>,
+    },
+    /// The annotation of `range` with `style` was undone, and the values it
+    /// replaced restored.
+    Unannotate {
+        range: AnchorRange,
+        style: Style,
+        replaced: Vec<(AnchorRange, Value)>,
+    },
+}
+
+impl LocalEdit {
+    /// Refer to the bytes of `ids_map` by their new ids. Deleted text written
+    /// back gets new ids, which the edits still referring to it must follow.
+    pub fn remap(&mut self, ids_map: &FxHashMap<OpID, OpID>) {
+        match self {
+            LocalEdit::Insert { ids } | LocalEdit::Delete { ids, .. } => remap_spans(ids, ids_map),
+            LocalEdit::Annotate {
+                range, replaced, ..
+            }
+            | LocalEdit::Unannotate {
+                range, replaced, ..
+            } => {
+                remap_range(range, ids_map);
+                for (range, _) in replaced {
+                    remap_range(range, ids_map);
+                }
+            }
+        }
+    }
+}
+
+/// The kind of a step made of `edits`.
+fn kind_of(edits: &[LocalEdit]) -> EditKind {
+    if edits
+        .iter()
+        .all(|edit| matches!(edit, LocalEdit::Insert { .. }))
+    {
+        EditKind::Typing
+    } else if edits
+        .iter()
+        .all(|edit| matches!(edit, LocalEdit::Delete { .. }))
+    {
+        EditKind::Deleting
+    } else {
+        EditKind::Other
+    }
+}
+
+/// Edits undone and redone together.
+#[derive(Clone, Debug)]
+pub struct Group {
+    pub edits: Vec<LocalEdit>,
+    kind: EditKind,
+    last_edit: Instant,
+    /// No more edits join a sealed group.
+    sealed: bool,
+}
+
+impl Group {
+    fn new(edits: Vec<LocalEdit>) -> Self {
+        Self {
+            kind: kind_of(&edits),
+            edits,
+            last_edit: Instant::now(),
+            sealed: false,
+        }
+    }
+}
+
+/// The undo and redo stacks of a client.
+///
+/// Consecutive edits of the same kind, typing or deleting, made less than
+/// `group_interval` apart are undone together, and so are the edits made
+/// between [`History::begin_group`] and [`History::end_group`].
+#[derive(Clone, Debug)]
+pub struct History {
+    undo: Vec<Group>,
+    redo: Vec<Group>,
+    pub group_interval: Duration,
+    /// How many explicit groups are open, nested ones included.
+    open: usize,
+    /// The number of groups when the outermost explicit group was opened.
+    opened_at: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            group_interval: DEFAULT_UNDO_GROUP_INTERVAL,
+            open: 0,
+            opened_at: 0,
+        }
+    }
+}
+
+impl History {
+    /// Record edits just made, as a single step. Redoing is no longer
+    /// possible afterwards.
+    pub fn record(&mut self, edits: Vec<LocalEdit>) {
+        if edits.is_empty() {
+            return;
+        }
+        self.redo.clear();
+
+        let kind = kind_of(&edits);
+        let joins = match self.undo.last() {
+            Some(_) if self.open > 0 => self.undo.len() > self.opened_at,
+            Some(last) => {
+                !last.sealed && kind.joins(last.kind, last.last_edit, self.group_interval)
+            }
+            None => false,
+        };
+
+        match (joins, self.undo.last_mut()) {
+            (true, Some(last)) => {
+                last.edits.extend(edits);
+                last.kind = kind;
+                last.last_edit = Instant::now();
+            }
+            _ => {
+                self.seal();
+                self.undo.push(Group::new(edits));
+            }
+        }
+    }
+
+    /// Make the next edit start a new group.
+    pub fn seal(&mut self) {
+        if let Some(last) = self.undo.last_mut() {
+            last.sealed = true;
+        }
+    }
+
+    /// Make every edit until the matching [`History::end_group`] part of a
+    /// single group.
+    pub fn begin_group(&mut self) {
+        if self.open == 0 {
+            self.seal();
+            self.opened_at = self.undo.len();
+        }
+        self.open += 1;
+    }
+
+    /// Close the group opened by [`History::begin_group`]. When `keep` is
+    /// false, the edits of the outermost group are forgotten: they have been
+    /// reverted already.
+    pub fn end_group(&mut self, keep: bool) {
+        self.open = self.open.saturating_sub(1);
+        if self.open > 0 {
+            return;
+        }
+
+        if !keep {
+            self.undo.truncate(self.opened_at);
+        }
+        self.seal();
+    }
+
+    pub fn pop_undo(&mut self) -> Option<Group> {
+        self.undo.pop()
+    }
+
+    pub fn pop_redo(&mut self) -> Option<Group> {
+        self.redo.pop()
+    }
+
+    /// Push a group made by redoing, which can be undone again.
+    pub fn push_undo(&mut self, edits: Vec<LocalEdit>) {
+        let mut group = Group::new(edits);
+        group.sealed = true;
+        self.undo.push(group);
+    }
+
+    /// Push a group made by undoing, which can be redone.
+    pub fn push_redo(&mut self, edits: Vec<LocalEdit>) {
+        let mut group = Group::new(edits);
+        group.sealed = true;
+        self.redo.push(group);
+    }
+
+    /// Refer to the bytes of `ids_map` by their new ids, in every edit.
+    pub fn remap(&mut self, ids_map: &FxHashMap<OpID, OpID>) {
+        if ids_map.is_empty() {
+            return;
+        }
+        for group in self.undo.iter_mut().chain(self.redo.iter_mut()) {
+            for edit in &mut group.edits {
+                edit.remap(ids_map);
+            }
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
@@ -1,3 +1,6 @@
+pub mod anchor;
 pub mod buffer_impl;
+pub mod builder;
 pub mod cursor_impl;
 pub mod grapheme;
+pub mod history;
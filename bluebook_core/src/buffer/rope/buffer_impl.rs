@@ -1,211 +1,495 @@
-use super::cursor_impl::{CursorRange, PeritextCursor};
-use crate::error::TextBufferWithCursorError;
-use crate::text_buffer_cursor::{CursorDocCoords, TextBufferCursor};
-use crate::{span::Span, text_buffer::TextBuffer};
-use std::ops::Bound;
+use super::{
+    cursor_impl::{RopeBlockCursor, RopeCharCursor, RopeLineCursor},
+    grapheme_cluster_cursor::RopeGraphemeClusterCursor,
+    history::UndoHistory,
+};
+use crate::{
+    block::{BlockCursor, BlockCursorError},
+    change::{ChangeEvent, Subscribers},
+    char::{CharCursor, CharCursorError},
+    coordinates::{ColPosition, RowPosition},
+    cursor::CursorRange,
+    graphemes::{GraphemeClusterCursor, GraphemeClusterCursorError},
+    line::{LineCursor, LineCursorError},
+    offset::{ByteOffset, CharOffset, GraphemeIndex, Utf16Offset},
+    paragraph::{CharParagraphCursor, ParagraphCursor, ParagraphCursorError},
+    search::Searchable,
+    sentence::{CharSentenceCursor, SentenceCursor, SentenceCursorError},
+    span::{Annotation, Span},
+    text_buffer::{ConversionError, CursorError, EditKind, TextBuffer},
+    word::{CharWordCursor, WordCursor, WordCursorError},
+};
+use serde_json::Value;
 use std::{
     borrow::Cow,
-    ops::{Range, RangeBounds},
+    collections::BTreeSet,
+    ops::{Bound, Range, RangeBounds},
+    sync::mpsc::Receiver,
 };
+use string_cache::DefaultAtom;
+
+use xi_rope::{engine::Engine, rope::Utf16CodeUnitsMetric, Cursor, LinesMetric, Rope, RopeDelta};
+
+/// A plain text buffer backed by an [`xi_rope::Rope`].
+///
+/// All offsets are utf8 byte offsets, and must lie on a codepoint boundary.
+///
+/// Every edit is made through an [`Engine`], in an undo group chosen by the
+/// buffer's [`UndoHistory`]. Besides undoing and redoing in order, any group
+/// still in the history can be undone on its own with
+/// [`RopeBuffer::undo_group`].
+#[derive(Clone, Debug)]
+pub struct RopeBuffer {
+    /// The head of `engine`, kept at hand for the cursors.
+    inner: Rope,
+    engine: Engine,
+    history: UndoHistory,
+    subscribers: Subscribers,
+}
 
-use peritext::rich_text::{self, IndexType, RichText as RichTextInner};
-
-use unicode_segmentation::UnicodeSegmentation;
-
-impl From<peritext::rich_text::Span> for Span {
-    fn from(val: peritext::rich_text::Span) -> Self {
-        Span {
-            insert: val.insert,
-            attributes: val.attributes,
-        }
+impl Default for RopeBuffer {
+    fn default() -> Self {
+        Self::from(Rope::default())
     }
 }
 
-pub struct Peritext {
-    inner: RichTextInner,
-}
+impl RopeBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-impl Peritext {
-    pub fn new(client_id: u64) -> Self {
-        Self {
-            inner: RichTextInner::new(client_id),
-        }
+    pub fn rope(&self) -> &Rope {
+        &self.inner
     }
 
-    fn convert_range<R: RangeBounds<usize>>(&self, range: R) -> (usize, usize) {
+    /// The number of lines in the buffer, counting a trailing empty line.
+    pub fn line_count(&self) -> usize {
+        self.inner.measure::<LinesMetric>() + 1
+    }
+
+    fn convert_range<R: RangeBounds<ByteOffset>>(&self, range: R) -> (usize, usize) {
         let start = match range.start_bound() {
-            Bound::Included(&start) => start,
-            Bound::Excluded(&start) => start + 1,
+            Bound::Included(&ByteOffset(start)) => start,
+            Bound::Excluded(&ByteOffset(start)) => start + 1,
             Bound::Unbounded => 0,
         };
 
         let end = match range.end_bound() {
-            Bound::Included(&end) => end + 1,
-            Bound::Excluded(&end) => end,
+            Bound::Included(&ByteOffset(end)) => end + 1,
+            Bound::Excluded(&ByteOffset(end)) => end,
             Bound::Unbounded => self.inner.len(),
         };
 
         (start, end)
     }
-}
 
-impl TextBuffer for Peritext {
-    type Cursor<'cursor> = PeritextCursor<'cursor> where Self:'cursor;
-    type SpanItem = rich_text::Span;
-    type SpanIter<'spans> = rich_text::iter::Iter<'spans> where Self: 'spans;
+    fn is_valid_offset(&self, offset: usize) -> bool {
+        offset <= self.inner.len() && self.inner.is_codepoint_boundary(offset)
+    }
 
-    fn cursor(
-        &mut self,
-        cursor_range: CursorRange,
-    ) -> Result<Self::Cursor<'_>, TextBufferWithCursorError> {
-        let new_cursor = PeritextCursor {
-            buffer: self.inner.to_string().into(),
-            cursor_range,
+    fn is_valid_range(&self, start: usize, end: usize) -> bool {
+        start <= end && self.is_valid_offset(start) && self.is_valid_offset(end)
+    }
+
+    fn check_offset(&self, offset: usize) -> Result<(), ConversionError> {
+        if offset > self.inner.len() {
+            return Err(ConversionError::OutOfBounds(offset));
+        }
+        if !self.inner.is_codepoint_boundary(offset) {
+            return Err(ConversionError::NotACodepointBoundary(offset));
+        }
+
+        Ok(())
+    }
+
+    pub fn history(&self) -> &UndoHistory {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut UndoHistory {
+        &mut self.history
+    }
+
+    /// The id of the undo group of the last edit, which grows with every new
+    /// group.
+    pub fn max_undo_group_id(&self) -> usize {
+        self.engine.max_undo_group_id()
+    }
+
+    /// Keep at most `limit` undo groups. Older groups can't be undone
+    /// anymore, and the engine forgets them.
+    pub fn set_history_limit(&mut self, limit: Option<usize>) {
+        self.history.set_limit(limit);
+        self.collect_garbage();
+    }
+
+    /// Undo the edits of `group`, however many groups were made after it, and
+    /// return the change that made.
+    pub fn undo_group(&mut self, group: usize) -> Option<ChangeEvent> {
+        let undone = self.history.undo_group(group)?;
+        Some(self.set_undone(undone))
+    }
+
+    /// Redo the edits of `group`, undone by [`RopeBuffer::undo_group`] or by
+    /// undoing in order.
+    pub fn redo_group(&mut self, group: usize) -> Option<ChangeEvent> {
+        let undone = self.history.redo_group(group)?;
+        Some(self.set_undone(undone))
+    }
+
+    /// Replace `range` with `text`, telling the subscribers about it.
+    fn edit(&mut self, range: Range<usize>, text: &str) {
+        if range.is_empty() && text.is_empty() {
+            return;
+        }
+
+        let kind = match (range.is_empty(), text.is_empty()) {
+            (true, _) => EditKind::Typing,
+            (false, true) => EditKind::Deleting,
+            (false, false) => EditKind::Other,
         };
+        let group = self
+            .history
+            .group_for(kind, self.engine.max_undo_group_id() + 1);
+
+        let delta = RopeDelta::simple_edit(range, Rope::from(text), self.inner.len());
+        let change = ChangeEvent::from_delta(&delta, true);
+        let head = self.engine.get_head_rev_id().token();
+        self.engine.edit_rev(0, group, head, delta);
+        self.inner = self.engine.get_head().clone();
+        if !self.subscribers.is_empty() {
+            self.subscribers.emit(change);
+        }
 
-        let is_boundary = new_cursor.is_grapheme_boundary()?;
-        Ok(new_cursor)
+        self.collect_garbage();
     }
 
-    /**
-     * Our cursor
-     */
+    /// Leave exactly the groups of `undone` undone, and tell the subscribers
+    /// about the change that made.
+    fn set_undone(&mut self, undone: BTreeSet<usize>) -> ChangeEvent {
+        let base = self.engine.get_head_rev_id().token();
+        self.engine.undo(undone);
+        self.inner = self.engine.get_head().clone();
+
+        // The base is the head from just before, so it can't be missing.
+        let change = match self.engine.try_delta_rev_head(base) {
+            Ok(delta) => ChangeEvent::from_delta(&delta, true),
+            Err(_) => ChangeEvent {
+                ops: Vec::new(),
+                is_local: true,
+            },
+        };
+        if !self.subscribers.is_empty() {
+            self.subscribers.emit(change.clone());
+        }
+
+        change
+    }
 
-    fn cursor_coords(
-        &mut self,
-        cursor_range: CursorRange,
-    ) -> Result<CursorDocCoords, TextBufferWithCursorError> {
-        let buf = &self.inner.slice_str(0..cursor_range.head, IndexType::Utf8);
-
-        let mut row = buf.chars().filter(|&c| c == '\n').count();
-        let mut col: usize = 0;
-
-        fn find_last_newline_position(s: &str) -> Option<usize> {
-            for (i, c) in s.char_indices().rev() {
-                if c == '\n' {
-                    return Some(i);
-                }
-            }
-            None
+    /// Let the engine forget the groups dropped from the history.
+    fn collect_garbage(&mut self) {
+        let groups = self.history.take_collectable();
+        if !groups.is_empty() {
+            self.engine.gc(&groups);
         }
+    }
+}
 
-        let newline_idx = find_last_newline_position(&buf);
+impl From<Rope> for RopeBuffer {
+    fn from(inner: Rope) -> Self {
+        Self {
+            engine: Engine::new(inner.clone()),
+            inner,
+            history: UndoHistory::default(),
+            subscribers: Subscribers::default(),
+        }
+    }
+}
 
-        match newline_idx {
-            Some(newline_idx) => {
-                let g = UnicodeSegmentation::graphemes(&buf[newline_idx..cursor_range.head], true);
+impl TextBuffer for RopeBuffer {
+    type CharCursor<'cursor>
+        = RopeCharCursor<'cursor>
+    where
+        Self: 'cursor;
+    type GraphemeClusterCursor<'cursor>
+        = RopeGraphemeClusterCursor<'cursor>
+    where
+        Self: 'cursor;
+    type WordCursor<'cursor>
+        = CharWordCursor<RopeCharCursor<'cursor>>
+    where
+        Self: 'cursor;
+    type SentenceCursor<'cursor>
+        = CharSentenceCursor<RopeCharCursor<'cursor>>
+    where
+        Self: 'cursor;
+    type ParagraphCursor<'cursor>
+        = CharParagraphCursor<RopeCharCursor<'cursor>>
+    where
+        Self: 'cursor;
+    type LineCursor<'cursor>
+        = RopeLineCursor<'cursor>
+    where
+        Self: 'cursor;
+    type BlockCursor<'cursor>
+        = RopeBlockCursor<'cursor>
+    where
+        Self: 'cursor;
 
-                col = g.collect::<Vec<&str>>().len();
-            }
-            None => {}
+    type SpanItem = Span;
+    type SpanIter<'spans>
+        = std::iter::Empty<Span>
+    where
+        Self: 'spans;
+
+    fn from_str(s: &str) -> Self {
+        Self::from(Rope::from(s))
+    }
+
+    fn slice(&self, range: Range<ByteOffset>) -> Result<Cow<'_, str>, ConversionError> {
+        let Range {
+            start: ByteOffset(start),
+            end: ByteOffset(end),
+        } = range;
+        if !self.is_valid_range(start, end) {
+            return Err(ConversionError::SomeError);
         }
 
-        tracing::info!("{:?}", buf);
+        Ok(self.inner.slice_to_cow(start..end))
+    }
+
+    fn char_cursor(
+        &mut self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Self::CharCursor<'_>, CharCursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(CharCursorError::InvalidCharacter);
+        }
 
-        // // If the cursor's position exceeds the buffer, return an error.
-        // if cursor_range.head > buf.len() {
-        //     return Err(TextBufferWithCursorError::OutOfBounds); // Assuming you have such an error variant
-        // }
+        Ok(RopeCharCursor::new(self, offset))
+    }
 
-        // let mut line_start_byte_offset: usize = 0;
-        // let mut row: usize = 0;
-        // let mut col: usize = 0;
+    fn grapheme_cluster_cursor(
+        &mut self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Self::GraphemeClusterCursor<'_>, GraphemeClusterCursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(GraphemeClusterCursorError::InvalidCharacter);
+        }
 
-        // for (line_idx, line) in buf.lines().enumerate() {
-        //     let newline_char = '\n'.to_string();
-        //     tracing::info!(
-        //         "{:?} <  {:?} < {:?} ",
-        //         line_start_byte_offset,
-        //         cursor_range.head,
-        //         line_start_byte_offset + line.len() + line_idx * &newline_char.len()
-        //     );
+        Ok(RopeGraphemeClusterCursor::new(
+            self,
+            CursorRange::new(offset, offset),
+        ))
+    }
 
-        //     if line_start_byte_offset <= cursor_range.head
-        //         && cursor_range.head
-        //             <= line_start_byte_offset + line.len() + line_idx * &newline_char.len()
-        //     {
-        //         let g = UnicodeSegmentation::graphemes(
-        //             &buf[line_start_byte_offset..cursor_range.head],
-        //             true,
-        //         );
+    fn word_cursor(
+        &mut self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Self::WordCursor<'_>, WordCursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(WordCursorError::InvalidCharacter);
+        }
 
-        //         col = g.collect::<Vec<&str>>().len();
-        //     }
-        //     row = line_idx;
-        //     line_start_byte_offset += line.len();
-        // }
+        Ok(CharWordCursor::new(self, offset))
+    }
 
-        if buf.ends_with("\n") {
-            row += 1;
+    fn sentence_cursor(
+        &mut self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Self::SentenceCursor<'_>, SentenceCursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(SentenceCursorError::InvalidSentence);
         }
 
-        let coords = CursorDocCoords::new(row, col);
-        tracing::info!("coords: {:?}", coords);
+        Ok(CharSentenceCursor::new(self, offset))
+    }
+
+    fn paragraph_cursor(
+        &mut self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Self::ParagraphCursor<'_>, ParagraphCursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(ParagraphCursorError::InvalidParagraph);
+        }
 
-        Ok(coords)
+        Ok(CharParagraphCursor::new(self, offset))
     }
 
-    // pub fn iter(&self) -> impl Iterator<Item = Span> + '_ {
-    fn annotate<R>(&mut self, range: R, annotation: peritext::Style)
-    where
-        R: RangeBounds<usize>,
-    {
-        self.inner.annotate(range, annotation)
+    fn line_cursor(
+        &mut self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Self::LineCursor<'_>, LineCursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(LineCursorError::InvalidCharacter);
+        }
+
+        Ok(RopeLineCursor::new(self, offset))
     }
 
-    fn span_iter<'spans, 'buffer: 'spans>(&'buffer self) -> Self::SpanIter<'spans> {
-        rich_text::iter::Iter::new(&self.inner)
+    fn block_cursor(
+        &mut self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Self::BlockCursor<'_>, BlockCursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(BlockCursorError::InvalidCharacter);
+        }
+
+        Ok(RopeBlockCursor::new(self, offset))
     }
 
-    fn write(&mut self, offset: usize, s: &str) -> Result<usize, TextBufferWithCursorError> {
-        self.inner.insert(offset, s);
+    /// The row is the number of line feeds before `offset`, and the column the
+    /// number of grapheme clusters between the start of that line and `offset`.
+    fn cursor_coords(
+        &mut self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<(RowPosition, ColPosition), CursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(CharCursorError::InvalidCharacter.into());
+        }
+
+        let row = self.inner.line_of_offset(offset);
+        let line_start = self.inner.offset_of_line(row);
 
-        Ok(offset + s.len())
+        let mut col = 0;
+        let mut pos = line_start;
+        while pos < offset {
+            match self.inner.next_grapheme_offset(pos) {
+                Some(next) => pos = next,
+                None => break,
+            }
+            col += 1;
+        }
+
+        Ok((RowPosition::Row(row), ColPosition::Col(col)))
     }
 
-    fn drain<R>(&mut self, range: R) -> Result<Cow<str>, TextBufferWithCursorError>
-    where
-        R: RangeBounds<usize>,
-    {
-        let (start, end) = self.convert_range(range);
+    fn write(
+        &mut self,
+        ByteOffset(offset): ByteOffset,
+        s: &str,
+    ) -> Result<ByteOffset, CursorError> {
+        if !self.is_valid_offset(offset) {
+            return Err(CharCursorError::InvalidCharacter.into());
+        }
+
+        self.edit(offset..offset, s);
 
-        self.inner.delete(start..end);
+        Ok(ByteOffset(offset).after(s))
+    }
+
+    fn drain(&mut self, range: Range<ByteOffset>) -> Result<Cow<'_, str>, CursorError> {
+        let Range {
+            start: ByteOffset(start),
+            end: ByteOffset(end),
+        } = range;
+        if !self.is_valid_range(start, end) {
+            return Err(CharCursorError::InvalidCharacter.into());
+        }
 
-        let slice = self.slice(start..end);
+        let drained = self.inner.slice_to_cow(start..end).into_owned();
 
-        slice
+        self.edit(start..end, "");
+
+        Ok(Cow::Owned(drained))
     }
 
     fn replace_range<R>(
         &mut self,
         range: R,
         replace_with: &str,
-    ) -> Result<Range<usize>, TextBufferWithCursorError>
+    ) -> Result<Range<ByteOffset>, CursorError>
     where
-        R: RangeBounds<usize>,
+        R: RangeBounds<ByteOffset>,
     {
         let (start, end) = self.convert_range(range);
+        if !self.is_valid_range(start, end) {
+            return Err(CharCursorError::InvalidCharacter.into());
+        }
 
-        self.inner.delete(start..end);
-        self.inner.insert(start, replace_with);
+        self.edit(start..end, replace_with);
 
         Ok(Range {
-            start,
-            end: start + replace_with.len(),
+            start: ByteOffset(start),
+            end: ByteOffset(start).after(replace_with),
         })
     }
-    // fn edit(&mut self, range: Range<usize>, new: impl Into<String>) {
-    //     self.replace_range(range, &new.into());
-    // }
 
-    fn take(&self) -> Cow<str> {
-        self.inner.to_string().into()
+    fn span_iter<'spans, 'buffer: 'spans>(&'buffer self) -> Self::SpanIter<'spans> {
+        std::iter::empty()
+    }
+
+    fn slice_spans(&self, range: Range<ByteOffset>) -> Result<Vec<Span>, ConversionError> {
+        let text = self.slice(range)?;
+        if text.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Ok(vec![Span {
+            insert: text.into_owned(),
+            attributes: Default::default(),
+        }])
+    }
+
+    /// A plain rope carries no rich text attributes, so annotations are dropped.
+    fn annotate<R>(&mut self, _range: R, _annotation: peritext::Style)
+    where
+        R: RangeBounds<ByteOffset>,
+    {
+    }
+
+    fn set_attribute(&mut self, _range: Range<ByteOffset>, _type_: DefaultAtom, _value: Value) {}
+
+    fn annotations_at(
+        &self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Vec<Annotation>, ConversionError> {
+        self.check_offset(offset)?;
+
+        Ok(Vec::new())
     }
 
-    fn slice(&self, range: Range<usize>) -> Result<Cow<str>, TextBufferWithCursorError> {
-        let str = self.inner.slice_str(range, IndexType::Utf16);
+    fn annotations_in(&self, range: Range<ByteOffset>) -> Result<Vec<Annotation>, ConversionError> {
+        let Range {
+            start: ByteOffset(start),
+            end: ByteOffset(end),
+        } = range;
+        if !self.is_valid_range(start, end) {
+            return Err(ConversionError::SomeError);
+        }
+
+        Ok(Vec::new())
+    }
+
+    fn remove_annotation(&mut self, _id: peritext::OpID) -> bool {
+        false
+    }
+
+    fn subscribe(&mut self) -> Receiver<ChangeEvent> {
+        self.subscribers.subscribe()
+    }
+
+    fn undo(&mut self) -> Option<ChangeEvent> {
+        let undone = self.history.undo()?;
+        Some(self.set_undone(undone))
+    }
+
+    fn redo(&mut self) -> Option<ChangeEvent> {
+        let undone = self.history.redo()?;
+        Some(self.set_undone(undone))
+    }
 
-        Ok(str.into())
+    fn begin_undo_group(&mut self) {
+        self.history.begin_group();
+    }
+
+    fn end_undo_group(&mut self, keep: bool) {
+        self.history.end_group(keep);
+        self.collect_garbage();
+    }
+
+    fn take(&self) -> Cow<'_, str> {
+        self.inner.slice_to_cow(..)
     }
 
     fn len(&self) -> usize {
@@ -216,22 +500,306 @@ impl TextBuffer for Peritext {
         self.inner.is_empty()
     }
 
-    fn from_str(_s: &str) -> Self {
-        todo!()
+    fn byte_to_utf16(
+        &self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<Utf16Offset, ConversionError> {
+        self.check_offset(offset)?;
+
+        Ok(Utf16Offset(
+            self.inner.count::<Utf16CodeUnitsMetric>(offset),
+        ))
+    }
+
+    fn utf16_to_byte(
+        &self,
+        Utf16Offset(offset): Utf16Offset,
+    ) -> Result<ByteOffset, ConversionError> {
+        if offset > self.inner.measure::<Utf16CodeUnitsMetric>() {
+            return Err(ConversionError::OutOfBounds(offset));
+        }
+
+        Ok(ByteOffset(
+            self.inner.count_base_units::<Utf16CodeUnitsMetric>(offset),
+        ))
+    }
+
+    fn byte_to_char(&self, ByteOffset(offset): ByteOffset) -> Result<CharOffset, ConversionError> {
+        self.check_offset(offset)?;
+
+        let mut cursor = Cursor::new(&self.inner, 0);
+        let mut count = 0;
+        while cursor.pos() < offset {
+            cursor.next_codepoint();
+            count += 1;
+        }
+
+        Ok(CharOffset(count))
+    }
+
+    fn char_to_byte(&self, CharOffset(offset): CharOffset) -> Result<ByteOffset, ConversionError> {
+        let mut cursor = Cursor::new(&self.inner, 0);
+        for _ in 0..offset {
+            if cursor.next_codepoint().is_none() {
+                return Err(ConversionError::OutOfBounds(offset));
+            }
+        }
+
+        Ok(ByteOffset(cursor.pos()))
+    }
+
+    fn byte_to_grapheme(
+        &self,
+        ByteOffset(offset): ByteOffset,
+    ) -> Result<GraphemeIndex, ConversionError> {
+        self.check_offset(offset)?;
+
+        let mut pos = 0;
+        let mut index = 0;
+        while let Some(next) = self.inner.next_grapheme_offset(pos) {
+            if next > offset {
+                break;
+            }
+            pos = next;
+            index += 1;
+        }
+
+        Ok(GraphemeIndex(index))
+    }
+
+    fn grapheme_to_byte(
+        &self,
+        GraphemeIndex(index): GraphemeIndex,
+    ) -> Result<ByteOffset, ConversionError> {
+        let mut pos = 0;
+        for _ in 0..index {
+            pos = self
+                .inner
+                .next_grapheme_offset(pos)
+                .ok_or(ConversionError::OutOfBounds(index))?;
+        }
+
+        Ok(ByteOffset(pos))
+    }
+}
+
+impl Searchable for RopeBuffer {
+    fn search_rope(&self) -> Cow<'_, Rope> {
+        Cow::Borrowed(&self.inner)
     }
 }
 
 #[cfg(test)]
 mod tests {
-
     use super::*;
 
+    const TEXT: &str = "Hello \nworld\n\n";
+    const COMPLEX_EMOJI: &str = "👨‍👩‍👧‍👦";
+
+    #[test]
+    fn edits_are_reported_to_subscribers() -> Result<(), CursorError> {
+        use crate::change::ChangeOp;
+
+        let mut buf = RopeBuffer::from_str("hello world");
+        let events = buf.subscribe();
+
+        buf.write(ByteOffset(5), ",")?;
+        buf.drain(ByteOffset(0)..ByteOffset(1))?;
+        buf.replace_range(ByteOffset(6)..ByteOffset(11), "earth")?;
+        assert_eq!(buf.take(), "ello, earth");
+
+        let events: Vec<_> = events.try_iter().collect();
+        assert!(events.iter().all(|event| event.is_local));
+        assert_eq!(
+            events
+                .iter()
+                .map(|event| event.ops.clone())
+                .collect::<Vec<_>>(),
+            [
+                vec![ChangeOp::retain(5), ChangeOp::insert(",")],
+                vec![ChangeOp::delete(1)],
+                vec![
+                    ChangeOp::retain(6),
+                    ChangeOp::insert("earth"),
+                    ChangeOp::delete(5)
+                ],
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn undo_and_redo_in_order() -> Result<(), CursorError> {
+        let mut buf = RopeBuffer::from_str("Roe v. Wade");
+
+        buf.write(ByteOffset(11), ",")?;
+        buf.write(ByteOffset(12), " 410")?;
+        buf.history_mut().seal();
+        buf.drain(ByteOffset(0)..ByteOffset(4))?;
+        assert_eq!(buf.take(), "v. Wade, 410");
+
+        assert!(buf.undo().is_some());
+        assert_eq!(buf.take(), "Roe v. Wade, 410");
+        assert!(buf.undo().is_some());
+        assert_eq!(buf.take(), "Roe v. Wade");
+        assert!(buf.undo().is_none());
+
+        let redone = buf.redo().expect("an undone group");
+        assert_eq!(redone.changed_range(), Some(11..16));
+        assert_eq!(buf.take(), "Roe v. Wade, 410");
+
+        // Editing drops what could still be redone.
+        buf.write(ByteOffset(0), "See ")?;
+        assert!(buf.redo().is_none());
+        assert!(buf.undo().is_some());
+        assert_eq!(buf.take(), "Roe v. Wade, 410");
+
+        Ok(())
+    }
+
+    #[test]
+    fn undo_any_group() -> Result<(), CursorError> {
+        let mut buf = RopeBuffer::from_str("Roe v. Wade");
+        buf.history_mut().group_interval = std::time::Duration::ZERO;
+
+        buf.write(ByteOffset(0), "See ")?;
+        let see = buf.max_undo_group_id();
+        buf.write(ByteOffset(15), ", 410 U.S. 113")?;
+        assert_eq!(buf.take(), "See Roe v. Wade, 410 U.S. 113");
+
+        assert!(buf.undo_group(see).is_some());
+        assert_eq!(buf.take(), "Roe v. Wade, 410 U.S. 113");
+        assert!(buf.undo_group(see).is_none());
+
+        // The later group is still undone in order, then redone.
+        assert!(buf.undo().is_some());
+        assert_eq!(buf.take(), "Roe v. Wade");
+        assert!(buf.redo().is_some());
+        assert!(buf.redo_group(see).is_some());
+        assert_eq!(buf.take(), "See Roe v. Wade, 410 U.S. 113");
+
+        Ok(())
+    }
+
+    #[test]
+    fn history_limit_forgets_old_groups() -> Result<(), CursorError> {
+        let mut buf = RopeBuffer::from_str("a");
+        buf.history_mut().group_interval = std::time::Duration::ZERO;
+        buf.set_history_limit(Some(2));
+
+        for (offset, s) in [(1, "b"), (2, "c"), (3, "d")] {
+            buf.write(ByteOffset(offset), s)?;
+        }
+        assert_eq!(buf.history().groups().len(), 2);
+
+        assert!(buf.undo().is_some());
+        assert!(buf.undo().is_some());
+        assert!(buf.undo().is_none());
+        assert_eq!(buf.take(), "ab");
+
+        // Groups undone and then dropped stay undone.
+        buf.write(ByteOffset(0), "_")?;
+        assert_eq!(buf.take(), "_ab");
+        assert!(buf.undo().is_some());
+        assert_eq!(buf.take(), "ab");
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_and_drain() -> Result<(), CursorError> {
+        let mut buf = RopeBuffer::from_str("hello world");
+
+        let end = buf.write(ByteOffset(5), ",")?;
+        assert_eq!(end, ByteOffset(6));
+        assert_eq!(buf.take(), "hello, world");
+
+        let drained = buf.drain(ByteOffset(5)..ByteOffset(6))?.into_owned();
+        assert_eq!(drained, ",");
+        assert_eq!(buf.take(), "hello world");
+
+        Ok(())
+    }
+
+    #[test]
+    fn replace() -> Result<(), CursorError> {
+        let mut buf = RopeBuffer::from_str("hello world");
+
+        let range = buf.replace_range(ByteOffset(1)..ByteOffset(9), "era")?;
+
+        assert_eq!(range, ByteOffset(1)..ByteOffset(4));
+        assert_eq!(buf.take(), "herald");
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_offsets_inside_a_codepoint() {
+        let mut buf = RopeBuffer::from_str("é");
+
+        assert!(buf.write(ByteOffset(1), "x").is_err());
+        assert!(buf.slice(ByteOffset(0)..ByteOffset(1)).is_err());
+        assert!(buf.char_cursor(ByteOffset(3)).is_err());
+    }
+
+    #[test]
+    fn cursor_coords() -> Result<(), CursorError> {
+        let mut buf = RopeBuffer::from_str(TEXT);
+
+        assert_eq!(
+            buf.cursor_coords(ByteOffset(0))?,
+            (RowPosition::Row(0), ColPosition::Col(0))
+        );
+        assert_eq!(
+            buf.cursor_coords(ByteOffset(6))?,
+            (RowPosition::Row(0), ColPosition::Col(6))
+        );
+        assert_eq!(
+            buf.cursor_coords(ByteOffset(9))?,
+            (RowPosition::Row(1), ColPosition::Col(2))
+        );
+        assert_eq!(
+            buf.cursor_coords(ByteOffset(14))?,
+            (RowPosition::Row(3), ColPosition::Col(0))
+        );
+        assert_eq!(buf.line_count(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn converts_between_units() -> Result<(), ConversionError> {
+        // 'é' is 2 bytes and 1 utf16 unit, '😀' 4 bytes and 2 utf16 units.
+        let buf = RopeBuffer::from_str(&format!("aé😀{COMPLEX_EMOJI}b"));
+        let before_b = ByteOffset(7 + COMPLEX_EMOJI.len());
+
+        assert_eq!(buf.byte_to_utf16(ByteOffset(7))?, Utf16Offset(4));
+        assert_eq!(buf.utf16_to_byte(Utf16Offset(4))?, ByteOffset(7));
+        assert_eq!(buf.byte_to_char(ByteOffset(3))?, CharOffset(2));
+        assert_eq!(buf.char_to_byte(CharOffset(3))?, ByteOffset(7));
+        assert_eq!(buf.byte_to_grapheme(before_b)?, GraphemeIndex(4));
+        assert_eq!(buf.grapheme_to_byte(GraphemeIndex(4))?, before_b);
+
+        // Offsets inside a cluster round down to the cluster.
+        assert_eq!(buf.byte_to_grapheme(ByteOffset(11))?, GraphemeIndex(3));
+
+        assert!(buf.byte_to_char(ByteOffset(2)).is_err());
+        assert!(buf.char_to_byte(CharOffset(100)).is_err());
+
+        Ok(())
+    }
+
     #[test]
-    fn replace() {
-        let mut buf = String::from("hello world");
+    fn cursor_coords_counts_graphemes() -> Result<(), CursorError> {
+        let mut buf = RopeBuffer::from_str(&format!("a{COMPLEX_EMOJI}b"));
+        let offset = 1 + COMPLEX_EMOJI.len();
 
-        buf.replace_range(1..9, "era");
+        assert_eq!(
+            buf.cursor_coords(ByteOffset(offset))?,
+            (RowPosition::Row(0), ColPosition::Col(2))
+        );
 
-        assert_eq!("herald", buf);
+        Ok(())
     }
 }
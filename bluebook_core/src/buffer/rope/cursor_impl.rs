@@ -1,243 +1,260 @@
-use std::borrow::Cow;
-
-// use unicode_segmentation::Graphemes;
-
-// use super::grapheme::Graphemes;
-use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete, UnicodeSegmentation};
-
-use crate::text_buffer_cursor::TextBufferCursor;
-
-use crate::movement::Direction;
-
-use crate::graphemes::UnicodeSegmentationError;
-
-/// A single selection range.
-///
-/// A range consists of an "anchor" and "head" position in
-/// the text.  The head is the part that the user moves when
-/// directly extending a selection.  The head and anchor
-/// can be in any order, or even share the same position.
-///
-/// The anchor and head positions use gap indexing, meaning
-/// that their indices represent the gaps *between* `char`s
-/// rather than the `char`s themselves. For example, 1
-/// represents the position between the first and second `char`.
-///
-/// Below are some examples of `Range` configurations.
-/// The anchor and head indices are shown as "(anchor, head)"
-/// tuples, followed by example text with "[" and "]" symbols
-/// representing the anchor and head positions:
-///
-/// - (0, 3): `[Som]e text`.
-/// - (3, 0): `]Som[e text`.
-/// - (2, 7): `So[me te]xt`.
-/// - (1, 1): `S[]ome text`.
-///
-/// Ranges are considered to be inclusive on the left and
-/// exclusive on the right, regardless of anchor-head ordering.
-/// This means, for example, that non-zero-width ranges that
-/// are directly adjacent, sharing an edge, do not overlap.
-/// However, a zero-width range will overlap with the shared
-/// left-edge of another range.
-///
-/// By convention, user-facing ranges are considered to have
-/// a block cursor on the head-side of the range that spans a
-/// single grapheme inward from the range's edge.  There are a
-/// variety of helper methods on `Range` for working in terms of
-/// that block cursor, all of which have `cursor` in their name.
-///
-///
-
-enum CursorEnd {
-    Head,
-    Tail,
+use xi_rope::{Cursor, LinesMetric, Rope, RopeInfo};
+
+use super::buffer_impl::RopeBuffer;
+use crate::{
+    block::{Block, BlockCursor},
+    char::CharCursor,
+    line::LineCursor,
+};
+
+/// Walks the rope one codepoint at a time, using xi_rope's `BaseMetric`
+/// cursor so that no part of the rope is ever copied.
+pub struct RopeCharCursor<'a> {
+    inner: Cursor<'a, RopeInfo>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Eq)]
-pub struct PeritextCursor<'a> {
-    pub buffer: Cow<'a, str>,
-    pub cursor_range: CursorRange,
-}
-
-// #[derive(Debug)]
-// pub struct StringCursor<'a> {
-//     text: &'a mut String,
-//     position: usize,
-// }
+impl<'a> CharCursor<'a> for RopeCharCursor<'a> {
+    type Buffer = RopeBuffer;
 
-impl<'a> PeritextCursor<'a> {
-    /// Create a new cursor.
-    pub fn new(buffer: Cow<'a, str>) -> Self {
+    fn new(text: &'a Self::Buffer, pos: usize) -> Self {
         Self {
-            buffer,
-            cursor_range: CursorRange::new(0, 0),
+            inner: Cursor::new(text.rope(), pos),
         }
     }
-}
 
-impl<'cursor> TextBufferCursor<'cursor> for PeritextCursor<'cursor> {
-    fn anchor(&self) -> usize {
-        self.cursor_range.anchor
-    }
-    fn head(&self) -> usize {
-        self.cursor_range.head
-    }
-    fn set_anchor(mut self, byte_offset: usize) -> Self {
-        self.cursor_range.anchor = byte_offset;
-        self
-    }
-    fn set_head(mut self, byte_offset: usize) -> Self {
-        self.cursor_range.head = byte_offset;
-        self
+    fn pos(&self) -> usize {
+        self.inner.pos()
     }
 
-    fn set_point(mut self, byte_offset: usize) -> Self {
-        self.cursor_range.head = byte_offset;
-        self.cursor_range.anchor = byte_offset;
-
-        self
+    fn set(&mut self, pos: usize) {
+        self.inner.set(pos)
     }
 
-    fn range(&self) -> CursorRange {
-        self.cursor_range
+    fn prev_codepoint(&mut self) -> Option<char> {
+        // xi_rope invalidates the cursor when it walks off either end of the
+        // rope, so put it back where it was.
+        let pos = self.inner.pos();
+        let ch = self.inner.prev_codepoint();
+        if ch.is_none() {
+            self.inner.set(pos);
+        }
+        ch
     }
 
-    fn is_grapheme_boundary(&self) -> Result<bool, UnicodeSegmentationError> {
-        // let graphemes = Graphemes::new(&self.buffer, false);
-
-        let s = self.buffer.as_ref();
-
-        let mut gc = GraphemeCursor::new(self.head(), s.len(), false);
+    fn next_codepoint(&mut self) -> Option<char> {
+        let pos = self.inner.pos();
+        let ch = self.inner.next_codepoint();
+        if ch.is_none() {
+            self.inner.set(pos);
+        }
+        ch
+    }
 
-        gc.is_boundary(s, 0)
-            .map_err(UnicodeSegmentationError::GraphemeIncompleteError)
+    fn peek_next_codepoint(&self) -> Option<char> {
+        self.inner.peek_next_codepoint()
     }
+}
 
-    fn next_grapheme_boundary(&self) -> Result<Option<usize>, UnicodeSegmentationError> {
-        let s = self.buffer.as_ref();
+/// Jumps between line starts using the rope's `LinesMetric`.
+pub struct RopeLineCursor<'a> {
+    rope: &'a Rope,
+    pos: usize,
+}
 
-        let mut gc = GraphemeCursor::new(self.head(), s.len(), false);
+impl<'a> LineCursor<'a> for RopeLineCursor<'a> {
+    type Buffer = RopeBuffer;
 
-        gc.next_boundary(s, 0)
-            .map_err(UnicodeSegmentationError::GraphemeIncompleteError)
+    fn new(text: &'a Self::Buffer, pos: usize) -> Self {
+        Self {
+            rope: text.rope(),
+            pos,
+        }
     }
 
-    fn prev_grapheme_boundary(&self) -> Result<Option<usize>, UnicodeSegmentationError> {
-        let s = self.buffer.as_ref();
-
-        let mut gc = GraphemeCursor::new(self.head(), s.len(), false);
-
-        gc.prev_boundary(s, 0)
-            .map_err(UnicodeSegmentationError::GraphemeIncompleteError)
+    fn prev_boundary(&mut self) -> Option<usize> {
+        let mut cursor = Cursor::new(self.rope, self.pos);
+        let line_start = match cursor.prev::<LinesMetric>() {
+            Some(offset) => offset,
+            // There is no line feed before the cursor: we're on the first line.
+            None if self.pos > 0 => 0,
+            None => return None,
+        };
+
+        self.pos = line_start;
+        Some(line_start)
     }
 
-    fn nth_next_grapheme_boundary(
-        &self,
-        n: usize,
-    ) -> Result<Option<usize>, UnicodeSegmentationError> {
-        let s = self.buffer.as_ref();
+    fn next_boundary(&mut self) -> Option<usize> {
+        let mut cursor = Cursor::new(self.rope, self.pos);
+        let line_start = cursor.next::<LinesMetric>()?;
 
-        let mut gc = GraphemeCursor::new(self.head(), s.len(), false);
+        self.pos = line_start;
+        Some(line_start)
+    }
+}
 
-        for _ in 0..n {
-            let next_byte_offset = gc
-                .next_boundary(s, 0)
-                .map_err(UnicodeSegmentationError::GraphemeIncompleteError)?;
+/// Blocks of a plain rope: every line is a block, and none carries attributes.
+pub struct RopeBlockCursor<'a> {
+    rope: &'a Rope,
+    pos: usize,
+}
 
-            match next_byte_offset {
-                Some(next_byte_offset) => {
-                    gc.set_cursor(next_byte_offset);
+impl<'a> BlockCursor<'a> for RopeBlockCursor<'a> {
+    type Buffer = RopeBuffer;
 
-                    continue;
-                }
-                None => {
-                    return Ok(None);
-                }
-            }
+    fn new(text: &'a Self::Buffer, pos: usize) -> Self {
+        Self {
+            rope: text.rope(),
+            pos,
         }
+    }
 
-        Ok(Some(gc.cur_cursor()))
-    }
-    fn nth_prev_grapheme_boundary(
-        &self,
-        n: usize,
-    ) -> Result<Option<usize>, UnicodeSegmentationError> {
-        let s = self.buffer.as_ref();
-
-        let mut gc = GraphemeCursor::new(self.head(), s.len(), false);
-
-        for _ in 0..n {
-            let prev_byte_offset = gc
-                .prev_boundary(s, 0)
-                .map_err(UnicodeSegmentationError::GraphemeIncompleteError)?;
-
-            match prev_byte_offset {
-                Some(prev_byte_offset) => {
-                    gc.set_cursor(prev_byte_offset);
-                    continue;
-                }
-                None => {
-                    return Ok(None);
-                }
-            }
-        }
+    fn pos(&self) -> usize {
+        self.pos
+    }
 
-        Ok(Some(gc.cur_cursor()))
+    fn set(&mut self, pos: usize) {
+        self.pos = pos.min(self.rope.len());
     }
-}
 
-pub fn len_utf8_from_first_byte(b: u8) -> usize {
-    match b {
-        b if b < 0x80 => 1,
-        b if b < 0xe0 => 2,
-        b if b < 0xf0 => 3,
-        _ => 4,
+    fn block_at(&self, pos: usize) -> Block {
+        let line = self.rope.line_of_offset(pos.min(self.rope.len()));
+        let range = self.rope.offset_of_line(line)..self.rope.offset_of_line(line + 1);
+
+        Block::new(range, [])
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        buffer::peritext_buffer::buffer_impl::Peritext, error::TextBufferWithCursorError,
-        text_buffer::TextBuffer, text_buffer_cursor::CursorDocCoords,
+        mode::Mode,
+        offset::ByteOffset,
+        paragraph::ParagraphCursor,
+        sentence::{Abbreviations, SentenceCursor},
+        text_buffer::TextBuffer,
+        word::WordCursor,
     };
 
     use super::*;
 
-    const TEXT: &str = &"Hello \nworld\n\n";
-    const EMOJI: &str = "😀👋🌍";
-    const COMPLEX_EMOJI: &str = "👨‍👩‍👧‍👦";
-
-    fn peritext_buffer(s: &str) -> Peritext {
-        let mut buffer = Peritext::new(1);
-        let _ = buffer.write(0, s);
+    #[test]
+    fn char_cursor_stays_valid_at_the_edges() {
+        let buf = RopeBuffer::from_str("aé");
+        let mut cursor = RopeCharCursor::new(&buf, 0);
+
+        assert_eq!(cursor.prev_codepoint(), None);
+        assert_eq!(cursor.next_codepoint(), Some('a'));
+        assert_eq!(cursor.next_codepoint(), Some('é'));
+        assert_eq!(cursor.pos(), 3);
+        assert_eq!(cursor.next_codepoint(), None);
+        assert_eq!(cursor.prev_codepoint(), Some('é'));
+        assert_eq!(cursor.pos(), 1);
+    }
 
-        buffer
+    #[test]
+    fn line_cursor() {
+        let buf = RopeBuffer::from_str("one\ntwo\nthree");
+        let mut cursor = RopeLineCursor::new(&buf, 5);
+
+        assert_eq!(cursor.next_boundary(), Some(8));
+        assert_eq!(cursor.next_boundary(), None);
+        assert_eq!(cursor.prev_boundary(), Some(4));
+        assert_eq!(cursor.prev_boundary(), Some(0));
+        assert_eq!(cursor.prev_boundary(), None);
     }
 
     #[test]
-    fn test_cursor_coords_single_line() -> Result<(), TextBufferWithCursorError> {
-        let mut buf = peritext_buffer(TEXT);
+    fn word_cursor() -> Result<(), crate::text_buffer::CursorError> {
+        let mut buf = RopeBuffer::from_str("hello brave world");
 
-        let c = CursorRange { anchor: 7, head: 7 };
-        let doc_coords = buf.cursor_coords(c)?;
+        let mut cursor = buf.word_cursor(ByteOffset(0))?;
+        assert_eq!(cursor.next_boundary(), Some(6));
+        assert_eq!(cursor.next_boundary(), Some(12));
+        assert_eq!(cursor.prev_boundary(Mode::Normal), Some(6));
 
-        assert_eq!(doc_coords, CursorDocCoords::new(0, 7));
+        let mut cursor = buf.word_cursor(ByteOffset(8))?;
+        assert_eq!(cursor.select_word(), (6, 11));
 
         Ok(())
     }
 
     #[test]
-    fn test_cursor_coords_multiple_lines_middle() {}
+    fn paragraph_cursor() -> Result<(), crate::text_buffer::CursorError> {
+        let mut buf = RopeBuffer::from_str("first\n\nsecond\nline\n\nthird");
+
+        let mut cursor = buf.paragraph_cursor(ByteOffset(0))?;
+        assert_eq!(cursor.next_boundary(), Some(6));
+
+        let mut cursor = buf.paragraph_cursor(ByteOffset(22))?;
+        assert_eq!(cursor.prev_boundary(), Some(19));
+        assert_eq!(cursor.prev_boundary(), Some(6));
+        assert_eq!(cursor.prev_boundary(), Some(0));
+        assert_eq!(cursor.prev_boundary(), None);
+
+        Ok(())
+    }
 
     #[test]
-    fn test_cursor_coords_multiple_lines_end() {}
+    fn paragraph_cursor_crlf() -> Result<(), crate::text_buffer::CursorError> {
+        let mut buf = RopeBuffer::from_str("first\r\n\r\nsecond\r\nline\r\n\r\nthird");
+
+        let mut cursor = buf.paragraph_cursor(ByteOffset(0))?;
+        assert_eq!(cursor.next_boundary(), Some(7));
+        assert_eq!(cursor.next_boundary(), Some(23));
+        assert_eq!(cursor.next_boundary(), Some(30));
+        assert_eq!(cursor.next_boundary(), None);
+        assert_eq!(cursor.prev_boundary(), Some(23));
+        assert_eq!(cursor.prev_boundary(), Some(7));
+        assert_eq!(cursor.prev_boundary(), Some(0));
+
+        Ok(())
+    }
 
     #[test]
-    fn test_cursor_coords_at_start() {}
+    fn sentence_cursor() -> Result<(), crate::text_buffer::CursorError> {
+        let mut buf = RopeBuffer::from_str("See Roe v. Wade. Id. at 153. The end.\nNew para.");
+
+        let mut cursor = buf.sentence_cursor(ByteOffset(0))?;
+        assert_eq!(cursor.next_boundary(), Some(17));
+        assert_eq!(cursor.next_boundary(), Some(29));
+        assert_eq!(cursor.next_boundary(), Some(38));
+        assert_eq!(cursor.next_boundary(), Some(47));
+        assert_eq!(cursor.next_boundary(), None);
+        assert_eq!(cursor.prev_boundary(), Some(38));
+        assert_eq!(cursor.prev_boundary(), Some(29));
+
+        let mut cursor = buf.sentence_cursor(ByteOffset(20))?;
+        assert_eq!(cursor.prev_boundary(), Some(17));
+        assert_eq!(cursor.prev_boundary(), Some(0));
+        assert_eq!(cursor.prev_boundary(), None);
+
+        let mut cursor = buf.sentence_cursor(ByteOffset(0))?;
+        cursor.set_abbreviations(&Abbreviations::none());
+        assert_eq!(cursor.next_boundary(), Some(11));
+
+        Ok(())
+    }
 
     #[test]
-    fn test_cursor_coords_past_end() {}
+    fn block_cursor() -> Result<(), crate::text_buffer::CursorError> {
+        let mut buf = RopeBuffer::from_str("one\n\ntwo\n");
+
+        let mut cursor = buf.block_cursor(ByteOffset(1))?;
+        assert_eq!(cursor.block().range, 0..4);
+        assert_eq!(cursor.next_boundary(), Some(4));
+        assert_eq!(cursor.next_boundary(), Some(5));
+        assert_eq!(cursor.next_boundary(), Some(9));
+        assert_eq!(cursor.block().range, 9..9);
+        assert_eq!(cursor.next_boundary(), None);
+        assert_eq!(cursor.prev_boundary(), Some(5));
+        assert_eq!(cursor.prev_header(None), None);
+        assert_eq!(cursor.pos(), 5);
+
+        let mut cursor = buf.block_cursor(ByteOffset(2))?;
+        assert_eq!(cursor.prev_boundary(), Some(0));
+        assert_eq!(cursor.prev_boundary(), None);
 
-    // Add more test cases as needed
+        Ok(())
+    }
 }
@@ -1,165 +1,149 @@
-//! This example shows how to implement a grapeheme iterator over the contents
-//! of a `Rope` or `RopeSlice`.  This also serves as a good starting point for
-//! iterators for other kinds of segementation, such as word boundaries.
-
-#![allow(clippy::redundant_field_names)]
-#![allow(dead_code)]
-
-// use std::str::pattern::Pattern;
-use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
+use xi_rope::Rope;
+
+use super::buffer_impl::RopeBuffer;
+use crate::{
+    cursor::CursorRange,
+    graphemes::{GraphemeClusterCursor, GraphemeClusterCursorError},
+};
+
+/// Grapheme cluster navigation over a rope. xi_rope's grapheme helpers already
+/// feed the segmenter context across leaf boundaries, so clusters spanning
+/// several chunks are handled without flattening the rope.
+pub struct RopeGraphemeClusterCursor<'a> {
+    rope: &'a Rope,
+    range: CursorRange,
+}
 
-#[derive(Clone, Debug)]
-pub struct Graphemes<'a> {
-    slice: &'a str,
-    gc: GraphemeCursor,
+impl<'a> RopeGraphemeClusterCursor<'a> {
+    pub fn range(&self) -> CursorRange {
+        self.range
+    }
 }
 
-impl<'a> Graphemes<'a> {
-    pub fn new(s: &str, is_extended: bool) -> Graphemes<'_> {
-        let len = s.len();
-        Graphemes {
-            slice: s,
-            gc: GraphemeCursor::new(0, len, is_extended),
+impl<'a> GraphemeClusterCursor<'a> for RopeGraphemeClusterCursor<'a> {
+    type Buffer = RopeBuffer;
+
+    fn new(text: &'a Self::Buffer, range: CursorRange) -> Self {
+        Self {
+            rope: text.rope(),
+            range,
         }
     }
-    pub fn set_cursor_offet(mut self, byte_offset: usize) -> Self {
-        self.gc.set_cursor(byte_offset);
-        self
-    }
-    pub fn is_grapheme_boundary(mut self, byte_offset: usize) -> bool {
-        loop {
-            match self.gc.is_boundary(self.slice, byte_offset) {
-                Ok(n) => return n,
-                Err(GraphemeIncomplete::PreContext(_n)) => {
-                    // let (ctx_chunk, ctx_byte_start, _, _) = self.slice.chunk_at_byte(n - 1);
-                    // self.gc.provide_context(ctx_chunk, ctx_byte_start);
-                }
-                Err(_) => unreachable!(),
-            }
-        }
+
+    fn prev_grapheme_cluster_boundary(&self) -> Result<Option<usize>, GraphemeClusterCursorError> {
+        Ok(self.rope.prev_grapheme_offset(self.range.head))
     }
-}
 
-pub struct GraphemeIterItem {
-    pub byte_offset: usize,
-}
-impl GraphemeIterItem {
-    fn new(byte_offset: usize) -> Self {
-        Self { byte_offset }
+    fn next_grapheme_cluster_boundary(&self) -> Result<Option<usize>, GraphemeClusterCursorError> {
+        Ok(self.rope.next_grapheme_offset(self.range.head))
     }
-}
-impl<'a> Iterator for Graphemes<'a> {
-    type Item = GraphemeIterItem;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let next_idx = self.gc.next_boundary(self.slice, 0).unwrap();
+    fn nth_next_grapheme_cluster_boundary(
+        &self,
+        n: usize,
+    ) -> Result<Option<usize>, GraphemeClusterCursorError> {
+        let mut offset = self.range.head;
+
+        for _ in 0..n {
+            match self.rope.next_grapheme_offset(offset) {
+                Some(next_offset) => offset = next_offset,
+                None => return Ok(None),
+            }
+        }
 
-        next_idx.map(GraphemeIterItem::new)
+        Ok(Some(offset))
     }
-}
 
-impl<'a> DoubleEndedIterator for Graphemes<'a> {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        let prev_idx = self.gc.prev_boundary(self.slice, 0).unwrap();
+    fn nth_prev_grapheme_cluster_boundary(
+        &self,
+        n: usize,
+    ) -> Result<Option<usize>, GraphemeClusterCursorError> {
+        let mut offset = self.range.head;
+
+        for _ in 0..n {
+            match self.rope.prev_grapheme_offset(offset) {
+                Some(prev_offset) => offset = prev_offset,
+                None => return Ok(None),
+            }
+        }
 
-        prev_idx.map(GraphemeIterItem::new)
+        Ok(Some(offset))
     }
-}
 
-pub fn nth_next_grapheme_boundary(
-    slice: &str,
-    byte_offset: usize,
-    n: usize,
-) -> Option<GraphemeIterItem> {
-    let mut graphemes = Graphemes::new(slice, false).set_cursor_offet(byte_offset);
+    fn is_grapheme_boundary(&self) -> Result<bool, GraphemeClusterCursorError> {
+        let head = self.range.head;
 
-    graphemes.nth(n)
-}
+        if head == 0 || head == self.rope.len() {
+            return Ok(true);
+        }
+        if !self.rope.is_codepoint_boundary(head) {
+            return Ok(false);
+        }
 
-pub fn nth_prev_grapheme_boundary(
-    slice: &str,
-    byte_offset: usize,
-    n: usize,
-) -> Option<GraphemeIterItem> {
-    let mut graphemes = Graphemes::new(slice, false).set_cursor_offet(byte_offset);
-    graphemes.nth_back(n)
+        let is_boundary = self
+            .rope
+            .prev_grapheme_offset(head)
+            .and_then(|prev| self.rope.next_grapheme_offset(prev))
+            == Some(head);
+
+        Ok(is_boundary)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::text_buffer::TextBuffer;
+
     use super::*;
 
-    const TEXT: &str = "\r\n\
-    Hello there!  How're you doing?  It's a fine day, \
-    isn't it?  Aren't you glad we're alive?\r\n\
-    こんにちは！元気ですか？日はいいですね。\
-    私たちが生きだって嬉しいではないか？\r\n\
-    Hello there!  How're you doing?  It's a fine day, \
-    isn't it?  Aren't you glad we're alive?\r\n\
-    こんにちは！元気ですか？日はいいですね。\
-    私たちが生きだって嬉しいではないか？\r\n\
-    Hello there!  How're you doing?  It's a fine day, \
-    isn't it?  Aren't you glad we're alive?\r\n\
-    こんにちは！元気ですか？日はいいですね。\
-    私たちが生きだって嬉しいではないか？\r\n\
-    Hello there!  How're you doing?  It's a fine day, \
-    isn't it?  Aren't you glad we're alive?\r\n\
-    こんにちは！元気ですか？日はいいですね。\
-    私たちが生きだって嬉しいではないか？\r\n\
-    Hello there!  How're you doing?  It's a fine day, \
-    isn't it?  Aren't you glad we're alive?\r\n\
-    こんにちは！元気ですか？日はいいですね。\
-    私たちが生きだって嬉しいではないか？\r\n\
-    Hello there!  How're you doing?  It's a fine day, \
-    isn't it?  Aren't you glad we're alive?\r\n\
-    こんにちは！元気ですか？日はいいですね。\
-    私たちが生きだって嬉しいではないか？\r\n\
-    Hello there!  How're you doing?  It's a fine day, \
-    isn't it?  Aren't you glad we're alive?\r\n\
-    こんにちは！元気ですか？日はいいですね。\
-    私たちが生きだって嬉しいではないか？\r\n\
-    Hello there!  How're you doing?  It's a fine day, \
-    isn't it?  Aren't you glad we're alive?\r\n\
-    こんにちは！元気ですか？日はいいですね。\
-    私たちが生きだって嬉しいではないか？\r\n\
-    Hello there!  How're you doing?  It's a fine day, \
-    isn't it?  Aren't you glad we're alive?\r\n\
-    こんにちは！元気ですか？日はいいですね。\
-    私たちが生きだって嬉しいではないか？\r\n\
-    Hello there!  How're you doing?  It's a fine day, \
-    isn't it?  Aren't you glad we're alive?\r\n\
-    こんにちは！元気ですか？日はいいですね。\
-    私たちが生きだって嬉しいではないか？\r\n\
-    Hello there!  How're you doing?  It's a fine day, \
-    isn't it?  Aren't you glad we're alive?\r\n\
-    こんにちは！元気ですか？日はいいですね。\
-    私たちが生きだって嬉しいではないか？\r\n\
-    Hello there!  How're you doing?  It's a fine day, \
-    isn't it?  Aren't you glad we're alive?\r\n\
-    こんにちは！元気ですか？日はいいですね。\
-    私たちが生きだって嬉しいではないか？\r\n\
-    Hello there!  How're you doing?  It's a fine day, \
-    isn't it?  Aren't you glad we're alive?\r\n\
-    こんにちは！元気ですか？日はいいですね。\
-    私たちが生きだって嬉しいではないか？\r\n\
-    Hello there!  How're you doing?  It's a fine day, \
-    isn't it?  Aren't you glad we're alive?\r\n\
-    こんにちは！元気ですか？日はいいですね。\
-    私たちが生きだって嬉しいではないか？\r\n\
-    Hello there!  How're you doing?  It's a fine day, \
-    isn't it?  Aren't you glad we're alive?\r\n\
-    こんにちは！元気ですか？日はいいですね。\
-    私たちが生きだって嬉しいではないか？\r\n\
-    Hello there!  How're you doing?  It's a fine day, \
-    isn't it?  Aren't you glad we're alive?\r\n\
-    こんにちは！元気ですか？日はいいですね。\
-    私たちが生きだって嬉しいではないか？\r\n\
-    ";
+    const EMOJI: &str = "😀👋🌍";
+    const COMPLEX_EMOJI: &str = "👨‍👩‍👧‍👦";
+
+    fn cursor_at(buf: &RopeBuffer, offset: usize) -> RopeGraphemeClusterCursor<'_> {
+        RopeGraphemeClusterCursor::new(buf, CursorRange::new(offset, offset))
+    }
 
     #[test]
-    fn nth_grapheme() {
-        let mut graphemes = Graphemes::new(TEXT, false).set_cursor_offet(TEXT.len());
+    fn steps_over_emoji() -> Result<(), GraphemeClusterCursorError> {
+        let buf = RopeBuffer::from_str(EMOJI);
+
+        assert_eq!(
+            cursor_at(&buf, 0).next_grapheme_cluster_boundary()?,
+            Some(4)
+        );
+        assert_eq!(
+            cursor_at(&buf, 0).nth_next_grapheme_cluster_boundary(3)?,
+            Some(12)
+        );
+        assert_eq!(
+            cursor_at(&buf, 0).nth_next_grapheme_cluster_boundary(4)?,
+            None
+        );
+        assert_eq!(
+            cursor_at(&buf, 12).nth_prev_grapheme_cluster_boundary(2)?,
+            Some(4)
+        );
+
+        Ok(())
+    }
 
-        assert_eq!(graphemes.nth_back(1).unwrap().byte_offset, TEXT.len() - 2);
+    #[test]
+    fn zwj_sequence_is_one_cluster() -> Result<(), GraphemeClusterCursorError> {
+        let text = format!("a{COMPLEX_EMOJI}b");
+        let buf = RopeBuffer::from_str(&text);
+        let end_of_emoji = 1 + COMPLEX_EMOJI.len();
+
+        assert_eq!(
+            cursor_at(&buf, 1).next_grapheme_cluster_boundary()?,
+            Some(end_of_emoji)
+        );
+        assert_eq!(
+            cursor_at(&buf, end_of_emoji).prev_grapheme_cluster_boundary()?,
+            Some(1)
+        );
+        assert!(cursor_at(&buf, end_of_emoji).is_grapheme_boundary()?);
+        assert!(!cursor_at(&buf, 5).is_grapheme_boundary()?);
+
+        Ok(())
     }
 }
@@ -0,0 +1,286 @@
+//! The undo history of a [`RopeBuffer`](super::buffer_impl::RopeBuffer).
+//!
+//! The text and every edit made to it live in an [`xi_rope::engine::Engine`],
+//! which undoes any set of undo groups, in any order. This module decides
+//! which group each edit belongs to, and which groups undo and redo toggle.
+
+use std::{
+    collections::BTreeSet,
+    time::{Duration, Instant},
+};
+
+use crate::text_buffer::{EditKind, DEFAULT_UNDO_GROUP_INTERVAL};
+
+/// Which undo groups of an engine exist and which are undone.
+///
+/// Consecutive edits of the same kind, typing or deleting, made less than
+/// `group_interval` apart share a group, and so do the edits made between
+/// [`UndoHistory::begin_group`] and [`UndoHistory::end_group`].
+///
+/// The engine can only collect a group that isn't undone along with every
+/// group before it, so groups past the limit are collected with all those
+/// before them: the initial contents, in group 0, and groups left out of the
+/// history after being reverted.
+#[derive(Clone, Debug)]
+pub struct UndoHistory {
+    /// The groups that can still be undone or redone, oldest first. Those
+    /// from `applied` on were undone, most recent first, and can be redone.
+    groups: Vec<usize>,
+    applied: usize,
+    /// Every group currently undone, whether undone in order or selectively.
+    undone: BTreeSet<usize>,
+    pub group_interval: Duration,
+    /// The most groups kept. Older ones are collected and can't be undone
+    /// anymore.
+    limit: Option<usize>,
+    /// The kind and time of the last edit, unless the next edit must start a
+    /// new group.
+    last_edit: Option<(EditKind, Instant)>,
+    /// How many explicit groups are open, nested ones included.
+    open: usize,
+    /// Groups dropped from the history, which the engine can collect.
+    collectable: BTreeSet<usize>,
+    /// The groups before this one were all collected.
+    swept: usize,
+}
+
+impl Default for UndoHistory {
+    fn default() -> Self {
+        Self {
+            groups: Vec::new(),
+            applied: 0,
+            undone: BTreeSet::new(),
+            group_interval: DEFAULT_UNDO_GROUP_INTERVAL,
+            limit: None,
+            last_edit: None,
+            open: 0,
+            collectable: BTreeSet::new(),
+            swept: 0,
+        }
+    }
+}
+
+impl UndoHistory {
+    /// The group an edit of `kind` about to be made belongs to. `next_group`
+    /// is the first group id the engine hasn't used yet.
+    ///
+    /// Starting a new group drops the groups that could be redone.
+    pub fn group_for(&mut self, kind: EditKind, next_group: usize) -> usize {
+        let joins = match (self.last_edit, self.groups.last()) {
+            (Some(_), Some(_)) if self.open > 0 => true,
+            (Some((last_kind, at)), Some(_)) => kind.joins(last_kind, at, self.group_interval),
+            _ => false,
+        };
+        self.last_edit = Some((kind, Instant::now()));
+
+        match (joins, self.groups.last()) {
+            (true, Some(&group)) => group,
+            _ => {
+                // Groups redone on their own are in the text, and wait to be
+                // swept like the groups reverted.
+                for group in self.groups.drain(self.applied..) {
+                    if self.undone.remove(&group) {
+                        self.collectable.insert(group);
+                    }
+                }
+                self.groups.push(next_group);
+                self.applied = self.groups.len();
+                self.enforce_limit();
+                next_group
+            }
+        }
+    }
+
+    /// Make the next edit start a new group.
+    pub fn seal(&mut self) {
+        self.last_edit = None;
+    }
+
+    /// Make every edit until the matching [`UndoHistory::end_group`] part of
+    /// a single group.
+    pub fn begin_group(&mut self) {
+        if self.open == 0 {
+            self.seal();
+        }
+        self.open += 1;
+    }
+
+    /// Close the group opened by [`UndoHistory::begin_group`]. When `keep` is
+    /// false, the edits of the outermost group have been reverted already,
+    /// and the group is dropped from the history.
+    pub fn end_group(&mut self, keep: bool) {
+        self.open = self.open.saturating_sub(1);
+        if self.open > 0 {
+            return;
+        }
+
+        if !keep && self.last_edit.is_some() && self.applied == self.groups.len() {
+            self.groups.pop();
+            self.applied = self.groups.len();
+        }
+        self.seal();
+    }
+
+    /// Undo the most recent group not undone yet, returning the groups to
+    /// leave undone, or None if there is nothing to undo.
+    pub fn undo(&mut self) -> Option<BTreeSet<usize>> {
+        let index = self.groups[..self.applied]
+            .iter()
+            .rposition(|group| !self.undone.contains(group))?;
+        self.undone.insert(self.groups[index]);
+        self.applied = index;
+        self.seal();
+
+        Some(self.undone.clone())
+    }
+
+    /// Redo the group undone last, returning the groups to leave undone, or
+    /// None if there is nothing to redo.
+    pub fn redo(&mut self) -> Option<BTreeSet<usize>> {
+        let group = *self.groups.get(self.applied)?;
+        self.undone.remove(&group);
+        self.applied += 1;
+        self.seal();
+
+        Some(self.undone.clone())
+    }
+
+    /// Undo `group` alone, leaving the groups after it as they are. Returns
+    /// the groups to leave undone, or None if `group` can't be undone.
+    pub fn undo_group(&mut self, group: usize) -> Option<BTreeSet<usize>> {
+        if !self.groups.contains(&group) || !self.undone.insert(group) {
+            return None;
+        }
+        self.seal();
+
+        Some(self.undone.clone())
+    }
+
+    /// Redo `group` alone. Returns the groups to leave undone, or None if
+    /// `group` wasn't undone.
+    pub fn redo_group(&mut self, group: usize) -> Option<BTreeSet<usize>> {
+        if !self.undone.remove(&group) {
+            return None;
+        }
+        self.seal();
+
+        Some(self.undone.clone())
+    }
+
+    /// The groups that can be undone or redone, oldest first.
+    pub fn groups(&self) -> &[usize] {
+        &self.groups
+    }
+
+    pub fn undone(&self) -> &BTreeSet<usize> {
+        &self.undone
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Keep at most `limit` groups, and at least one, collecting the oldest
+    /// ones.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit.map(|limit| limit.max(1));
+        self.enforce_limit();
+    }
+
+    /// The groups dropped from the history since the last call, for the
+    /// engine to collect.
+    pub fn take_collectable(&mut self) -> BTreeSet<usize> {
+        std::mem::take(&mut self.collectable)
+    }
+
+    fn enforce_limit(&mut self) {
+        let Some(limit) = self.limit else {
+            return;
+        };
+        let excess = self.groups.len().saturating_sub(limit);
+        if excess == 0 {
+            return;
+        }
+        for group in self.groups.drain(..excess) {
+            self.undone.remove(&group);
+        }
+        self.applied = self.applied.saturating_sub(excess);
+
+        if let Some(&first_kept) = self.groups.first() {
+            self.collectable.extend(self.swept..first_kept);
+            self.swept = first_kept;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_group_by_kind_and_time() {
+        let mut history = UndoHistory::default();
+
+        assert_eq!(history.group_for(EditKind::Typing, 1), 1);
+        assert_eq!(history.group_for(EditKind::Typing, 2), 1);
+        assert_eq!(history.group_for(EditKind::Deleting, 2), 2);
+        history.seal();
+        assert_eq!(history.group_for(EditKind::Deleting, 3), 3);
+
+        history.group_interval = Duration::ZERO;
+        assert_eq!(history.group_for(EditKind::Deleting, 4), 4);
+
+        history.begin_group();
+        assert_eq!(history.group_for(EditKind::Typing, 5), 5);
+        assert_eq!(history.group_for(EditKind::Other, 6), 5);
+        history.end_group(true);
+        assert_eq!(history.groups(), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn new_edits_drop_what_could_be_redone() {
+        let mut history = UndoHistory {
+            group_interval: Duration::ZERO,
+            ..Default::default()
+        };
+        for group in 1..=3 {
+            history.group_for(EditKind::Typing, group);
+        }
+
+        assert_eq!(history.undo(), Some(BTreeSet::from([3])));
+        assert_eq!(history.undo(), Some(BTreeSet::from([2, 3])));
+        assert_eq!(history.redo(), Some(BTreeSet::from([3])));
+
+        assert_eq!(history.group_for(EditKind::Typing, 4), 4);
+        assert_eq!(history.groups(), [1, 2, 4]);
+        assert!(history.undone().is_empty());
+        assert_eq!(history.take_collectable(), BTreeSet::from([3]));
+        assert_eq!(history.redo(), None);
+    }
+
+    #[test]
+    fn selective_undo_and_limits() {
+        let mut history = UndoHistory {
+            group_interval: Duration::ZERO,
+            ..Default::default()
+        };
+        for group in 1..=3 {
+            history.group_for(EditKind::Typing, group);
+        }
+
+        assert_eq!(history.undo_group(1), Some(BTreeSet::from([1])));
+        assert_eq!(history.undo_group(1), None);
+        // Linear undo skips what was undone selectively.
+        assert_eq!(history.undo(), Some(BTreeSet::from([1, 3])));
+        assert_eq!(history.undo(), Some(BTreeSet::from([1, 2, 3])));
+        assert_eq!(history.undo(), None);
+        assert_eq!(history.redo_group(1), Some(BTreeSet::from([2, 3])));
+
+        history.set_limit(Some(2));
+        assert_eq!(history.groups(), [2, 3]);
+        assert_eq!(history.take_collectable(), BTreeSet::from([0, 1]));
+        history.group_for(EditKind::Typing, 4);
+        assert_eq!(history.groups(), [4]);
+        assert_eq!(history.take_collectable(), BTreeSet::from([2, 3]));
+    }
+}
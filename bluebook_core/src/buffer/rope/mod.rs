@@ -1,2 +1,4 @@
 pub mod buffer_impl;
+pub mod cursor_impl;
 pub mod grapheme_cluster_cursor;
+pub mod history;
@@ -0,0 +1,493 @@
+//! Structured change events, for views and indexes to follow the changes made
+//! to a buffer without diffing its text.
+//!
+//! Every backend describes its changes the same way: as a delta walking the
+//! text as it was before the change, in utf8 bytes.
+
+use std::{
+    ops::Range,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use string_cache::DefaultAtom;
+use xi_rope::{DeltaElement, RopeDelta};
+
+use crate::span::FxHashMap;
+
+/// One step of a [`ChangeEvent`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeOp {
+    /// Keep `len` bytes, setting `attributes` on them. A null value removes
+    /// the attribute.
+    Retain {
+        len: usize,
+        attributes: FxHashMap<DefaultAtom, Value>,
+    },
+    /// Insert `text`, carrying `attributes`.
+    Insert {
+        text: String,
+        attributes: FxHashMap<DefaultAtom, Value>,
+    },
+    /// Delete `len` bytes.
+    Delete { len: usize },
+}
+
+impl ChangeOp {
+    pub fn retain(len: usize) -> Self {
+        ChangeOp::Retain {
+            len,
+            attributes: Default::default(),
+        }
+    }
+
+    pub fn insert(text: impl Into<String>) -> Self {
+        ChangeOp::Insert {
+            text: text.into(),
+            attributes: Default::default(),
+        }
+    }
+
+    pub fn delete(len: usize) -> Self {
+        ChangeOp::Delete { len }
+    }
+
+    /// Append `op` to this one if they are of the same kind, with the same
+    /// attributes. Gives `op` back otherwise.
+    fn absorb(&mut self, op: ChangeOp) -> Option<ChangeOp> {
+        match (self, op) {
+            (
+                ChangeOp::Retain { len, attributes },
+                ChangeOp::Retain {
+                    len: more,
+                    attributes: other,
+                },
+            ) if *attributes == other => *len += more,
+            (
+                ChangeOp::Insert { text, attributes },
+                ChangeOp::Insert {
+                    text: more,
+                    attributes: other,
+                },
+            ) if *attributes == other => text.push_str(&more),
+            (ChangeOp::Delete { len }, ChangeOp::Delete { len: more }) => *len += more,
+            (_, op) => return Some(op),
+        }
+
+        None
+    }
+}
+
+/// A change made to a buffer, either locally or by another replica.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    /// The steps of the change, from the start of the text. The text after
+    /// the last step is left as it is.
+    pub ops: Vec<ChangeOp>,
+    pub is_local: bool,
+}
+
+impl ChangeEvent {
+    /// The change `delta` makes to the rope it applies to.
+    pub fn from_delta(delta: &RopeDelta, is_local: bool) -> Self {
+        let mut ops = Vec::new();
+        let mut copied_to = 0;
+        for element in &delta.els {
+            match element {
+                DeltaElement::Copy(start, end) => {
+                    if *start > copied_to {
+                        ops.push(ChangeOp::delete(start - copied_to));
+                    }
+                    ops.push(ChangeOp::retain(end - start));
+                    copied_to = *end;
+                }
+                DeltaElement::Insert(rope) => ops.push(ChangeOp::insert(rope)),
+            }
+        }
+        if delta.base_len > copied_to {
+            ops.push(ChangeOp::delete(delta.base_len - copied_to));
+        }
+
+        Self::new(ops, is_local)
+    }
+
+    /// An event made of `ops`, without the steps that do nothing and with
+    /// consecutive steps of the same kind merged.
+    pub fn new(ops: Vec<ChangeOp>, is_local: bool) -> Self {
+        let mut merged: Vec<ChangeOp> = Vec::with_capacity(ops.len());
+        for op in ops {
+            let is_noop = match &op {
+                ChangeOp::Retain { len, .. } | ChangeOp::Delete { len } => *len == 0,
+                ChangeOp::Insert { text, .. } => text.is_empty(),
+            };
+            let op = match merged.last_mut() {
+                _ if is_noop => continue,
+                Some(last) => match last.absorb(op) {
+                    Some(op) => op,
+                    None => continue,
+                },
+                None => op,
+            };
+            merged.push(op);
+        }
+        let mut ops = merged;
+        while matches!(ops.last(), Some(ChangeOp::Retain { attributes, .. }) if attributes.is_empty())
+        {
+            ops.pop();
+        }
+
+        Self { ops, is_local }
+    }
+
+    /// The ranges of the new text carried over from the old one.
+    pub fn retained(&self) -> Vec<Range<usize>> {
+        self.walk()
+            .filter_map(|(op, new, _)| matches!(op, ChangeOp::Retain { .. }).then_some(new))
+            .collect()
+    }
+
+    /// The ranges of the new text that were inserted.
+    pub fn inserted(&self) -> Vec<Range<usize>> {
+        self.walk()
+            .filter_map(|(op, new, _)| matches!(op, ChangeOp::Insert { .. }).then_some(new))
+            .collect()
+    }
+
+    /// The ranges of the old text that were deleted.
+    pub fn deleted(&self) -> Vec<Range<usize>> {
+        self.walk()
+            .filter_map(|(op, _, old)| matches!(op, ChangeOp::Delete { .. }).then_some(old))
+            .collect()
+    }
+
+    /// The attributes set on retained text, with the range of the new text
+    /// they were set on. A null value means the attribute was removed.
+    pub fn attribute_changes(&self) -> Vec<(Range<usize>, DefaultAtom, Value)> {
+        self.walk()
+            .filter_map(|(op, new, _)| match op {
+                ChangeOp::Retain { attributes, .. } => Some((new, attributes)),
+                _ => None,
+            })
+            .flat_map(|(range, attributes)| {
+                attributes
+                    .iter()
+                    .map(move |(type_, value)| (range.clone(), type_.clone(), value.clone()))
+            })
+            .collect()
+    }
+
+    /// The smallest range of the new text covering everything that changed,
+    /// or None if nothing did. A deletion counts as the empty range where the
+    /// deleted text used to be.
+    pub fn changed_range(&self) -> Option<Range<usize>> {
+        self.walk()
+            .filter(|(op, _, _)| {
+                !matches!(op, ChangeOp::Retain { attributes, .. } if attributes.is_empty())
+            })
+            .map(|(_, new, _)| new)
+            .reduce(|changed, new| changed.start.min(new.start)..changed.end.max(new.end))
+    }
+
+    /// Every op, with the range it covers in the new text and in the old one.
+    fn walk(&self) -> impl Iterator<Item = (&ChangeOp, Range<usize>, Range<usize>)> {
+        let (mut new, mut old) = (0, 0);
+        self.ops.iter().map(move |op| {
+            let (new_len, old_len) = match op {
+                ChangeOp::Retain { len, .. } => (*len, *len),
+                ChangeOp::Insert { text, .. } => (text.len(), 0),
+                ChangeOp::Delete { len } => (0, *len),
+            };
+            let ranges = (new..new + new_len, old..old + old_len);
+            new += new_len;
+            old += old_len;
+            (op, ranges.0, ranges.1)
+        })
+    }
+}
+
+/// Composes the edits made to a text one after the other into a single
+/// [`ChangeEvent`].
+#[derive(Clone, Debug)]
+pub struct ChangeSet {
+    base_len: usize,
+    /// The current text, as pieces of the original one and inserted text.
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug)]
+enum Segment {
+    Copy {
+        range: Range<usize>,
+        attributes: FxHashMap<DefaultAtom, Value>,
+    },
+    Insert {
+        text: String,
+        attributes: FxHashMap<DefaultAtom, Value>,
+    },
+}
+
+impl Segment {
+    fn len(&self) -> usize {
+        match self {
+            Segment::Copy { range, .. } => range.len(),
+            Segment::Insert { text, .. } => text.len(),
+        }
+    }
+
+    fn attributes_mut(&mut self) -> &mut FxHashMap<DefaultAtom, Value> {
+        match self {
+            Segment::Copy { attributes, .. } | Segment::Insert { attributes, .. } => attributes,
+        }
+    }
+
+    /// Split the segment in two at `at`, keeping the first half.
+    fn split_off(&mut self, at: usize) -> Segment {
+        match self {
+            Segment::Copy { range, attributes } => {
+                let rest = range.start + at..range.end;
+                range.end = rest.start;
+                Segment::Copy {
+                    range: rest,
+                    attributes: attributes.clone(),
+                }
+            }
+            Segment::Insert { text, attributes } => Segment::Insert {
+                text: text.split_off(at),
+                attributes: attributes.clone(),
+            },
+        }
+    }
+}
+
+impl ChangeSet {
+    /// A change set for a text `base_len` bytes long, with no edits yet.
+    pub fn new(base_len: usize) -> Self {
+        let segments = match base_len {
+            0 => Vec::new(),
+            _ => vec![Segment::Copy {
+                range: 0..base_len,
+                attributes: Default::default(),
+            }],
+        };
+
+        Self { base_len, segments }
+    }
+
+    /// Record `range` of the current text being replaced with `text`.
+    pub fn replace(&mut self, range: Range<usize>, text: &str) {
+        let start = self.split_at(range.start);
+        let end = self.split_at(range.end);
+        let inserted = (!text.is_empty()).then(|| Segment::Insert {
+            text: text.to_string(),
+            attributes: Default::default(),
+        });
+        self.segments.splice(start..end, inserted);
+    }
+
+    /// Record the attribute `type_` being set to `value` on `range` of the
+    /// current text.
+    pub fn set_attribute(&mut self, range: Range<usize>, type_: DefaultAtom, value: Value) {
+        let start = self.split_at(range.start);
+        let end = self.split_at(range.end);
+        for segment in &mut self.segments[start..end] {
+            segment
+                .attributes_mut()
+                .insert(type_.clone(), value.clone());
+        }
+    }
+
+    /// Every edit recorded so far, as a single event. Deleted text comes
+    /// before the text inserted in its place.
+    pub fn to_event(&self, is_local: bool) -> ChangeEvent {
+        let mut ops = Vec::new();
+        let mut copied_to = 0;
+        for (index, segment) in self.segments.iter().enumerate() {
+            // Where the original text picks up again after this segment.
+            let next_copy = self.segments[index..]
+                .iter()
+                .find_map(|segment| match segment {
+                    Segment::Copy { range, .. } => Some(range.start),
+                    Segment::Insert { .. } => None,
+                })
+                .unwrap_or(self.base_len);
+            if next_copy > copied_to {
+                ops.push(ChangeOp::delete(next_copy - copied_to));
+                copied_to = next_copy;
+            }
+
+            match segment {
+                Segment::Copy { range, attributes } => {
+                    ops.push(ChangeOp::Retain {
+                        len: range.len(),
+                        attributes: attributes.clone(),
+                    });
+                    copied_to = range.end;
+                }
+                Segment::Insert { text, attributes } => ops.push(ChangeOp::Insert {
+                    text: text.clone(),
+                    attributes: attributes.clone(),
+                }),
+            }
+        }
+        if self.base_len > copied_to {
+            ops.push(ChangeOp::delete(self.base_len - copied_to));
+        }
+
+        ChangeEvent::new(ops, is_local)
+    }
+
+    /// Split the segments at `offset` in the current text, returning the
+    /// index of the first segment starting at or after it.
+    fn split_at(&mut self, offset: usize) -> usize {
+        let mut start = 0;
+        for index in 0..self.segments.len() {
+            let len = self.segments[index].len();
+            if offset == start {
+                return index;
+            }
+            if offset < start + len {
+                let rest = self.segments[index].split_off(offset - start);
+                self.segments.insert(index + 1, rest);
+                return index + 1;
+            }
+            start += len;
+        }
+
+        self.segments.len()
+    }
+}
+
+/// The subscribers of a buffer that doesn't keep track of them itself.
+///
+/// Cloning a buffer doesn't subscribe anyone to the clone, so the clone starts
+/// out without subscribers.
+#[derive(Debug, Default)]
+pub struct Subscribers {
+    senders: Vec<Sender<ChangeEvent>>,
+}
+
+impl Clone for Subscribers {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl Subscribers {
+    pub fn subscribe(&mut self) -> Receiver<ChangeEvent> {
+        let (sender, receiver) = channel();
+        self.senders.push(sender);
+        receiver
+    }
+
+    /// Whether anyone is listening, for buffers to skip building events no one
+    /// would receive.
+    pub fn is_empty(&self) -> bool {
+        self.senders.is_empty()
+    }
+
+    /// Send `event` to every subscriber, forgetting the ones that are gone.
+    pub fn emit(&mut self, event: ChangeEvent) {
+        self.senders
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xi_rope::{DeltaBuilder, Rope};
+
+    #[test]
+    fn events_from_rope_deltas() {
+        let rope = Rope::from("See Roe v. Wade.");
+        let mut builder = DeltaBuilder::new(rope.len());
+        builder.replace(4..7, Rope::from("Brown"));
+        builder.replace(11..15, Rope::from("Board"));
+        let event = ChangeEvent::from_delta(&builder.build(), true);
+
+        assert_eq!(
+            event.ops,
+            [
+                ChangeOp::retain(4),
+                ChangeOp::insert("Brown"),
+                ChangeOp::delete(3),
+                ChangeOp::retain(4),
+                ChangeOp::insert("Board"),
+                ChangeOp::delete(4),
+            ]
+        );
+        assert_eq!(event.retained(), [0..4, 9..13]);
+        assert_eq!(event.inserted(), [4..9, 13..18]);
+        assert_eq!(event.deleted(), [4..7, 11..15]);
+        assert!(event.attribute_changes().is_empty());
+    }
+
+    #[test]
+    fn attribute_changes_are_in_new_offsets() {
+        let bold: FxHashMap<_, _> = [(DefaultAtom::from("Bold"), Value::Bool(true))]
+            .into_iter()
+            .collect();
+        let event = ChangeEvent::new(
+            vec![
+                ChangeOp::delete(2),
+                ChangeOp::insert("ab"),
+                ChangeOp::Retain {
+                    len: 3,
+                    attributes: bold,
+                },
+                ChangeOp::retain(10),
+            ],
+            false,
+        );
+
+        assert_eq!(event.ops.len(), 3);
+        assert_eq!(
+            event.attribute_changes(),
+            [(2..5, DefaultAtom::from("Bold"), Value::Bool(true))]
+        );
+    }
+
+    #[test]
+    fn change_sets_compose_edits() {
+        let bold: FxHashMap<_, _> = [(DefaultAtom::from("Bold"), Value::Bool(true))]
+            .into_iter()
+            .collect();
+        let mut changes = ChangeSet::new("See Roe v. Wade.".len());
+        changes.replace(4..7, "Brown");
+        changes.replace(0..4, "");
+        changes.set_attribute(0..5, "Bold".into(), Value::Bool(true));
+        changes.replace(5..5, ",");
+        changes.replace(7..9, "");
+        let event = changes.to_event(true);
+
+        assert_eq!(
+            event.ops,
+            [
+                ChangeOp::delete(7),
+                ChangeOp::Insert {
+                    text: "Brown".to_string(),
+                    attributes: bold,
+                },
+                ChangeOp::insert(","),
+                ChangeOp::retain(1),
+                ChangeOp::delete(2),
+            ]
+        );
+        assert_eq!(event.inserted(), [0..5, 5..6]);
+        assert_eq!(event.deleted(), [0..7, 8..10]);
+        assert_eq!(event.changed_range(), Some(0..7));
+    }
+
+    #[test]
+    fn gone_subscribers_are_dropped() {
+        let mut subscribers = Subscribers::default();
+        let kept = subscribers.subscribe();
+        drop(subscribers.subscribe());
+
+        subscribers.emit(ChangeEvent::new(vec![ChangeOp::insert("a")], true));
+        assert_eq!(kept.try_iter().count(), 1);
+        assert!(!subscribers.is_empty());
+        assert!(subscribers.clone().is_empty());
+    }
+}
@@ -2,12 +2,17 @@ use crate::text_buffer::TextBuffer;
 
 pub struct CharIter<'s> {
     slice: &'s str,
-    index: usize,
+    start: usize,
+    end: usize,
 }
 
 impl<'s> CharIter<'s> {
     pub fn new(slice: &'s str) -> CharIter<'s> {
-        CharIter { slice, index: 0 }
+        CharIter {
+            slice,
+            start: 0,
+            end: slice.len(),
+        }
     }
 }
 
@@ -28,7 +33,7 @@ impl<'s> Iterator for CharIter<'s> {
 impl<'s> DoubleEndedIterator for CharIter<'s> {
     fn next_back(&mut self) -> Option<Self::Item> {
         if self.start < self.end {
-            let slice = &self.slice[..self.end - self.start];
+            let slice = &self.slice[self.start..self.end];
             let ch = slice.chars().next_back().unwrap();
             self.end -= ch.len_utf8();
             Some(ch)
@@ -92,4 +97,16 @@ pub enum CharCursorError {
 pub trait CharCursor<'buffer> {
     type Buffer: TextBuffer;
     fn new(text: &'buffer Self::Buffer, pos: usize) -> Self;
+    /// The byte offset the cursor currently sits at.
+    fn pos(&self) -> usize;
+    /// Move the cursor to the given byte offset.
+    fn set(&mut self, pos: usize);
+    /// Step back over the previous codepoint and return it, leaving the cursor
+    /// untouched at the start of the text.
+    fn prev_codepoint(&mut self) -> Option<char>;
+    /// Step over the next codepoint and return it, leaving the cursor untouched
+    /// at the end of the text.
+    fn next_codepoint(&mut self) -> Option<char>;
+    /// Return the next codepoint without moving the cursor.
+    fn peek_next_codepoint(&self) -> Option<char>;
 }
@@ -0,0 +1,185 @@
+//! A rich clipboard: copied text keeps its annotations as a list of spans.
+//!
+//! The system clipboard only ever sees the plain text of a fragment. The editor
+//! keeps the fragment itself, and pastes it with its annotations whenever the
+//! text coming back from the system clipboard is still that plain text, i.e.
+//! whenever nothing else was copied in between. Platforms that support custom
+//! clipboard formats can carry the fragment itself, using [`Fragment::serialise`].
+
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use string_cache::DefaultAtom;
+
+use crate::{
+    line::{normalise_line_endings, LineEnding},
+    span::{attribute_runs, Span},
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ClipboardError {
+    #[error("Clipboard data is not a bluebook fragment")]
+    UnknownFormat,
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// A run of rich text, as copied out of a buffer.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fragment {
+    pub spans: Vec<Span>,
+}
+
+/// The serialised form of a [`Fragment`], tagged so that arbitrary JSON on the
+/// clipboard isn't mistaken for one.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    format: String,
+    spans: Vec<Span>,
+}
+
+impl Fragment {
+    /// The clipboard format (mime type) of serialised fragments.
+    pub const FORMAT: &'static str = "application/x-bluebook-fragment+json";
+
+    pub fn new(spans: Vec<Span>) -> Self {
+        Self { spans }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.iter().all(|span| span.insert.is_empty())
+    }
+
+    /// Append `other`, separated from this fragment by an unstyled `separator`.
+    pub fn append(&mut self, separator: &str, other: Fragment) {
+        if !self.spans.is_empty() && !separator.is_empty() {
+            self.spans.push(Span {
+                insert: separator.to_string(),
+                attributes: Default::default(),
+            });
+        }
+        self.spans.extend(other.spans);
+    }
+
+    /// The text of the fragment, without its annotations.
+    pub fn plain_text(&self) -> String {
+        self.spans.iter().map(|span| span.insert.as_str()).collect()
+    }
+
+    /// The same fragment, with its line endings rewritten to `line_ending`.
+    pub fn with_line_ending(&self, line_ending: LineEnding) -> Self {
+        Self {
+            spans: self
+                .spans
+                .iter()
+                .map(|span| Span {
+                    insert: normalise_line_endings(&span.insert, line_ending).into_owned(),
+                    attributes: span.attributes.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// The annotations to re-apply when pasting the fragment, with ranges
+    /// relative to its start.
+    ///
+    /// Only bold, italic, link and comment annotations are carried across, one
+    /// per run of spans sharing it.
+    pub fn annotations(&self) -> Vec<(Range<usize>, DefaultAtom, Value)> {
+        attribute_runs(&self.spans)
+            .into_iter()
+            .filter(|(_, type_, _)| is_pasted(type_))
+            .collect()
+    }
+
+    pub fn serialise(&self) -> Result<String, ClipboardError> {
+        Ok(serde_json::to_string(&Envelope {
+            format: Self::FORMAT.to_string(),
+            spans: self.spans.clone(),
+        })?)
+    }
+
+    pub fn deserialise(data: &str) -> Result<Self, ClipboardError> {
+        let envelope: Envelope = serde_json::from_str(data)?;
+        if envelope.format != Self::FORMAT {
+            return Err(ClipboardError::UnknownFormat);
+        }
+
+        Ok(Self::new(envelope.spans))
+    }
+}
+
+/// Whether annotations of `type_` are pasted, matching the type
+/// case-insensitively.
+fn is_pasted(type_: &DefaultAtom) -> bool {
+    matches!(
+        &*type_.to_ascii_lowercase(),
+        "bold" | "italic" | "link" | "comment"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(insert: &str, attributes: &[(&str, Value)]) -> Span {
+        Span {
+            insert: insert.to_string(),
+            attributes: attributes
+                .iter()
+                .map(|(type_, value)| (DefaultAtom::from(*type_), value.clone()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn round_trips_through_the_internal_format() -> Result<(), ClipboardError> {
+        let fragment = Fragment::new(vec![
+            span("See ", &[]),
+            span("Roe", &[("Bold", Value::from(0))]),
+        ]);
+
+        assert_eq!(Fragment::deserialise(&fragment.serialise()?)?, fragment);
+        assert!(matches!(
+            Fragment::deserialise(r#"{"format": "text/html", "spans": []}"#),
+            Err(ClipboardError::UnknownFormat)
+        ));
+        assert!(Fragment::deserialise("See Roe").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn annotation_runs_span_several_spans() {
+        let comment = ("comment", Value::from("check cite"));
+        let fragment = Fragment::new(vec![
+            span("ab", std::slice::from_ref(&comment)),
+            span("cd", &[comment.clone(), ("bold", Value::from(0))]),
+            span("ef", &[("header", Value::from(1))]),
+        ]);
+
+        let mut annotations: Vec<_> = fragment
+            .annotations()
+            .into_iter()
+            .map(|(range, type_, _)| (range, type_.to_string()))
+            .collect();
+        annotations.sort_by_key(|(range, _)| (range.start, range.end));
+
+        assert_eq!(
+            annotations,
+            [(0..4, "comment".to_string()), (2..4, "bold".to_string())]
+        );
+    }
+
+    #[test]
+    fn line_endings_follow_the_destination() {
+        let mut fragment = Fragment::new(vec![span("a\r\nb", &[])]);
+        fragment.append("\n", Fragment::new(vec![span("c", &[])]));
+
+        assert_eq!(
+            fragment.with_line_ending(LineEnding::LF).plain_text(),
+            "a\nb\nc"
+        );
+    }
+}
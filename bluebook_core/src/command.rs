@@ -1,5 +1,7 @@
 use strum::{Display, EnumMessage, IntoStaticStr};
 
+use crate::{movement::Movement, pair::pair_named, search::SearchQuery, text_object::TextObject};
+
 // use strum_macros::{Display, EnumIter, EnumMessage, EnumString, IntoStaticStr};
 
 #[derive(Display, Clone, PartialEq, Eq, Debug, EnumMessage, IntoStaticStr)]
@@ -69,8 +71,15 @@ pub enum Transaction {
     Paste {
         clipboard: String,
     },
+    #[strum(serialize = "paste_after")]
+    PasteAfter,
     #[strum(serialize = "paste_before")]
     PasteBefore,
+    /// Make the next command yank to, or put from, register `name`.
+    #[strum(serialize = "select_register")]
+    SelectRegister {
+        name: char,
+    },
     #[strum(serialize = "normal_mode")]
     NormalMode,
     #[strum(serialize = "insert_mode")]
@@ -87,6 +96,27 @@ pub enum Transaction {
     ToggleLinewiseVisualMode,
     #[strum(serialize = "toggle_blockwise_visual_mode")]
     ToggleBlockwiseVisualMode,
+    /// Start an operator, which applies to the motion or text object that
+    /// follows, or to whole lines when repeated.
+    #[strum(serialize = "delete_operator")]
+    DeleteOperator,
+    #[strum(serialize = "yank_operator")]
+    YankOperator,
+    #[strum(serialize = "change_operator")]
+    ChangeOperator,
+    #[strum(serialize = "indent_operator")]
+    IndentOperator,
+    #[strum(serialize = "outdent_operator")]
+    OutdentOperator,
+    /// Add a digit to the count of the next motion or operator.
+    #[strum(serialize = "count")]
+    Count {
+        digit: usize,
+    },
+    /// Replay the last command that changed the text, with the text typed in
+    /// the insert mode it entered.
+    #[strum(serialize = "repeat_last_change")]
+    RepeatLastChange,
     #[strum(serialize = "duplicate_line_up")]
     DuplicateLineUp,
     #[strum(serialize = "duplicate_line_down")]
@@ -98,19 +128,288 @@ pub enum Transaction {
     // Annotate {
     //     range: Annotation,
     // },
-    RemoveAnnotation,
+    #[strum(message = "Remove Annotation")]
+    #[strum(serialize = "remove_annotation")]
+    RemoveAnnotation {
+        id: peritext::OpID,
+    },
     MoveCursorHeadTo {
         offset: usize,
     },
     MoveCursorAnchorTo {
         offset: usize,
     },
+    #[strum(serialize = "move_cursor_left")]
     MoveCursorLeft {
         grapheme_count: usize,
     },
+    #[strum(serialize = "move_cursor_right")]
     MoveCursorRight {
         grapheme_count: usize,
     },
+    #[strum(serialize = "move_cursor_up")]
+    MoveCursorUp,
+    #[strum(serialize = "move_cursor_down")]
+    MoveCursorDown,
+    #[strum(serialize = "move_word_forward")]
+    MoveWordForward,
+    #[strum(serialize = "move_word_backward")]
+    MoveWordBackward,
+    #[strum(serialize = "move_word_end_forward")]
+    MoveWordEndForward,
+    #[strum(serialize = "move_to_line_start")]
+    MoveToLineStart,
+    #[strum(serialize = "move_to_first_non_blank")]
+    MoveToFirstNonBlank,
+    #[strum(serialize = "move_to_line_end")]
+    MoveToLineEnd,
+    #[strum(serialize = "move_to_document_start")]
+    MoveToDocumentStart,
+    #[strum(serialize = "move_to_document_end")]
+    MoveToDocumentEnd,
+    #[strum(serialize = "move_sentence_forward")]
+    MoveSentenceForward,
+    #[strum(serialize = "move_sentence_backward")]
+    MoveSentenceBackward,
+    #[strum(serialize = "select_word")]
+    SelectWord,
+    #[strum(serialize = "select_sentence")]
+    SelectSentence,
+    #[strum(serialize = "move_paragraph_forward")]
+    MoveParagraphForward,
+    #[strum(serialize = "move_paragraph_backward")]
+    MoveParagraphBackward,
+    #[strum(serialize = "select_paragraph")]
+    SelectParagraph,
+    #[strum(serialize = "move_block_forward")]
+    MoveBlockForward,
+    #[strum(serialize = "move_block_backward")]
+    MoveBlockBackward,
+    #[strum(serialize = "move_header_forward")]
+    MoveHeaderForward {
+        level: Option<u64>,
+    },
+    #[strum(serialize = "move_header_backward")]
+    MoveHeaderBackward {
+        level: Option<u64>,
+    },
+    #[strum(serialize = "move_to_matching_pair")]
+    MoveToMatchingPair,
+    /// Move to the `ch` closing the pair the cursor is in.
+    #[strum(serialize = "move_to_next_unmatched")]
+    MoveToNextUnmatched {
+        ch: char,
+    },
+    /// Move to the `ch` opening the pair the cursor is in.
+    #[strum(serialize = "move_to_previous_unmatched")]
+    MoveToPreviousUnmatched {
+        ch: char,
+    },
+    #[strum(serialize = "select_block")]
+    SelectBlock,
+    /// Select a text object around the cursor: the inner one, or the one
+    /// taking in its delimiters or the white space after it if `around`.
+    #[strum(serialize = "select_text_object")]
+    SelectTextObject {
+        object: TextObject,
+        around: bool,
+    },
+    #[strum(message = "Add Cursor Above")]
+    #[strum(serialize = "add_cursor_above")]
+    AddCursorAbove,
+    #[strum(message = "Add Cursor Below")]
+    #[strum(serialize = "add_cursor_below")]
+    AddCursorBelow,
+    #[strum(message = "Add Cursor at Next Match")]
+    #[strum(serialize = "add_cursor_at_next_match")]
+    AddCursorAtNextMatch,
+    #[strum(message = "Select All Matches")]
+    #[strum(serialize = "select_all_matches")]
+    SelectAllMatches,
+    #[strum(serialize = "keep_primary_cursor")]
+    KeepPrimaryCursor,
+    #[strum(message = "Find Next")]
+    #[strum(serialize = "find_next")]
+    FindNext {
+        query: SearchQuery,
+    },
+    #[strum(message = "Find Previous")]
+    #[strum(serialize = "find_previous")]
+    FindPrevious {
+        query: SearchQuery,
+    },
+    #[strum(message = "Replace All")]
+    #[strum(serialize = "replace_all")]
+    ReplaceAll {
+        query: SearchQuery,
+        replacement: String,
+    },
 }
 
 // use strum_macros::{Display, EnumIter, EnumMessage, EnumString, IntoStaticStr};
+
+impl Transaction {
+    /// The transaction serialised as `name`, e.g. "delete_word_backward", for
+    /// binding to keys. Motions move by one, and transactions carrying
+    /// anything else, like text or a search, can't be named. Text objects
+    /// are named by size and object, as in "select_inner_word" or
+    /// "select_around_parens", and pairs by the pair, as in
+    /// "move_to_next_unmatched_parens".
+    pub fn from_name(name: &str) -> Option<Self> {
+        use Transaction::*;
+
+        if let Some(pair) = name.strip_prefix("move_to_next_unmatched_") {
+            let (_, close) = pair_named(pair)?;
+            return Some(MoveToNextUnmatched { ch: close });
+        }
+        if let Some(pair) = name.strip_prefix("move_to_previous_unmatched_") {
+            let (open, _) = pair_named(pair)?;
+            return Some(MoveToPreviousUnmatched { ch: open });
+        }
+        if let Some(object) = name.strip_prefix("select_inner_") {
+            let object = TextObject::from_name(object)?;
+            return Some(SelectTextObject {
+                object,
+                around: false,
+            });
+        }
+        if let Some(object) = name.strip_prefix("select_around_") {
+            let object = TextObject::from_name(object)?;
+            return Some(SelectTextObject {
+                object,
+                around: true,
+            });
+        }
+
+        let named = [
+            MoveLineUp,
+            MoveLineDown,
+            InsertNewLine,
+            InsertTab,
+            NewLineAbove,
+            NewLineBelow,
+            DeleteBackward,
+            DeleteForward,
+            DeleteLine,
+            DeleteForwardAndInsert,
+            DeleteWordAndInsert,
+            DeleteLineAndInsert,
+            DeleteWordForward,
+            DeleteWordBackward,
+            DeleteToBeginningOfLine,
+            DeleteToEndOfLine,
+            DeleteToEndOfLineAndInsert,
+            JoinLines,
+            IndentLine,
+            OutdentLine,
+            ToggleLineComment,
+            Undo,
+            Redo,
+            ClipboardCopy,
+            ClipboardCut,
+            ClipboardPaste,
+            Yank,
+            PasteAfter,
+            PasteBefore,
+            SelectRegister {
+                name: crate::register::UNNAMED,
+            },
+            NormalMode,
+            InsertMode,
+            InsertFirstNonBlank,
+            Append,
+            AppendEndOfLine,
+            ToggleVisualMode,
+            ToggleLinewiseVisualMode,
+            ToggleBlockwiseVisualMode,
+            DeleteOperator,
+            YankOperator,
+            ChangeOperator,
+            IndentOperator,
+            OutdentOperator,
+            RepeatLastChange,
+            DuplicateLineUp,
+            DuplicateLineDown,
+            MoveCursorLeft { grapheme_count: 1 },
+            MoveCursorRight { grapheme_count: 1 },
+            MoveCursorUp,
+            MoveCursorDown,
+            MoveWordForward,
+            MoveWordBackward,
+            MoveWordEndForward,
+            MoveToLineStart,
+            MoveToFirstNonBlank,
+            MoveToLineEnd,
+            MoveToDocumentStart,
+            MoveToDocumentEnd,
+            MoveSentenceForward,
+            MoveSentenceBackward,
+            SelectWord,
+            SelectSentence,
+            MoveParagraphForward,
+            MoveParagraphBackward,
+            SelectParagraph,
+            MoveBlockForward,
+            MoveBlockBackward,
+            MoveHeaderForward { level: None },
+            MoveHeaderBackward { level: None },
+            MoveToMatchingPair,
+            SelectBlock,
+            AddCursorAbove,
+            AddCursorBelow,
+            AddCursorAtNextMatch,
+            SelectAllMatches,
+            KeepPrimaryCursor,
+        ];
+
+        named
+            .into_iter()
+            .find(|transaction| <&'static str>::from(transaction) == name)
+    }
+
+    /// The movement a motion makes, and how many times, if the transaction is
+    /// one. Operators apply to the text a motion moves over.
+    pub fn motion(&self) -> Option<(Movement, usize)> {
+        let motion = match self {
+            Transaction::MoveCursorLeft { grapheme_count } => (Movement::Left, *grapheme_count),
+            Transaction::MoveCursorRight { grapheme_count } => (Movement::Right, *grapheme_count),
+            Transaction::MoveCursorUp => (Movement::Up, 1),
+            Transaction::MoveCursorDown => (Movement::Down, 1),
+            Transaction::MoveWordForward => (Movement::WordForward, 1),
+            Transaction::MoveWordBackward => (Movement::WordBackward, 1),
+            Transaction::MoveWordEndForward => (Movement::WordEndForward, 1),
+            Transaction::MoveToLineStart => (Movement::StartOfLine, 1),
+            Transaction::MoveToFirstNonBlank => (Movement::FirstNonBlank, 1),
+            Transaction::MoveToLineEnd => (Movement::EndOfLine, 1),
+            Transaction::MoveToDocumentStart => (Movement::DocumentStart, 1),
+            Transaction::MoveToDocumentEnd => (Movement::DocumentEnd, 1),
+            Transaction::MoveSentenceForward => (Movement::SentenceForward, 1),
+            Transaction::MoveSentenceBackward => (Movement::SentenceBackward, 1),
+            Transaction::MoveParagraphForward => (Movement::ParagraphForward, 1),
+            Transaction::MoveParagraphBackward => (Movement::ParagraphBackward, 1),
+            Transaction::MoveBlockForward => (Movement::BlockForward, 1),
+            Transaction::MoveBlockBackward => (Movement::BlockBackward, 1),
+            Transaction::MoveToMatchingPair => (Movement::MatchPairs, 1),
+            Transaction::MoveToNextUnmatched { ch } => (Movement::NextUnmatched(*ch), 1),
+            Transaction::MoveToPreviousUnmatched { ch } => (Movement::PreviousUnmatched(*ch), 1),
+            Transaction::MoveHeaderForward { level } => (Movement::HeaderForward(*level), 1),
+            Transaction::MoveHeaderBackward { level } => (Movement::HeaderBackward(*level), 1),
+            _ => return None,
+        };
+
+        Some(motion)
+    }
+
+    /// Whether the transaction selects a unit of text around the cursor,
+    /// which operators can apply to.
+    pub fn is_text_object(&self) -> bool {
+        matches!(
+            self,
+            Transaction::SelectWord
+                | Transaction::SelectSentence
+                | Transaction::SelectParagraph
+                | Transaction::SelectBlock
+                | Transaction::SelectTextObject { .. }
+        )
+    }
+}
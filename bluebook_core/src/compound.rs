@@ -0,0 +1,391 @@
+//! Compound transactions: several edits and transactions applied as a single,
+//! atomic unit.
+//!
+//! While a compound transaction is applied, every edit the context makes is
+//! recorded in a [`Journal`], along with what the edited text looked like
+//! before. If a step fails, the journal is played back in reverse to undo the
+//! steps before it; otherwise it gives the change made by the whole unit.
+
+use std::ops::Range;
+
+use serde_json::Value;
+use string_cache::DefaultAtom;
+
+use crate::{
+    change::{ChangeEvent, ChangeSet},
+    command::Transaction,
+    ctx::{replace_text, TextEditorContext},
+    error::BluebookCoreError,
+    offset::ByteOffset,
+    search::Searchable,
+    span::{attribute_runs, Annotation, Behavior, Span},
+    text_buffer::TextBuffer,
+};
+
+/// One step of a [`CompoundTransaction`]. Offsets are in bytes, in the text as
+/// the steps before left it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    Replace {
+        range: Range<usize>,
+        text: String,
+    },
+    /// Set the attribute `type_` to `value` over `range`, as
+    /// [`TextBuffer::set_attribute`] does.
+    Annotate {
+        range: Range<usize>,
+        type_: DefaultAtom,
+        value: Value,
+    },
+    /// A transaction, applied to the selection as
+    /// [`TextEditorContext::consume_transaction`] would.
+    Transaction(Transaction),
+}
+
+/// Edits and transactions, applied in order by
+/// [`TextEditorContext::apply_compound`]: all of them, or none if one fails.
+///
+/// ```ignore
+/// let compound = CompoundTransaction::new()
+///     .insert(0, "See ")
+///     .annotate(4..7, "bold", true)
+///     .then(Transaction::MoveCursorHeadTo { offset: 7 });
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompoundTransaction {
+    steps: Vec<Step>,
+}
+
+impl CompoundTransaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(self, offset: usize, text: impl Into<String>) -> Self {
+        self.replace(offset..offset, text)
+    }
+
+    pub fn delete(self, range: Range<usize>) -> Self {
+        self.replace(range, "")
+    }
+
+    pub fn replace(mut self, range: Range<usize>, text: impl Into<String>) -> Self {
+        self.steps.push(Step::Replace {
+            range,
+            text: text.into(),
+        });
+        self
+    }
+
+    pub fn annotate(
+        mut self,
+        range: Range<usize>,
+        type_: impl Into<DefaultAtom>,
+        value: impl Into<Value>,
+    ) -> Self {
+        self.steps.push(Step::Annotate {
+            range,
+            type_: type_.into(),
+            value: value.into(),
+        });
+        self
+    }
+
+    /// Apply `transaction`, e.g. to move the cursors between two edits.
+    pub fn then(mut self, transaction: Transaction) -> Self {
+        self.steps.push(Step::Transaction(transaction));
+        self
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+/// An edit made to the buffer, with what it takes to revert it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JournalEntry {
+    /// `range` was replaced with `inserted`. It held `removed`.
+    Replace {
+        range: Range<usize>,
+        inserted: String,
+        removed: Vec<Span>,
+    },
+    /// `range` was given the attribute `type_`, set to `value`, adding the
+    /// annotations `added`. It looked like `before`.
+    Annotate {
+        range: Range<usize>,
+        type_: DefaultAtom,
+        value: Value,
+        added: Vec<Annotation>,
+        before: Vec<Span>,
+    },
+}
+
+/// The edits made since a compound transaction started, in order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Journal {
+    entries: Vec<JournalEntry>,
+}
+
+impl Journal {
+    pub fn push(&mut self, entry: JournalEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn extend(&mut self, other: Journal) {
+        self.entries.extend(other.entries);
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// Every edit recorded, as a single change to a text `base_len` bytes
+    /// long.
+    pub fn change(&self, base_len: usize) -> ChangeEvent {
+        let mut change = ChangeSet::new(base_len);
+        for entry in &self.entries {
+            match entry {
+                JournalEntry::Replace {
+                    range, inserted, ..
+                } => change.replace(range.clone(), inserted),
+                JournalEntry::Annotate {
+                    range,
+                    type_,
+                    value,
+                    ..
+                } => change.set_attribute(range.clone(), type_.clone(), value.clone()),
+            }
+        }
+
+        change.to_event(true)
+    }
+
+    /// Undo every edit recorded, last first.
+    ///
+    /// Removed text is written back with the annotations it had, set as
+    /// [`TextBuffer::set_attribute`] sets them.
+    pub fn revert<B: TextBuffer>(self, buffer: &mut B) -> Result<(), BluebookCoreError> {
+        for entry in self.entries.into_iter().rev() {
+            match entry {
+                JournalEntry::Replace {
+                    range,
+                    inserted,
+                    removed,
+                } => {
+                    let text: String = removed.iter().map(|span| span.insert.as_str()).collect();
+                    replace_text(buffer, range.start..range.start + inserted.len(), &text)?;
+                    restore_annotations(buffer, range.start, &removed, |_| true);
+                }
+                JournalEntry::Annotate {
+                    range,
+                    type_,
+                    added,
+                    before,
+                    ..
+                } => {
+                    // Merged annotations replaced whatever value the type had.
+                    let merged = added
+                        .iter()
+                        .any(|annotation| annotation.behavior != Behavior::AllowMultiple);
+                    for annotation in added {
+                        buffer.remove_annotation(annotation.id);
+                    }
+                    if merged {
+                        restore_annotations(buffer, range.start, &before, |restored| {
+                            *restored == type_
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Annotate the text at `offset` with the attributes of `spans` whose type
+/// passes `filter`.
+fn restore_annotations<B: TextBuffer>(
+    buffer: &mut B,
+    offset: usize,
+    spans: &[Span],
+    filter: impl Fn(&string_cache::DefaultAtom) -> bool,
+) {
+    for (range, type_, value) in attribute_runs(spans) {
+        if !value.is_null() && filter(&type_) {
+            buffer.set_attribute(
+                ByteOffset(offset + range.start)..ByteOffset(offset + range.end),
+                type_,
+                value,
+            );
+        }
+    }
+}
+
+impl<Buffer> TextEditorContext<Buffer>
+where
+    Buffer: TextBuffer + Searchable,
+{
+    /// Apply every step of `compound`, as a single unit.
+    ///
+    /// If a step fails, the edits made by the steps before it are reverted,
+    /// the selection is restored, and the error is returned. Otherwise, the
+    /// change made by all the steps together is returned, and undoing undoes
+    /// all of it at once. Compound transactions applied while another one is
+    /// being applied become part of it, and are only reverted with it.
+    pub fn apply_compound(
+        &mut self,
+        compound: &CompoundTransaction,
+    ) -> Result<ChangeEvent, BluebookCoreError> {
+        let base_len = self.text_buffer.len();
+        let ((), journal) = self.atomically(|ctx| {
+            compound
+                .steps()
+                .iter()
+                .try_for_each(|step| ctx.apply_step(step))
+        })?;
+
+        Ok(journal.change(base_len))
+    }
+
+    /// Run `apply` as a single unit, undone in one step, returning the edits
+    /// it made.
+    ///
+    /// If it fails, its edits are reverted, the selection is restored, and
+    /// the error is returned. Units run while another one is running become
+    /// part of it, and are only reverted with it.
+    pub(crate) fn atomically<T>(
+        &mut self,
+        apply: impl FnOnce(&mut Self) -> Result<T, BluebookCoreError>,
+    ) -> Result<(T, Journal), BluebookCoreError> {
+        let selection = self.selection.clone();
+        let outer = self.journal.replace(Journal::default());
+        self.text_buffer.begin_undo_group();
+
+        let applied = apply(self);
+        let journal = self.journal.take().unwrap_or_default();
+
+        match applied {
+            Ok(value) => {
+                self.text_buffer.end_undo_group(true);
+                self.journal = outer.map(|mut outer| {
+                    outer.extend(journal.clone());
+                    outer
+                });
+                Ok((value, journal))
+            }
+            Err(err) => {
+                self.journal = outer;
+                let reverted = journal.revert(&mut self.text_buffer);
+                self.text_buffer.end_undo_group(false);
+                self.selection = selection;
+                self.cursor_range = self.selection.primary();
+                reverted.and(Err(err))
+            }
+        }
+    }
+
+    fn apply_step(&mut self, step: &Step) -> Result<(), BluebookCoreError> {
+        match step {
+            Step::Replace { range, text } => {
+                self.edit(range.clone(), text)?;
+                self.remap_selection(&[(range.clone(), text.clone())]);
+            }
+            Step::Annotate {
+                range,
+                type_,
+                value,
+            } => self.annotate(range.clone(), type_.clone(), value.clone())?,
+            Step::Transaction(transaction) => {
+                self.consume_transaction::<Buffer>(transaction.clone())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        buffer::rope::buffer_impl::RopeBuffer, change::ChangeOp, cursor::CursorRange,
+        test_utils::ctx,
+    };
+
+    #[test]
+    fn steps_make_a_single_change() -> Result<(), BluebookCoreError> {
+        let mut ctx = ctx("Roe v. Wade", 0);
+        let compound = CompoundTransaction::new()
+            .insert(0, "See ")
+            .then(Transaction::MoveCursorHeadTo { offset: 15 })
+            .then(Transaction::InsertAtCursorHead {
+                value: ".".to_string(),
+            })
+            .replace(8..10, "vs");
+
+        let change = ctx.apply_compound(&compound)?;
+
+        assert_eq!(ctx.text_buffer.take(), "See Roe vs Wade.");
+        assert_eq!(ctx.cursor_range, CursorRange::new(16, 16));
+        assert_eq!(
+            change.ops,
+            [
+                ChangeOp::insert("See "),
+                ChangeOp::retain(4),
+                ChangeOp::delete(2),
+                ChangeOp::insert("vs"),
+                ChangeOp::retain(5),
+                ChangeOp::insert("."),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn compounds_undo_as_one_step() -> Result<(), BluebookCoreError> {
+        let mut ctx = ctx("Roe v. Wade", 11);
+        ctx.consume_transaction::<RopeBuffer>(Transaction::InsertAtCursorHead {
+            value: ",".to_string(),
+        })?;
+        ctx.apply_compound(&CompoundTransaction::new().insert(0, "See ").delete(8..11))?;
+        assert_eq!(ctx.text_buffer.take(), "See Roe Wade,");
+
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::Undo)?);
+        assert_eq!(ctx.text_buffer.take(), "Roe v. Wade,");
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::Redo)?);
+        assert_eq!(ctx.text_buffer.take(), "See Roe Wade,");
+
+        // A rolled back compound leaves nothing to undo.
+        assert!(ctx
+            .apply_compound(&CompoundTransaction::new().insert(0, "x").insert(40, "y"))
+            .is_err());
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::Undo)?);
+        assert_eq!(ctx.text_buffer.take(), "Roe v. Wade,");
+
+        Ok(())
+    }
+
+    #[test]
+    fn failed_steps_roll_back() {
+        let mut ctx = ctx("Roe v. Wade", 3);
+        let compound = CompoundTransaction::new()
+            .delete(0..4)
+            .then(Transaction::InsertAtCursorHead {
+                value: "x".to_string(),
+            })
+            .insert(40, "out of bounds");
+
+        assert!(ctx.apply_compound(&compound).is_err());
+        assert_eq!(ctx.text_buffer.take(), "Roe v. Wade");
+        assert_eq!(ctx.cursor_range, CursorRange::new(3, 3));
+        assert!(ctx.journal.is_none());
+    }
+}
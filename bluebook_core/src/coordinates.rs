@@ -6,6 +6,7 @@ pub enum ColPosition {
     Col(usize),
 }
 
+#[derive(Clone, Copy, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
 pub enum RowPosition {
     Start,
     End,
@@ -1,5 +1,31 @@
+use std::ops::Range;
+
+use serde_json::Value;
+use string_cache::DefaultAtom;
+use unicode_segmentation::UnicodeSegmentation;
+
 use crate::{
-    command::Transaction, cursor::CursorRange, error::BluebookCoreError, text_buffer::TextBuffer,
+    block::BlockCursor,
+    change::ChangeEvent,
+    clipboard::Fragment,
+    command::Transaction,
+    compound::{Journal, JournalEntry},
+    coordinates::ColPosition,
+    cursor::CursorRange,
+    error::BluebookCoreError,
+    graphemes::GraphemeClusterCursor,
+    line::{normalise_line_endings, LineEnding, LineEndingReport},
+    modal::{LastChange, Modal, ModalState},
+    mode::Mode,
+    movement::{LinePosition, Movement},
+    offset::ByteOffset,
+    paragraph::ParagraphCursor,
+    register::Registers,
+    search::{SearchQuery, Searchable},
+    selection::Selection,
+    sentence::{Abbreviations, SentenceCursor},
+    text_buffer::{CursorError, TextBuffer},
+    word::WordCursor,
 };
 
 pub struct TextEditorContext<Buffer>
@@ -7,33 +33,238 @@ where
     Buffer: TextBuffer,
 {
     pub text_buffer: Buffer,
+    /// The primary range of `selection`, updated after every transaction.
+    /// While a transaction is being applied, it is the range it applies to.
     pub cursor_range: CursorRange,
-    // cursor_mode: CursorMode,
-    // motion_mode: MotionMode,
+    /// Every cursor, the primary one included.
+    pub selection: Selection,
+    /// The last fragment copied or cut. Only its plain text goes to the
+    /// system clipboard.
+    pub clipboard: Option<Fragment>,
+    /// Abbreviations that don't end a sentence, for sentence motions.
+    pub abbreviations: Abbreviations,
+    /// The line endings found in the document when it was loaded.
+    pub line_ending_report: LineEndingReport,
+    /// The line ending new lines and pasted text are written with. The text
+    /// already in the buffer is never rewritten, so saving preserves it.
+    pub line_ending: LineEnding,
+    /// The edits made by the compound transaction being applied, if any.
+    pub(crate) journal: Option<Journal>,
+    /// The editing mode, and the count and operator typed so far.
+    pub modal: ModalState,
+    pub last_change: LastChange,
+    /// Text yanked and deleted by vi commands.
+    pub registers: Registers,
 }
 
 impl<'ctx, Buffer> TextEditorContext<Buffer>
 where
-    Buffer: TextBuffer,
+    Buffer: TextBuffer + Searchable,
 {
     pub fn new(text_buffer: Buffer, cursor_range: CursorRange) -> Self {
+        let line_ending_report = LineEndingReport::detect(&text_buffer.take());
+        let line_ending = line_ending_report.line_ending();
+
         Self {
             text_buffer,
             cursor_range,
+            selection: Selection::single(cursor_range),
+            clipboard: None,
+            abbreviations: Abbreviations::default(),
+            line_ending_report,
+            line_ending,
+            journal: None,
+            modal: ModalState::default(),
+            last_change: LastChange::default(),
+            registers: Registers::default(),
         }
     }
 
+    /// Apply `transaction` to every range of the selection.
+    ///
+    /// The ranges are visited in document order, each one shifted by the edits
+    /// made at the ranges before it, and the selection is only replaced once
+    /// all of them have been applied, merging the ranges that came to overlap.
+    ///
+    /// Outside of insert mode, the transaction is first read as a vi command:
+    /// see [`TextEditorContext::apply_modal`]. Those changing the text are
+    /// recorded for [`Transaction::RepeatLastChange`].
     pub fn consume_transaction<B: TextBuffer>(
         &mut self,
         transaction: Transaction,
+    ) -> Result<bool, BluebookCoreError> {
+        if transaction == Transaction::RepeatLastChange {
+            let repeated = self.repeat_last_change();
+            self.cursor_range = self.selection.primary();
+            return repeated;
+        }
+
+        let mode = self.modal.mode;
+        let prefix = matches!(
+            transaction,
+            Transaction::Count { .. } | Transaction::SelectRegister { .. }
+        );
+        self.last_change.record(&transaction);
+        let applied = self.apply_command(transaction);
+        self.last_change.finish(mode, &self.modal);
+
+        // The register selected applies until the end of the command.
+        if !prefix && self.modal.pending.is_none() {
+            self.modal.register = None;
+        }
+
+        applied
+    }
+
+    /// Apply `transaction` as a vi command, or to every range of the
+    /// selection, as many times as the count typed before it unless it fails.
+    fn apply_command(&mut self, transaction: Transaction) -> Result<bool, BluebookCoreError> {
+        let (transaction, count) = match self.apply_modal(transaction) {
+            Ok(Modal::Pass(transaction, count)) => (transaction, count),
+            done => {
+                self.cursor_range = self.selection.primary();
+                return done.map(|done| matches!(done, Modal::Done(true)));
+            }
+        };
+
+        let mut success = Ok(false);
+        for _ in 0..count.max(1) {
+            match self.apply_to_selection(transaction.clone()) {
+                Ok(true) => success = Ok(true),
+                Ok(false) => break,
+                Err(err) => {
+                    success = Err(err);
+                    break;
+                }
+            }
+        }
+        self.cursor_range = self.selection.primary();
+
+        success
+    }
+
+    fn apply_to_selection(&mut self, transaction: Transaction) -> Result<bool, BluebookCoreError> {
+        match transaction {
+            Transaction::AddCursorAbove => self.add_cursor_vertically(false),
+            Transaction::AddCursorBelow => self.add_cursor_vertically(true),
+            Transaction::AddCursorAtNextMatch => self.add_cursor_at_next_match(),
+            Transaction::SelectAllMatches => {
+                let mut added = false;
+                while self.add_cursor_at_next_match()? {
+                    added = true;
+                }
+                Ok(added)
+            }
+            Transaction::ClipboardCopy => self.copy_selection(),
+            Transaction::Yank => self.yank(),
+            Transaction::PasteAfter => self.put(false),
+            Transaction::PasteBefore => self.put(true),
+            Transaction::ClipboardCut => match self.copy_selection()? {
+                true => self.apply_to_all_ranges(Transaction::DeleteSelection),
+                false => Ok(false),
+            },
+            Transaction::FindNext { query } => {
+                let from = self.selection.primary().to();
+                let found = self.text_buffer.find_next(&query, from)?;
+                Ok(self.select_match(found))
+            }
+            Transaction::FindPrevious { query } => {
+                let to = self.selection.primary().from();
+                let found = self.text_buffer.find_prev(&query, to)?;
+                Ok(self.select_match(found))
+            }
+            Transaction::ReplaceAll { query, replacement } => {
+                self.replace_all(&query, &replacement)
+            }
+            Transaction::RemoveAnnotation { id } => Ok(self.text_buffer.remove_annotation(id)),
+            Transaction::Undo => Ok(self.step_history(Buffer::undo)),
+            Transaction::Redo => Ok(self.step_history(Buffer::redo)),
+            Transaction::KeepPrimaryCursor => {
+                let had_secondary = self.selection.ranges().len() > 1;
+                self.selection.keep_primary();
+                Ok(had_secondary)
+            }
+            transaction => self.apply_to_all_ranges(transaction),
+        }
+    }
+
+    /// Undo or redo with `step`, leaving a single cursor at the end of the
+    /// text that changed.
+    fn step_history(&mut self, step: impl FnOnce(&mut Buffer) -> Option<ChangeEvent>) -> bool {
+        let Some(change) = step(&mut self.text_buffer) else {
+            return false;
+        };
+        if let Some(changed) = change.changed_range() {
+            self.selection = Selection::single(CursorRange::new(changed.end, changed.end));
+        }
+
+        true
+    }
+
+    pub(crate) fn apply_to_all_ranges(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<bool, BluebookCoreError> {
+        self.apply_to_all_ranges_with(|ctx| ctx.apply_transaction(transaction.clone()))
+    }
+
+    /// Apply `apply` to every range of the selection in turn, as
+    /// [`TextEditorContext::consume_transaction`] applies transactions.
+    ///
+    /// With several ranges, the edits are undone in one step, and if one
+    /// range fails, the edits made at the ranges before it are reverted, as
+    /// [`TextEditorContext::atomically`] does. Edits at a single cursor are
+    /// left to group with the edits before them, like typing.
+    pub(crate) fn apply_to_all_ranges_with(
+        &mut self,
+        apply: impl FnMut(&mut Self) -> Result<bool, BluebookCoreError>,
+    ) -> Result<bool, BluebookCoreError> {
+        let apply_to_each = |ctx: &mut Self| ctx.apply_to_each_range(apply);
+        match self.selection.ranges().len() {
+            1 => apply_to_each(self),
+            _ => self.atomically(apply_to_each).map(|(success, _)| success),
+        }
+    }
+
+    fn apply_to_each_range(
+        &mut self,
+        mut apply: impl FnMut(&mut Self) -> Result<bool, BluebookCoreError>,
+    ) -> Result<bool, BluebookCoreError> {
+        let primary = self.selection.primary_index();
+        let mut ranges = Vec::with_capacity(self.selection.ranges().len());
+        let mut shift = 0isize;
+        let mut success = false;
+
+        for range in self.selection.ranges().to_vec() {
+            let len = self.text_buffer.len();
+            self.cursor_range = CursorRange::new(
+                range.anchor.saturating_add_signed(shift),
+                range.head.saturating_add_signed(shift),
+            );
+
+            success |= apply(self)?;
+
+            shift += self.text_buffer.len() as isize - len as isize;
+            ranges.push(self.cursor_range);
+        }
+
+        self.selection = Selection::new(ranges, primary);
+
+        Ok(success)
+    }
+
+    /// Apply `transaction` to `cursor_range` alone.
+    pub(crate) fn apply_transaction(
+        &mut self,
+        transaction: Transaction,
     ) -> Result<bool, BluebookCoreError> {
         let success = match transaction {
             Transaction::DeleteSelection => match self.cursor_range.is_empty() {
                 true => Ok(false),
                 false => {
-                    let CursorRange { anchor, head } = self.cursor_range;
-                    let _ = self.text_buffer.drain(anchor..head)?;
-                    self.cursor_range.set_point(anchor);
+                    let (from, to) = (self.cursor_range.from(), self.cursor_range.to());
+                    self.edit(from..to, "")?;
+                    self.cursor_range.set_point(from);
 
                     Ok(true)
                 }
@@ -42,9 +273,15 @@ where
                 true => {
                     let CursorRange { anchor: _, head } = self.cursor_range;
 
-                    let cursor = self.text_buffer.cursor(self.cursor_range)?;
+                    let cursor = self
+                        .text_buffer
+                        .grapheme_cluster_cursor(ByteOffset(head))
+                        .map_err(CursorError::from)?;
 
-                    let offset = if let Some(offset) = cursor.nth_prev_grapheme_boundary(1)? {
+                    let offset = if let Some(offset) = cursor
+                        .nth_prev_grapheme_cluster_boundary(1)
+                        .map_err(CursorError::from)?
+                    {
                         offset
                     } else {
                         return Ok(false);
@@ -52,7 +289,7 @@ where
 
                     drop(cursor);
 
-                    let _ = self.text_buffer.drain(offset..head)?;
+                    self.edit(offset..head, "")?;
 
                     self.cursor_range.set_point(offset);
 
@@ -64,60 +301,954 @@ where
                     Ok(true)
                 }
             },
-            Transaction::InsertAtCursorHead { value: s } | Transaction::Paste { clipboard: s } => {
+            Transaction::DeleteForward => match self.cursor_range.is_empty() {
+                true => {
+                    let head = self.cursor_range.head;
+                    let Some(offset) = self.movement_offset(head, &Movement::Right, 1)? else {
+                        return Ok(false);
+                    };
+                    self.edit(head..offset, "")?;
+
+                    Ok(true)
+                }
+                false => self.apply_transaction(Transaction::DeleteSelection),
+            },
+            Transaction::ClipboardPaste => match self.clipboard.clone() {
+                Some(fragment) => self.paste_fragment(&fragment),
+                None => Ok(false),
+            },
+            Transaction::Paste { clipboard } => match self.clipboard.clone() {
+                // Nothing else was copied since: paste the fragment, annotations included.
+                Some(fragment) if same_text(&fragment.plain_text(), &clipboard) => {
+                    self.paste_fragment(&fragment)
+                }
+                _ => self.apply_transaction(Transaction::InsertAtCursorHead { value: clipboard }),
+            },
+            Transaction::InsertAtCursorHead { value: s } => {
                 let CursorRange { head, .. } = self.cursor_range;
 
-                let byte_idx = self.text_buffer.write(head, &s)?;
+                let s = normalise_line_endings(&s, self.line_ending);
+                let byte_idx = self.edit(head..head, &s)?;
                 self.cursor_range.set_point(byte_idx);
                 Ok(true)
             }
 
             Transaction::InsertNewLine => {
-                let newline = &"\n".to_string();
+                let newline = self.line_ending.as_str();
                 let CursorRange { head, .. } = self.cursor_range;
-                let byte_idx = self.text_buffer.write(head, newline)?;
+                let byte_idx = self.edit(head..head, newline)?;
                 self.cursor_range.set_point(byte_idx);
                 Ok(true)
             }
 
             Transaction::MoveCursorHeadTo { offset } => {
-                let r = self.cursor_range.set_head(offset);
+                let cursor = self
+                    .text_buffer
+                    .grapheme_cluster_cursor(ByteOffset(offset))
+                    .map_err(CursorError::from)?;
 
-                let cursor = self.text_buffer.cursor(*r)?;
+                if !cursor.is_grapheme_boundary().map_err(CursorError::from)? {
+                    return Ok(false);
+                }
 
-                self.cursor_range.set(cursor.range());
+                self.cursor_range.set_head(offset);
 
                 Ok(true)
             }
             Transaction::MoveCursorLeft { grapheme_count } => {
-                let cursor = self.text_buffer.cursor(self.cursor_range)?;
+                let cursor = self
+                    .text_buffer
+                    .grapheme_cluster_cursor(ByteOffset(self.cursor_range.head))
+                    .map_err(CursorError::from)?;
 
-                let transaction_suceeded =
-                    if let Some(offset) = cursor.nth_prev_grapheme_boundary(grapheme_count)? {
-                        self.cursor_range.set_point(offset);
-                        true
-                    } else {
-                        return Ok(false);
-                    };
+                let transaction_suceeded = if let Some(offset) = cursor
+                    .nth_prev_grapheme_cluster_boundary(grapheme_count)
+                    .map_err(CursorError::from)?
+                {
+                    self.cursor_range.set_point(offset);
+                    true
+                } else {
+                    return Ok(false);
+                };
 
                 Ok(transaction_suceeded)
             }
             Transaction::MoveCursorRight { grapheme_count } => {
-                let cursor = self.text_buffer.cursor(self.cursor_range)?;
+                let cursor = self
+                    .text_buffer
+                    .grapheme_cluster_cursor(ByteOffset(self.cursor_range.head))
+                    .map_err(CursorError::from)?;
 
-                let transaction_suceeded =
-                    if let Some(offset) = cursor.nth_next_grapheme_boundary(grapheme_count)? {
-                        self.cursor_range.set_point(offset);
-                        true
-                    } else {
-                        return Ok(false);
-                    };
+                let transaction_suceeded = if let Some(offset) = cursor
+                    .nth_next_grapheme_cluster_boundary(grapheme_count)
+                    .map_err(CursorError::from)?
+                {
+                    self.cursor_range.set_point(offset);
+                    true
+                } else {
+                    return Ok(false);
+                };
 
                 Ok(transaction_suceeded)
             }
-            _ => Ok(false),
+            Transaction::MoveSentenceForward => self.move_cursor_by(&Movement::SentenceForward, 1),
+            Transaction::MoveSentenceBackward => {
+                self.move_cursor_by(&Movement::SentenceBackward, 1)
+            }
+            Transaction::MoveParagraphForward => {
+                self.move_cursor_by(&Movement::ParagraphForward, 1)
+            }
+            Transaction::MoveParagraphBackward => {
+                self.move_cursor_by(&Movement::ParagraphBackward, 1)
+            }
+            Transaction::SelectSentence => {
+                self.select_around(&Movement::SentenceForward, &Movement::SentenceBackward)
+            }
+            Transaction::SelectParagraph => {
+                self.select_around(&Movement::ParagraphForward, &Movement::ParagraphBackward)
+            }
+            Transaction::MoveBlockForward => self.move_cursor_by(&Movement::BlockForward, 1),
+            Transaction::MoveBlockBackward => self.move_cursor_by(&Movement::BlockBackward, 1),
+            Transaction::MoveHeaderForward { level } => {
+                self.move_cursor_by(&Movement::HeaderForward(level), 1)
+            }
+            Transaction::MoveHeaderBackward { level } => {
+                self.move_cursor_by(&Movement::HeaderBackward(level), 1)
+            }
+            Transaction::MoveToMatchingPair => self.move_cursor_by(&Movement::MatchPairs, 1),
+            Transaction::MoveToNextUnmatched { ch } => {
+                self.move_cursor_by(&Movement::NextUnmatched(ch), 1)
+            }
+            Transaction::MoveToPreviousUnmatched { ch } => {
+                self.move_cursor_by(&Movement::PreviousUnmatched(ch), 1)
+            }
+            Transaction::SelectBlock => {
+                let block = self
+                    .text_buffer
+                    .block_cursor(ByteOffset(self.cursor_range.head))
+                    .map_err(CursorError::from)?
+                    .block();
+
+                self.cursor_range.set_anchor(block.range.start);
+                self.cursor_range.set_head(block.range.end);
+
+                Ok(!block.range.is_empty())
+            }
+            Transaction::SelectTextObject { object, around } => {
+                self.select_text_object(&object, around)
+            }
+            Transaction::SelectWord => {
+                let (start, end) = self
+                    .text_buffer
+                    .word_cursor(ByteOffset(self.cursor_range.head))
+                    .map_err(CursorError::from)?
+                    .select_word();
+
+                self.cursor_range.set_anchor(start);
+                self.cursor_range.set_head(end);
+
+                Ok(start != end)
+            }
+            Transaction::Append => {
+                let head = self.cursor_range.head;
+                let (start, content) = self.line_text(head)?;
+                if head < start + content.len() {
+                    self.move_cursor_by(&Movement::Right, 1)?;
+                }
+
+                Ok(true)
+            }
+            Transaction::AppendEndOfLine => self.move_cursor_by(&Movement::EndOfLine, 1),
+            Transaction::InsertFirstNonBlank => self.move_cursor_by(&Movement::FirstNonBlank, 1),
+            Transaction::NewLineBelow => {
+                let (start, content) = self.line_text(self.cursor_range.head)?;
+                let end = start + content.len();
+                let byte_idx = self.edit(end..end, self.line_ending.as_str())?;
+                self.cursor_range.set_point(byte_idx);
+
+                Ok(true)
+            }
+            Transaction::NewLineAbove => {
+                let start = self.line_at(self.cursor_range.head)?.start;
+                self.edit(start..start, self.line_ending.as_str())?;
+                self.cursor_range.set_point(start);
+
+                Ok(true)
+            }
+            transaction => match transaction.motion() {
+                Some((movement, count)) => self.move_cursor_by(&movement, count),
+                None => Ok(false),
+            },
         };
 
         success
     }
+
+    /// The offset `movement` takes `offset` to, applied `count` times, or `None`
+    /// if it can't move at all. Movements to a line land at its start, and
+    /// [`Movement::WordEndForward`] right after the end of the word. Pair
+    /// matching isn't handled here, and leaves the offset where it is.
+    pub fn movement_offset(
+        &mut self,
+        offset: usize,
+        movement: &Movement,
+        count: usize,
+    ) -> Result<Option<usize>, BluebookCoreError> {
+        let mut target = None;
+
+        match movement {
+            Movement::Left | Movement::Right => {
+                let mut pos = offset;
+                for _ in 0..count.max(1) {
+                    let cursor = self
+                        .text_buffer
+                        .grapheme_cluster_cursor(ByteOffset(pos))
+                        .map_err(CursorError::from)?;
+                    let boundary = match movement {
+                        Movement::Left => cursor.nth_prev_grapheme_cluster_boundary(1),
+                        _ => cursor.nth_next_grapheme_cluster_boundary(1),
+                    }
+                    .map_err(CursorError::from)?;
+                    match boundary {
+                        Some(boundary) => pos = boundary,
+                        None => break,
+                    }
+                }
+                target = (pos != offset).then_some(pos);
+            }
+            Movement::Up | Movement::Down => {
+                target = self.vertical_offset(offset, *movement == Movement::Down, count.max(1))?;
+            }
+            Movement::WordForward | Movement::WordBackward | Movement::WordEndForward => {
+                let mut cursor = self
+                    .text_buffer
+                    .word_cursor(ByteOffset(offset))
+                    .map_err(CursorError::from)?;
+                for _ in 0..count.max(1) {
+                    let boundary = match movement {
+                        Movement::WordForward => cursor.next_boundary(),
+                        Movement::WordBackward => cursor.prev_boundary(Mode::Normal),
+                        _ => cursor.end_boundary(),
+                    };
+                    match boundary {
+                        Some(boundary) => target = Some(boundary),
+                        None => break,
+                    }
+                }
+            }
+            Movement::StartOfLine => target = Some(self.line_at(offset)?.start),
+            Movement::FirstNonBlank => target = Some(self.first_non_blank(offset)?),
+            Movement::EndOfLine => {
+                let (start, content) = self.line_text(offset)?;
+                target = Some(start + content.len());
+            }
+            Movement::DocumentStart | Movement::Line(LinePosition::First) => target = Some(0),
+            Movement::DocumentEnd | Movement::Line(LinePosition::Last) => {
+                target = Some(self.line_at(self.text_buffer.len())?.start);
+            }
+            Movement::Line(LinePosition::Line(line)) => {
+                let mut cursor = self
+                    .text_buffer
+                    .block_cursor(ByteOffset(0))
+                    .map_err(CursorError::from)?;
+                let mut start = 0;
+                for _ in 0..*line {
+                    match cursor.next_boundary() {
+                        Some(boundary) => start = boundary,
+                        None => break,
+                    }
+                }
+                target = Some(start);
+            }
+            Movement::Offset(offset) => target = Some(*offset),
+            Movement::ParagraphForward | Movement::ParagraphBackward => {
+                let mut cursor = self
+                    .text_buffer
+                    .paragraph_cursor(ByteOffset(offset))
+                    .map_err(CursorError::from)?;
+                for _ in 0..count.max(1) {
+                    let boundary = match movement {
+                        Movement::ParagraphForward => cursor.next_boundary(),
+                        _ => cursor.prev_boundary(),
+                    };
+                    match boundary {
+                        Some(boundary) => target = Some(boundary),
+                        None => break,
+                    }
+                }
+            }
+            Movement::SentenceForward | Movement::SentenceBackward => {
+                let mut cursor = self
+                    .text_buffer
+                    .sentence_cursor(ByteOffset(offset))
+                    .map_err(CursorError::from)?;
+                cursor.set_abbreviations(&self.abbreviations);
+                for _ in 0..count.max(1) {
+                    let boundary = match movement {
+                        Movement::SentenceForward => cursor.next_boundary(),
+                        _ => cursor.prev_boundary(),
+                    };
+                    match boundary {
+                        Some(boundary) => target = Some(boundary),
+                        None => break,
+                    }
+                }
+            }
+            Movement::BlockForward
+            | Movement::BlockBackward
+            | Movement::HeaderForward(_)
+            | Movement::HeaderBackward(_) => {
+                let mut cursor = self
+                    .text_buffer
+                    .block_cursor(ByteOffset(offset))
+                    .map_err(CursorError::from)?;
+                for _ in 0..count.max(1) {
+                    let boundary = match movement {
+                        Movement::BlockForward => cursor.next_boundary(),
+                        Movement::BlockBackward => cursor.prev_boundary(),
+                        Movement::HeaderForward(level) => cursor.next_header(*level),
+                        Movement::HeaderBackward(level) => cursor.prev_header(*level),
+                        _ => None,
+                    };
+                    match boundary {
+                        Some(boundary) => target = Some(boundary),
+                        None => break,
+                    }
+                }
+            }
+            Movement::MatchPairs => target = self.match_pairs(offset)?,
+            Movement::NextUnmatched(ch) => target = self.unmatched(offset, *ch, true, count)?,
+            Movement::PreviousUnmatched(ch) => {
+                target = self.unmatched(offset, *ch, false, count)?;
+            }
+        }
+
+        Ok(target)
+    }
+
+    fn move_cursor_by(
+        &mut self,
+        movement: &Movement,
+        count: usize,
+    ) -> Result<bool, BluebookCoreError> {
+        match self.movement_offset(self.cursor_range.head, movement, count)? {
+            Some(offset) => {
+                self.cursor_range.set_point(offset);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Select the unit the head is in: step forward past it, then back to its start.
+    fn select_around(
+        &mut self,
+        forward: &Movement,
+        backward: &Movement,
+    ) -> Result<bool, BluebookCoreError> {
+        let head = self.cursor_range.head;
+        let end = self.movement_offset(head, forward, 1)?.unwrap_or(head);
+        let start = self.movement_offset(end, backward, 1)?.unwrap_or(end);
+
+        self.cursor_range.set_anchor(start);
+        self.cursor_range.set_head(end);
+
+        Ok(start != end)
+    }
+
+    /// Select `found`, dropping every other cursor.
+    fn select_match(&mut self, found: Option<Range<usize>>) -> bool {
+        match found {
+            Some(found) => {
+                self.selection = Selection::single(CursorRange::new(found.start, found.end));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replace every match of `query` in a single transaction, keeping the
+    /// cursors where they were relative to the text around them.
+    fn replace_all(
+        &mut self,
+        query: &SearchQuery,
+        replacement: &str,
+    ) -> Result<bool, BluebookCoreError> {
+        let edits = self.text_buffer.replacements(query, replacement)?;
+
+        // Back to front, so that the ranges still to replace don't move, and
+        // undone at once.
+        self.text_buffer.begin_undo_group();
+        let replaced = edits
+            .iter()
+            .rev()
+            .try_for_each(|(range, replaced)| self.edit(range.clone(), replaced).map(|_| ()));
+        self.text_buffer.end_undo_group(true);
+        replaced?;
+        self.remap_selection(&edits);
+
+        Ok(!edits.is_empty())
+    }
+
+    /// Move every cursor along with the text around it once `edits`, sorted
+    /// and non-overlapping, have been made.
+    pub(crate) fn remap_selection(&mut self, edits: &[(Range<usize>, String)]) {
+        let ranges: Vec<_> = self
+            .selection
+            .ranges()
+            .iter()
+            .map(|range| {
+                CursorRange::new(
+                    offset_after_edits(edits, range.anchor),
+                    offset_after_edits(edits, range.head),
+                )
+            })
+            .collect();
+        self.selection = Selection::new(ranges, self.selection.primary_index());
+        self.cursor_range = self.selection.primary();
+    }
+
+    /// Replace `range` with `text`, returning where the text ends. Every edit
+    /// the context makes goes through here, or through
+    /// [`TextEditorContext::annotate`], to be journaled.
+    pub(crate) fn edit(
+        &mut self,
+        range: Range<usize>,
+        text: &str,
+    ) -> Result<usize, BluebookCoreError> {
+        let removed = match self.journal {
+            Some(_) if !range.is_empty() => self
+                .text_buffer
+                .slice_spans(ByteOffset(range.start)..ByteOffset(range.end))?,
+            _ => Vec::new(),
+        };
+
+        replace_text(&mut self.text_buffer, range.clone(), text)?;
+        self.last_change.changed = true;
+
+        if let Some(journal) = &mut self.journal {
+            journal.push(JournalEntry::Replace {
+                range: range.clone(),
+                inserted: text.to_string(),
+                removed,
+            });
+        }
+
+        Ok(range.start + text.len())
+    }
+
+    pub(crate) fn annotate(
+        &mut self,
+        range: Range<usize>,
+        type_: DefaultAtom,
+        value: Value,
+    ) -> Result<(), BluebookCoreError> {
+        let bytes = ByteOffset(range.start)..ByteOffset(range.end);
+        let before = match self.journal {
+            Some(_) => Some((
+                self.text_buffer.slice_spans(bytes.clone())?,
+                self.text_buffer.annotations_in(bytes.clone())?,
+            )),
+            None => None,
+        };
+
+        self.text_buffer
+            .set_attribute(bytes.clone(), type_.clone(), value.clone());
+        self.last_change.changed = true;
+
+        if let (Some(journal), Some((spans, annotations))) = (&mut self.journal, before) {
+            let added = self
+                .text_buffer
+                .annotations_in(bytes)?
+                .into_iter()
+                .filter(|added| annotations.iter().all(|before| before.id != added.id))
+                .collect();
+            journal.push(JournalEntry::Annotate {
+                range,
+                type_,
+                value,
+                added,
+                before: spans,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Copy the text of every non-empty range into the clipboard, one range
+    /// per line.
+    fn copy_selection(&mut self) -> Result<bool, BluebookCoreError> {
+        let mut fragment = Fragment::default();
+        for range in self
+            .selection
+            .ranges()
+            .iter()
+            .filter(|range| !range.is_empty())
+        {
+            let spans = self
+                .text_buffer
+                .slice_spans(ByteOffset(range.from())..ByteOffset(range.to()))?;
+            fragment.append(self.line_ending.as_str(), Fragment::new(spans));
+        }
+
+        if fragment.is_empty() {
+            return Ok(false);
+        }
+        self.clipboard = Some(fragment);
+
+        Ok(true)
+    }
+
+    pub(crate) fn paste_fragment(
+        &mut self,
+        fragment: &Fragment,
+    ) -> Result<bool, BluebookCoreError> {
+        let fragment = fragment.with_line_ending(self.line_ending);
+        let head = self.cursor_range.head;
+
+        let end = self.edit(head..head, &fragment.plain_text())?;
+        for (range, type_, value) in fragment.annotations() {
+            self.annotate(head + range.start..head + range.end, type_, value)?;
+        }
+        self.cursor_range.set_point(end);
+
+        Ok(true)
+    }
+
+    /// Add a cursor on the line above the first range, or below the last one,
+    /// in the same grapheme column as its head or at the end of the line if
+    /// the line is shorter.
+    fn add_cursor_vertically(&mut self, below: bool) -> Result<bool, BluebookCoreError> {
+        let ranges = self.selection.ranges();
+        let head = match below {
+            true => ranges[ranges.len() - 1].head,
+            false => ranges[0].head,
+        };
+
+        match self.vertical_offset(head, below, 1)? {
+            Some(offset) => {
+                self.selection.push(CursorRange::new(offset, offset));
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// The offset in the same grapheme column as `offset`, `lines` lines below
+    /// or above it, or the end of that line if it is shorter. Stops at the
+    /// first or last line, and is `None` if there is no line to go to.
+    fn vertical_offset(
+        &mut self,
+        offset: usize,
+        below: bool,
+        lines: usize,
+    ) -> Result<Option<usize>, BluebookCoreError> {
+        let col = match self.text_buffer.cursor_coords(ByteOffset(offset))? {
+            (_, ColPosition::Col(col)) => col,
+            _ => 0,
+        };
+
+        let line = {
+            let mut cursor = self
+                .text_buffer
+                .block_cursor(ByteOffset(offset))
+                .map_err(CursorError::from)?;
+            cursor.set(cursor.block().range.start);
+            let mut moved = false;
+            for _ in 0..lines {
+                let boundary = match below {
+                    true => cursor.next_boundary(),
+                    false => cursor.prev_boundary(),
+                };
+                match boundary {
+                    Some(_) => moved = true,
+                    None => break,
+                }
+            }
+            if !moved {
+                return Ok(None);
+            }
+            cursor.block().range
+        };
+
+        let text = self
+            .text_buffer
+            .slice(ByteOffset(line.start)..ByteOffset(line.end))?;
+        let content = text.trim_end_matches(['\n', '\r']);
+        let offset = content
+            .grapheme_indices(true)
+            .nth(col)
+            .map_or(content.len(), |(offset, _)| offset);
+
+        Ok(Some(line.start + offset))
+    }
+
+    /// The line holding `offset`, its line break included.
+    pub(crate) fn line_at(&mut self, offset: usize) -> Result<Range<usize>, CursorError> {
+        Ok(self
+            .text_buffer
+            .block_cursor(ByteOffset(offset))?
+            .block()
+            .range)
+    }
+
+    /// Where the line holding `offset` starts, and its text without the line
+    /// break.
+    pub(crate) fn line_text(
+        &mut self,
+        offset: usize,
+    ) -> Result<(usize, String), BluebookCoreError> {
+        let line = self.line_at(offset)?;
+        let text = self
+            .text_buffer
+            .slice(ByteOffset(line.start)..ByteOffset(line.end))?;
+
+        Ok((line.start, text.trim_end_matches(['\n', '\r']).to_string()))
+    }
+
+    /// The first character of the line holding `offset` that isn't a space or
+    /// a tab, or the end of the line if there is none.
+    pub(crate) fn first_non_blank(&mut self, offset: usize) -> Result<usize, BluebookCoreError> {
+        let (start, content) = self.line_text(offset)?;
+        let indent = content.len() - content.trim_start_matches([' ', '\t']).len();
+
+        Ok(start + indent)
+    }
+
+    /// Select the next occurrence of the primary range's text, wrapping around
+    /// the end of the document. An empty primary range first grows to the word
+    /// it is in.
+    fn add_cursor_at_next_match(&mut self) -> Result<bool, BluebookCoreError> {
+        let primary = self.selection.primary();
+        if primary.is_empty() {
+            let (start, end) = self
+                .text_buffer
+                .word_cursor(ByteOffset(primary.head))
+                .map_err(CursorError::from)?
+                .select_word();
+            if start == end {
+                return Ok(false);
+            }
+            self.selection.set_primary(CursorRange::new(start, end));
+            return Ok(true);
+        }
+
+        // Search on from the range added last, which is the primary one,
+        // until a match that isn't selected yet, or back to the first match.
+        let needle = self
+            .text_buffer
+            .slice(ByteOffset(primary.from())..ByteOffset(primary.to()))?
+            .into_owned();
+        let query = SearchQuery::literal(needle);
+        let mut from = primary.to();
+        let mut first = None;
+        while let Some(found) = self.text_buffer.find_next(&query, from)? {
+            if first == Some(found.start) {
+                break;
+            }
+            first.get_or_insert(found.start);
+
+            let range = CursorRange::new(found.start, found.end);
+            let selected = self
+                .selection
+                .ranges()
+                .iter()
+                .any(|selected| selected.contains_range(&range));
+            if !selected {
+                self.selection
+                    .push(range.with_orientation(primary.orientation()));
+                return Ok(true);
+            }
+            from = found.end;
+        }
+
+        Ok(false)
+    }
+}
+
+/// Where `offset` ends up once every edit in `edits`, sorted and
+/// non-overlapping, has been made. An offset inside a replaced range is kept
+/// inside its replacement.
+fn offset_after_edits(edits: &[(Range<usize>, String)], offset: usize) -> usize {
+    let mut shift = 0isize;
+    for (range, replaced) in edits {
+        if range.start >= offset {
+            break;
+        }
+        if range.end > offset {
+            let within = (offset - range.start).min(replaced.len());
+            return range.start.saturating_add_signed(shift) + within;
+        }
+        shift += replaced.len() as isize - range.len() as isize;
+    }
+
+    offset.saturating_add_signed(shift)
+}
+
+/// Replace `range` of `buffer` with `text`, as a plain write or drain when
+/// either is empty.
+pub(crate) fn replace_text<B: TextBuffer>(
+    buffer: &mut B,
+    range: Range<usize>,
+    text: &str,
+) -> Result<(), CursorError> {
+    let bytes = ByteOffset(range.start)..ByteOffset(range.end);
+    match (range.is_empty(), text.is_empty()) {
+        (true, _) => {
+            buffer.write(bytes.start, text)?;
+        }
+        (false, true) => {
+            buffer.drain(bytes)?;
+        }
+        (false, false) => {
+            buffer.replace_range(bytes, text)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `a` and `b` are the same text, up to their line endings, which the
+/// system clipboard may have rewritten.
+fn same_text(a: &str, b: &str) -> bool {
+    normalise_line_endings(a, LineEnding::LF) == normalise_line_endings(b, LineEnding::LF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{buffer::rope::buffer_impl::RopeBuffer, test_utils::ctx};
+
+    #[test]
+    fn paragraph_jumps() -> Result<(), BluebookCoreError> {
+        let mut ctx = ctx("one\n\ntwo\nlines\n\nthree", 0);
+
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::MoveParagraphForward)?);
+        assert_eq!(ctx.cursor_range.head, 4);
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::MoveParagraphForward)?);
+        assert_eq!(ctx.cursor_range.head, 15);
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::MoveParagraphBackward)?);
+        assert_eq!(ctx.cursor_range.head, 4);
+
+        assert_eq!(
+            ctx.movement_offset(0, &Movement::ParagraphForward, 2)?,
+            Some(15)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn new_lines_follow_the_document_line_ending() -> Result<(), BluebookCoreError> {
+        let mut ctx = ctx("one\r\ntwo\r\nthree\n", 3);
+        assert!(ctx.line_ending_report.is_mixed());
+        assert_eq!(ctx.line_ending, LineEnding::Crlf);
+
+        ctx.consume_transaction::<RopeBuffer>(Transaction::InsertNewLine)?;
+        ctx.consume_transaction::<RopeBuffer>(Transaction::Paste {
+            clipboard: "a\nb".to_string(),
+        })?;
+
+        assert_eq!(ctx.text_buffer.take(), "one\r\na\r\nb\r\ntwo\r\nthree\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn select_paragraph() -> Result<(), BluebookCoreError> {
+        let mut ctx = ctx("one\n\ntwo\nlines\n\nthree", 7);
+
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::SelectParagraph)?);
+        assert_eq!(ctx.cursor_range, CursorRange::new(4, 15));
+
+        Ok(())
+    }
+
+    #[test]
+    fn block_jumps_and_selection() -> Result<(), BluebookCoreError> {
+        let mut ctx = ctx("one\ntwo\nthree", 5);
+
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::MoveBlockForward)?);
+        assert_eq!(ctx.cursor_range.head, 8);
+        assert!(!ctx.consume_transaction::<RopeBuffer>(Transaction::MoveBlockForward)?);
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::SelectBlock)?);
+        assert_eq!(ctx.cursor_range, CursorRange::new(8, 13));
+
+        // A plain rope has no headers to jump to.
+        assert_eq!(
+            ctx.movement_offset(0, &Movement::HeaderForward(None), 1)?,
+            None
+        );
+        assert_eq!(
+            ctx.movement_offset(13, &Movement::BlockBackward, 2)?,
+            Some(4)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn edits_apply_to_every_cursor() -> Result<(), BluebookCoreError> {
+        let mut ctx = ctx("ab\ncd\nef", 1);
+
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::AddCursorBelow)?);
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::AddCursorBelow)?);
+        assert!(!ctx.consume_transaction::<RopeBuffer>(Transaction::AddCursorBelow)?);
+        ctx.consume_transaction::<RopeBuffer>(Transaction::InsertAtCursorHead {
+            value: "-".to_string(),
+        })?;
+
+        assert_eq!(ctx.text_buffer.take(), "a-b\nc-d\ne-f");
+        assert_eq!(
+            ctx.selection.ranges(),
+            [
+                CursorRange::new(2, 2),
+                CursorRange::new(6, 6),
+                CursorRange::new(10, 10)
+            ]
+        );
+        assert_eq!(ctx.cursor_range, CursorRange::new(10, 10));
+
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::KeepPrimaryCursor)?);
+        assert_eq!(ctx.selection.ranges(), [CursorRange::new(10, 10)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn edits_at_every_cursor_undo_at_once() -> Result<(), BluebookCoreError> {
+        let mut ctx = ctx("ab\ncd\nef", 1);
+        ctx.consume_transaction::<RopeBuffer>(Transaction::AddCursorBelow)?;
+        ctx.consume_transaction::<RopeBuffer>(Transaction::AddCursorBelow)?;
+
+        ctx.consume_transaction::<RopeBuffer>(Transaction::Paste {
+            clipboard: "--".to_string(),
+        })?;
+        assert_eq!(ctx.text_buffer.take(), "a--b\nc--d\ne--f");
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::Undo)?);
+        assert_eq!(ctx.text_buffer.take(), "ab\ncd\nef");
+
+        // A range failing reverts the edits made at the ranges before it.
+        let selection = ctx.selection.clone();
+        let failed = ctx.apply_to_all_ranges_with(|ctx| match ctx.cursor_range.head {
+            head if head < 6 => ctx.edit(head..head, "x").map(|_| true),
+            _ => ctx.edit(40..40, "x").map(|_| true),
+        });
+        assert!(failed.is_err());
+        assert_eq!(ctx.text_buffer.take(), "ab\ncd\nef");
+        assert_eq!(ctx.selection, selection);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cursors_keep_their_column() -> Result<(), BluebookCoreError> {
+        let mut ctx = ctx("abcdef\nab\nabcdef", 4);
+
+        ctx.consume_transaction::<RopeBuffer>(Transaction::AddCursorBelow)?;
+        assert_eq!(ctx.cursor_range, CursorRange::new(9, 9));
+
+        let mut ctx = self::ctx("abcdef\nabcdef", 11);
+        ctx.consume_transaction::<RopeBuffer>(Transaction::AddCursorAbove)?;
+        assert_eq!(ctx.cursor_range, CursorRange::new(4, 4));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rename_every_match() -> Result<(), BluebookCoreError> {
+        let mut ctx = ctx("Acme shall pay. Acme may not. Acme Corp.", 2);
+
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::SelectAllMatches)?);
+        assert_eq!(ctx.selection.ranges().len(), 3);
+
+        ctx.consume_transaction::<RopeBuffer>(Transaction::DeleteSelection)?;
+        ctx.consume_transaction::<RopeBuffer>(Transaction::InsertAtCursorHead {
+            value: "Globex".to_string(),
+        })?;
+
+        assert_eq!(
+            ctx.text_buffer.take(),
+            "Globex shall pay. Globex may not. Globex Corp."
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn overlapping_cursors_merge() -> Result<(), BluebookCoreError> {
+        let mut ctx = ctx("abc", 1);
+        ctx.selection.push(CursorRange::new(2, 2));
+
+        ctx.consume_transaction::<RopeBuffer>(Transaction::MoveCursorLeft { grapheme_count: 1 })?;
+        ctx.consume_transaction::<RopeBuffer>(Transaction::MoveCursorLeft { grapheme_count: 1 })?;
+
+        assert_eq!(ctx.selection.ranges(), [CursorRange::new(0, 0)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn copy_cut_and_paste() -> Result<(), BluebookCoreError> {
+        let mut ctx = ctx("one two", 0);
+        ctx.selection = Selection::single(CursorRange::new(4, 7));
+
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::ClipboardCut)?);
+        assert_eq!(ctx.text_buffer.take(), "one ");
+        assert_eq!(
+            ctx.clipboard.as_ref().map(Fragment::plain_text).as_deref(),
+            Some("two")
+        );
+
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::ClipboardPaste)?);
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::Paste {
+            clipboard: " and three".to_string(),
+        })?);
+        assert_eq!(ctx.text_buffer.take(), "one two and three");
+
+        ctx.selection = Selection::new([CursorRange::new(0, 3), CursorRange::new(8, 11)], 0);
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::ClipboardCopy)?);
+        assert_eq!(
+            ctx.clipboard
+                .map(|fragment| fragment.plain_text())
+                .as_deref(),
+            Some("one\nand")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn find_and_replace() -> Result<(), BluebookCoreError> {
+        let mut ctx = ctx("Roe v. Wade; roe v. wade", 21);
+        let query = SearchQuery::literal("wade").case_insensitive();
+
+        assert!(
+            ctx.consume_transaction::<RopeBuffer>(Transaction::FindNext {
+                query: query.clone()
+            })?
+        );
+        assert_eq!(ctx.cursor_range, CursorRange::new(7, 11));
+        assert!(
+            ctx.consume_transaction::<RopeBuffer>(Transaction::FindPrevious {
+                query: query.clone()
+            })?
+        );
+        assert_eq!(ctx.cursor_range, CursorRange::new(20, 24));
+
+        ctx.selection = Selection::single(CursorRange::new(13, 13));
+        assert!(
+            ctx.consume_transaction::<RopeBuffer>(Transaction::ReplaceAll {
+                query,
+                replacement: "Bolton".to_string(),
+            })?
+        );
+        assert_eq!(ctx.text_buffer.take(), "Roe v. Bolton; roe v. Bolton");
+        assert_eq!(ctx.cursor_range, CursorRange::new(15, 15));
+
+        assert!(ctx.consume_transaction::<RopeBuffer>(Transaction::Undo)?);
+        assert_eq!(ctx.text_buffer.take(), "Roe v. Wade; roe v. wade");
+
+        Ok(())
+    }
 }
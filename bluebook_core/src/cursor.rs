@@ -5,8 +5,9 @@ use serde::{Deserialize, Serialize};
 /// We have a variety of cursor traits, which are used to find the next cursor offset, given an
 /// underlying text buffer, cursor mode etc.
 
-enum CursorOrientation {
-    Foward,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorOrientation {
+    Forward,
     Backward,
 }
 
@@ -2,15 +2,19 @@ use crate::ctx::TextEditorContext;
 use crate::expr::Expr;
 
 use crate::{
-    command::Transaction, text_buffer::TextBuffer,
+    command::Transaction,
+    keymap::{Input, Keymap, PendingKeys},
+    text_buffer::TextBuffer,
 };
 
-
 pub struct TextEditor<Buf: TextBuffer, EventRep, ViewCtx> {
     pub edit_ctx: TextEditorContext<Buf>,
-    pub transact_fn: fn(&TextEditorContext<Buf>, &EventRep) -> Option<Transaction>,
+    pub transact_fn: fn(&TextEditorContext<Buf>, &EventRep) -> Option<Input>,
     // pub cursor_view_transform_fn: fn(&CursorDocCoords, CursorFnArgs) -> CursorDocCoords,
     pub view_ctx: ViewCtx,
+    /// What the keys read by `transact_fn` are bound to.
+    pub keymap: Keymap,
+    pub pending_keys: PendingKeys,
 }
 
 impl<Buf: TextBuffer, EventRep, ViewCtx> Expr for TextEditor<Buf, EventRep, ViewCtx> {
@@ -29,7 +33,7 @@ impl<Buf: TextBuffer, EventRep, ViewCtx> Expr for TextEditor<Buf, EventRep, View
 impl<Buf: TextBuffer, EventRep, ViewCtx> TextEditor<Buf, EventRep, ViewCtx> {
     pub fn new(
         edit_ctx: TextEditorContext<Buf>,
-        transact_fn: fn(&TextEditorContext<Buf>, &EventRep) -> Option<Transaction>,
+        transact_fn: fn(&TextEditorContext<Buf>, &EventRep) -> Option<Input>,
         // cursor_view_transform_fn: fn(&CursorDocCoords, CursorFnArgs) -> CursorDocCoords,
         view_ctx: ViewCtx,
     ) -> Self {
@@ -37,6 +41,8 @@ impl<Buf: TextBuffer, EventRep, ViewCtx> TextEditor<Buf, EventRep, ViewCtx> {
             edit_ctx,
             transact_fn,
             view_ctx,
+            keymap: Keymap::default(),
+            pending_keys: PendingKeys::default(),
         }
     }
     pub fn edit_ctx(&mut self) -> &mut TextEditorContext<Buf> {
@@ -45,8 +51,15 @@ impl<Buf: TextBuffer, EventRep, ViewCtx> TextEditor<Buf, EventRep, ViewCtx> {
     pub fn view_ctx(&mut self) -> &mut ViewCtx {
         &mut self.view_ctx
     }
-    pub fn emit_transcation(&self, event: &EventRep) -> Option<Transaction> {
-        Self::app(&(self.edit_ctx), self.transact_fn, event)
+    /// The transaction `event` applies, looking keys up in the keymap. Keys
+    /// starting a longer sequence apply nothing until it is complete.
+    pub fn emit_transcation(&mut self, event: &EventRep) -> Option<Transaction> {
+        match Self::app(&(self.edit_ctx), self.transact_fn, event)? {
+            Input::Transaction(transaction) => Some(transaction),
+            Input::Key(key) => self
+                .pending_keys
+                .feed(&self.keymap, &self.edit_ctx.modal, key),
+        }
     }
 }
 
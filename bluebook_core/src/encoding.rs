@@ -1,4 +1,6 @@
-use std::{ffi::OsStr, os::unix::prelude::OsStringExt};
+use std::ffi::OsStr;
+
+use crate::line::{normalise_line_endings, LineEnding};
 
 /// A code point boundary refers to the position within a sequence of text where a Unicode code point starts or ends.
 /// In Unicode, characters are represented by numeric values called code points. Each code point corresponds to a
@@ -22,13 +24,17 @@ use std::{ffi::OsStr, os::unix::prelude::OsStringExt};
 /// represented by the code point U+1F600, starts at the beginning of a 16-bit code unit sequence (D83D DC00
 /// in hexadecimal) and ends at the end of that sequence.
 
-fn normalise_copied_str(os_str: OsStr) -> String {
-    // Simulate copying text from the clipboard (UTF-16 representation)
-    let utf16_text: Vec<u16> = os_str.encode_wide().collect();
-    // Convert UTF-16 to UTF-8 and store it in a String
-    let utf8_text = String::from_utf16_lossy(&utf16_text);
+/// Turn text copied from the system clipboard into a `String` whose line endings
+/// follow `line_ending`. Whatever can't be decoded is replaced with U+FFFD.
+pub fn normalise_copied_str(os_str: &OsStr, line_ending: LineEnding) -> String {
+    normalise_line_endings(&os_str.to_string_lossy(), line_ending).into_owned()
+}
+
+/// Like [`normalise_copied_str`], for clipboards that hand out utf16 code units.
+pub fn normalise_copied_utf16(utf16_text: &[u16], line_ending: LineEnding) -> String {
+    let utf8_text = String::from_utf16_lossy(utf16_text);
 
-    utf8_text
+    normalise_line_endings(&utf8_text, line_ending).into_owned()
 }
 
 /// Convert a utf8 offset into a utf16 offset, if possible  
@@ -132,7 +138,26 @@ pub fn offset_utf16_to_utf8_str(text: &str, offset: usize) -> usize {
 mod tests {
     // TODO: more tests with unicode characters
 
-    use crate::encoding::{offset_utf16_to_utf8_str, offset_utf8_to_utf16_str};
+    use std::ffi::OsStr;
+
+    use crate::{
+        encoding::{
+            normalise_copied_str, normalise_copied_utf16, offset_utf16_to_utf8_str,
+            offset_utf8_to_utf16_str,
+        },
+        line::LineEnding,
+    };
+
+    #[test]
+    fn copied_text_follows_the_document_line_ending() {
+        assert_eq!(
+            normalise_copied_str(OsStr::new("a\nb"), LineEnding::Crlf),
+            "a\r\nb"
+        );
+
+        let utf16_text: Vec<u16> = "é\r\n😀".encode_utf16().collect();
+        assert_eq!(normalise_copied_utf16(&utf16_text, LineEnding::LF), "é\n😀");
+    }
 
     #[test]
     fn utf8_to_utf16() {
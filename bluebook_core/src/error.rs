@@ -1,4 +1,4 @@
-use crate::text_buffer;
+use crate::{search, text_buffer};
 
 #[derive(thiserror::Error, Debug)]
 pub enum BluebookCoreError {
@@ -6,4 +6,8 @@ pub enum BluebookCoreError {
     CursorError(#[from] text_buffer::CursorError),
     #[error(transparent)]
     ConversionError(#[from] text_buffer::ConversionError),
+    #[error(transparent)]
+    SearchError(#[from] search::SearchError),
+    #[error(transparent)]
+    PeritextError(#[from] peritext::rich_text::Error),
 }
@@ -0,0 +1,713 @@
+//! Keymaps: which key sequences apply which transactions, in which modes.
+//!
+//! Keymaps load from TOML or JSON files listing bindings:
+//!
+//! ```toml
+//! [[keymaps]]
+//! key = "g g"
+//! command = "move_to_document_start"
+//! mode = "nvo"
+//! ```
+//!
+//! The keys of a sequence are separated by spaces, and modifiers are joined
+//! to a key with `+`, as in `ctrl+shift+z`. Commands are the names
+//! transactions serialise to, as [`Transaction::from_name`] reads them, and
+//! modes are read by [`Modes::parse`]; a binding without modes applies in
+//! all of them. User keymaps are loaded over the default one with
+//! [`Keymap::extend`].
+
+use std::{fmt, path::Path, str::FromStr};
+
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    command::Transaction,
+    modal::ModalState,
+    mode::{Mode, Modes},
+    register::is_register,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum KeymapError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Toml(#[from] toml::de::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("Keymaps are read from .toml or .json files, not {0}")]
+    UnknownFormat(String),
+    #[error("Not a key: {0}")]
+    UnknownKey(String),
+    #[error("Not a command: {0}")]
+    UnknownCommand(String),
+    #[error("No keys to bind {0} to")]
+    NoKeys(String),
+}
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+    pub struct Modifiers: u8 {
+        const CTRL = 0x1;
+        const ALT = 0x2;
+        const SHIFT = 0x4;
+        /// Command on macOS, the Windows key elsewhere.
+        const META = 0x8;
+    }
+}
+
+/// Keys without a character of their own, by the names keymaps give them.
+const NAMED_KEYS: [&str; 15] = [
+    "esc",
+    "enter",
+    "tab",
+    "space",
+    "backspace",
+    "delete",
+    "insert",
+    "left",
+    "right",
+    "up",
+    "down",
+    "home",
+    "end",
+    "pageup",
+    "pagedown",
+];
+
+/// A key pressed along with some modifiers.
+///
+/// Keys typing a character are named by it, so "G" is a shifted "g", and
+/// shift only applies to named keys, and to keys pressed with another
+/// modifier.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct KeyPress {
+    pub key: String,
+    pub modifiers: Modifiers,
+}
+
+impl KeyPress {
+    pub fn new(key: impl Into<String>, modifiers: Modifiers) -> Self {
+        Self {
+            key: key.into(),
+            modifiers,
+        }
+    }
+
+    /// The key typing `ch`, without modifiers.
+    pub fn char(ch: char) -> Self {
+        Self::new(ch, Modifiers::empty())
+    }
+
+    /// The character the key types, if it types one without modifiers.
+    pub fn text(&self) -> Option<char> {
+        let mut chars = self.key.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) if self.modifiers.is_empty() => Some(ch),
+            _ => None,
+        }
+    }
+
+    /// The digit the key types, if it types one without modifiers.
+    pub fn digit(&self) -> Option<usize> {
+        self.text()?.to_digit(10).map(|digit| digit as usize)
+    }
+}
+
+impl FromStr for KeyPress {
+    type Err = KeymapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // "+" is a key too, as in "ctrl++".
+        let (modifiers, key) = match s.strip_suffix('+') {
+            Some(rest) if rest.is_empty() || rest.ends_with('+') => {
+                (rest.strip_suffix('+').unwrap_or(rest), "+")
+            }
+            _ => s.rsplit_once('+').unwrap_or(("", s)),
+        };
+
+        let mut parsed = Modifiers::empty();
+        for modifier in modifiers.split('+').filter(|modifier| !modifier.is_empty()) {
+            parsed |= match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => Modifiers::CTRL,
+                "alt" | "option" => Modifiers::ALT,
+                "shift" => Modifiers::SHIFT,
+                "meta" | "cmd" | "super" => Modifiers::META,
+                _ => return Err(KeymapError::UnknownKey(s.to_string())),
+            };
+        }
+
+        if key.chars().count() == 1 {
+            // Keys pressed with ctrl or meta are named by their lowercase
+            // character, as they type nothing.
+            let key = match parsed.intersects(Modifiers::CTRL | Modifiers::META) {
+                true => key.to_lowercase(),
+                false => key.to_string(),
+            };
+            return Ok(Self::new(key, parsed));
+        }
+
+        let key = match key.to_lowercase().as_str() {
+            "escape" => "esc".to_string(),
+            "return" => "enter".to_string(),
+            "del" => "delete".to_string(),
+            key => key.trim_start_matches("arrow").to_string(),
+        };
+        match NAMED_KEYS.contains(&key.as_str()) {
+            true => Ok(Self::new(key, parsed)),
+            false => Err(KeymapError::UnknownKey(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for KeyPress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (Modifiers::CTRL, "ctrl+"),
+            (Modifiers::ALT, "alt+"),
+            (Modifiers::SHIFT, "shift+"),
+            (Modifiers::META, "meta+"),
+        ];
+        for (modifier, name) in names {
+            if self.modifiers.contains(modifier) {
+                f.write_str(name)?;
+            }
+        }
+
+        f.write_str(&self.key)
+    }
+}
+
+/// Parse keys separated by spaces, like "g g" or "ctrl+k ctrl+c".
+pub fn parse_keys(s: &str) -> Result<Vec<KeyPress>, KeymapError> {
+    s.split_whitespace().map(KeyPress::from_str).collect()
+}
+
+/// What a frontend reads an event as: a key to look up in the keymap, or a
+/// transaction to apply as it is, like inserting typed text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Input {
+    Key(KeyPress),
+    Transaction(Transaction),
+}
+
+/// A binding, as keymap files list them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeymapEntry {
+    pub key: String,
+    /// The command to bind, or to unbind from the keys if prefixed with `-`.
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeymapFile {
+    #[serde(default)]
+    pub keymaps: Vec<KeymapEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Binding {
+    pub keys: Vec<KeyPress>,
+    pub modes: Modes,
+    pub transaction: Transaction,
+}
+
+impl Binding {
+    fn parse(entry: &KeymapEntry) -> Result<(Self, bool), KeymapError> {
+        let keys = parse_keys(&entry.key)?;
+        if keys.is_empty() {
+            return Err(KeymapError::NoKeys(entry.command.clone()));
+        }
+        let (name, unbind) = match entry.command.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (entry.command.as_str(), false),
+        };
+        let transaction = Transaction::from_name(name)
+            .ok_or_else(|| KeymapError::UnknownCommand(entry.command.clone()))?;
+        let modes = entry.mode.as_deref().map_or(Modes::all(), Modes::parse);
+
+        Ok((
+            Self {
+                keys,
+                modes,
+                transaction,
+            },
+            unbind,
+        ))
+    }
+}
+
+/// Two bindings that get in each other's way, in the modes they share.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Conflict {
+    /// Both bindings have the same keys, and the later one wins.
+    Duplicate {
+        first: Binding,
+        second: Binding,
+        modes: Modes,
+    },
+    /// The keys of `shadowed` start those of `by`, so they wait for more keys
+    /// and never apply `shadowed`.
+    Prefix {
+        shadowed: Binding,
+        by: Binding,
+        modes: Modes,
+    },
+}
+
+/// The result of looking keys up in a keymap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Lookup {
+    Matched(Transaction),
+    /// The keys start some binding, and more are needed.
+    Pending,
+    Unbound,
+}
+
+/// Key sequences bound to transactions, per mode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    bindings: Vec<Binding>,
+    /// Bindings this keymap removes from the one it extends.
+    unbound: Vec<Binding>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::from_toml(include_str!("default.toml")).expect("the default keymap is valid")
+    }
+}
+
+impl Keymap {
+    /// A keymap binding nothing.
+    pub fn empty() -> Self {
+        Self {
+            bindings: Vec::new(),
+            unbound: Vec::new(),
+        }
+    }
+
+    pub fn from_file(file: &KeymapFile) -> Result<Self, KeymapError> {
+        let mut keymap = Self::empty();
+        for entry in &file.keymaps {
+            match Binding::parse(entry)? {
+                (binding, true) => keymap.unbound.push(binding),
+                (binding, false) => keymap.bindings.push(binding),
+            }
+        }
+
+        Ok(keymap)
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self, KeymapError> {
+        Self::from_file(&toml::from_str(s)?)
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, KeymapError> {
+        Self::from_file(&serde_json::from_str(s)?)
+    }
+
+    /// Load the keymap at `path`, as TOML or JSON depending on its extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, KeymapError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Self::from_toml(&contents),
+            Some("json") => Self::from_json(&contents),
+            _ => Err(KeymapError::UnknownFormat(path.display().to_string())),
+        }
+    }
+
+    /// Bind `keys` to `transaction` in `modes`, in place of whatever they
+    /// were bound to there.
+    pub fn bind(&mut self, keys: Vec<KeyPress>, modes: Modes, transaction: Transaction) {
+        self.unbind(&keys, modes, None);
+        self.bindings.push(Binding {
+            keys,
+            modes,
+            transaction,
+        });
+    }
+
+    /// Unbind `keys` in `modes`, from `transaction` only if given.
+    pub fn unbind(&mut self, keys: &[KeyPress], modes: Modes, transaction: Option<&Transaction>) {
+        for binding in &mut self.bindings {
+            let unbinds = match transaction {
+                Some(transaction) => binding.transaction == *transaction,
+                None => true,
+            };
+            if unbinds && binding.keys == keys {
+                binding.modes.remove(modes);
+            }
+        }
+        self.bindings.retain(|binding| !binding.modes.is_empty());
+    }
+
+    /// Load `overrides` over this keymap: its bindings replace the ones for
+    /// the same keys and modes, and its unbindings remove them.
+    pub fn extend(&mut self, overrides: Keymap) {
+        for binding in &overrides.unbound {
+            self.unbind(&binding.keys, binding.modes, Some(&binding.transaction));
+        }
+        for binding in overrides.bindings {
+            self.bind(binding.keys, binding.modes, binding.transaction);
+        }
+    }
+
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+
+    /// Look up `keys` in `modes`. The last binding for them wins.
+    pub fn lookup(&self, modes: Modes, keys: &[KeyPress]) -> Lookup {
+        let mut lookup = Lookup::Unbound;
+        for binding in self
+            .bindings
+            .iter()
+            .filter(|binding| binding.modes.intersects(modes))
+        {
+            if binding.keys.len() > keys.len() && binding.keys.starts_with(keys) {
+                return Lookup::Pending;
+            }
+            if binding.keys == keys {
+                lookup = Lookup::Matched(binding.transaction.clone());
+            }
+        }
+
+        lookup
+    }
+
+    /// Every pair of bindings getting in each other's way: the same keys
+    /// bound twice in a mode, or keys starting a longer sequence bound in
+    /// the same mode.
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        let mut conflicts = Vec::new();
+        for (index, first) in self.bindings.iter().enumerate() {
+            for second in &self.bindings[index + 1..] {
+                let modes = first.modes & second.modes;
+                if modes.is_empty() {
+                    continue;
+                }
+                let (shorter, longer) = match first.keys.len() <= second.keys.len() {
+                    true => (first, second),
+                    false => (second, first),
+                };
+                if shorter.keys == longer.keys {
+                    conflicts.push(Conflict::Duplicate {
+                        first: first.clone(),
+                        second: second.clone(),
+                        modes,
+                    });
+                } else if longer.keys.starts_with(&shorter.keys) {
+                    conflicts.push(Conflict::Prefix {
+                        shadowed: shorter.clone(),
+                        by: longer.clone(),
+                        modes,
+                    });
+                }
+            }
+        }
+
+        conflicts
+    }
+}
+
+/// The keys typed so far of a sequence not complete yet.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PendingKeys {
+    keys: Vec<KeyPress>,
+    /// Whether the next key names a register rather than a command.
+    awaiting_register: bool,
+}
+
+impl PendingKeys {
+    pub fn keys(&self) -> &[KeyPress] {
+        &self.keys
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.awaiting_register = false;
+    }
+
+    /// Add `key` to the keys typed so far, returning the transaction they
+    /// are bound to in the mode `modal` is in, once complete.
+    ///
+    /// Digits starting a sequence in normal or visual mode are counts, except
+    /// for a leading zero, and keys that complete no binding are dropped
+    /// along with those before them, unless the last one is bound on its own.
+    /// The key after one bound to selecting a register names the register.
+    pub fn feed(
+        &mut self,
+        keymap: &Keymap,
+        modal: &ModalState,
+        key: KeyPress,
+    ) -> Option<Transaction> {
+        let modes = match (modal.mode, &modal.pending) {
+            (Mode::Normal | Mode::Visual, Some(_)) => Modes::OPERATOR_PENDING,
+            (mode, _) => Modes::from(mode),
+        };
+
+        if self.awaiting_register {
+            self.awaiting_register = false;
+            return key
+                .text()
+                .filter(|name| is_register(*name))
+                .map(|name| Transaction::SelectRegister { name });
+        }
+
+        let counts = matches!(modal.mode, Mode::Normal | Mode::Visual);
+        match key.digit() {
+            Some(digit)
+                if counts && self.keys.is_empty() && (digit > 0 || modal.count.is_some()) =>
+            {
+                return Some(Transaction::Count { digit });
+            }
+            _ => {}
+        }
+
+        self.keys.push(key);
+        match keymap.lookup(modes, &self.keys) {
+            Lookup::Matched(Transaction::SelectRegister { .. }) => {
+                self.keys.clear();
+                self.awaiting_register = true;
+                None
+            }
+            Lookup::Matched(transaction) => {
+                self.keys.clear();
+                Some(transaction)
+            }
+            Lookup::Pending => None,
+            Lookup::Unbound => {
+                let last = self.keys.pop();
+                let retry = !self.keys.is_empty();
+                self.keys.clear();
+                match last {
+                    Some(last) if retry => self.feed(keymap, modal, last),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(s: &str) -> Vec<KeyPress> {
+        parse_keys(s).unwrap()
+    }
+
+    fn normal() -> ModalState {
+        ModalState {
+            mode: Mode::Normal,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keys_parse_and_print() -> Result<(), KeymapError> {
+        let press: KeyPress = "Ctrl+Shift+Z".parse()?;
+        assert_eq!(
+            press,
+            KeyPress::new("z", Modifiers::CTRL | Modifiers::SHIFT)
+        );
+        assert_eq!(press.to_string(), "ctrl+shift+z");
+
+        assert_eq!(
+            "ctrl++".parse::<KeyPress>()?,
+            KeyPress::new("+", Modifiers::CTRL)
+        );
+        assert_eq!(
+            "ArrowLeft".parse::<KeyPress>()?,
+            KeyPress::new("left", Modifiers::empty())
+        );
+        assert_eq!(keys("g G"), [KeyPress::char('g'), KeyPress::char('G')]);
+        assert!("hyper+x".parse::<KeyPress>().is_err());
+        assert!("leftish".parse::<KeyPress>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn default_keymap_loads_without_conflicts() {
+        let keymap = Keymap::default();
+
+        assert_eq!(keymap.conflicts(), []);
+        assert_eq!(
+            keymap.lookup(Modes::INSERT, &keys("ctrl+z")),
+            Lookup::Matched(Transaction::Undo)
+        );
+        assert_eq!(keymap.lookup(Modes::NORMAL, &keys("g")), Lookup::Pending);
+        assert_eq!(keymap.lookup(Modes::INSERT, &keys("g")), Lookup::Unbound);
+    }
+
+    #[test]
+    fn overrides_replace_and_unbind() -> Result<(), KeymapError> {
+        let mut keymap = Keymap::from_toml(
+            r#"
+            [[keymaps]]
+            key = "x"
+            command = "delete_forward"
+            mode = "nv"
+
+            [[keymaps]]
+            key = "u"
+            command = "undo"
+            "#,
+        )?;
+        let overrides = Keymap::from_json(
+            r#"{"keymaps": [
+                {"key": "x", "command": "delete_backward", "mode": "n"},
+                {"key": "u", "command": "-undo", "mode": "i"}
+            ]}"#,
+        )?;
+        keymap.extend(overrides);
+
+        let x = keys("x");
+        assert_eq!(
+            keymap.lookup(Modes::NORMAL, &x),
+            Lookup::Matched(Transaction::DeleteBackward)
+        );
+        assert_eq!(
+            keymap.lookup(Modes::VISUAL, &x),
+            Lookup::Matched(Transaction::DeleteForward)
+        );
+        assert_eq!(keymap.lookup(Modes::INSERT, &keys("u")), Lookup::Unbound);
+        assert_eq!(
+            keymap.lookup(Modes::NORMAL, &keys("u")),
+            Lookup::Matched(Transaction::Undo)
+        );
+
+        assert!(matches!(
+            Keymap::from_toml("[[keymaps]]\nkey = \"q\"\ncommand = \"fly\""),
+            Err(KeymapError::UnknownCommand(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn conflicts_are_detected() -> Result<(), KeymapError> {
+        let keymap = Keymap::from_toml(
+            r#"
+            [[keymaps]]
+            key = "d"
+            command = "delete_operator"
+            mode = "n"
+
+            [[keymaps]]
+            key = "d d"
+            command = "delete_line"
+            mode = "nv"
+
+            [[keymaps]]
+            key = "d"
+            command = "delete_forward"
+            mode = "v"
+
+            [[keymaps]]
+            key = "x"
+            command = "delete_forward"
+            mode = "i"
+
+            [[keymaps]]
+            key = "x"
+            command = "delete_backward"
+            mode = "n"
+            "#,
+        )?;
+
+        let conflicts = keymap.conflicts();
+        assert_eq!(conflicts.len(), 2);
+        assert!(matches!(
+            &conflicts[0],
+            Conflict::Prefix { shadowed, modes, .. }
+                if shadowed.transaction == Transaction::DeleteOperator && *modes == Modes::NORMAL
+        ));
+        assert!(matches!(
+            &conflicts[1],
+            Conflict::Prefix { by, modes, .. }
+                if by.transaction == Transaction::DeleteLine && *modes == Modes::VISUAL
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn sequences_and_counts_resolve() {
+        let keymap = Keymap::default();
+        let mut pending = PendingKeys::default();
+        let mut modal = normal();
+
+        assert_eq!(pending.feed(&keymap, &modal, KeyPress::char('g')), None);
+        assert_eq!(pending.keys(), keys("g"));
+        assert_eq!(
+            pending.feed(&keymap, &modal, KeyPress::char('g')),
+            Some(Transaction::MoveToDocumentStart)
+        );
+
+        // A key completing nothing starts over.
+        pending.feed(&keymap, &modal, KeyPress::char('g'));
+        assert_eq!(
+            pending.feed(&keymap, &modal, KeyPress::char('w')),
+            Some(Transaction::MoveWordForward)
+        );
+        assert!(pending.keys().is_empty());
+
+        assert_eq!(
+            pending.feed(&keymap, &modal, KeyPress::char('0')),
+            Some(Transaction::MoveToLineStart)
+        );
+        assert_eq!(
+            pending.feed(&keymap, &modal, KeyPress::char('2')),
+            Some(Transaction::Count { digit: 2 })
+        );
+        modal.count = Some(2);
+        assert_eq!(
+            pending.feed(&keymap, &modal, KeyPress::char('0')),
+            Some(Transaction::Count { digit: 0 })
+        );
+
+        // Text objects only follow operators.
+        assert_eq!(
+            pending.feed(&keymap, &modal, KeyPress::char('i')),
+            Some(Transaction::InsertMode)
+        );
+        modal.pending = Some(crate::mode::MotionMode::Delete { count: 1 });
+        pending.feed(&keymap, &modal, KeyPress::char('i'));
+        assert_eq!(
+            pending.feed(&keymap, &modal, KeyPress::char('w')),
+            Transaction::from_name("select_inner_word")
+        );
+    }
+
+    #[test]
+    fn register_names_follow_the_quote() {
+        let keymap = Keymap::default();
+        let mut pending = PendingKeys::default();
+        let modal = normal();
+
+        assert_eq!(pending.feed(&keymap, &modal, KeyPress::char('"')), None);
+        assert_eq!(
+            pending.feed(&keymap, &modal, KeyPress::char('a')),
+            Some(Transaction::SelectRegister { name: 'a' })
+        );
+        assert_eq!(
+            pending.feed(&keymap, &modal, KeyPress::char('p')),
+            Some(Transaction::PasteAfter)
+        );
+
+        // Keys naming no register are dropped.
+        pending.feed(&keymap, &modal, KeyPress::char('"'));
+        assert_eq!(pending.feed(&keymap, &modal, KeyPress::char('!')), None);
+        assert_eq!(
+            pending.feed(&keymap, &modal, KeyPress::char('x')),
+            Some(Transaction::DeleteForward)
+        );
+    }
+}
@@ -1,7 +1,11 @@
 pub mod block;
+pub mod blockwise;
 pub mod buffer;
+pub mod change;
 pub mod char;
+pub mod clipboard;
 pub mod command;
+pub mod compound;
 pub mod coordinates;
 pub mod ctx;
 pub mod cursor;
@@ -10,11 +14,21 @@ pub mod encoding;
 pub mod error;
 pub mod expr;
 pub mod graphemes;
+pub mod keymap;
 pub mod line;
+pub mod modal;
 pub mod mode;
 pub mod movement;
+pub mod offset;
+pub mod pair;
 pub mod paragraph;
+pub mod register;
+pub mod search;
+pub mod selection;
 pub mod sentence;
 pub mod span;
 pub mod text_buffer;
+pub mod text_object;
+#[cfg(test)]
+mod test_utils;
 pub mod word;
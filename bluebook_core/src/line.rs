@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use crate::text_buffer::TextBuffer;
 
 #[cfg(target_os = "windows")]
@@ -119,6 +121,90 @@ pub fn str_is_line_ending(s: &str) -> bool {
     LineEnding::from_str(s).is_some()
 }
 
+/// Iterate over the line endings in `text`, as `(byte offset, ending)` pairs.
+/// A `\r\n` is always read as one [`LineEnding::Crlf`].
+pub fn line_endings(text: &str) -> impl Iterator<Item = (usize, LineEnding)> + '_ {
+    let mut chars = text.char_indices().peekable();
+
+    std::iter::from_fn(move || loop {
+        let (offset, ch) = chars.next()?;
+        if ch == '\r' && matches!(chars.peek(), Some((_, '\n'))) {
+            chars.next();
+            return Some((offset, LineEnding::Crlf));
+        }
+        if let Some(ending) = LineEnding::from_char(ch) {
+            return Some((offset, ending));
+        }
+    })
+}
+
+/// How often each kind of line ending occurs in a document.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LineEndingReport {
+    /// Kinds of line ending in order of first appearance, with their count.
+    counts: Vec<(LineEnding, usize)>,
+}
+
+impl LineEndingReport {
+    pub fn detect(text: &str) -> Self {
+        let mut report = Self::default();
+        for (_, ending) in line_endings(text) {
+            match report.counts.iter_mut().find(|(e, _)| *e == ending) {
+                Some((_, count)) => *count += 1,
+                None => report.counts.push((ending, 1)),
+            }
+        }
+
+        report
+    }
+
+    pub fn count(&self, ending: LineEnding) -> usize {
+        self.counts
+            .iter()
+            .find(|(e, _)| *e == ending)
+            .map_or(0, |(_, count)| *count)
+    }
+
+    /// The kinds of line ending found, with their count.
+    pub fn counts(&self) -> &[(LineEnding, usize)] {
+        &self.counts
+    }
+
+    /// Whether more than one kind of line ending was found.
+    pub fn is_mixed(&self) -> bool {
+        self.counts.len() > 1
+    }
+
+    /// The convention of the document: its most common line ending, the first
+    /// one to appear on a tie, or [`NATIVE_LINE_ENDING`] if it has no line break.
+    pub fn line_ending(&self) -> LineEnding {
+        self.counts
+            .iter()
+            .rev()
+            .max_by_key(|(_, count)| *count)
+            .map_or(NATIVE_LINE_ENDING, |(ending, _)| *ending)
+    }
+}
+
+/// Rewrite every line ending in `text` as `ending`. Borrows `text` back when it
+/// already follows that convention.
+pub fn normalise_line_endings(text: &str, ending: LineEnding) -> Cow<'_, str> {
+    if line_endings(text).all(|(_, e)| e == ending) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut normalised = String::with_capacity(text.len());
+    let mut last = 0;
+    for (offset, e) in line_endings(text) {
+        normalised.push_str(&text[last..offset]);
+        normalised.push_str(ending.as_str());
+        last = offset + e.as_str().len();
+    }
+    normalised.push_str(&text[last..]);
+
+    Cow::Owned(normalised)
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum LineCursorError {
     #[error("Invalid character encountered")]
@@ -129,4 +215,61 @@ pub enum LineCursorError {
 pub trait LineCursor<'buffer> {
     type Buffer: TextBuffer;
     fn new(text: &'buffer Self::Buffer, pos: usize) -> Self;
+    /// Get the start of the current line, or of the previous line if the cursor
+    /// already sits at a line start, and move the cursor there.
+    fn prev_boundary(&mut self) -> Option<usize>;
+    /// Get the start of the next line, and move the cursor there.
+    fn next_boundary(&mut self) -> Option<usize>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_line_endings() {
+        let report = LineEndingReport::detect("a\r\nb\r\nc\nd");
+
+        assert_eq!(report.count(LineEnding::Crlf), 2);
+        assert_eq!(report.count(LineEnding::LF), 1);
+        assert!(report.is_mixed());
+        assert_eq!(report.line_ending(), LineEnding::Crlf);
+
+        let report = LineEndingReport::detect("a\nb\r\n");
+        assert_eq!(report.line_ending(), LineEnding::LF);
+
+        let report = LineEndingReport::detect("no breaks");
+        assert!(!report.is_mixed());
+        assert_eq!(report.line_ending(), NATIVE_LINE_ENDING);
+    }
+
+    #[test]
+    fn normalises_line_endings() {
+        assert_eq!(
+            normalise_line_endings("a\nb\r\nc\n", LineEnding::Crlf),
+            "a\r\nb\r\nc\r\n"
+        );
+        assert_eq!(
+            normalise_line_endings("a\r\nb\r\n", LineEnding::LF),
+            "a\nb\n"
+        );
+        assert!(matches!(
+            normalise_line_endings("a\r\nb", LineEnding::Crlf),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[cfg(feature = "unicode-lines")]
+    #[test]
+    fn unicode_line_endings() {
+        let report = LineEndingReport::detect("a\u{2028}b\rc\r\n");
+
+        assert_eq!(report.count(LineEnding::LS), 1);
+        assert_eq!(report.count(LineEnding::CR), 1);
+        assert_eq!(report.count(LineEnding::Crlf), 1);
+        assert_eq!(
+            normalise_line_endings("a\u{2028}b\rc", LineEnding::LF),
+            "a\nb\nc"
+        );
+    }
 }
@@ -0,0 +1,1058 @@
+//! Modal, vi-style editing.
+//!
+//! The context starts in insert mode, where every transaction applies as it
+//! comes, so editors without modes never leave it. In normal mode, motions
+//! move the cursors, and operators wait for the motion or text object they
+//! apply to: `d` then `w` deletes a word, and repeating the operator applies
+//! it to whole lines. Counts typed before the operator and before the motion
+//! multiply, so `2d3w` deletes six words. In visual mode, motions and text
+//! objects grow the selection, and operators apply to it right away.
+//!
+//! The transactions of the last command that changed the text are recorded,
+//! along with everything typed in the insert mode it entered, for
+//! [`Transaction::RepeatLastChange`] to replay them.
+//!
+//! Cursors stay between characters in every mode. Motions vi makes inclusive,
+//! like `e`, stop on the last character they move over, and operators apply
+//! up to the end of it.
+
+use std::ops::Range;
+
+use crate::{
+    block::BlockCursor,
+    char::CharCursor,
+    clipboard::Fragment,
+    command::Transaction,
+    ctx::TextEditorContext,
+    cursor::CursorRange,
+    error::BluebookCoreError,
+    mode::{Mode, MotionMode, VisualMode},
+    movement::{LinePosition, Movement},
+    offset::ByteOffset,
+    register::{is_register, Register},
+    search::Searchable,
+    selection::Selection,
+    text_buffer::{CursorError, TextBuffer},
+    word::{get_char_property, CharClassification, WordCursor},
+};
+
+/// What the indent operators add to, or remove from, the start of lines.
+pub const INDENT: &str = "\t";
+
+/// The editing mode, and the parts of a command typed so far.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModalState {
+    pub mode: Mode,
+    /// How the selection grows in visual mode.
+    pub visual: VisualMode,
+    /// The count typed since the last command, if any.
+    pub count: Option<usize>,
+    /// The operator waiting for a motion or a text object.
+    pub pending: Option<MotionMode>,
+    /// The register the command yanks to or puts from, if not the default.
+    pub register: Option<char>,
+}
+
+impl Default for ModalState {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Insert,
+            visual: VisualMode::default(),
+            count: None,
+            pending: None,
+            register: None,
+        }
+    }
+}
+
+impl ModalState {
+    /// Take the count typed so far, or 1 if none was.
+    pub fn take_count(&mut self) -> usize {
+        self.count.take().unwrap_or(1)
+    }
+
+    /// Forget the count, the operator and the register typed so far.
+    pub fn cancel(&mut self) {
+        self.count = None;
+        self.pending = None;
+        self.register = None;
+    }
+}
+
+/// How [`TextEditorContext::apply_modal`] handled a transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Modal {
+    /// The transaction was applied as a vi command, successfully or not.
+    Done(bool),
+    /// The transaction applies as it does in insert mode, as many times as
+    /// the count typed before it.
+    Pass(Transaction, usize),
+}
+
+/// The last command that changed the text, as the transactions typed for it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LastChange {
+    transactions: Vec<Transaction>,
+    /// The command being typed: its count and operator, or the transactions
+    /// typed since it entered visual or insert mode.
+    current: Vec<Transaction>,
+    /// Whether the command being typed changed the text so far.
+    pub(crate) changed: bool,
+    /// Whether the command being typed entered insert mode, which it ends
+    /// by leaving.
+    inserting: bool,
+    replaying: bool,
+}
+
+impl LastChange {
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    pub(crate) fn record(&mut self, transaction: &Transaction) {
+        if !self.replaying {
+            self.current.push(transaction.clone());
+        }
+    }
+
+    /// Keep the command typed so far as the last change if it is complete
+    /// and changed the text, `before` being the mode it was in before its
+    /// last transaction.
+    pub(crate) fn finish(&mut self, before: Mode, modal: &ModalState) {
+        if self.replaying {
+            return;
+        }
+        if matches!(before, Mode::Normal | Mode::Visual) && modal.mode == Mode::Insert {
+            self.inserting = true;
+        }
+
+        let complete = match modal.mode {
+            Mode::Normal => modal.pending.is_none() && modal.count.is_none(),
+            Mode::Insert | Mode::Terminal => !self.inserting,
+            Mode::Visual => false,
+        };
+        if !complete {
+            return;
+        }
+        match self.changed {
+            true => self.transactions = std::mem::take(&mut self.current),
+            false => self.current.clear(),
+        }
+        self.changed = false;
+        self.inserting = false;
+    }
+}
+
+/// The text an operator applies to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Target {
+    /// The text between two offsets.
+    Chars(Range<usize>),
+    /// Every line holding an offset from the start to the end of the range,
+    /// both included.
+    Lines(Range<usize>),
+}
+
+/// The operator `transaction` starts, with `count`.
+fn operator(transaction: &Transaction, count: usize) -> Option<MotionMode> {
+    let operator = match transaction {
+        Transaction::DeleteOperator => MotionMode::Delete { count },
+        Transaction::YankOperator => MotionMode::Yank { count },
+        Transaction::ChangeOperator => MotionMode::Change { count },
+        Transaction::IndentOperator => MotionMode::Indent { count },
+        Transaction::OutdentOperator => MotionMode::Outdent { count },
+        _ => return None,
+    };
+
+    Some(operator)
+}
+
+impl<Buffer> TextEditorContext<Buffer>
+where
+    Buffer: TextBuffer + Searchable,
+{
+    /// Apply `transaction` as a vi command in the current mode.
+    ///
+    /// Transactions changing the mode apply in every mode. Counts, operators,
+    /// motions and text objects are read as vi reads them in normal and
+    /// visual mode; everything else passes through, and applies as it does in
+    /// insert mode. A transaction that is neither a motion nor a text object
+    /// cancels the operator waiting for one.
+    pub fn apply_modal(&mut self, transaction: Transaction) -> Result<Modal, BluebookCoreError> {
+        match transaction {
+            Transaction::NormalMode => return self.enter_normal_mode().map(Modal::Done),
+            Transaction::InsertMode => {
+                let changed = self.modal.mode != Mode::Insert;
+                self.modal.cancel();
+                self.modal.mode = Mode::Insert;
+                return Ok(Modal::Done(changed));
+            }
+            Transaction::AppendEndOfLine | Transaction::InsertFirstNonBlank
+                if self.modal.mode == Mode::Visual
+                    && self.modal.visual == VisualMode::Blockwise =>
+            {
+                self.modal.cancel();
+                let append = transaction == Transaction::AppendEndOfLine;
+                return self.block_insert(append).map(Modal::Done);
+            }
+            Transaction::Append
+            | Transaction::AppendEndOfLine
+            | Transaction::InsertFirstNonBlank
+            | Transaction::NewLineAbove
+            | Transaction::NewLineBelow => {
+                self.modal.cancel();
+                let success = self.apply_to_all_ranges(transaction)?;
+                self.modal.mode = Mode::Insert;
+                return Ok(Modal::Done(success));
+            }
+            Transaction::ToggleVisualMode => {
+                return self.toggle_visual_mode(VisualMode::Normal).map(Modal::Done)
+            }
+            Transaction::ToggleLinewiseVisualMode => {
+                return self
+                    .toggle_visual_mode(VisualMode::Linewise)
+                    .map(Modal::Done)
+            }
+            Transaction::ToggleBlockwiseVisualMode => {
+                return self
+                    .toggle_visual_mode(VisualMode::Blockwise)
+                    .map(Modal::Done)
+            }
+            _ => {}
+        }
+
+        if !matches!(self.modal.mode, Mode::Normal | Mode::Visual) {
+            return Ok(Modal::Pass(transaction, 1));
+        }
+
+        if let Transaction::Count { digit } = transaction {
+            // A leading zero isn't a count.
+            if digit > 9 || (digit == 0 && self.modal.count.is_none()) {
+                return Ok(Modal::Done(false));
+            }
+            let count = self.modal.count.unwrap_or(0);
+            self.modal.count = Some(count.saturating_mul(10).saturating_add(digit));
+            return Ok(Modal::Done(true));
+        }
+
+        if let Transaction::SelectRegister { name } = transaction {
+            let valid = is_register(name);
+            if valid {
+                self.modal.register = Some(name);
+            }
+            return Ok(Modal::Done(valid));
+        }
+
+        if let Some(operator) = operator(&transaction, 1) {
+            return self.start_operator(operator).map(Modal::Done);
+        }
+
+        if let Some((movement, count)) = transaction.motion() {
+            return self.apply_motion(movement, count).map(Modal::Done);
+        }
+
+        if transaction.is_text_object() {
+            return self.apply_text_object(&transaction).map(Modal::Done);
+        }
+
+        if self.modal.pending.is_some() {
+            self.modal.cancel();
+            return Ok(Modal::Done(false));
+        }
+        let count = self.modal.take_count();
+
+        Ok(Modal::Pass(transaction, count))
+    }
+
+    /// Replay the last change at the cursors, as a single undo group. A count
+    /// typed before replaces every count typed for the change, before the
+    /// operator and before the motion alike.
+    pub(crate) fn repeat_last_change(&mut self) -> Result<bool, BluebookCoreError> {
+        let count = self.modal.count.take();
+        let mut transactions = self.last_change.transactions.clone();
+        if transactions.is_empty() {
+            return Ok(false);
+        }
+        if let Some(count) = count {
+            transactions.retain(|transaction| !matches!(transaction, Transaction::Count { .. }));
+            let digits: Vec<_> = count
+                .to_string()
+                .chars()
+                .filter_map(|digit| digit.to_digit(10))
+                .map(|digit| Transaction::Count {
+                    digit: digit as usize,
+                })
+                .collect();
+            transactions.splice(..0, digits);
+        }
+
+        self.last_change.replaying = true;
+        self.text_buffer.begin_undo_group();
+        let replayed = transactions.into_iter().try_for_each(|transaction| {
+            self.consume_transaction::<Buffer>(transaction).map(|_| ())
+        });
+        self.text_buffer.end_undo_group(true);
+        self.last_change.replaying = false;
+        self.last_change.current.clear();
+        self.last_change.changed = false;
+
+        replayed.map(|()| true)
+    }
+
+    /// Leave insert or visual mode, or drop the command typed so far.
+    fn enter_normal_mode(&mut self) -> Result<bool, BluebookCoreError> {
+        if self.modal.count.is_some()
+            || self.modal.pending.is_some()
+            || self.modal.register.is_some()
+        {
+            self.modal.cancel();
+            return Ok(true);
+        }
+
+        match self.modal.mode {
+            Mode::Normal => return Ok(false),
+            // Back onto the last character typed, without leaving its line.
+            Mode::Insert => self.map_ranges(|ctx, range| {
+                let head = range.head;
+                let offset = match head > ctx.line_at(head)?.start {
+                    true => ctx.movement_offset(head, &Movement::Left, 1)?,
+                    false => None,
+                };
+                let offset = offset.unwrap_or(head);
+                Ok(CursorRange::new(offset, offset))
+            })?,
+            Mode::Visual | Mode::Terminal => {
+                self.map_ranges(|_, range| Ok(CursorRange::new(range.head, range.head)))?
+            }
+        }
+        self.modal.mode = Mode::Normal;
+
+        Ok(true)
+    }
+
+    /// Enter visual mode of kind `visual`, or go back to normal mode if that
+    /// is the mode already.
+    fn toggle_visual_mode(&mut self, visual: VisualMode) -> Result<bool, BluebookCoreError> {
+        self.modal.cancel();
+        if self.modal.mode == Mode::Visual && self.modal.visual == visual {
+            return self.enter_normal_mode();
+        }
+
+        self.modal.mode = Mode::Visual;
+        self.modal.visual = visual;
+
+        Ok(true)
+    }
+
+    /// Start `operator`, or apply it to whole lines if it is the operator
+    /// waiting already. In visual mode, it applies to the selection.
+    fn start_operator(&mut self, operator: MotionMode) -> Result<bool, BluebookCoreError> {
+        let count = self.modal.take_count();
+
+        if self.modal.mode == Mode::Visual {
+            let mut targets = Vec::new();
+            for range in self.selection.ranges().to_vec() {
+                match self.modal.visual {
+                    VisualMode::Normal => targets.push(Target::Chars(range.from()..range.to())),
+                    VisualMode::Linewise => targets.push(Target::Lines(range.from()..range.to())),
+                    VisualMode::Blockwise => {
+                        let block = self.block_selection(range)?;
+                        let ranges = self.block_ranges(&block)?;
+                        targets.extend(ranges.into_iter().map(Target::Chars));
+                    }
+                }
+            }
+            let blockwise = self.modal.visual == VisualMode::Blockwise;
+            self.modal.mode = Mode::Normal;
+            let success = self.apply_operator(&operator, targets)?;
+
+            // Only a change goes on with a cursor on every line of the block.
+            if blockwise && success && !matches!(operator, MotionMode::Change { .. }) {
+                let first = self.selection.ranges()[0];
+                self.selection = Selection::single(first);
+            }
+            return Ok(success);
+        }
+
+        match self.modal.pending.take() {
+            Some(pending)
+                if std::mem::discriminant(&pending) == std::mem::discriminant(&operator) =>
+            {
+                let lines = pending.count().saturating_mul(count);
+                let mut targets = Vec::new();
+                for range in self.selection.ranges().to_vec() {
+                    let last = match lines > 1 {
+                        true => self.movement_offset(range.head, &Movement::Down, lines - 1)?,
+                        false => None,
+                    };
+                    targets.push(Target::Lines(range.head..last.unwrap_or(range.head)));
+                }
+                self.apply_operator(&pending, targets)
+            }
+            _ => {
+                self.modal.pending = Some(match operator {
+                    MotionMode::Delete { .. } => MotionMode::Delete { count },
+                    MotionMode::Yank { .. } => MotionMode::Yank { count },
+                    MotionMode::Change { .. } => MotionMode::Change { count },
+                    MotionMode::Indent { .. } => MotionMode::Indent { count },
+                    MotionMode::Outdent { .. } => MotionMode::Outdent { count },
+                });
+                Ok(true)
+            }
+        }
+    }
+
+    /// Move the cursors by `movement`, extend the selection with it in visual
+    /// mode, or apply the pending operator to the text it moves over.
+    fn apply_motion(
+        &mut self,
+        movement: Movement,
+        count: usize,
+    ) -> Result<bool, BluebookCoreError> {
+        let typed = self.modal.count.take();
+        let pending = self.modal.pending.take();
+        let count = pending
+            .as_ref()
+            .map_or(1, MotionMode::count)
+            .saturating_mul(typed.unwrap_or(1))
+            .saturating_mul(count.max(1));
+
+        // `G` and `gg` go to the line given by the count, if there is one.
+        let movement = match (&movement, typed) {
+            (Movement::DocumentStart | Movement::DocumentEnd, Some(line)) => {
+                Movement::Line(LinePosition::Line(line.saturating_sub(1)))
+            }
+            _ => movement,
+        };
+
+        let Some(operator) = pending else {
+            let visual = self.modal.mode == Mode::Visual;
+            let mut moved = false;
+            self.map_ranges(|ctx, range| {
+                let Some(mut target) = ctx.movement_offset(range.head, &movement, count)? else {
+                    return Ok(range);
+                };
+                moved = true;
+                if visual {
+                    return Ok(CursorRange::new(range.anchor, target));
+                }
+                if movement.is_inclusive() && target > range.head {
+                    target = ctx
+                        .movement_offset(target, &Movement::Left, 1)?
+                        .map_or(target, |prev| prev.max(range.head));
+                }
+                Ok(CursorRange::new(target, target))
+            })?;
+            return Ok(moved);
+        };
+
+        let mut targets = Vec::new();
+        for range in self.selection.ranges().to_vec() {
+            let head = range.head;
+            let change_word =
+                matches!(operator, MotionMode::Change { .. }) && movement == Movement::WordForward;
+            let target = match change_word {
+                // Like vi, `cw` changes up to the end of the word, not the
+                // whitespace after it.
+                true => self.word_end(head, count)?,
+                false => self.movement_offset(head, &movement, count)?,
+            };
+            let Some(target) = target else {
+                continue;
+            };
+
+            let mut range = head.min(target)..head.max(target);
+            // Going back, an inclusive motion takes in the grapheme it
+            // started on, like the bracket `%` goes back from.
+            if movement.is_inclusive() && target < head {
+                range.end = self
+                    .movement_offset(head, &Movement::Right, 1)?
+                    .unwrap_or(head);
+            }
+            targets.push(match movement.is_linewise() {
+                true => Target::Lines(range),
+                false => Target::Chars(range),
+            });
+        }
+
+        self.apply_operator(&operator, targets)
+    }
+
+    /// The end of the `count`th word from `offset`, where `cw` changes up to.
+    fn word_end(
+        &mut self,
+        offset: usize,
+        count: usize,
+    ) -> Result<Option<usize>, BluebookCoreError> {
+        let on_word = self
+            .text_buffer
+            .char_cursor(ByteOffset(offset))
+            .map_err(CursorError::from)?
+            .peek_next_codepoint()
+            .is_some_and(|ch| get_char_property(ch) == CharClassification::Other);
+        if !on_word {
+            return self.movement_offset(offset, &Movement::WordForward, count);
+        }
+
+        let end = self
+            .text_buffer
+            .word_cursor(ByteOffset(offset))
+            .map_err(CursorError::from)?
+            .next_code_boundary();
+        match count > 1 {
+            true => self.movement_offset(end, &Movement::WordEndForward, count - 1),
+            false => Ok(Some(end)),
+        }
+    }
+
+    /// Apply the pending operator to the text object `transaction` selects,
+    /// or grow the selection to it in visual mode.
+    fn apply_text_object(&mut self, transaction: &Transaction) -> Result<bool, BluebookCoreError> {
+        let visual = self.modal.mode == Mode::Visual;
+        let pending = self.modal.pending.take();
+        self.modal.count = None;
+        let linewise = matches!(
+            transaction,
+            Transaction::SelectTextObject { object, .. } if object.is_linewise()
+        );
+
+        let mut targets = Vec::new();
+        let mut ranges = Vec::new();
+        for range in self.selection.ranges().to_vec() {
+            let object = self.text_object(transaction, range.head)?;
+            match object {
+                Some(object) => {
+                    ranges.push(CursorRange::new(
+                        range.from().min(object.start),
+                        range.to().max(object.end),
+                    ));
+                    targets.push(match linewise {
+                        // The line break ending the object is on its last line.
+                        true => {
+                            let last = object.end.saturating_sub(1).max(object.start);
+                            Target::Lines(object.start..last)
+                        }
+                        false => Target::Chars(object),
+                    });
+                }
+                None => ranges.push(range),
+            }
+        }
+
+        match (pending, visual) {
+            (Some(operator), _) => self.apply_operator(&operator, targets),
+            (None, true) => {
+                self.selection = Selection::new(ranges, self.selection.primary_index());
+                Ok(!targets.is_empty())
+            }
+            // Outside of visual mode, text objects only select.
+            (None, false) => self.apply_to_all_ranges(transaction.clone()),
+        }
+    }
+
+    /// The range `transaction` selects around `offset`, if it selects any.
+    fn text_object(
+        &mut self,
+        transaction: &Transaction,
+        offset: usize,
+    ) -> Result<Option<Range<usize>>, BluebookCoreError> {
+        let cursor_range = self.cursor_range;
+        self.cursor_range = CursorRange::new(offset, offset);
+        let selected = self.apply_transaction(transaction.clone());
+        let object = self.cursor_range;
+        self.cursor_range = cursor_range;
+
+        Ok(selected?.then(|| object.from()..object.to()))
+    }
+
+    /// Apply `operator` to every target, leaving a cursor where each one
+    /// starts. Deleted, changed and yanked text goes to the registers.
+    fn apply_operator(
+        &mut self,
+        operator: &MotionMode,
+        targets: Vec<Target>,
+    ) -> Result<bool, BluebookCoreError> {
+        let indents = matches!(
+            operator,
+            MotionMode::Indent { .. } | MotionMode::Outdent { .. }
+        );
+
+        let mut ranges: Vec<(Range<usize>, bool)> = Vec::new();
+        for target in targets {
+            let (range, linewise) = match target {
+                Target::Lines(range) => (self.line_span(range)?, true),
+                Target::Chars(range) if indents => (self.line_span(range)?, true),
+                Target::Chars(range) => (range, false),
+            };
+            if !range.is_empty() {
+                ranges.push((range, linewise));
+            }
+        }
+        ranges.sort_by_key(|(range, _)| range.start);
+        ranges.dedup_by(|(next, _), (range, _)| {
+            let overlaps = next.start < range.end;
+            if overlaps {
+                range.end = range.end.max(next.end);
+            }
+            overlaps
+        });
+        if ranges.is_empty() {
+            return Ok(false);
+        }
+
+        if !indents {
+            let linewise = ranges.iter().all(|(_, linewise)| *linewise);
+            let separator = match linewise {
+                true => "",
+                false => self.line_ending.as_str(),
+            };
+            let mut fragment = Fragment::default();
+            for (range, _) in &ranges {
+                let spans = self
+                    .text_buffer
+                    .slice_spans(ByteOffset(range.start)..ByteOffset(range.end))?;
+                fragment.append(separator, Fragment::new(spans));
+            }
+            let register = match linewise {
+                true => Register::lines(fragment, self.line_ending.as_str()),
+                false => Register::new(fragment, false),
+            };
+            let deleted = !matches!(operator, MotionMode::Yank { .. });
+            self.store_register(register, deleted);
+        }
+
+        let mut shift = 0isize;
+        let mut carets = Vec::with_capacity(ranges.len());
+        for (range, linewise) in ranges {
+            let len = self.text_buffer.len();
+            let range =
+                range.start.saturating_add_signed(shift)..range.end.saturating_add_signed(shift);
+
+            let caret = match operator {
+                MotionMode::Yank { .. } => range.start,
+                MotionMode::Delete { .. } if linewise => {
+                    let range = self.with_previous_line_break(range)?;
+                    self.edit(range.clone(), "")?;
+                    self.first_non_blank(range.start)?
+                }
+                MotionMode::Change { .. } if linewise => {
+                    let (start, content) = self.line_text(range.end.saturating_sub(1))?;
+                    let end = (start + content.len()).max(range.start);
+                    self.edit(range.start..end, "")?;
+                    range.start
+                }
+                MotionMode::Delete { .. } | MotionMode::Change { .. } => {
+                    self.edit(range.clone(), "")?;
+                    range.start
+                }
+                MotionMode::Indent { .. } | MotionMode::Outdent { .. } => {
+                    let outdent = matches!(operator, MotionMode::Outdent { .. });
+                    self.indent_lines(range.clone(), outdent)?;
+                    self.first_non_blank(range.start)?
+                }
+            };
+
+            shift += self.text_buffer.len() as isize - len as isize;
+            carets.push(CursorRange::new(caret, caret));
+        }
+
+        let primary = self.selection.primary_index().min(carets.len() - 1);
+        self.selection = Selection::new(carets, primary);
+        if matches!(operator, MotionMode::Change { .. }) {
+            self.modal.mode = Mode::Insert;
+        }
+
+        Ok(true)
+    }
+
+    /// From the start of the line holding `range.start` to the end of the
+    /// line holding `range.end`, line break included.
+    fn line_span(&mut self, range: Range<usize>) -> Result<Range<usize>, BluebookCoreError> {
+        let start = self.line_at(range.start)?.start;
+        let end = self.line_at(range.end)?.end;
+
+        Ok(start..end)
+    }
+
+    /// `lines`, along with the line break before them if they are the last
+    /// lines and don't end with one, so that deleting them leaves no empty
+    /// line behind.
+    fn with_previous_line_break(
+        &mut self,
+        lines: Range<usize>,
+    ) -> Result<Range<usize>, BluebookCoreError> {
+        let ends_with_break = self
+            .text_buffer
+            .slice(ByteOffset(lines.start)..ByteOffset(lines.end))?
+            .ends_with('\n');
+        if ends_with_break || lines.start == 0 || lines.end != self.text_buffer.len() {
+            return Ok(lines);
+        }
+
+        let (start, content) = self.line_text(lines.start - 1)?;
+        Ok(start + content.len()..lines.end)
+    }
+
+    /// Add [`INDENT`] to the start of every line of `lines` that isn't empty,
+    /// or remove one level of indentation: a tab, or up to four spaces.
+    fn indent_lines(
+        &mut self,
+        lines: Range<usize>,
+        outdent: bool,
+    ) -> Result<(), BluebookCoreError> {
+        let mut starts = vec![lines.start];
+        {
+            let mut cursor = self
+                .text_buffer
+                .block_cursor(ByteOffset(lines.start))
+                .map_err(CursorError::from)?;
+            while let Some(start) = cursor.next_boundary() {
+                if start >= lines.end {
+                    break;
+                }
+                starts.push(start);
+            }
+        }
+
+        // Last line first, so that the starts of the others don't move.
+        for start in starts.into_iter().rev() {
+            let (_, content) = self.line_text(start)?;
+            match outdent {
+                true => {
+                    let width = match content.starts_with('\t') {
+                        true => 1,
+                        false => content.len() - content.trim_start_matches(' ').len(),
+                    };
+                    if width > 0 {
+                        self.edit(start..start + width.min(4), "")?;
+                    }
+                }
+                false if !content.is_empty() => {
+                    self.edit(start..start, INDENT)?;
+                }
+                false => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replace every range of the selection with `f` of it.
+    fn map_ranges(
+        &mut self,
+        mut f: impl FnMut(&mut Self, CursorRange) -> Result<CursorRange, BluebookCoreError>,
+    ) -> Result<(), BluebookCoreError> {
+        let primary = self.selection.primary_index();
+        let mut ranges = Vec::with_capacity(self.selection.ranges().len());
+        for range in self.selection.ranges().to_vec() {
+            ranges.push(f(self, range)?);
+        }
+        self.selection = Selection::new(ranges, primary);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        buffer::rope::buffer_impl::RopeBuffer,
+        test_utils::{normal_ctx, run},
+    };
+
+    fn count(digit: usize) -> Transaction {
+        Transaction::Count { digit }
+    }
+
+    fn unnamed(ctx: &TextEditorContext<RopeBuffer>) -> Option<String> {
+        let register = ctx.registers.get(crate::register::UNNAMED)?;
+        Some(register.fragment.plain_text())
+    }
+
+    #[test]
+    fn operators_take_motions_and_counts() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx("one two three four five six seven eight", 0);
+
+        run(
+            &mut ctx,
+            [
+                count(2),
+                Transaction::DeleteOperator,
+                count(3),
+                Transaction::MoveWordForward,
+            ],
+        )?;
+        assert_eq!(ctx.text_buffer.take(), "seven eight");
+        assert_eq!(
+            unnamed(&ctx).as_deref(),
+            Some("one two three four five six ")
+        );
+
+        run(
+            &mut ctx,
+            [Transaction::DeleteOperator, Transaction::MoveWordEndForward],
+        )?;
+        assert_eq!(ctx.text_buffer.take(), " eight");
+        assert_eq!(ctx.cursor_range, CursorRange::new(0, 0));
+        assert_eq!(
+            ctx.modal,
+            ModalState {
+                mode: Mode::Normal,
+                ..Default::default()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn repeated_operators_apply_to_lines() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx("one\n  two\nthree\nfour", 1);
+
+        run(
+            &mut ctx,
+            [Transaction::YankOperator, Transaction::YankOperator],
+        )?;
+        assert_eq!(unnamed(&ctx).as_deref(), Some("one\n"));
+
+        run(
+            &mut ctx,
+            [
+                count(2),
+                Transaction::DeleteOperator,
+                Transaction::DeleteOperator,
+            ],
+        )?;
+        assert_eq!(ctx.text_buffer.take(), "three\nfour");
+
+        // The last line takes the line break before it along.
+        run(
+            &mut ctx,
+            [
+                Transaction::MoveCursorDown,
+                Transaction::DeleteOperator,
+                Transaction::DeleteOperator,
+            ],
+        )?;
+        assert_eq!(ctx.text_buffer.take(), "three");
+
+        run(
+            &mut ctx,
+            [Transaction::IndentOperator, Transaction::IndentOperator],
+        )?;
+        assert_eq!(ctx.text_buffer.take(), "\tthree");
+        assert_eq!(ctx.cursor_range, CursorRange::new(1, 1));
+        run(
+            &mut ctx,
+            [Transaction::OutdentOperator, Transaction::MoveToLineEnd],
+        )?;
+        assert_eq!(ctx.text_buffer.take(), "three");
+
+        Ok(())
+    }
+
+    #[test]
+    fn linewise_motions_and_text_objects() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx("one\ntwo\nthree\nfour", 5);
+
+        run(
+            &mut ctx,
+            [Transaction::DeleteOperator, Transaction::MoveCursorDown],
+        )?;
+        assert_eq!(ctx.text_buffer.take(), "one\nfour");
+
+        run(
+            &mut ctx,
+            [
+                Transaction::MoveToDocumentStart,
+                Transaction::ChangeOperator,
+                Transaction::SelectWord,
+            ],
+        )?;
+        assert_eq!(ctx.text_buffer.take(), "\nfour");
+        assert_eq!(ctx.modal.mode, Mode::Insert);
+
+        Ok(())
+    }
+
+    #[test]
+    fn change_word_keeps_the_space_after_it() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx("Roe v. Wade", 0);
+
+        run(
+            &mut ctx,
+            [Transaction::ChangeOperator, Transaction::MoveWordForward],
+        )?;
+        run(
+            &mut ctx,
+            [Transaction::InsertAtCursorHead {
+                value: "Doe".to_string(),
+            }],
+        )?;
+        assert_eq!(ctx.text_buffer.take(), "Doe v. Wade");
+
+        // Back to normal mode, on the last character typed.
+        run(&mut ctx, [Transaction::NormalMode])?;
+        assert_eq!(ctx.cursor_range, CursorRange::new(2, 2));
+        assert_eq!(ctx.modal.mode, Mode::Normal);
+
+        Ok(())
+    }
+
+    #[test]
+    fn normal_mode_motions_and_cancelling() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx("Roe v. Wade\n  410 U.S. 113", 0);
+
+        run(&mut ctx, [Transaction::MoveWordEndForward])?;
+        assert_eq!(ctx.cursor_range, CursorRange::new(2, 2));
+
+        run(&mut ctx, [count(2), Transaction::MoveToDocumentEnd])?;
+        assert_eq!(ctx.cursor_range, CursorRange::new(12, 12));
+        run(&mut ctx, [Transaction::MoveToFirstNonBlank])?;
+        assert_eq!(ctx.cursor_range, CursorRange::new(14, 14));
+
+        // Typing anything but a motion drops the operator.
+        run(
+            &mut ctx,
+            [
+                Transaction::DeleteOperator,
+                Transaction::InsertAtCursorHead {
+                    value: "x".to_string(),
+                },
+                Transaction::MoveWordForward,
+            ],
+        )?;
+        assert_eq!(ctx.text_buffer.take(), "Roe v. Wade\n  410 U.S. 113");
+        assert_eq!(ctx.cursor_range, CursorRange::new(18, 18));
+
+        Ok(())
+    }
+
+    #[test]
+    fn counts_repeat_edits() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx("Roe v. Wade", 0);
+
+        run(&mut ctx, [count(4), Transaction::DeleteForward])?;
+        assert_eq!(ctx.text_buffer.take(), "v. Wade");
+
+        // Repeating stops where the edit can't be made.
+        run(&mut ctx, [count(9), Transaction::DeleteForward])?;
+        assert_eq!(ctx.text_buffer.take(), "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn dot_repeats_the_last_change() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx("one two three four five six", 0);
+
+        run(
+            &mut ctx,
+            [
+                Transaction::DeleteOperator,
+                Transaction::MoveWordForward,
+                Transaction::MoveWordEndForward,
+                Transaction::MoveToLineStart,
+                Transaction::RepeatLastChange,
+            ],
+        )?;
+        assert_eq!(ctx.text_buffer.take(), "three four five six");
+
+        // A count replaces the one the change was typed with.
+        run(&mut ctx, [count(2), Transaction::RepeatLastChange])?;
+        assert_eq!(ctx.text_buffer.take(), "five six");
+        assert_eq!(
+            ctx.last_change.transactions(),
+            [Transaction::DeleteOperator, Transaction::MoveWordForward]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn dot_count_replaces_the_motion_count() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx("one two three four five six seven eight", 0);
+
+        run(
+            &mut ctx,
+            [
+                Transaction::DeleteOperator,
+                count(3),
+                Transaction::MoveWordForward,
+            ],
+        )?;
+        assert_eq!(ctx.text_buffer.take(), "four five six seven eight");
+
+        // "2." deletes two words, not three twice over.
+        run(&mut ctx, [count(2), Transaction::RepeatLastChange])?;
+        assert_eq!(ctx.text_buffer.take(), "six seven eight");
+
+        Ok(())
+    }
+
+    #[test]
+    fn dot_repeats_text_typed_in_insert_mode() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx("Roe v. Wade\nSee also", 0);
+
+        run(
+            &mut ctx,
+            [
+                Transaction::AppendEndOfLine,
+                Transaction::InsertAtCursorHead {
+                    value: ",".to_string(),
+                },
+                Transaction::InsertAtCursorHead {
+                    value: " 410 U.S. 113".to_string(),
+                },
+                Transaction::NormalMode,
+                Transaction::MoveCursorDown,
+                Transaction::RepeatLastChange,
+            ],
+        )?;
+        assert_eq!(
+            ctx.text_buffer.take(),
+            "Roe v. Wade, 410 U.S. 113\nSee also, 410 U.S. 113"
+        );
+        assert_eq!(ctx.modal.mode, Mode::Normal);
+
+        // The repeat undoes at once.
+        run(&mut ctx, [Transaction::Undo])?;
+        assert_eq!(
+            ctx.text_buffer.take(),
+            "Roe v. Wade, 410 U.S. 113\nSee also"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn visual_mode_extends_the_selection() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx("Roe v. Wade\nSee also", 4);
+
+        run(
+            &mut ctx,
+            [
+                Transaction::ToggleVisualMode,
+                count(2),
+                Transaction::MoveCursorRight { grapheme_count: 1 },
+            ],
+        )?;
+        assert_eq!(ctx.selection.primary(), CursorRange::new(4, 6));
+
+        run(&mut ctx, [Transaction::DeleteOperator])?;
+        assert_eq!(ctx.text_buffer.take(), "Roe  Wade\nSee also");
+        assert_eq!(ctx.modal.mode, Mode::Normal);
+
+        run(
+            &mut ctx,
+            [
+                Transaction::ToggleLinewiseVisualMode,
+                Transaction::YankOperator,
+            ],
+        )?;
+        assert_eq!(unnamed(&ctx).as_deref(), Some("Roe  Wade\n"));
+
+        Ok(())
+    }
+}
@@ -3,12 +3,35 @@ use serde::{Deserialize, Serialize};
 use std::fmt::Write;
 use tracing::warn;
 
+/// An operator waiting for the motion or text object it applies to, with the
+/// count typed before it.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MotionMode {
     Delete { count: usize },
     Yank { count: usize },
-    Indent,
-    Outdent,
+    Change { count: usize },
+    Indent { count: usize },
+    Outdent { count: usize },
+}
+
+impl MotionMode {
+    pub fn count(&self) -> usize {
+        match self {
+            MotionMode::Delete { count }
+            | MotionMode::Yank { count }
+            | MotionMode::Change { count }
+            | MotionMode::Indent { count }
+            | MotionMode::Outdent { count } => *count,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Copy, Deserialize, Serialize, Default)]
+pub enum VisualMode {
+    #[default]
+    Normal,
+    Linewise,
+    Blockwise,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -26,11 +49,15 @@ pub enum Mode {
 }
 
 bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
     pub struct Modes: u32 {
         const NORMAL = 0x1;
         const INSERT = 0x2;
         const VISUAL = 0x4;
         const TERMINAL = 0x8;
+        /// Normal or visual mode, while an operator waits for the motion or
+        /// text object it applies to.
+        const OPERATOR_PENDING = 0x10;
     }
 }
 
@@ -55,6 +82,7 @@ impl Modes {
                 'n' | 'N' => this.set(Self::NORMAL, true),
                 'v' | 'V' => this.set(Self::VISUAL, true),
                 't' | 'T' => this.set(Self::TERMINAL, true),
+                'o' | 'O' => this.set(Self::OPERATOR_PENDING, true),
                 _ => warn!("Not an editor mode: {c}"),
             }
         }
@@ -70,6 +98,7 @@ impl std::fmt::Display for Modes {
             (Self::NORMAL, 'n'),
             (Self::VISUAL, 'v'),
             (Self::TERMINAL, 't'),
+            (Self::OPERATOR_PENDING, 'o'),
         ];
         for (bit, chr) in bits {
             if self.contains(bit) {
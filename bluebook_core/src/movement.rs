@@ -21,11 +21,19 @@ pub enum Movement {
     WordEndForward,
     WordForward,
     WordBackward,
+    SentenceForward,
+    SentenceBackward,
     NextUnmatched(char),
     PreviousUnmatched(char),
     MatchPairs,
     ParagraphForward,
     ParagraphBackward,
+    BlockForward,
+    BlockBackward,
+    /// The next header of the given level, or of any level.
+    HeaderForward(Option<u64>),
+    /// The previous header of the given level, or of any level.
+    HeaderBackward(Option<u64>),
 }
 
 impl PartialEq for Movement {
@@ -45,11 +53,28 @@ impl Movement {
                 | Movement::DocumentEnd
                 | Movement::ParagraphForward
                 | Movement::ParagraphBackward
+                | Movement::BlockForward
+                | Movement::BlockBackward
+                | Movement::HeaderForward(_)
+                | Movement::HeaderBackward(_)
+        )
+    }
+
+    /// Whether operators apply to every line the movement touches, rather
+    /// than to the text it moves over.
+    pub fn is_linewise(&self) -> bool {
+        matches!(
+            self,
+            Movement::Up
+                | Movement::Down
+                | Movement::Line(_)
+                | Movement::DocumentStart
+                | Movement::DocumentEnd
         )
     }
 
     pub fn is_inclusive(&self) -> bool {
-        matches!(self, Movement::WordEndForward)
+        matches!(self, Movement::WordEndForward | Movement::MatchPairs)
     }
 
     pub fn is_jump(&self) -> bool {
@@ -61,6 +86,10 @@ impl Movement {
                 | Movement::DocumentEnd
                 | Movement::ParagraphForward
                 | Movement::ParagraphBackward
+                | Movement::BlockForward
+                | Movement::BlockBackward
+                | Movement::HeaderForward(_)
+                | Movement::HeaderBackward(_)
         )
     }
 
@@ -85,8 +114,8 @@ impl Movement {
                 LinePosition::Last => last,
             },
 
-            Movement::ParagraphForward => 0,
-            Movement::ParagraphBackward => 0,
+            // Paragraph, sentence and block jumps depend on the text rather than on an
+            // index, see `TextEditorContext::movement_offset`.
             _ => index,
         }
     }
@@ -0,0 +1,66 @@
+//! Offsets into a text buffer, one type per unit.
+//!
+//! The backends don't agree on a unit: xi_rope and `RichText::insert` count utf8
+//! bytes, the peritext wire format counts utf16 code units, and the view counts
+//! grapheme clusters. Wrapping each unit in its own type means an offset in one
+//! unit can't be handed to an API expecting another: it has to go through one of
+//! the conversions on [`TextBuffer`](crate::text_buffer::TextBuffer).
+
+use std::ops::{Add, Sub};
+
+use serde::{Deserialize, Serialize};
+
+macro_rules! offset_type {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(
+            Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+        )]
+        pub struct $name(pub usize);
+
+        impl Add for $name {
+            type Output = Self;
+
+            fn add(self, rhs: Self) -> Self {
+                Self(self.0 + rhs.0)
+            }
+        }
+
+        impl Sub for $name {
+            type Output = Self;
+
+            fn sub(self, rhs: Self) -> Self {
+                Self(self.0 - rhs.0)
+            }
+        }
+    };
+}
+
+offset_type!(
+    /// An offset in utf8 bytes. This is the unit the buffers store text in, and
+    /// the one cursors move in.
+    ByteOffset
+);
+
+offset_type!(
+    /// An offset in utf16 code units, as used by peritext deltas and most
+    /// platform text APIs.
+    Utf16Offset
+);
+
+offset_type!(
+    /// An offset in unicode scalar values.
+    CharOffset
+);
+
+offset_type!(
+    /// An index in extended grapheme clusters, i.e. in user perceived characters.
+    GraphemeIndex
+);
+
+impl ByteOffset {
+    /// The offset just past `s`, when `s` is inserted at `self`.
+    pub fn after(self, s: &str) -> Self {
+        Self(self.0 + s.len())
+    }
+}
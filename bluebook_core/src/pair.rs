@@ -0,0 +1,499 @@
+//! Bracket and quote pairs: matching one with its partner, and walking out
+//! from an offset to the pair around it.
+//!
+//! Brackets nest, so a walk counts the pairs it passes over. Quotes that
+//! open and close with the same character can't nest, and pair up along
+//! their line instead. Either way, a character escaped with a backslash
+//! doesn't count, and neither does a single quote between two letters or
+//! digits, which is an apostrophe, as in "the Court’s holding".
+
+use std::ops::Range;
+
+use crate::{
+    char::CharCursor,
+    ctx::TextEditorContext,
+    error::BluebookCoreError,
+    mode::Mode,
+    offset::ByteOffset,
+    search::Searchable,
+    text_buffer::{CursorError, TextBuffer},
+};
+
+/// Pairs, opening first, by the names keymaps give them. Quotes open and
+/// close with the same character.
+pub const NAMED_PAIRS: &[(&str, char, char)] = &[
+    ("parens", '(', ')'),
+    ("brackets", '[', ']'),
+    ("braces", '{', '}'),
+    ("angle_brackets", '<', '>'),
+    ("curly_quotes", '“', '”'),
+    ("curly_single_quotes", '‘', '’'),
+    ("double_quotes", '"', '"'),
+    ("single_quotes", '\'', '\''),
+    ("backticks", '`', '`'),
+];
+
+/// The pairs matched and highlighted. Angle brackets are left out, as they
+/// are less-than and greater-than signs more often than a pair.
+pub const MATCHED_PAIRS: &[(char, char)] = &[
+    ('(', ')'),
+    ('[', ']'),
+    ('{', '}'),
+    ('“', '”'),
+    ('‘', '’'),
+    ('"', '"'),
+    ('\'', '\''),
+];
+
+/// The pair keymaps name `name`, as in `select_inner_<name>`.
+pub fn pair_named(name: &str) -> Option<(char, char)> {
+    NAMED_PAIRS
+        .iter()
+        .find(|(named, _, _)| *named == name)
+        .map(|(_, open, close)| (*open, *close))
+}
+
+/// How far, in bytes, highlighting looks for the partner of a bracket, so
+/// that an unmatched one doesn't cost a walk over the whole document every
+/// frame.
+pub const HIGHLIGHT_SCAN_LIMIT: usize = 10_000;
+
+/// The pair `ch` opens or closes.
+pub fn pair_of(ch: char) -> Option<(char, char)> {
+    NAMED_PAIRS
+        .iter()
+        .find(|(_, open, close)| ch == *open || ch == *close)
+        .map(|(_, open, close)| (*open, *close))
+}
+
+/// Whether `ch`, between `before` and `after`, is an apostrophe rather than
+/// a quote.
+fn is_apostrophe(ch: char, before: Option<char>, after: Option<char>) -> bool {
+    matches!(ch, '\'' | '’')
+        && before.is_some_and(char::is_alphanumeric)
+        && after.is_some_and(char::is_alphanumeric)
+}
+
+/// The offsets in `line` of each pair of `quote`s, opening first. Quotes pair
+/// up from the start of the line.
+pub fn quote_pairs(line: &str, quote: char) -> Vec<(usize, usize)> {
+    let mut quotes = Vec::new();
+    let mut before = None;
+    let mut escaped = false;
+    let mut chars = line.char_indices().peekable();
+    while let Some((index, ch)) = chars.next() {
+        let after = chars.peek().map(|(_, after)| *after);
+        if ch == quote && !escaped && !is_apostrophe(ch, before, after) {
+            quotes.push(index);
+        }
+        escaped = ch == '\\' && !escaped;
+        before = Some(ch);
+    }
+
+    quotes
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .collect()
+}
+
+/// A cursor looking for the brackets enclosing its position, skipping the
+/// pairs nested in between.
+pub struct PairCursor<C> {
+    inner: C,
+    /// How far from where it starts a walk looks, in bytes.
+    limit: usize,
+}
+
+impl<'buffer, C> PairCursor<C>
+where
+    C: CharCursor<'buffer>,
+{
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            limit: usize::MAX,
+        }
+    }
+
+    /// Give up walks once they are `limit` bytes away from where they start.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// The offset of the `open` before the cursor that no `close` between it
+    /// and the cursor matches.
+    pub fn prev_unmatched(&mut self, open: char, close: char) -> Option<usize> {
+        let from = self.inner.pos();
+        let mut depth = 0usize;
+        while let Some(ch) = self.inner.prev_codepoint() {
+            let pos = self.inner.pos();
+            if from - pos > self.limit {
+                break;
+            }
+            if (ch != open && ch != close) || !self.counts(pos, ch) {
+                continue;
+            }
+            if ch == close {
+                depth += 1;
+                continue;
+            }
+            match depth.checked_sub(1) {
+                Some(outer) => depth = outer,
+                None => return Some(pos),
+            }
+        }
+
+        None
+    }
+
+    /// The offset of the `close` after the cursor that no `open` between the
+    /// cursor and it matches.
+    pub fn next_unmatched(&mut self, open: char, close: char) -> Option<usize> {
+        let from = self.inner.pos();
+        let mut depth = 0usize;
+        while let Some(ch) = self.inner.next_codepoint() {
+            let pos = self.inner.pos() - ch.len_utf8();
+            if pos - from > self.limit {
+                break;
+            }
+            if (ch != open && ch != close) || !self.counts(pos, ch) {
+                continue;
+            }
+            if ch == open {
+                depth += 1;
+                continue;
+            }
+            match depth.checked_sub(1) {
+                Some(outer) => depth = outer,
+                None => return Some(pos),
+            }
+        }
+
+        None
+    }
+
+    /// Whether `ch`, at `pos`, is part of a pair rather than escaped or an
+    /// apostrophe. Leaves the cursor where it was.
+    pub fn counts(&mut self, pos: usize, ch: char) -> bool {
+        let restore = self.inner.pos();
+
+        self.inner.set(pos);
+        let mut backslashes = 0;
+        let mut before = None;
+        while let Some(prev) = self.inner.prev_codepoint() {
+            before = before.or(Some(prev));
+            if prev != '\\' {
+                break;
+            }
+            backslashes += 1;
+        }
+        self.inner.set(pos + ch.len_utf8());
+        let after = self.inner.peek_next_codepoint();
+        self.inner.set(restore);
+
+        backslashes % 2 == 0 && !is_apostrophe(ch, before, after)
+    }
+}
+
+impl<Buffer> TextEditorContext<Buffer>
+where
+    Buffer: TextBuffer + Searchable,
+{
+    fn char_at(&mut self, offset: usize) -> Result<Option<char>, BluebookCoreError> {
+        let cursor = self
+            .text_buffer
+            .char_cursor(ByteOffset(offset))
+            .map_err(CursorError::from)?;
+
+        Ok(cursor.peek_next_codepoint())
+    }
+
+    /// The offset of the bracket or quote matching the one at `offset`, if
+    /// there is one there.
+    pub fn matching_pair(&mut self, offset: usize) -> Result<Option<usize>, BluebookCoreError> {
+        self.matching_pair_within(offset, usize::MAX)
+    }
+
+    /// The offset of the bracket or quote matching the one at `offset`, if
+    /// there is one there no more than `limit` bytes away.
+    fn matching_pair_within(
+        &mut self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Option<usize>, BluebookCoreError> {
+        let Some(ch) = self.char_at(offset)? else {
+            return Ok(None);
+        };
+        let Some(&(open, close)) = MATCHED_PAIRS
+            .iter()
+            .find(|(open, close)| ch == *open || ch == *close)
+        else {
+            return Ok(None);
+        };
+
+        if open == close {
+            let (start, content) = self.line_text(offset)?;
+            let column = offset - start;
+            let partner = quote_pairs(&content, ch)
+                .into_iter()
+                .find_map(|(open, close)| match column {
+                    column if column == open => Some(close),
+                    column if column == close => Some(open),
+                    _ => None,
+                });
+            return Ok(partner.map(|partner| start + partner));
+        }
+
+        let mut cursor = PairCursor::new(
+            self.text_buffer
+                .char_cursor(ByteOffset(offset))
+                .map_err(CursorError::from)?,
+        )
+        .with_limit(limit);
+        if !cursor.counts(offset, ch) {
+            return Ok(None);
+        }
+
+        Ok(match ch == open {
+            true => {
+                cursor.inner.set(offset + ch.len_utf8());
+                cursor.next_unmatched(open, close)
+            }
+            false => cursor.prev_unmatched(open, close),
+        })
+    }
+
+    /// Where `%` goes from `offset`: past the partner of the first bracket or
+    /// quote from `offset` on to the end of its line that has one, or to it
+    /// if it comes before.
+    pub(crate) fn match_pairs(
+        &mut self,
+        offset: usize,
+    ) -> Result<Option<usize>, BluebookCoreError> {
+        let (start, content) = self.line_text(offset)?;
+        let from = offset.saturating_sub(start).min(content.len());
+
+        for (index, ch) in content[from..].char_indices() {
+            let at = start + from + index;
+            if !MATCHED_PAIRS
+                .iter()
+                .any(|(open, close)| ch == *open || ch == *close)
+            {
+                continue;
+            }
+            if let Some(partner) = self.matching_pair(at)? {
+                let partner_len = self.char_at(partner)?.map_or(0, char::len_utf8);
+                return Ok(Some(match partner > at {
+                    true => partner + partner_len,
+                    false => partner,
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The `count`th bracket or quote `ch` after `offset` that closes a pair
+    /// around it, or before it that opens one, if not `forward`.
+    pub(crate) fn unmatched(
+        &mut self,
+        offset: usize,
+        ch: char,
+        forward: bool,
+        count: usize,
+    ) -> Result<Option<usize>, BluebookCoreError> {
+        let Some((open, close)) = pair_of(ch) else {
+            return Ok(None);
+        };
+
+        let mut target = None;
+        let mut from = offset;
+        for _ in 0..count.max(1) {
+            let found = match open == close {
+                true => {
+                    let (start, content) = self.line_text(from)?;
+                    let column = from - start;
+                    let pairs = quote_pairs(&content, ch).into_iter();
+                    let found = match forward {
+                        true => pairs.map(|(_, close)| close).find(|close| *close > column),
+                        false => pairs
+                            .rev()
+                            .map(|(open, _)| open)
+                            .find(|open| *open < column),
+                    };
+                    found.map(|found| start + found)
+                }
+                false => {
+                    // From a closing bracket, look past it to the pair around.
+                    let start = match forward && self.char_at(from)? == Some(close) {
+                        true => from + close.len_utf8(),
+                        false => from,
+                    };
+                    let mut cursor = PairCursor::new(
+                        self.text_buffer
+                            .char_cursor(ByteOffset(start))
+                            .map_err(CursorError::from)?,
+                    );
+                    match forward {
+                        true => cursor.next_unmatched(open, close),
+                        false => cursor.prev_unmatched(open, close),
+                    }
+                }
+            };
+            match found {
+                Some(found) => {
+                    target = Some(found);
+                    from = found;
+                }
+                None => break,
+            }
+        }
+
+        Ok(target)
+    }
+
+    /// The bracket and quote pairs to highlight: for each cursor on one, or
+    /// right after one in insert mode, it and its partner, if that is no more
+    /// than [`HIGHLIGHT_SCAN_LIMIT`] bytes away.
+    pub fn pair_highlights(&mut self) -> Result<Vec<Range<usize>>, BluebookCoreError> {
+        let mut highlights = Vec::new();
+        for range in self.selection.ranges().to_vec() {
+            let mut candidates = vec![range.head];
+            if self.modal.mode == Mode::Insert {
+                let mut cursor = self
+                    .text_buffer
+                    .char_cursor(ByteOffset(range.head))
+                    .map_err(CursorError::from)?;
+                if cursor.prev_codepoint().is_some() {
+                    candidates.push(cursor.pos());
+                }
+            }
+
+            for at in candidates {
+                let Some(partner) = self.matching_pair_within(at, HIGHLIGHT_SCAN_LIMIT)? else {
+                    continue;
+                };
+                for offset in [at, partner] {
+                    let len = self.char_at(offset)?.map_or(0, char::len_utf8);
+                    highlights.push(offset..offset + len);
+                }
+                break;
+            }
+        }
+
+        highlights.sort_by_key(|range| range.start);
+        highlights.dedup();
+
+        Ok(highlights)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        command::Transaction,
+        cursor::CursorRange,
+        movement::Movement,
+        selection::Selection,
+        test_utils::{normal_ctx, run},
+    };
+
+    const CITE: &str = r"Roe v. Wade (holding (that \) isn't) a “pin” cite), 410 U.S.";
+
+    #[test]
+    fn quotes_pair_along_the_line() {
+        assert_eq!(
+            quote_pairs(r"'a' and the Court's 'b \' c'", '\''),
+            [(0, 2), (20, 27)]
+        );
+        assert_eq!(quote_pairs(r#"say "hi" and ""#, '"'), [(4, 7)]);
+    }
+
+    #[test]
+    fn pairs_match_across_nesting_and_escapes() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx(CITE, 0);
+
+        assert_eq!(ctx.matching_pair(12)?, Some(53));
+        assert_eq!(ctx.matching_pair(53)?, Some(12));
+        // The escaped bracket is skipped over.
+        assert_eq!(ctx.matching_pair(21)?, Some(35));
+        assert_eq!(ctx.matching_pair(35)?, Some(21));
+        assert_eq!(ctx.matching_pair(28)?, None);
+        assert_eq!(ctx.matching_pair(39)?, Some(45));
+        assert_eq!(ctx.matching_pair(45)?, Some(39));
+        // An apostrophe is no quote.
+        assert_eq!(ctx.matching_pair(33)?, None);
+        assert_eq!(ctx.matching_pair(0)?, None);
+
+        assert_eq!(
+            ctx.movement_offset(24, &Movement::NextUnmatched(')'), 1)?,
+            Some(35)
+        );
+        assert_eq!(
+            ctx.movement_offset(24, &Movement::NextUnmatched(')'), 2)?,
+            Some(53)
+        );
+        assert_eq!(
+            ctx.movement_offset(24, &Movement::PreviousUnmatched('('), 2)?,
+            Some(12)
+        );
+        assert_eq!(
+            ctx.movement_offset(35, &Movement::NextUnmatched(')'), 1)?,
+            Some(53)
+        );
+        assert_eq!(
+            ctx.movement_offset(60, &Movement::NextUnmatched(')'), 1)?,
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn percent_jumps_between_pairs() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx(CITE, 0);
+
+        // From before any bracket, the first one on the line is matched.
+        run(&mut ctx, [Transaction::MoveToMatchingPair])?;
+        assert_eq!(ctx.cursor_range, CursorRange::new(53, 53));
+        run(&mut ctx, [Transaction::MoveToMatchingPair])?;
+        assert_eq!(ctx.cursor_range, CursorRange::new(12, 12));
+
+        // Deleting back to the partner takes in the bracket started on.
+        let mut ctx = normal_ctx(CITE, 35);
+        run(
+            &mut ctx,
+            [Transaction::DeleteOperator, Transaction::MoveToMatchingPair],
+        )?;
+        assert_eq!(
+            ctx.text_buffer.take(),
+            "Roe v. Wade (holding  a “pin” cite), 410 U.S."
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn highlights_follow_the_cursors() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx(CITE, 21);
+        assert_eq!(ctx.pair_highlights()?, [21..22, 35..36]);
+
+        // In insert mode, the bracket just typed counts too.
+        ctx.modal.mode = Mode::Insert;
+        ctx.selection = Selection::single(CursorRange::new(48, 48));
+        assert_eq!(ctx.pair_highlights()?, [39..42, 45..48]);
+
+        ctx.selection = Selection::single(CursorRange::new(1, 1));
+        assert!(ctx.pair_highlights()?.is_empty());
+
+        // Partners too far away aren't looked for, though % still finds them.
+        let far = format!("({})", "x".repeat(HIGHLIGHT_SCAN_LIMIT + 1));
+        let mut ctx = normal_ctx(&far, 0);
+        assert!(ctx.pair_highlights()?.is_empty());
+        assert_eq!(ctx.matching_pair(0)?, Some(far.len() - 1));
+
+        Ok(())
+    }
+}
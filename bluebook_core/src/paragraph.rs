@@ -1,4 +1,4 @@
-use crate::text_buffer::TextBuffer;
+use crate::{char::CharCursor, text_buffer::TextBuffer};
 
 /// Describe char classifications used to compose word boundaries
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -49,6 +49,9 @@ pub fn get_char_property(codepoint: char) -> CharClassification {
     }
 }
 
+/// Classify the gap between `prev` and `next`. Cursors collapse `\r\n` into a
+/// single [`CharClassification::Lf`] before calling this, so CRLF and LF text
+/// share the same boundaries.
 fn classify_boundary(
     before_prev: CharClassification,
     prev: CharClassification,
@@ -60,9 +63,7 @@ fn classify_boundary(
     match (before_prev, prev, next, after_next) {
         (Other, Lf, Lf, Other) => Both,
         (_, Lf, Lf, Other) => Start,
-        (Lf, Cr, Lf, Other) => Start,
         (Other, Lf, Lf, _) => End,
-        (Other, Cr, Lf, Cr) => End,
         _ => Interior,
     }
 }
@@ -83,3 +84,136 @@ pub trait ParagraphCursor<'buffer> {
     fn prev_boundary(&mut self) -> Option<usize>;
     fn next_boundary(&mut self) -> Option<usize>;
 }
+
+/// A [`ParagraphCursor`] built on top of any [`CharCursor`]. Boundaries are
+/// found by sliding a four codepoint window over the text and feeding it to
+/// [`classify_boundary`].
+pub struct CharParagraphCursor<C> {
+    inner: C,
+}
+
+impl<'buffer, C> ParagraphCursor<'buffer> for CharParagraphCursor<C>
+where
+    C: CharCursor<'buffer>,
+{
+    type Buffer = C::Buffer;
+
+    fn new(text: &'buffer Self::Buffer, pos: usize) -> Self {
+        Self {
+            inner: C::new(text, pos),
+        }
+    }
+
+    /// Move to the closest paragraph start before the cursor, or to the start
+    /// of the text if there is none.
+    fn prev_boundary(&mut self) -> Option<usize> {
+        let origin = self.inner.pos();
+
+        let mut next = self.next_unit();
+        let mut after_next = self.next_unit();
+        self.inner.set(origin);
+
+        let mut gap = origin;
+        let mut prev = self.prev_unit()?;
+        loop {
+            let prev_start = self.inner.pos();
+            let before_prev = self.prev_unit();
+
+            if gap < origin
+                && classify_boundary(
+                    before_prev.unwrap_or(CharClassification::Other),
+                    prev,
+                    next.unwrap_or(CharClassification::Other),
+                    after_next.unwrap_or(CharClassification::Other),
+                )
+                .is_start()
+            {
+                self.inner.set(gap);
+                return Some(gap);
+            }
+
+            after_next = next;
+            next = Some(prev);
+            gap = prev_start;
+            prev = match before_prev {
+                Some(class) => class,
+                None => break,
+            };
+        }
+
+        self.inner.set(0);
+        Some(0)
+    }
+
+    /// Move to the closest paragraph end after the cursor, or to the end of the
+    /// text if there is none.
+    fn next_boundary(&mut self) -> Option<usize> {
+        let origin = self.inner.pos();
+
+        let mut prev = self.prev_unit();
+        let mut before_prev = self.prev_unit();
+        self.inner.set(origin);
+
+        let mut gap = origin;
+        let mut next = self.next_unit()?;
+        loop {
+            let next_end = self.inner.pos();
+            let after_next = self.next_unit();
+            self.inner.set(next_end);
+
+            if gap > origin
+                && classify_boundary(
+                    before_prev.unwrap_or(CharClassification::Other),
+                    prev.unwrap_or(CharClassification::Other),
+                    next,
+                    after_next.unwrap_or(CharClassification::Other),
+                )
+                .is_end()
+            {
+                self.inner.set(gap);
+                return Some(gap);
+            }
+
+            before_prev = prev;
+            prev = Some(next);
+            gap = next_end;
+            next = match self.next_unit() {
+                Some(class) => class,
+                None => break,
+            };
+        }
+
+        Some(self.inner.pos())
+    }
+}
+
+impl<'buffer, C> CharParagraphCursor<C>
+where
+    C: CharCursor<'buffer>,
+{
+    /// Step back over the codepoint before the cursor, or over the whole of a
+    /// `\r\n`, and classify it. A `\r\n` classifies as a single line feed.
+    fn prev_unit(&mut self) -> Option<CharClassification> {
+        let ch = self.inner.prev_codepoint()?;
+        if ch == '\n' {
+            let pos = self.inner.pos();
+            if self.inner.prev_codepoint() != Some('\r') {
+                self.inner.set(pos);
+            }
+        }
+
+        Some(get_char_property(ch))
+    }
+
+    /// Step over the codepoint after the cursor, or over the whole of a `\r\n`,
+    /// and classify it. A `\r\n` classifies as a single line feed.
+    fn next_unit(&mut self) -> Option<CharClassification> {
+        let ch = self.inner.next_codepoint()?;
+        if ch == '\r' && self.inner.peek_next_codepoint() == Some('\n') {
+            self.inner.next_codepoint();
+            return Some(CharClassification::Lf);
+        }
+
+        Some(get_char_property(ch))
+    }
+}
@@ -0,0 +1,385 @@
+//! Registers: named places to yank rich text to and put it back from, as vi
+//! has them.
+//!
+//! Yanks go to register `0`, and deletes and changes to `1`, shifting the
+//! deletes before it down to `9`, where the oldest falls off. Both also go to
+//! the unnamed register `"`, which puts read from unless another register is
+//! selected first. Letters name registers only written when selected, and
+//! writing to their capital appends to them. Writing to `_` discards the
+//! text, and `+` is the clipboard.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::{
+    clipboard::Fragment, ctx::TextEditorContext, error::BluebookCoreError, mode::Mode,
+    movement::Movement, offset::ByteOffset, search::Searchable, span::Span,
+    text_buffer::TextBuffer,
+};
+
+pub const UNNAMED: char = '"';
+pub const BLACK_HOLE: char = '_';
+pub const CLIPBOARD: char = '+';
+
+/// How many deletes the numbered registers keep, in `1` to `9`.
+const DELETE_RING_LEN: usize = 9;
+
+/// Text yanked or deleted, with its annotations.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Register {
+    pub fragment: Fragment,
+    /// Whether the text is whole lines, line breaks included, which are put
+    /// above or below the cursor's line rather than at the cursor.
+    pub linewise: bool,
+}
+
+impl Register {
+    pub fn new(fragment: Fragment, linewise: bool) -> Self {
+        Self { fragment, linewise }
+    }
+
+    /// Whole lines, ending with a line break even if the last of them had
+    /// none in the text.
+    pub fn lines(mut fragment: Fragment, line_ending: &str) -> Self {
+        if !fragment.plain_text().ends_with('\n') {
+            fragment.spans.push(Span {
+                insert: line_ending.to_string(),
+                attributes: Default::default(),
+            });
+        }
+
+        Self::new(fragment, true)
+    }
+
+    /// Add `other` to the end of this register. Adding lines to text that
+    /// isn't starts them on a line of their own.
+    pub fn append(&mut self, other: Register, line_ending: &str) {
+        let separator = match other.linewise && !self.linewise && !self.fragment.is_empty() {
+            true => line_ending,
+            false => "",
+        };
+        self.fragment.append(separator, other.fragment);
+        self.linewise |= other.linewise;
+    }
+}
+
+/// Whether `name` names a register.
+pub fn is_register(name: char) -> bool {
+    matches!(name, UNNAMED | BLACK_HOLE | CLIPBOARD | '0'..='9') || name.is_ascii_alphabetic()
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Registers {
+    unnamed: Option<Register>,
+    yanked: Option<Register>,
+    /// The last deletes, most recent first.
+    deleted: VecDeque<Register>,
+    named: BTreeMap<char, Register>,
+}
+
+impl Registers {
+    /// The contents of register `name`, other than the clipboard.
+    pub fn get(&self, name: char) -> Option<&Register> {
+        match name {
+            UNNAMED => self.unnamed.as_ref(),
+            '0' => self.yanked.as_ref(),
+            '1'..='9' => {
+                let index = name.to_digit(10)? as usize - 1;
+                self.deleted.get(index)
+            }
+            name if name.is_ascii_alphabetic() => self.named.get(&name.to_ascii_lowercase()),
+            _ => None,
+        }
+    }
+
+    /// Write `register` to register `name`, or to the numbered registers if
+    /// no register is named, `deleted` telling a delete from a yank. Either
+    /// way, the unnamed register gets what was written, except to `_`.
+    pub fn store(
+        &mut self,
+        name: Option<char>,
+        register: Register,
+        deleted: bool,
+        line_ending: &str,
+    ) {
+        let stored = match name {
+            Some(BLACK_HOLE) => return,
+            Some(name) if name.is_ascii_lowercase() => {
+                self.named.insert(name, register.clone());
+                register
+            }
+            Some(name) if name.is_ascii_uppercase() => {
+                let named = self.named.entry(name.to_ascii_lowercase()).or_default();
+                named.append(register, line_ending);
+                named.clone()
+            }
+            _ if deleted => {
+                self.deleted.push_front(register.clone());
+                self.deleted.truncate(DELETE_RING_LEN);
+                register
+            }
+            _ => {
+                self.yanked = Some(register.clone());
+                register
+            }
+        };
+
+        self.unnamed = Some(stored);
+    }
+}
+
+impl<Buffer> TextEditorContext<Buffer>
+where
+    Buffer: TextBuffer + Searchable,
+{
+    /// Write `register` to the register selected for this command, if any.
+    pub(crate) fn store_register(&mut self, register: Register, deleted: bool) {
+        let name = self.modal.register;
+        if name == Some(CLIPBOARD) {
+            self.clipboard = Some(register.fragment.clone());
+        }
+
+        let name = name.filter(|name| *name != CLIPBOARD);
+        let line_ending = self.line_ending.as_str();
+        self.registers.store(name, register, deleted, line_ending);
+    }
+
+    /// The register selected for this command, or the unnamed one.
+    fn selected_register(&self) -> Option<Register> {
+        match self.modal.register.unwrap_or(UNNAMED) {
+            CLIPBOARD => self
+                .clipboard
+                .clone()
+                .map(|fragment| Register::new(fragment, false)),
+            name => self.registers.get(name).cloned(),
+        }
+    }
+
+    /// Yank the text of every range of the selection, or the lines holding
+    /// the cursors if no range selects any text.
+    pub(crate) fn yank(&mut self) -> Result<bool, BluebookCoreError> {
+        let ranges = self.selection.ranges().to_vec();
+        let linewise = ranges.iter().all(|range| range.is_empty());
+
+        let mut fragment = Fragment::default();
+        for range in ranges {
+            let (range, separator) = match linewise {
+                true => (self.line_at(range.head)?, ""),
+                false if range.is_empty() => continue,
+                false => (range.from()..range.to(), self.line_ending.as_str()),
+            };
+            let spans = self
+                .text_buffer
+                .slice_spans(ByteOffset(range.start)..ByteOffset(range.end))?;
+            fragment.append(separator, Fragment::new(spans));
+        }
+
+        let register = match linewise {
+            true => Register::lines(fragment, self.line_ending.as_str()),
+            false => Register::new(fragment, false),
+        };
+        self.store_register(register, false);
+
+        Ok(true)
+    }
+
+    /// Put the selected register after every cursor, or before it: lines go
+    /// below or above the cursor's line, and other text right after the
+    /// grapheme the cursor is on, or at the cursor.
+    pub(crate) fn put(&mut self, before: bool) -> Result<bool, BluebookCoreError> {
+        let Some(register) = self.selected_register() else {
+            return Ok(false);
+        };
+        if register.fragment.is_empty() {
+            return Ok(false);
+        }
+
+        self.apply_to_all_ranges_with(|ctx| match register.linewise {
+            true => ctx.put_lines(&register.fragment, before),
+            false => ctx.put_text(&register.fragment, before),
+        })
+    }
+
+    fn put_lines(&mut self, fragment: &Fragment, before: bool) -> Result<bool, BluebookCoreError> {
+        let head = self.cursor_range.head;
+        let line = self.line_at(head)?;
+        let (start, content) = self.line_text(head)?;
+
+        let first_line = match before {
+            true => {
+                self.cursor_range.set_point(line.start);
+                self.paste_fragment(fragment)?;
+                line.start
+            }
+            // The last line may have no line break to put lines after.
+            false if line.end == start + content.len() => {
+                let line_ending = self.line_ending.as_str();
+                let mut lines = Fragment::new(vec![Span {
+                    insert: line_ending.to_string(),
+                    attributes: Default::default(),
+                }]);
+                lines.append("", trim_line_break(fragment.clone()));
+                self.cursor_range.set_point(line.end);
+                self.paste_fragment(&lines)?;
+                line.end + line_ending.len()
+            }
+            false => {
+                self.cursor_range.set_point(line.end);
+                self.paste_fragment(fragment)?;
+                line.end
+            }
+        };
+
+        let caret = self.first_non_blank(first_line)?;
+        self.cursor_range.set_point(caret);
+
+        Ok(true)
+    }
+
+    fn put_text(&mut self, fragment: &Fragment, before: bool) -> Result<bool, BluebookCoreError> {
+        let head = self.cursor_range.head;
+        let (start, content) = self.line_text(head)?;
+        let at = match before || head >= start + content.len() {
+            true => head,
+            false => self
+                .movement_offset(head, &Movement::Right, 1)?
+                .unwrap_or(head),
+        };
+        self.cursor_range.set_point(at);
+        self.paste_fragment(fragment)?;
+
+        // In normal mode, the cursor stays on the last grapheme put.
+        let end = self.cursor_range.head;
+        if self.modal.mode == Mode::Normal && end > at {
+            let last = self.movement_offset(end, &Movement::Left, 1)?;
+            self.cursor_range.set_point(last.unwrap_or(end));
+        }
+
+        Ok(true)
+    }
+}
+
+/// `fragment` without the line break it ends with, if any.
+fn trim_line_break(mut fragment: Fragment) -> Fragment {
+    fragment.spans.retain(|span| !span.insert.is_empty());
+    if let Some(span) = fragment.spans.last_mut() {
+        if span.insert.ends_with('\n') {
+            span.insert.pop();
+            if span.insert.ends_with('\r') {
+                span.insert.pop();
+            }
+        }
+        if span.insert.is_empty() {
+            fragment.spans.pop();
+        }
+    }
+
+    fragment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        command::Transaction,
+        cursor::CursorRange,
+        test_utils::{normal_ctx, run},
+    };
+
+    fn text(s: &str) -> Register {
+        Register::new(
+            Fragment::new(vec![Span {
+                insert: s.to_string(),
+                attributes: Default::default(),
+            }]),
+            false,
+        )
+    }
+
+    fn contents(registers: &Registers, name: char) -> Option<String> {
+        Some(registers.get(name)?.fragment.plain_text())
+    }
+
+    #[test]
+    fn deletes_shift_through_the_numbered_registers() {
+        let mut registers = Registers::default();
+        registers.store(None, text("yanked"), false, "\n");
+        for index in 0..10 {
+            registers.store(None, text(&index.to_string()), true, "\n");
+        }
+
+        assert_eq!(contents(&registers, '0').as_deref(), Some("yanked"));
+        assert_eq!(contents(&registers, '1').as_deref(), Some("9"));
+        assert_eq!(contents(&registers, '9').as_deref(), Some("1"));
+        assert_eq!(contents(&registers, UNNAMED).as_deref(), Some("9"));
+
+        registers.store(Some(BLACK_HOLE), text("gone"), true, "\n");
+        assert_eq!(contents(&registers, '1').as_deref(), Some("9"));
+        assert_eq!(contents(&registers, UNNAMED).as_deref(), Some("9"));
+    }
+
+    #[test]
+    fn capitals_append_to_named_registers() {
+        let mut registers = Registers::default();
+        registers.store(Some('a'), text("one"), false, "\n");
+        registers.store(Some('A'), text(" two"), false, "\n");
+        registers.store(
+            Some('A'),
+            Register::lines(text("three").fragment, "\n"),
+            false,
+            "\n",
+        );
+
+        let register = registers.get('a').unwrap();
+        assert_eq!(register.fragment.plain_text(), "one two\nthree\n");
+        assert!(register.linewise);
+        assert_eq!(registers.get('0'), None);
+    }
+
+    #[test]
+    fn yank_and_put_through_registers() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx("one two\nthree", 0);
+
+        run(
+            &mut ctx,
+            [
+                Transaction::SelectRegister { name: 'a' },
+                Transaction::YankOperator,
+                Transaction::MoveWordForward,
+                Transaction::DeleteOperator,
+                Transaction::DeleteOperator,
+            ],
+        )?;
+        assert_eq!(ctx.text_buffer.take(), "three");
+        assert_eq!(contents(&ctx.registers, 'a').as_deref(), Some("one "));
+        assert_eq!(contents(&ctx.registers, '1').as_deref(), Some("one two\n"));
+        assert_eq!(ctx.modal.register, None);
+
+        run(
+            &mut ctx,
+            [
+                Transaction::SelectRegister { name: 'a' },
+                Transaction::PasteAfter,
+            ],
+        )?;
+        assert_eq!(ctx.text_buffer.take(), "tone hree");
+        assert_eq!(ctx.cursor_range, CursorRange::new(4, 4));
+
+        run(&mut ctx, [Transaction::PasteBefore])?;
+        assert_eq!(ctx.text_buffer.take(), "one two\ntone hree");
+        assert_eq!(ctx.cursor_range, CursorRange::new(0, 0));
+
+        // The last line has no line break to put lines after.
+        run(
+            &mut ctx,
+            [
+                Transaction::MoveToDocumentEnd,
+                Transaction::SelectRegister { name: '1' },
+                Transaction::PasteAfter,
+            ],
+        )?;
+        assert_eq!(ctx.text_buffer.take(), "one two\ntone hree\none two");
+        assert_eq!(ctx.cursor_range, CursorRange::new(18, 18));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,431 @@
+//! Search and replace, built on [`xi_rope::find`].
+
+use std::{borrow::Cow, cell::OnceCell, ops::Range};
+
+use regex::{Regex, RegexBuilder};
+use xi_rope::{
+    find::{find, is_multiline_regex, CaseMatching},
+    Cursor, Rope,
+};
+
+use crate::text_buffer::TextBuffer;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SearchError {
+    #[error("Invalid regular expression: {0}")]
+    InvalidRegex(#[from] regex::Error),
+}
+
+/// What to search for, and how.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    pub pattern: String,
+    pub case_sensitive: bool,
+    /// Only match whole words: the match may neither start nor end in the
+    /// middle of a word.
+    pub whole_word: bool,
+    /// Treat `pattern` as a regular expression.
+    pub regex: bool,
+}
+
+impl SearchQuery {
+    /// A case-sensitive search for `pattern` as is.
+    pub fn literal(pattern: impl Into<String>) -> Self {
+        Self {
+            pattern: pattern.into(),
+            case_sensitive: true,
+            whole_word: false,
+            regex: false,
+        }
+    }
+
+    /// A case-sensitive search for the regular expression `pattern`.
+    pub fn regex(pattern: impl Into<String>) -> Self {
+        Self {
+            regex: true,
+            ..Self::literal(pattern)
+        }
+    }
+
+    pub fn case_insensitive(self) -> Self {
+        Self {
+            case_sensitive: false,
+            ..self
+        }
+    }
+
+    pub fn whole_word(self) -> Self {
+        Self {
+            whole_word: true,
+            ..self
+        }
+    }
+
+    fn matcher(&self) -> Result<Matcher, SearchError> {
+        let regex = match self.regex {
+            true => Some(
+                RegexBuilder::new(&self.pattern)
+                    .case_insensitive(!self.case_sensitive)
+                    .build()?,
+            ),
+            false => None,
+        };
+        // Case-insensitive literal search expects a lowercase pattern.
+        let pattern = match self.case_sensitive || self.regex {
+            true => self.pattern.clone(),
+            false => self.pattern.to_lowercase(),
+        };
+        let case_matching = match self.case_sensitive {
+            true => CaseMatching::Exact,
+            false => CaseMatching::CaseInsensitive,
+        };
+
+        Ok(Matcher {
+            pattern,
+            case_matching,
+            regex,
+            whole_word: self.whole_word,
+        })
+    }
+}
+
+struct Matcher {
+    pattern: String,
+    case_matching: CaseMatching,
+    regex: Option<Regex>,
+    whole_word: bool,
+}
+
+/// The text searched. A copy of it as a single string is only made for
+/// patterns that may match a line break, once per search.
+struct Haystack<'a> {
+    rope: &'a Rope,
+    text: OnceCell<Cow<'a, str>>,
+}
+
+impl<'a> Haystack<'a> {
+    fn new(rope: &'a Rope) -> Self {
+        Self {
+            rope,
+            text: OnceCell::new(),
+        }
+    }
+
+    fn text(&self) -> &str {
+        self.text.get_or_init(|| self.rope.slice_to_cow(..))
+    }
+}
+
+/// A match, with the text replacing it when a replacement was asked for.
+struct Found {
+    range: Range<usize>,
+    replaced: Option<String>,
+}
+
+impl Matcher {
+    /// The first match starting at or after `from`. Regular expression
+    /// replacements may refer to capture groups, as in `$1` or `${name}`.
+    fn next_match(
+        &self,
+        haystack: &Haystack,
+        from: usize,
+        replacement: Option<&str>,
+    ) -> Option<Found> {
+        match &self.regex {
+            Some(regex) => self.next_regex_match(regex, haystack, from, replacement),
+            None => self
+                .next_literal_match(haystack.rope, from)
+                .map(|range| Found {
+                    range,
+                    replaced: replacement.map(str::to_string),
+                }),
+        }
+    }
+
+    fn next_literal_match(&self, rope: &Rope, from: usize) -> Option<Range<usize>> {
+        let mut from = from;
+        while from <= rope.len() {
+            let mut cursor = Cursor::new(rope, from);
+            let mut lines = rope.lines_raw(from..rope.len());
+            let start = find(
+                &mut cursor,
+                &mut lines,
+                self.case_matching,
+                &self.pattern,
+                None,
+            )?;
+            let end = cursor.pos();
+
+            if start < end && (!self.whole_word || is_whole_word(rope, start..end)) {
+                return Some(start..end);
+            }
+            from = rope.next_codepoint_offset(start)?;
+        }
+
+        None
+    }
+
+    /// Patterns that may match a line break are matched against the whole
+    /// text, the others a line at a time, as [`xi_rope::find`] does. Either
+    /// way, the captures replacing a match are those of the match itself.
+    fn next_regex_match(
+        &self,
+        regex: &Regex,
+        haystack: &Haystack,
+        from: usize,
+        replacement: Option<&str>,
+    ) -> Option<Found> {
+        let rope = haystack.rope;
+        if from > rope.len() {
+            return None;
+        }
+        if is_multiline_regex(&self.pattern) {
+            return self.regex_match_in(regex, rope, haystack.text(), 0, from, replacement);
+        }
+
+        let mut line_start = rope.offset_of_line(rope.line_of_offset(from));
+        for line in rope.lines_raw(line_start..rope.len()) {
+            let found = self.regex_match_in(
+                regex,
+                rope,
+                &line,
+                line_start,
+                from.max(line_start),
+                replacement,
+            );
+            if found.is_some() {
+                return found;
+            }
+            line_start += line.len();
+        }
+
+        None
+    }
+
+    /// The first match in `haystack`, which starts at offset `base` of `rope`,
+    /// starting at or after offset `from` of `rope`.
+    fn regex_match_in(
+        &self,
+        regex: &Regex,
+        rope: &Rope,
+        haystack: &str,
+        base: usize,
+        from: usize,
+        replacement: Option<&str>,
+    ) -> Option<Found> {
+        let mut at = from - base;
+        while at <= haystack.len() {
+            let captures = regex.captures_at(haystack, at)?;
+            let found = captures.get(0)?;
+            let range = base + found.start()..base + found.end();
+
+            if !range.is_empty() && (!self.whole_word || is_whole_word(rope, range.clone())) {
+                let replaced = replacement.map(|replacement| {
+                    let mut expanded = String::new();
+                    captures.expand(replacement, &mut expanded);
+                    expanded
+                });
+                return Some(Found { range, replaced });
+            }
+            at = match haystack[found.start()..].chars().next() {
+                Some(ch) => found.start() + ch.len_utf8(),
+                None => break,
+            };
+        }
+
+        None
+    }
+
+    /// Every match in `rope`, in order.
+    fn all_matches(&self, rope: &Rope, replacement: Option<&str>) -> Vec<Found> {
+        let haystack = Haystack::new(rope);
+        let mut matches = Vec::new();
+        let mut from = 0;
+        while let Some(found) = self.next_match(&haystack, from, replacement) {
+            from = found.range.end;
+            matches.push(found);
+        }
+
+        matches
+    }
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// Whether `range` neither starts nor ends in the middle of a word.
+fn is_whole_word(rope: &Rope, range: Range<usize>) -> bool {
+    let char_at = |offset: usize| Cursor::new(rope, offset).peek_next_codepoint();
+    let before = rope.prev_codepoint_offset(range.start).and_then(char_at);
+    let first = char_at(range.start);
+    let last = rope.prev_codepoint_offset(range.end).and_then(char_at);
+    let after = char_at(range.end);
+
+    let starts_word = !(before.is_some_and(is_word_char) && first.is_some_and(is_word_char));
+    let ends_word = !(last.is_some_and(is_word_char) && after.is_some_and(is_word_char));
+
+    starts_word && ends_word
+}
+
+/// Search over the text of a buffer.
+///
+/// Every method is provided: backends only decide how their text is handed to
+/// [`xi_rope::find`]. By default it is copied into a rope, which backends
+/// already built on one can skip by overriding [`Searchable::search_rope`].
+pub trait Searchable: TextBuffer {
+    /// The text of the buffer, as a rope.
+    fn search_rope(&self) -> Cow<'_, Rope> {
+        Cow::Owned(Rope::from(self.take().as_ref()))
+    }
+
+    /// Every match of `query`, in order. Matches never overlap.
+    fn find_all(&self, query: &SearchQuery) -> Result<Vec<Range<usize>>, SearchError> {
+        Ok(query
+            .matcher()?
+            .all_matches(&self.search_rope(), None)
+            .into_iter()
+            .map(|found| found.range)
+            .collect())
+    }
+
+    /// The first match starting at or after `from`, wrapping around to the
+    /// start of the text.
+    fn find_next(
+        &self,
+        query: &SearchQuery,
+        from: usize,
+    ) -> Result<Option<Range<usize>>, SearchError> {
+        let matcher = query.matcher()?;
+        let rope = self.search_rope();
+        let haystack = Haystack::new(&rope);
+
+        Ok(matcher
+            .next_match(&haystack, from, None)
+            .or_else(|| matcher.next_match(&haystack, 0, None))
+            .map(|found| found.range))
+    }
+
+    /// The last match ending at or before `to`, wrapping around to the end of
+    /// the text.
+    fn find_prev(
+        &self,
+        query: &SearchQuery,
+        to: usize,
+    ) -> Result<Option<Range<usize>>, SearchError> {
+        let matches = self.find_all(query)?;
+
+        Ok(matches
+            .iter()
+            .rev()
+            .find(|found| found.end <= to)
+            .or(matches.last())
+            .cloned())
+    }
+
+    /// The edits replacing every match of `query` with `replacement`, in
+    /// order, as the range replaced and the text replacing it.
+    fn replacements(
+        &self,
+        query: &SearchQuery,
+        replacement: &str,
+    ) -> Result<Vec<(Range<usize>, String)>, SearchError> {
+        let matcher = query.matcher()?;
+        let rope = self.search_rope();
+
+        Ok(matcher
+            .all_matches(&rope, Some(replacement))
+            .into_iter()
+            .map(|found| (found.range, found.replaced.unwrap_or_default()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::rope::buffer_impl::RopeBuffer;
+
+    fn find_all(text: &str, query: SearchQuery) -> Vec<Range<usize>> {
+        RopeBuffer::from_str(text).find_all(&query).unwrap()
+    }
+
+    #[test]
+    fn literal_and_case_insensitive() {
+        assert_eq!(
+            find_all("Roe v. Wade, roe, Roe", SearchQuery::literal("Roe")),
+            [0..3, 18..21]
+        );
+        assert_eq!(
+            find_all(
+                "Roe v. Wade, roe, Roe",
+                SearchQuery::literal("ROE").case_insensitive()
+            ),
+            [0..3, 13..16, 18..21]
+        );
+    }
+
+    #[test]
+    fn whole_words_only() {
+        assert_eq!(
+            find_all(
+                "cat concat cat_ cat.",
+                SearchQuery::literal("cat").whole_word()
+            ),
+            [0..3, 16..19]
+        );
+    }
+
+    #[test]
+    fn regex_matches_and_replacements() -> Result<(), SearchError> {
+        let buf = RopeBuffer::from_str("347 U.S. 483\n410 U.S. 113");
+        let query = SearchQuery::regex(r"(\d+) U\.S\. (\d+)");
+
+        assert_eq!(buf.find_all(&query)?, [0..12, 13..25]);
+        assert_eq!(
+            buf.replacements(&query, "$1 US $2")?,
+            [
+                (0..12, "347 US 483".to_string()),
+                (13..25, "410 US 113".to_string())
+            ]
+        );
+        // Captures are those of the match in its context, which may differ
+        // from those of the matched text on its own.
+        let buf = RopeBuffer::from_str("ab a");
+        assert_eq!(
+            buf.replacements(&SearchQuery::regex(r"(a)\B|(a)"), "[$1|$2]")?,
+            [(0..1, "[a|]".to_string()), (3..4, "[|a]".to_string())]
+        );
+        assert!(matches!(
+            buf.find_all(&SearchQuery::regex("(")),
+            Err(SearchError::InvalidRegex(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn regexes_match_a_line_at_a_time() -> Result<(), SearchError> {
+        let buf = RopeBuffer::from_str("12 a\n34 b");
+
+        assert_eq!(buf.find_all(&SearchQuery::regex(r"^\d+"))?, [0..2, 5..7]);
+        // Unless they may match a line break.
+        let query = SearchQuery::regex(r"a\n\d+");
+        assert_eq!(buf.find_next(&query, 0)?, Some(3..7));
+
+        Ok(())
+    }
+
+    #[test]
+    fn next_and_prev_wrap_around() -> Result<(), SearchError> {
+        let buf = RopeBuffer::from_str("a b a b");
+        let query = SearchQuery::literal("a");
+
+        assert_eq!(buf.find_next(&query, 1)?, Some(4..5));
+        assert_eq!(buf.find_next(&query, 5)?, Some(0..1));
+        assert_eq!(buf.find_prev(&query, 4)?, Some(0..1));
+        assert_eq!(buf.find_prev(&query, 0)?, Some(4..5));
+
+        Ok(())
+    }
+}
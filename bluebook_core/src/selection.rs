@@ -0,0 +1,163 @@
+use smallvec::{smallvec, SmallVec};
+
+use crate::cursor::CursorRange;
+
+/// A set of cursor ranges, one of which is the primary one.
+///
+/// The ranges are kept sorted by their start and never overlap: ranges that
+/// come to overlap are merged into one. The primary range is the one the view
+/// follows and that single-cursor commands act on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    ranges: SmallVec<[CursorRange; 1]>,
+    primary: usize,
+}
+
+impl Selection {
+    pub fn single(range: CursorRange) -> Self {
+        Self {
+            ranges: smallvec![range],
+            primary: 0,
+        }
+    }
+
+    /// Build a selection out of `ranges`, merging the ones that overlap.
+    /// `primary` indexes into `ranges` as given, and falls back to the last
+    /// range if it is out of bounds.
+    pub fn new(ranges: impl IntoIterator<Item = CursorRange>, primary: usize) -> Self {
+        let ranges: SmallVec<[CursorRange; 1]> = ranges.into_iter().collect();
+        if ranges.is_empty() {
+            return Self::default();
+        }
+
+        let primary = primary.min(ranges.len() - 1);
+        let mut selection = Self { ranges, primary };
+        selection.normalise();
+        selection
+    }
+
+    pub fn ranges(&self) -> &[CursorRange] {
+        &self.ranges
+    }
+
+    pub fn primary(&self) -> CursorRange {
+        self.ranges[self.primary]
+    }
+
+    pub fn primary_index(&self) -> usize {
+        self.primary
+    }
+
+    /// Replace the primary range.
+    pub fn set_primary(&mut self, range: CursorRange) {
+        self.ranges[self.primary] = range;
+        self.normalise();
+    }
+
+    /// Add a range and make it the primary one.
+    pub fn push(&mut self, range: CursorRange) {
+        self.ranges.push(range);
+        self.primary = self.ranges.len() - 1;
+        self.normalise();
+    }
+
+    /// Drop every range but the primary one.
+    pub fn keep_primary(&mut self) {
+        *self = Self::single(self.primary());
+    }
+
+    /// Sort the ranges and merge the overlapping ones, keeping track of
+    /// where the primary range ends up.
+    fn normalise(&mut self) {
+        let primary = self.ranges[self.primary];
+        let mut indexed: SmallVec<[(usize, CursorRange); 1]> =
+            self.ranges.iter().copied().enumerate().collect();
+        indexed.sort_by_key(|(_, range)| (range.from(), range.to()));
+
+        let mut ranges: SmallVec<[CursorRange; 1]> = SmallVec::with_capacity(indexed.len());
+        let mut new_primary = 0;
+        for (index, range) in indexed {
+            match ranges.last_mut() {
+                Some(last) if last.overlaps(&range) || touches(last, &range) => {
+                    *last = last.extend(range.from(), range.to());
+                }
+                _ => ranges.push(range),
+            }
+            if index == self.primary {
+                new_primary = ranges.len() - 1;
+            }
+        }
+
+        // A primary range that swallowed others keeps its own orientation.
+        if ranges[new_primary].contains_range(&primary) {
+            let merged = ranges[new_primary];
+            ranges[new_primary] = CursorRange::new(merged.from(), merged.to())
+                .with_orientation(primary.orientation());
+        }
+
+        self.ranges = ranges;
+        self.primary = new_primary;
+    }
+}
+
+/// A point right at the end of a range would edit the same offset as the
+/// range does, so the two count as overlapping.
+fn touches(left: &CursorRange, right: &CursorRange) -> bool {
+    (left.is_empty() || right.is_empty()) && left.to() == right.from()
+}
+
+impl Default for Selection {
+    fn default() -> Self {
+        Self::single(CursorRange::default())
+    }
+}
+
+impl From<CursorRange> for Selection {
+    fn from(range: CursorRange) -> Self {
+        Self::single(range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlapping_ranges_are_merged() {
+        let selection = Selection::new(
+            [
+                CursorRange::new(10, 12),
+                CursorRange::new(0, 4),
+                CursorRange::new(3, 6),
+                CursorRange::new(12, 12),
+            ],
+            2,
+        );
+
+        assert_eq!(
+            selection.ranges(),
+            [CursorRange::new(0, 6), CursorRange::new(10, 12)]
+        );
+        assert_eq!(selection.primary_index(), 0);
+    }
+
+    #[test]
+    fn points_at_the_same_offset_collapse() {
+        let mut selection = Selection::single(CursorRange::new(4, 4));
+        selection.push(CursorRange::new(8, 8));
+        selection.push(CursorRange::new(4, 4));
+
+        assert_eq!(selection.ranges().len(), 2);
+        assert_eq!(selection.primary(), CursorRange::new(4, 4));
+
+        selection.keep_primary();
+        assert_eq!(selection, Selection::single(CursorRange::new(4, 4)));
+    }
+
+    #[test]
+    fn merged_primary_keeps_its_orientation() {
+        let selection = Selection::new([CursorRange::new(2, 5), CursorRange::new(8, 4)], 1);
+
+        assert_eq!(selection.ranges(), [CursorRange::new(8, 2)]);
+    }
+}
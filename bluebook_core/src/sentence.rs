@@ -1,4 +1,8 @@
-use crate::text_buffer::TextBuffer;
+use std::borrow::Cow;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{char::CharCursor, text_buffer::TextBuffer};
 
 #[derive(thiserror::Error, Debug)]
 pub enum SentenceCursorError {
@@ -7,7 +11,210 @@ pub enum SentenceCursorError {
     // Add more error variants as needed
 }
 
+/// Abbreviations that end in a full stop without ending the sentence.
+///
+/// UAX #29 happily breaks "Roe v. Wade" after "v.", and legal citations are full
+/// of such abbreviations, so a boundary is dropped whenever the word right
+/// before it is in this list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Abbreviations {
+    words: Vec<Cow<'static, str>>,
+}
+
+impl Abbreviations {
+    /// The abbreviations most common in legal writing.
+    pub const LEGAL: &'static [&'static str] = &[
+        "v.", "vs.", "U.S.", "Id.", "id.", "e.g.", "i.e.", "cf.", "Cf.", "al.", "No.", "Nos.",
+        "Inc.", "Corp.", "Co.", "Ltd.", "Cir.", "Ct.", "App.", "Supp.", "F.", "Mr.", "Mrs.", "Ms.",
+        "Dr.", "Jr.", "Sr.", "St.", "Art.", "Sec.", "para.",
+    ];
+
+    /// An empty list: every UAX #29 boundary is kept.
+    pub fn none() -> Self {
+        Self { words: Vec::new() }
+    }
+
+    pub fn new<I, S>(words: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<Cow<'static, str>>,
+    {
+        Self {
+            words: words.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    pub fn insert(&mut self, word: impl Into<Cow<'static, str>>) {
+        let word = word.into();
+        if !self.words.contains(&word) {
+            self.words.push(word);
+        }
+    }
+
+    pub fn remove(&mut self, word: &str) {
+        self.words.retain(|w| w != word);
+    }
+
+    /// Whether a sentence may end after `sentence`, i.e. whether it does not end
+    /// on one of the abbreviations. Trailing whitespace and any opening
+    /// punctuation glued to the front of the last word are ignored.
+    pub fn is_sentence_end(&self, sentence: &str) -> bool {
+        let last_word = sentence
+            .trim_end()
+            .rsplit(char::is_whitespace)
+            .next()
+            .unwrap_or_default()
+            .trim_start_matches(['(', '[', '"', '\'', '“', '‘']);
+
+        !self.words.iter().any(|word| word == last_word)
+    }
+}
+
+impl Default for Abbreviations {
+    fn default() -> Self {
+        Self::new(Self::LEGAL.iter().copied())
+    }
+}
+
 pub trait SentenceCursor<'buffer> {
     type Buffer: TextBuffer;
     fn new(text: &'buffer Self::Buffer, pos: usize) -> Self;
+    /// Replace the abbreviations a sentence may not end on.
+    fn set_abbreviations(&mut self, abbreviations: &Abbreviations);
+    /// Move to the start of the sentence before the cursor.
+    fn prev_boundary(&mut self) -> Option<usize>;
+    /// Move to the start of the sentence after the cursor.
+    fn next_boundary(&mut self) -> Option<usize>;
+}
+
+/// A [`SentenceCursor`] built on top of any [`CharCursor`].
+///
+/// A line feed always ends a sentence, so only the line around the cursor is
+/// copied out and fed to the UAX #29 segmenter.
+pub struct CharSentenceCursor<C> {
+    inner: C,
+    abbreviations: Abbreviations,
+}
+
+impl<'buffer, C> CharSentenceCursor<C>
+where
+    C: CharCursor<'buffer>,
+{
+    /// Copy out the line holding the codepoint right after `pos`, up to and
+    /// including its line feed. Returns the line and the offset it starts at.
+    fn line_at(&mut self, pos: usize) -> (String, usize) {
+        self.inner.set(pos);
+        while let Some(ch) = self.inner.prev_codepoint() {
+            if ch == '\n' {
+                self.inner.next_codepoint();
+                break;
+            }
+        }
+
+        let start = self.inner.pos();
+        let mut line = String::new();
+        while let Some(ch) = self.inner.next_codepoint() {
+            line.push(ch);
+            if ch == '\n' {
+                break;
+            }
+        }
+
+        (line, start)
+    }
+
+    /// The sentence starts in the line holding the codepoint after `pos`, as
+    /// absolute offsets. The end of the line is included.
+    fn boundaries_at(&mut self, pos: usize) -> Vec<usize> {
+        let (line, start) = self.line_at(pos);
+
+        let mut boundaries = vec![start];
+        let mut sentence_start = 0;
+        for (offset, _) in line.split_sentence_bound_indices().skip(1) {
+            if self
+                .abbreviations
+                .is_sentence_end(&line[sentence_start..offset])
+            {
+                boundaries.push(start + offset);
+                sentence_start = offset;
+            }
+        }
+        boundaries.push(start + line.len());
+
+        boundaries
+    }
+}
+
+impl<'buffer, C> SentenceCursor<'buffer> for CharSentenceCursor<C>
+where
+    C: CharCursor<'buffer>,
+{
+    type Buffer = C::Buffer;
+
+    fn new(text: &'buffer Self::Buffer, pos: usize) -> Self {
+        Self {
+            inner: C::new(text, pos),
+            abbreviations: Abbreviations::default(),
+        }
+    }
+
+    fn set_abbreviations(&mut self, abbreviations: &Abbreviations) {
+        self.abbreviations = abbreviations.clone();
+    }
+
+    fn prev_boundary(&mut self) -> Option<usize> {
+        let origin = self.inner.pos();
+        self.inner.prev_codepoint()?;
+        let before = self.inner.pos();
+
+        let boundary = self
+            .boundaries_at(before)
+            .into_iter()
+            .rev()
+            .find(|&boundary| boundary < origin)
+            .unwrap_or(0);
+
+        self.inner.set(boundary);
+        Some(boundary)
+    }
+
+    fn next_boundary(&mut self) -> Option<usize> {
+        let origin = self.inner.pos();
+        self.inner.peek_next_codepoint()?;
+
+        let boundary = self
+            .boundaries_at(origin)
+            .into_iter()
+            .find(|&boundary| boundary > origin)?;
+
+        self.inner.set(boundary);
+        Some(boundary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abbreviations_do_not_end_sentences() {
+        let abbreviations = Abbreviations::default();
+
+        assert!(!abbreviations.is_sentence_end("See Roe v. "));
+        assert!(!abbreviations.is_sentence_end("Brown v. Board, 347 U.S. "));
+        assert!(!abbreviations.is_sentence_end("(Id. "));
+        assert!(abbreviations.is_sentence_end("The court agreed. "));
+    }
+
+    #[test]
+    fn abbreviations_are_configurable() {
+        let mut abbreviations = Abbreviations::none();
+        assert!(abbreviations.is_sentence_end("Roe v. "));
+
+        abbreviations.insert("v.");
+        assert!(!abbreviations.is_sentence_end("Roe v. "));
+
+        abbreviations.remove("v.");
+        assert!(abbreviations.is_sentence_end("Roe v. "));
+    }
 }
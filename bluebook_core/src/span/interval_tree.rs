@@ -137,7 +137,7 @@ impl<K: Ord, V> IntervalTree<K, V> {
     /// Queries the interval tree for all elements overlapping a given interval.
     ///
     /// This runs in `O(log n + m)`.
-    pub fn query(&self, range: Range<K>) -> QueryIter<K, V> {
+    pub fn query(&self, range: Range<K>) -> QueryIter<'_, K, V> {
         QueryIter {
             todo: self.todo(),
             tree: self,
@@ -148,7 +148,7 @@ impl<K: Ord, V> IntervalTree<K, V> {
     /// Queries the interval tree for all elements containing a given point.
     ///
     /// This runs in `O(log n + m)`.
-    pub fn query_point(&self, point: K) -> QueryIter<K, V> {
+    pub fn query_point(&self, point: K) -> QueryIter<'_, K, V> {
         QueryIter {
             todo: self.todo(),
             tree: self,
@@ -157,7 +157,7 @@ impl<K: Ord, V> IntervalTree<K, V> {
     }
 
     /// Returns an iterator over all elements in the tree (in no particular order).
-    pub fn iter(&self) -> TreeIter<K, V> {
+    pub fn iter(&self) -> TreeIter<'_, K, V> {
         TreeIter(self.data.iter())
     }
 
@@ -262,6 +262,466 @@ impl<'a, K: Ord, V> Iterator for QueryIter<'a, K, V> {
     }
 }
 
+/// Whether an interval of a [`MutableIntervalTree`] grows when text is inserted
+/// right at one of its ends.
+///
+/// Text inserted strictly inside an interval always ends up in it, and text
+/// inserted away from it never does. At its ends, it depends on what the
+/// interval stands for: bold text grows as you type at its end, a link doesn't.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Growth {
+    pub start: bool,
+    pub end: bool,
+}
+
+/// A handle on an element of a [`MutableIntervalTree`], valid until the
+/// element is removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IntervalId(usize);
+
+#[derive(Clone, Debug)]
+struct TreapNode<V> {
+    range: Range<usize>,
+    growth: Growth,
+    value: V,
+    /// The largest end in this subtree.
+    max: usize,
+    /// A shift already applied to this node, but not yet to its children.
+    shift: isize,
+    priority: u64,
+    parent: Option<usize>,
+    left: Option<usize>,
+    right: Option<usize>,
+}
+
+/// An interval tree over byte offsets that can be edited in place, and whose
+/// intervals follow the edits made to the text they cover.
+///
+/// It is a treap ordered by start, in which every node knows the largest end
+/// in its subtree. An edit shifts all the intervals after it at once, by
+/// tagging the root of the subtree holding them: only the intervals starting
+/// at the edit or spanning it are updated one by one. Inserting and removing
+/// elements takes `O(log n)` expected time, and edits and queries `O(log n + m)`,
+/// where `m` is the number of intervals the edit touches, or of results.
+#[derive(Clone, Debug)]
+pub struct MutableIntervalTree<V> {
+    nodes: Vec<Option<TreapNode<V>>>,
+    free: Vec<usize>,
+    root: Option<usize>,
+    len: usize,
+    seed: u64,
+}
+
+impl<V> Default for MutableIntervalTree<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> MutableIntervalTree<V> {
+    pub fn new() -> Self {
+        MutableIntervalTree {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: None,
+            len: 0,
+            seed: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.free.clear();
+        self.root = None;
+        self.len = 0;
+    }
+
+    /// Adds an element covering `range`.
+    pub fn insert(&mut self, range: Range<usize>, growth: Growth, value: V) -> IntervalId {
+        let range = range.start..range.end.max(range.start);
+        let node = TreapNode {
+            max: range.end,
+            range,
+            growth,
+            value,
+            shift: 0,
+            priority: self.next_priority(),
+            parent: None,
+            left: None,
+            right: None,
+        };
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.nodes[id] = Some(node);
+                id
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+
+        self.link(id);
+        self.len += 1;
+        IntervalId(id)
+    }
+
+    /// Removes the element `id`, returning it with the range it covered.
+    pub fn remove(&mut self, IntervalId(id): IntervalId) -> Option<Element<usize, V>> {
+        self.nodes.get(id)?.as_ref()?;
+
+        self.unlink(id);
+        let node = self.nodes[id].take()?;
+        self.free.push(id);
+        self.len -= 1;
+
+        Some(Element {
+            range: node.range,
+            value: node.value,
+        })
+    }
+
+    /// The element `id`, with the range it currently covers.
+    pub fn get(&self, IntervalId(id): IntervalId) -> Option<Element<usize, &V>> {
+        let node = self.nodes.get(id)?.as_ref()?;
+        let mut shift = 0;
+        let mut ancestor = node.parent;
+        while let Some(parent) = ancestor {
+            shift += self.node(parent).shift;
+            ancestor = self.node(parent).parent;
+        }
+
+        Some(Element {
+            range: shifted(node.range.start, shift)..shifted(node.range.end, shift),
+            value: &node.value,
+        })
+    }
+
+    /// Queries the tree for all elements overlapping a given interval, in no
+    /// particular order.
+    ///
+    /// This runs in `O(log n + m)`.
+    pub fn query(&self, range: Range<usize>) -> MutableQueryIter<'_, V> {
+        MutableQueryIter {
+            todo: self.root.map(|root| (root, 0)).into_iter().collect(),
+            tree: self,
+            query: Query::Range(range),
+        }
+    }
+
+    /// Queries the tree for all elements containing a given point, in no
+    /// particular order.
+    ///
+    /// This runs in `O(log n + m)`.
+    pub fn query_point(&self, point: usize) -> MutableQueryIter<'_, V> {
+        MutableQueryIter {
+            todo: self.root.map(|root| (root, 0)).into_iter().collect(),
+            tree: self,
+            query: Query::Point(point),
+        }
+    }
+
+    /// Follows `len` bytes being inserted at `offset`.
+    pub fn on_insert(&mut self, offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+
+        let (before, rest) = self.split(self.root, offset);
+        let (starting, after) = self.split(rest, offset + 1);
+        if let Some(after) = after {
+            self.apply_shift(after, len as isize);
+        }
+        if let Some(before) = before {
+            self.update(before, offset, &mut |node| {
+                if node.range.end > offset || (node.range.end == offset && node.growth.end) {
+                    node.range.end += len;
+                }
+            });
+        }
+        let root = self.merge(before, after);
+        self.set_root(root);
+
+        // Whether the intervals starting at `offset` move depends on each of
+        // them, so they are taken out and put back where they now belong.
+        let mut detached = Vec::new();
+        self.detach(starting, &mut detached);
+        for id in detached {
+            let node = self.node_mut(id);
+            if node.range.end > offset || node.growth.end || !node.growth.start {
+                node.range.end += len;
+            }
+            if !node.growth.start {
+                node.range.start += len;
+            }
+            node.max = node.range.end;
+            self.link(id);
+        }
+    }
+
+    /// Follows the bytes in `range` being deleted. Intervals inside it end up
+    /// empty, at its start.
+    pub fn on_delete(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let deleted = |offset: usize| match offset >= range.end {
+            true => offset - range.len(),
+            false => offset.min(range.start),
+        };
+        let (before, rest) = self.split(self.root, range.start);
+        let (inside, after) = self.split(rest, range.end);
+        if let Some(after) = after {
+            self.apply_shift(after, -(range.len() as isize));
+        }
+        if let Some(before) = before {
+            self.update(before, range.start + 1, &mut |node| {
+                node.range.end = deleted(node.range.end);
+            });
+        }
+        // Every interval starting inside now starts at `range.start`, which
+        // keeps them sorted.
+        if let Some(inside) = inside {
+            self.update(inside, 0, &mut |node| {
+                node.range = deleted(node.range.start)..deleted(node.range.end);
+            });
+        }
+        let merged = self.merge(before, inside);
+        let root = self.merge(merged, after);
+        self.set_root(root);
+    }
+
+    fn node(&self, id: usize) -> &TreapNode<V> {
+        self.nodes[id].as_ref().expect("a node of the tree")
+    }
+
+    fn node_mut(&mut self, id: usize) -> &mut TreapNode<V> {
+        self.nodes[id].as_mut().expect("a node of the tree")
+    }
+
+    /// A xorshift, which is all the randomness a treap needs.
+    fn next_priority(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+
+    fn apply_shift(&mut self, id: usize, shift: isize) {
+        let node = self.node_mut(id);
+        node.range = shifted(node.range.start, shift)..shifted(node.range.end, shift);
+        node.max = shifted(node.max, shift);
+        node.shift += shift;
+    }
+
+    fn push(&mut self, id: usize) {
+        let node = self.node_mut(id);
+        let shift = core::mem::take(&mut node.shift);
+        if shift != 0 {
+            let children = [node.left, node.right];
+            for child in children.into_iter().flatten() {
+                self.apply_shift(child, shift);
+            }
+        }
+    }
+
+    fn pull(&mut self, id: usize) {
+        let node = self.node(id);
+        let children = [node.left, node.right];
+        let mut max = node.range.end;
+        for child in children.into_iter().flatten() {
+            max = cmp::max(max, self.node(child).max);
+            self.node_mut(child).parent = Some(id);
+        }
+        self.node_mut(id).max = max;
+    }
+
+    fn set_root(&mut self, root: Option<usize>) {
+        self.root = root;
+        if let Some(root) = root {
+            self.node_mut(root).parent = None;
+        }
+    }
+
+    /// Splits the subtree at `id` into the elements starting before `key` and
+    /// the others.
+    fn split(&mut self, id: Option<usize>, key: usize) -> (Option<usize>, Option<usize>) {
+        let Some(id) = id else {
+            return (None, None);
+        };
+
+        self.push(id);
+        if self.node(id).range.start < key {
+            let (left, right) = self.split(self.node(id).right, key);
+            self.node_mut(id).right = left;
+            self.pull(id);
+            (Some(id), right)
+        } else {
+            let (left, right) = self.split(self.node(id).left, key);
+            self.node_mut(id).left = right;
+            self.pull(id);
+            (left, Some(id))
+        }
+    }
+
+    /// Merges two subtrees, all of `left` starting no later than `right`.
+    fn merge(&mut self, left: Option<usize>, right: Option<usize>) -> Option<usize> {
+        let (left, right) = match (left, right) {
+            (Some(left), Some(right)) => (left, right),
+            (left, None) => return left,
+            (None, right) => return right,
+        };
+
+        if self.node(left).priority > self.node(right).priority {
+            self.push(left);
+            let merged = self.merge(self.node(left).right, Some(right));
+            self.node_mut(left).right = merged;
+            self.pull(left);
+            Some(left)
+        } else {
+            self.push(right);
+            let merged = self.merge(Some(left), self.node(right).left);
+            self.node_mut(right).left = merged;
+            self.pull(right);
+            Some(right)
+        }
+    }
+
+    /// Links a lone node into the tree.
+    fn link(&mut self, id: usize) {
+        let (left, right) = self.split(self.root, self.node(id).range.start);
+        let merged = self.merge(left, Some(id));
+        let root = self.merge(merged, right);
+        self.set_root(root);
+    }
+
+    /// Unlinks a node from the tree, putting its children in its place.
+    fn unlink(&mut self, id: usize) {
+        let mut ancestors = Vec::new();
+        let mut ancestor = self.node(id).parent;
+        while let Some(parent) = ancestor {
+            ancestors.push(parent);
+            ancestor = self.node(parent).parent;
+        }
+        // Settle the shifts pending above the node, so that its children hold
+        // their actual ranges once it's gone.
+        for &ancestor in ancestors.iter().rev() {
+            self.push(ancestor);
+        }
+        self.push(id);
+
+        let node = self.node(id);
+        let (parent, left, right) = (node.parent, node.left, node.right);
+        let merged = self.merge(left, right);
+        match parent {
+            Some(parent) => {
+                let parent_node = self.node_mut(parent);
+                if parent_node.left == Some(id) {
+                    parent_node.left = merged;
+                } else {
+                    parent_node.right = merged;
+                }
+                for ancestor in ancestors {
+                    self.pull(ancestor);
+                }
+            }
+            None => self.set_root(merged),
+        }
+
+        let node = self.node_mut(id);
+        node.parent = None;
+        node.left = None;
+        node.right = None;
+    }
+
+    /// Takes apart the subtree at `id`, collecting its nodes.
+    fn detach(&mut self, id: Option<usize>, detached: &mut Vec<usize>) {
+        let Some(id) = id else {
+            return;
+        };
+
+        self.push(id);
+        let node = self.node_mut(id);
+        let (left, right) = (node.left.take(), node.right.take());
+        node.parent = None;
+        detached.push(id);
+        self.detach(left, detached);
+        self.detach(right, detached);
+    }
+
+    /// Applies `f` to every node of the subtree at `id` whose subtree reaches
+    /// `from`, i.e. at least to every node ending at or after `from`.
+    fn update(&mut self, id: usize, from: usize, f: &mut impl FnMut(&mut TreapNode<V>)) {
+        if self.node(id).max < from {
+            return;
+        }
+
+        self.push(id);
+        let node = self.node(id);
+        let children = [node.left, node.right];
+        for child in children.into_iter().flatten() {
+            self.update(child, from, f);
+        }
+        f(self.node_mut(id));
+        self.pull(id);
+    }
+}
+
+fn shifted(offset: usize, shift: isize) -> usize {
+    offset.saturating_add_signed(shift)
+}
+
+/// Iterator for the query results of a [`MutableIntervalTree`].
+pub struct MutableQueryIter<'a, V: 'a> {
+    tree: &'a MutableIntervalTree<V>,
+    /// Nodes still to visit, with the shift pending above them.
+    todo: SmallVec<[(usize, isize); 16]>,
+    query: Query<usize>,
+}
+
+impl<'a, V> Iterator for MutableQueryIter<'a, V> {
+    type Item = (IntervalId, Element<usize, &'a V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((id, shift)) = self.todo.pop() {
+            let node = self.tree.node(id);
+            if self.query.point() < &shifted(node.max, shift) {
+                let inner_shift = shift + node.shift;
+                if let Some(left) = node.left {
+                    self.todo.push((left, inner_shift));
+                }
+
+                let range = shifted(node.range.start, shift)..shifted(node.range.end, shift);
+                if self.query.go_right(&range.start) {
+                    if let Some(right) = node.right {
+                        self.todo.push((right, inner_shift));
+                    }
+
+                    if self.query.intersect(&range) {
+                        return Some((
+                            IntervalId(id),
+                            Element {
+                                range,
+                                value: &node.value,
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -309,4 +769,148 @@ mod tests {
         let tree: IntervalTree<u32, u32> = iter::empty::<Element<u32, u32>>().collect();
         verify(&tree, 42, &[]);
     }
+
+    const BOLD: Growth = Growth {
+        start: false,
+        end: true,
+    };
+
+    fn ranges(tree: &MutableIntervalTree<u32>, query: Range<usize>) -> Vec<(u32, Range<usize>)> {
+        let mut found: Vec<_> = tree
+            .query(query)
+            .map(|(_, element)| (*element.value, element.range))
+            .collect();
+        found.sort_by_key(|(value, _)| *value);
+        found
+    }
+
+    #[test]
+    fn intervals_follow_edits() {
+        let mut tree = MutableIntervalTree::new();
+        let bold = tree.insert(4..8, BOLD, 1);
+        let link = tree.insert(10..14, Growth::default(), 2);
+        tree.insert(20..30, Growth::default(), 3);
+
+        // Typing at the end of the bold text makes it longer, typing at the
+        // start of the link doesn't.
+        tree.on_insert(8, 2);
+        tree.on_insert(12, 3);
+        assert_eq!(tree.get(bold).map(|e| e.range), Some(4..10));
+        assert_eq!(tree.get(link).map(|e| e.range), Some(15..19));
+        assert_eq!(
+            ranges(&tree, 0..100),
+            [(1, 4..10), (2, 15..19), (3, 25..35)]
+        );
+
+        tree.on_delete(6..17);
+        assert_eq!(ranges(&tree, 0..100), [(1, 4..6), (2, 6..8), (3, 14..24)]);
+        assert_eq!(ranges(&tree, 7..14), [(2, 6..8)]);
+
+        assert_eq!(tree.remove(link).map(|e| e.range), Some(6..8));
+        assert_eq!(tree.remove(link), None);
+        assert_eq!(tree.len(), 2);
+        assert_eq!(
+            tree.query_point(5).map(|(id, _)| id).collect::<Vec<_>>(),
+            [bold]
+        );
+    }
+
+    #[test]
+    fn deleted_intervals_collapse() {
+        let both = Growth {
+            start: true,
+            end: true,
+        };
+        let mut tree = MutableIntervalTree::new();
+        tree.insert(2..4, BOLD, 1);
+        tree.insert(3..6, both, 2);
+
+        tree.on_delete(1..8);
+        assert_eq!(tree.query_point(1).count(), 0);
+
+        // An empty interval only takes in the text inserted at it if it grows
+        // at both ends.
+        tree.on_insert(1, 2);
+        assert_eq!(ranges(&tree, 0..10), [(1, 3..3), (2, 1..3)]);
+    }
+
+    /// Checks the tree against the same edits applied to a plain list.
+    #[test]
+    fn matches_a_naive_model() {
+        let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut random = |bound: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % bound as u64) as usize
+        };
+
+        let mut tree = MutableIntervalTree::new();
+        let mut model: Vec<(IntervalId, Range<usize>, Growth)> = Vec::new();
+        let mut text_len = 200;
+        for step in 0..2_000 {
+            match random(5) {
+                0 | 1 => {
+                    let start = random(text_len + 1);
+                    let end = start + random(text_len + 1 - start).min(20);
+                    let growth = Growth {
+                        start: random(2) == 0,
+                        end: random(2) == 0,
+                    };
+                    let id = tree.insert(start..end, growth, step);
+                    model.push((id, start..end, growth));
+                }
+                2 if !model.is_empty() => {
+                    let (id, range, _) = model.swap_remove(random(model.len()));
+                    assert_eq!(tree.remove(id).map(|e| e.range), Some(range));
+                }
+                3 => {
+                    let offset = random(text_len + 1);
+                    let len = 1 + random(10);
+                    tree.on_insert(offset, len);
+                    text_len += len;
+                    for (_, range, growth) in model.iter_mut() {
+                        let start_moves =
+                            range.start > offset || (range.start == offset && !growth.start);
+                        let end_moves = range.end > offset
+                            || (range.end == offset && (growth.end || start_moves));
+                        if start_moves {
+                            range.start += len;
+                        }
+                        if end_moves {
+                            range.end += len;
+                        }
+                    }
+                }
+                _ => {
+                    let start = random(text_len + 1);
+                    let end = start + random(text_len + 1 - start).min(30);
+                    tree.on_delete(start..end);
+                    text_len -= end - start;
+                    let deleted = |at: usize| match at >= end {
+                        true => at - (end - start),
+                        false => at.min(start),
+                    };
+                    for (_, range, _) in model.iter_mut() {
+                        *range = deleted(range.start)..deleted(range.end);
+                    }
+                }
+            }
+
+            let query = random(text_len + 1);
+            let mut expected: Vec<_> = model
+                .iter()
+                .filter(|(_, range, _)| range.start <= query && query < range.end)
+                .map(|(id, _, _)| *id)
+                .collect();
+            let mut found: Vec<_> = tree.query_point(query).map(|(id, _)| id).collect();
+            expected.sort();
+            found.sort();
+            assert_eq!(found, expected, "step {step}");
+        }
+
+        for (id, range, _) in &model {
+            assert_eq!(tree.get(*id).map(|e| e.range), Some(range.clone()));
+        }
+    }
 }
@@ -27,12 +27,24 @@ pub enum Behavior {
     Delete = 1,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// A run of text whose characters all carry the same attributes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Span {
-    pub range: Range<usize>,
-    pub behavior: Behavior,
+    pub insert: String,
+    pub attributes: FxHashMap<DefaultAtom, Value>,
+}
+
+/// An annotation, together with the range of text it currently covers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Annotation {
+    pub id: peritext::OpID,
+    /// "bold", "comment", "link", etc.
     pub type_: DefaultAtom,
     pub value: Value,
+    pub behavior: Behavior,
+    /// The range covered, in bytes. Annotations on deleted text have an empty
+    /// range.
+    pub range: Range<usize>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -60,3 +72,33 @@ pub type FxBuildHasher = BuildHasherDefault<FxHasher>;
 
 /// A `HashMap` using a default Fx hasher.
 pub type FxHashMap<K, V> = HashMap<K, V, FxBuildHasher>;
+
+/// The runs of `spans` carrying each attribute, with ranges relative to the
+/// start of the first span, in order of where they start.
+///
+/// Adjacent spans sharing an attribute give a single run, so a comment over
+/// partly bold text is one run rather than several.
+pub fn attribute_runs(spans: &[Span]) -> Vec<(Range<usize>, DefaultAtom, Value)> {
+    let mut runs: Vec<(Range<usize>, DefaultAtom, Value)> = Vec::new();
+    let mut offset = 0;
+
+    for span in spans {
+        let range = offset..offset + span.insert.len();
+        offset = range.end;
+
+        for (type_, value) in &span.attributes {
+            let run = runs
+                .iter_mut()
+                .rev()
+                .find(|(run_range, run_type, run_value)| {
+                    run_type == type_ && run_value == value && run_range.end == range.start
+                });
+            match run {
+                Some((run_range, _, _)) => run_range.end = range.end,
+                None => runs.push((range.clone(), type_.clone(), value.clone())),
+            }
+        }
+    }
+
+    runs
+}
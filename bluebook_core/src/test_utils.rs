@@ -0,0 +1,30 @@
+//! Fixtures shared by the tests of the editing context.
+
+use crate::{
+    buffer::rope::buffer_impl::RopeBuffer, command::Transaction, ctx::TextEditorContext,
+    cursor::CursorRange, error::BluebookCoreError, mode::Mode, text_buffer::TextBuffer,
+};
+
+/// A context editing `s` with the cursor at `head`, in insert mode as new
+/// contexts are.
+pub(crate) fn ctx(s: &str, head: usize) -> TextEditorContext<RopeBuffer> {
+    TextEditorContext::new(RopeBuffer::from_str(s), CursorRange::new(head, head))
+}
+
+/// A context editing `s` with the cursor at `head`, in normal mode.
+pub(crate) fn normal_ctx(s: &str, head: usize) -> TextEditorContext<RopeBuffer> {
+    let mut ctx = ctx(s, head);
+    ctx.modal.mode = Mode::Normal;
+    ctx
+}
+
+/// Consume `transactions` in order, stopping at the first that fails.
+pub(crate) fn run(
+    ctx: &mut TextEditorContext<RopeBuffer>,
+    transactions: impl IntoIterator<Item = Transaction>,
+) -> Result<(), BluebookCoreError> {
+    for transaction in transactions {
+        ctx.consume_transaction::<RopeBuffer>(transaction)?;
+    }
+    Ok(())
+}
@@ -1,19 +1,47 @@
 use crate::{
     block::{BlockCursor, BlockCursorError},
+    change::ChangeEvent,
     char::{CharCursor, CharCursorError},
     coordinates::{ColPosition, RowPosition},
     graphemes::{GraphemeClusterCursor, GraphemeClusterCursorError},
     line::{LineCursor, LineCursorError},
+    offset::{ByteOffset, CharOffset, GraphemeIndex, Utf16Offset},
     paragraph::{ParagraphCursor, ParagraphCursorError},
     sentence::{SentenceCursor, SentenceCursorError},
-    span::Span,
+    span::{Annotation, Span},
     word::{WordCursor, WordCursorError},
 };
 
+use serde_json::Value;
 use std::{
     borrow::Cow,
     ops::{Range, RangeBounds},
+    sync::mpsc::Receiver,
+    time::{Duration, Instant},
 };
+use string_cache::DefaultAtom;
+
+/// How close in time consecutive edits of the same kind, typing or deleting,
+/// must be for the buffers to undo them together.
+pub const DEFAULT_UNDO_GROUP_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What an edit does, for deciding whether it is undone along with the edit
+/// before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditKind {
+    Typing,
+    Deleting,
+    Other,
+}
+
+impl EditKind {
+    /// Whether an edit of this kind joins the group of an edit of kind `last`
+    /// made at `last_at`: consecutive edits of the same kind, typing or
+    /// deleting, made less than `interval` apart are undone together.
+    pub fn joins(self, last: EditKind, last_at: Instant, interval: Duration) -> bool {
+        self != EditKind::Other && self == last && last_at.elapsed() < interval
+    }
+}
 
 /**
  *
@@ -42,6 +70,10 @@ This design effectively locks the TextBuffer during the draining process. Once t
 pub enum ConversionError {
     #[error("Failed to convert text representation")]
     SomeError,
+    #[error("Offset {0} lies past the end of the text")]
+    OutOfBounds(usize),
+    #[error("Offset {0} does not lie on a codepoint boundary")]
+    NotACodepointBoundary(usize),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -100,7 +132,7 @@ pub trait TextBuffer {
     //Quite crucial: we will implemenet default implementation to most of the traits for string slices.
     fn from_str(s: &str) -> Self;
     /// Construct an instance of this type from a `&str`.
-    fn slice(&self, range: Range<usize>) -> Result<Cow<str>, ConversionError>;
+    fn slice(&self, range: Range<ByteOffset>) -> Result<Cow<'_, str>, ConversionError>;
 
     //Curors take a snapshot of the underlying text buffer, and then navigate around it, holding the state of their
     //offset position internal to them. As soon as the underlying buffer changes, the cursor is invalidated, and we
@@ -114,7 +146,7 @@ pub trait TextBuffer {
     ///Basic Use Case:
     /// Useful for iterating through text when you don't need to account for complex characters or grapheme
     /// clusters, such as when processing text at a byte level or when character-level navigation suffices.
-    fn char_cursor(&mut self, offset: usize) -> Result<Self::CharCursor<'_>, CharCursorError>;
+    fn char_cursor(&mut self, offset: ByteOffset) -> Result<Self::CharCursor<'_>, CharCursorError>;
     ////Grapheme Cluster Cursor:
     /// Unit of Movement:
     /// A Grapheme Cluster Cursor moves through the text one grapheme cluster at a time. A grapheme cluster is
@@ -126,54 +158,121 @@ pub trait TextBuffer {
     /// the user's perception of characters.
     fn grapheme_cluster_cursor(
         &mut self,
-        offset: usize,
+        offset: ByteOffset,
     ) -> Result<Self::GraphemeClusterCursor<'_>, GraphemeClusterCursorError>;
     ///Word Cursor: Jumps between word boundaries, allowing for efficient word-based navigation.
-    fn word_cursor(&mut self, offset: usize) -> Result<Self::WordCursor<'_>, WordCursorError>;
+    fn word_cursor(&mut self, offset: ByteOffset) -> Result<Self::WordCursor<'_>, WordCursorError>;
     ///Sentence Cursor: Moves between sentences or sentence-like structures in the text, based on punctuation and context.
     fn sentence_cursor(
         &mut self,
-        offset: usize,
+        offset: ByteOffset,
     ) -> Result<Self::SentenceCursor<'_>, SentenceCursorError>;
     ///Paragraph Cursor: Navigates between paragraphs, which are typically separated by line breaks or indentation.
     fn paragraph_cursor(
         &mut self,
-        offset: usize,
+        offset: ByteOffset,
     ) -> Result<Self::ParagraphCursor<'_>, ParagraphCursorError>;
     ///Line Cursor: Advances through lines or rows of text. Useful for quick navigation within a paragraph or block of text.
-    fn line_cursor(&mut self, offset: usize) -> Result<Self::LineCursor<'_>, LineCursorError>;
+    fn line_cursor(&mut self, offset: ByteOffset) -> Result<Self::LineCursor<'_>, LineCursorError>;
     ///Block Cursor: Allows you to jump between blocks of text, which could be defined by headers, section breaks, or other structural elements.
-    fn block_cursor(&mut self, offset: usize) -> Result<Self::BlockCursor<'_>, BlockCursorError>;
+    fn block_cursor(
+        &mut self,
+        offset: ByteOffset,
+    ) -> Result<Self::BlockCursor<'_>, BlockCursorError>;
 
-    fn cursor_coords(&mut self, offset: usize) -> Result<(RowPosition, ColPosition), CursorError>;
+    fn cursor_coords(
+        &mut self,
+        offset: ByteOffset,
+    ) -> Result<(RowPosition, ColPosition), CursorError>;
 
-    fn write(&mut self, offset: usize, s: &str) -> Result<usize, CursorError>;
+    fn write(&mut self, offset: ByteOffset, s: &str) -> Result<ByteOffset, CursorError>;
 
-    fn drain(&mut self, range: Range<usize>) -> std::vec::Drain<&str>;
+    /// Remove the text in `range` from the buffer, returning the removed text.
+    fn drain(&mut self, range: Range<ByteOffset>) -> Result<Cow<'_, str>, CursorError>;
 
     fn replace_range<R>(
         &mut self,
         range: R,
         replace_with: &str,
-    ) -> Result<Range<usize>, CursorError>
+    ) -> Result<Range<ByteOffset>, CursorError>
     where
-        R: RangeBounds<usize>;
+        R: RangeBounds<ByteOffset>;
 
     // fn flush(&mut self) -> Result<(), TextBufferError>;
 
     fn span_iter<'spans, 'buffer: 'spans>(&'buffer self) -> Self::SpanIter<'spans>;
 
+    /// The text in `range`, as consecutive runs of identically styled text.
+    fn slice_spans(&self, range: Range<ByteOffset>) -> Result<Vec<Span>, ConversionError>;
+
     fn annotate<R>(&mut self, range: R, annotation: peritext::Style)
     where
-        R: RangeBounds<usize>;
+        R: RangeBounds<ByteOffset>;
+
+    /// Give `range` the attribute `type_`, set to `value`, annotating it the
+    /// way attributes of loaded [`Span`]s are.
+    fn set_attribute(&mut self, range: Range<ByteOffset>, type_: DefaultAtom, value: Value);
+
+    /// The annotations in effect on the character right after `offset`.
+    fn annotations_at(&self, offset: ByteOffset) -> Result<Vec<Annotation>, ConversionError>;
+
+    /// The annotations in effect on some character of `range`, each with the
+    /// whole range it covers.
+    ///
+    /// Backends with annotations keep them indexed by range, so asking for
+    /// the ones in view costs about as much as there are in view.
+    fn annotations_in(&self, range: Range<ByteOffset>) -> Result<Vec<Annotation>, ConversionError>;
+
+    /// Remove the annotation `id`. Returns false if there was none to remove.
+    fn remove_annotation(&mut self, id: peritext::OpID) -> bool;
 
-    fn take(&self) -> Cow<str>;
+    /// Subscribe to every change made to the buffer from now on, local or
+    /// remote, in the order they are made.
+    fn subscribe(&mut self) -> Receiver<ChangeEvent>;
+
+    /// Undo the last group of edits made through this buffer that hasn't been
+    /// undone, returning the change that made, or None if there was nothing
+    /// to undo. Edits merged in from other replicas are never undone.
+    fn undo(&mut self) -> Option<ChangeEvent>;
+
+    /// Redo the last group of edits undone, unless edits were made since.
+    fn redo(&mut self) -> Option<ChangeEvent>;
+
+    /// Make every edit until the matching [`TextBuffer::end_undo_group`] a
+    /// single undo group. Groups may be nested.
+    fn begin_undo_group(&mut self);
+
+    /// Close the group opened by [`TextBuffer::begin_undo_group`]. When `keep`
+    /// is false, the edits of the outermost group are left out of the history,
+    /// having been reverted already.
+    fn end_undo_group(&mut self, keep: bool);
+
+    fn take(&self) -> Cow<'_, str>;
 
     /// Get length of text (in bytes).
     fn len(&self) -> usize;
 
     /// Returns `true` if this text has 0 length.
     fn is_empty(&self) -> bool;
+
+    /// Convert a byte offset into the number of utf16 code units before it.
+    fn byte_to_utf16(&self, offset: ByteOffset) -> Result<Utf16Offset, ConversionError>;
+
+    /// Convert a utf16 offset into the byte offset of the same position.
+    fn utf16_to_byte(&self, offset: Utf16Offset) -> Result<ByteOffset, ConversionError>;
+
+    /// Convert a byte offset into the number of codepoints before it.
+    fn byte_to_char(&self, offset: ByteOffset) -> Result<CharOffset, ConversionError>;
+
+    /// Convert a codepoint offset into the byte offset of the same position.
+    fn char_to_byte(&self, offset: CharOffset) -> Result<ByteOffset, ConversionError>;
+
+    /// Convert a byte offset into the number of grapheme clusters before it.
+    /// Offsets inside a cluster count the cluster they're in as not yet passed.
+    fn byte_to_grapheme(&self, offset: ByteOffset) -> Result<GraphemeIndex, ConversionError>;
+
+    /// Convert a grapheme cluster index into the byte offset the cluster starts at.
+    fn grapheme_to_byte(&self, index: GraphemeIndex) -> Result<ByteOffset, ConversionError>;
 }
 
 #[cfg(test)]
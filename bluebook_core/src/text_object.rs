@@ -0,0 +1,408 @@
+//! Text objects: the units of text around a cursor that operators apply to
+//! and visual mode selects, as vi has them.
+//!
+//! Each object comes in two sizes. The inner object is the unit itself, or
+//! what is between its delimiters, and the outer one takes in the
+//! delimiters, or the white space after it. Rich text adds objects for the
+//! text an annotation covers, found from the annotation ranges the buffer
+//! keeps rather than from the text.
+
+use std::ops::Range;
+
+use string_cache::DefaultAtom;
+
+use crate::{
+    char::CharCursor,
+    ctx::TextEditorContext,
+    error::BluebookCoreError,
+    movement::Movement,
+    offset::ByteOffset,
+    pair::{pair_named, quote_pairs, PairCursor},
+    search::Searchable,
+    span::{Annotation, Behavior},
+    text_buffer::{CursorError, TextBuffer},
+    word::WordCursor,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TextObject {
+    Word,
+    Sentence,
+    /// A run of lines that are all blank or all not.
+    Paragraph,
+    /// Text between two of the same quote on a line.
+    Quotes(char),
+    /// Text between an opening bracket and the closing one matching it.
+    Brackets(char, char),
+    /// The text an annotation of this type covers, like a comment or a link.
+    /// Annotations that merge, like bold, give the whole run of text they
+    /// cover together.
+    Annotation(DefaultAtom),
+}
+
+impl TextObject {
+    /// The object keymaps name `name`, as in `select_inner_<name>`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let object = match name {
+            "word" => TextObject::Word,
+            "sentence" => TextObject::Sentence,
+            "paragraph" => TextObject::Paragraph,
+            "comment" | "link" | "bold" | "italic" => {
+                TextObject::Annotation(DefaultAtom::from(name))
+            }
+            _ => match pair_named(name)? {
+                (open, close) if open == close => TextObject::Quotes(open),
+                (open, close) => TextObject::Brackets(open, close),
+            },
+        };
+
+        Some(object)
+    }
+
+    /// Whether the object is whole lines, which operators apply to linewise.
+    pub fn is_linewise(&self) -> bool {
+        matches!(self, TextObject::Paragraph)
+    }
+}
+
+impl<Buffer> TextEditorContext<Buffer>
+where
+    Buffer: TextBuffer + Searchable,
+{
+    /// Select `object` around the cursor, or the outer object if `around`.
+    pub(crate) fn select_text_object(
+        &mut self,
+        object: &TextObject,
+        around: bool,
+    ) -> Result<bool, BluebookCoreError> {
+        match self.text_object_range(self.cursor_range.head, object, around)? {
+            Some(range) => {
+                self.cursor_range.set_anchor(range.start);
+                self.cursor_range.set_head(range.end);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// The range of `object` around `offset`, if there is one there.
+    pub fn text_object_range(
+        &mut self,
+        offset: usize,
+        object: &TextObject,
+        around: bool,
+    ) -> Result<Option<Range<usize>>, BluebookCoreError> {
+        let range = match object {
+            TextObject::Word => {
+                let (start, end) = self
+                    .text_buffer
+                    .word_cursor(ByteOffset(offset))
+                    .map_err(CursorError::from)?
+                    .select_word();
+                Some(start..end).filter(|range| !range.is_empty())
+            }
+            TextObject::Sentence => self.sentence(offset)?,
+            TextObject::Paragraph => return self.paragraph(offset, around).map(Some),
+            TextObject::Quotes(quote) => self.quotes(offset, *quote, around)?,
+            TextObject::Brackets(open, close) => {
+                return self.brackets(offset, *open, *close, around);
+            }
+            TextObject::Annotation(type_) => self.annotation(offset, type_)?,
+        };
+
+        match (range, around) {
+            (Some(range), true) => self.with_white_space(range).map(Some),
+            (range, _) => Ok(range),
+        }
+    }
+
+    /// The sentence holding `offset`, without the white space after it.
+    fn sentence(&mut self, offset: usize) -> Result<Option<Range<usize>>, BluebookCoreError> {
+        let end = self
+            .movement_offset(offset, &Movement::SentenceForward, 1)?
+            .unwrap_or(offset);
+        let start = self
+            .movement_offset(end, &Movement::SentenceBackward, 1)?
+            .unwrap_or(end);
+
+        let text = self.text_buffer.slice(ByteOffset(start)..ByteOffset(end))?;
+        let end = start + text.trim_end().len();
+
+        Ok(Some(start..end).filter(|range| !range.is_empty()))
+    }
+
+    /// The lines around `offset` that are blank if its line is, and not
+    /// otherwise, and with `around`, the blank lines after them, or before
+    /// them if there are none after.
+    fn paragraph(
+        &mut self,
+        offset: usize,
+        around: bool,
+    ) -> Result<Range<usize>, BluebookCoreError> {
+        let line = self.line_at(offset)?;
+        let blank = self.is_blank_line(offset)?;
+        let mut range = self.lines_while(line.clone(), |is_blank| is_blank == blank)?;
+
+        if around {
+            let after = self.lines_while(range.end..range.end, |is_blank| is_blank != blank)?;
+            match after.is_empty() {
+                false => range.end = after.end,
+                true => {
+                    let before =
+                        self.lines_while(range.start..range.start, |is_blank| is_blank != blank)?;
+                    range.start = before.start;
+                }
+            }
+        }
+
+        Ok(range)
+    }
+
+    /// `lines`, grown over the lines before and after it for which `keep`
+    /// holds, given whether they are blank.
+    fn lines_while(
+        &mut self,
+        mut lines: Range<usize>,
+        keep: impl Fn(bool) -> bool,
+    ) -> Result<Range<usize>, BluebookCoreError> {
+        while lines.start > 0 {
+            let previous = self.line_at(lines.start - 1)?;
+            if !keep(self.is_blank_line(previous.start)?) {
+                break;
+            }
+            lines.start = previous.start;
+        }
+
+        let len = self.text_buffer.len();
+        while lines.end < len {
+            let next = self.line_at(lines.end)?;
+            if next.is_empty() || !keep(self.is_blank_line(next.start)?) {
+                break;
+            }
+            lines.end = next.end;
+        }
+
+        Ok(lines)
+    }
+
+    fn is_blank_line(&mut self, offset: usize) -> Result<bool, BluebookCoreError> {
+        let (_, content) = self.line_text(offset)?;
+        Ok(content.trim().is_empty())
+    }
+
+    /// The text between the two `quote`s on the line of `offset` around it,
+    /// or the first two after it.
+    fn quotes(
+        &mut self,
+        offset: usize,
+        quote: char,
+        around: bool,
+    ) -> Result<Option<Range<usize>>, BluebookCoreError> {
+        let (start, content) = self.line_text(offset)?;
+
+        let pair = quote_pairs(&content, quote)
+            .into_iter()
+            .map(|(open, close)| (start + open, start + close))
+            .find(|(_, close)| offset <= *close);
+        Ok(pair.map(|(open, close)| match around {
+            true => open..close + quote.len_utf8(),
+            false => open + quote.len_utf8()..close,
+        }))
+    }
+
+    /// The text between the `open` and `close` enclosing `offset`, or that
+    /// the grapheme at `offset` is one of.
+    fn brackets(
+        &mut self,
+        offset: usize,
+        open: char,
+        close: char,
+        around: bool,
+    ) -> Result<Option<Range<usize>>, BluebookCoreError> {
+        let open_at = {
+            let cursor = self
+                .text_buffer
+                .char_cursor(ByteOffset(offset))
+                .map_err(CursorError::from)?;
+            // On a closing bracket, the walk back finds the one it matches.
+            match cursor.peek_next_codepoint() {
+                Some(ch) if ch == open => Some(offset),
+                _ => PairCursor::new(cursor).prev_unmatched(open, close),
+            }
+        };
+        let Some(open_at) = open_at else {
+            return Ok(None);
+        };
+
+        let cursor = self
+            .text_buffer
+            .char_cursor(ByteOffset(open_at + open.len_utf8()))
+            .map_err(CursorError::from)?;
+        let Some(close_at) = PairCursor::new(cursor).next_unmatched(open, close) else {
+            return Ok(None);
+        };
+
+        Ok(Some(match around {
+            true => open_at..close_at + close.len_utf8(),
+            false => open_at + open.len_utf8()..close_at,
+        }))
+    }
+
+    /// The text the annotation of type `type_` at `offset` covers. Of
+    /// several that may overlap, like comments, the innermost is taken, and
+    /// the others grow to the whole run of text they cover.
+    fn annotation(
+        &mut self,
+        offset: usize,
+        type_: &DefaultAtom,
+    ) -> Result<Option<Range<usize>>, BluebookCoreError> {
+        let matching = |annotations: Vec<Annotation>| {
+            annotations.into_iter().filter(|annotation| {
+                annotation.type_.eq_ignore_ascii_case(type_) && !annotation.range.is_empty()
+            })
+        };
+
+        let found = matching(self.text_buffer.annotations_at(ByteOffset(offset))?)
+            .min_by_key(|annotation| annotation.range.len());
+        let Some(found) = found else {
+            return Ok(None);
+        };
+        if found.behavior == Behavior::AllowMultiple {
+            return Ok(Some(found.range));
+        }
+
+        let mut range = found.range;
+        loop {
+            let around = ByteOffset(range.start.saturating_sub(1))
+                ..ByteOffset((range.end + 1).min(self.text_buffer.len()));
+            let grown = matching(self.text_buffer.annotations_in(around)?)
+                .filter(|annotation| annotation.behavior != Behavior::AllowMultiple)
+                .fold(range.clone(), |range, annotation| {
+                    range.start.min(annotation.range.start)..range.end.max(annotation.range.end)
+                });
+            if grown == range {
+                return Ok(Some(range));
+            }
+            range = grown;
+        }
+    }
+
+    /// `range` with the spaces and tabs after it on its line, or before it if
+    /// there are none after.
+    fn with_white_space(&mut self, range: Range<usize>) -> Result<Range<usize>, BluebookCoreError> {
+        let (start, content) = self.line_text(range.end)?;
+        let after = content.get(range.end - start..).unwrap_or_default();
+        let trailing = after.len() - after.trim_start_matches([' ', '\t']).len();
+        if trailing > 0 {
+            return Ok(range.start..range.end + trailing);
+        }
+
+        let (start, content) = self.line_text(range.start)?;
+        let before = content.get(..range.start - start).unwrap_or_default();
+        let leading = before.len() - before.trim_end_matches([' ', '\t']).len();
+
+        Ok(range.start - leading..range.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        command::Transaction,
+        mode::Mode,
+        test_utils::{normal_ctx, run},
+    };
+
+    fn inner(object: &str) -> Transaction {
+        Transaction::from_name(&format!("select_inner_{object}")).unwrap()
+    }
+
+    fn around(object: &str) -> Transaction {
+        Transaction::from_name(&format!("select_around_{object}")).unwrap()
+    }
+
+    #[test]
+    fn words_and_sentences() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx("one two  three", 5);
+
+        assert_eq!(
+            ctx.text_object_range(5, &TextObject::Word, false)?,
+            Some(4..7)
+        );
+        assert_eq!(
+            ctx.text_object_range(5, &TextObject::Word, true)?,
+            Some(4..9)
+        );
+        // Without white space after it, a word takes in the white space before.
+        assert_eq!(
+            ctx.text_object_range(10, &TextObject::Word, true)?,
+            Some(7..14)
+        );
+
+        let mut ctx = normal_ctx("One sentence. Another one.", 16);
+        assert_eq!(
+            ctx.text_object_range(16, &TextObject::Sentence, false)?,
+            Some(14..26)
+        );
+        assert_eq!(
+            ctx.text_object_range(2, &TextObject::Sentence, true)?,
+            Some(0..14)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn paragraphs_are_linewise() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx("one\ntwo\n\n\nthree", 4);
+
+        assert_eq!(
+            ctx.text_object_range(4, &TextObject::Paragraph, false)?,
+            Some(0..8)
+        );
+        assert_eq!(
+            ctx.text_object_range(4, &TextObject::Paragraph, true)?,
+            Some(0..10)
+        );
+        // The last paragraph has no blank lines after it to take in.
+        assert_eq!(
+            ctx.text_object_range(12, &TextObject::Paragraph, true)?,
+            Some(8..15)
+        );
+
+        run(&mut ctx, [Transaction::DeleteOperator, around("paragraph")])?;
+        assert_eq!(ctx.text_buffer.take(), "three");
+        assert!(ctx
+            .registers
+            .get('"')
+            .is_some_and(|register| register.linewise));
+
+        Ok(())
+    }
+
+    #[test]
+    fn quotes_and_nested_brackets() -> Result<(), BluebookCoreError> {
+        let mut ctx = normal_ctx(r#"say "a \"b\" c" and f(x, (y), z)"#, 23);
+        let quotes = TextObject::Quotes('"');
+        let parens = TextObject::Brackets('(', ')');
+
+        // Escaped quotes don't end the string.
+        assert_eq!(ctx.text_object_range(9, &quotes, false)?, Some(5..14));
+        assert_eq!(ctx.text_object_range(9, &quotes, true)?, Some(4..16));
+        assert_eq!(ctx.text_object_range(0, &quotes, false)?, Some(5..14));
+
+        assert_eq!(ctx.text_object_range(30, &parens, false)?, Some(22..31));
+        assert_eq!(ctx.text_object_range(30, &parens, true)?, Some(21..32));
+        for offset in [25, 26, 27] {
+            assert_eq!(ctx.text_object_range(offset, &parens, false)?, Some(26..27));
+        }
+        assert_eq!(ctx.text_object_range(16, &parens, false)?, None);
+
+        run(&mut ctx, [Transaction::ChangeOperator, inner("parens")])?;
+        assert_eq!(ctx.text_buffer.take(), r#"say "a \"b\" c" and f()"#);
+        assert_eq!(ctx.modal.mode, Mode::Insert);
+
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{mode::Mode, text_buffer::TextBuffer};
+use crate::{char::CharCursor, mode::Mode, text_buffer::TextBuffer};
 
 /// Describe char classifications used to compose word boundaries
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -43,6 +43,30 @@ impl WordBoundary {
     }
 }
 
+/// Return the [`CharClassification`] of the input character
+pub fn get_char_property(codepoint: char) -> CharClassification {
+    if codepoint <= ' ' {
+        if codepoint == '\r' {
+            return CharClassification::Cr;
+        }
+        if codepoint == '\n' {
+            return CharClassification::Lf;
+        }
+        return CharClassification::Space;
+    } else if codepoint <= '\u{3f}' {
+        // Hardcoded: !"#$%&'()*+,-./:;<=>?
+        if (0xfc00fffe00000000u64 >> (codepoint as u32)) & 1 != 0 {
+            return CharClassification::Punctuation;
+        }
+    } else if codepoint <= '\u{7f}' {
+        // Hardcoded: @[\]^`{|}~
+        if (0x7800000178000001u64 >> ((codepoint as u32) & 0x3f)) & 1 != 0 {
+            return CharClassification::Punctuation;
+        }
+    }
+    CharClassification::Other
+}
+
 fn classify_boundary(prev: CharClassification, next: CharClassification) -> WordBoundary {
     use self::{CharClassification::*, WordBoundary::*};
     match (prev, next) {
@@ -96,3 +120,180 @@ pub trait WordCursor<'buffer> {
     /// Return the previous and end boundaries of the word under cursor.
     fn select_word(&mut self) -> (usize, usize);
 }
+
+/// A [`WordCursor`] built on top of any [`CharCursor`], so every backend gets
+/// the same word semantics as long as it can walk codepoints.
+pub struct CharWordCursor<C> {
+    inner: C,
+}
+
+impl<'buffer, C> WordCursor<'buffer> for CharWordCursor<C>
+where
+    C: CharCursor<'buffer>,
+{
+    type Buffer = C::Buffer;
+
+    fn new(text: &'buffer Self::Buffer, pos: usize) -> Self {
+        Self {
+            inner: C::new(text, pos),
+        }
+    }
+
+    fn prev_boundary(&mut self, mode: Mode) -> Option<usize> {
+        if let Some(ch) = self.inner.prev_codepoint() {
+            let mut prop = get_char_property(ch);
+            let mut candidate = self.inner.pos();
+            while let Some(prev) = self.inner.prev_codepoint() {
+                let prop_prev = get_char_property(prev);
+                if classify_boundary(prop_prev, prop).is_start() {
+                    break;
+                }
+
+                // Stop if line beginning reached, without any non-whitespace characters
+                if mode == Mode::Insert
+                    && prop_prev == CharClassification::Lf
+                    && prop == CharClassification::Space
+                {
+                    break;
+                }
+
+                prop = prop_prev;
+                candidate = self.inner.pos();
+            }
+            self.inner.set(candidate);
+            return Some(candidate);
+        }
+        None
+    }
+
+    fn prev_deletion_boundary(&mut self) -> Option<usize> {
+        if let Some(ch) = self.inner.prev_codepoint() {
+            let mut prop = get_char_property(ch);
+            let mut candidate = self.inner.pos();
+
+            // Flag, determines if the word should be deleted or not
+            // If not, erase only whitespace characters.
+            let mut keep_word = false;
+            while let Some(prev) = self.inner.prev_codepoint() {
+                let prop_prev = get_char_property(prev);
+
+                // Stop if line beginning reached, without any non-whitespace characters
+                if prop_prev == CharClassification::Lf && prop == CharClassification::Space {
+                    break;
+                }
+
+                // More than a single whitespace: keep word, remove only whitespaces
+                if prop == CharClassification::Space && prop_prev == CharClassification::Space {
+                    keep_word = true;
+                }
+
+                // Line break found: keep words, delete line break & trailing whitespaces
+                if prop == CharClassification::Lf || prop == CharClassification::Cr {
+                    keep_word = true;
+                }
+
+                // Skip word deletion if above conditions were met
+                if keep_word
+                    && (prop_prev == CharClassification::Punctuation
+                        || prop_prev == CharClassification::Other)
+                {
+                    break;
+                }
+
+                // Default deletion
+                if classify_boundary(prop_prev, prop).is_start() {
+                    break;
+                }
+                prop = prop_prev;
+                candidate = self.inner.pos();
+            }
+            self.inner.set(candidate);
+            return Some(candidate);
+        }
+        None
+    }
+
+    fn next_non_blank_char(&mut self) -> usize {
+        let mut candidate: usize = self.inner.pos();
+        while let Some(next) = self.inner.next_codepoint() {
+            let prop = get_char_property(next);
+            if prop != CharClassification::Space {
+                break;
+            }
+            candidate = self.inner.pos();
+        }
+        self.inner.set(candidate);
+        candidate
+    }
+
+    fn next_boundary(&mut self) -> Option<usize> {
+        if let Some(ch) = self.inner.next_codepoint() {
+            let mut prop = get_char_property(ch);
+            let mut candidate = self.inner.pos();
+            while let Some(next) = self.inner.next_codepoint() {
+                let prop_next = get_char_property(next);
+                if classify_boundary(prop, prop_next).is_start() {
+                    break;
+                }
+                prop = prop_next;
+                candidate = self.inner.pos();
+            }
+            self.inner.set(candidate);
+            return Some(candidate);
+        }
+        None
+    }
+
+    fn end_boundary(&mut self) -> Option<usize> {
+        self.inner.next_codepoint();
+        if let Some(ch) = self.inner.next_codepoint() {
+            let mut prop = get_char_property(ch);
+            let mut candidate = self.inner.pos();
+            while let Some(next) = self.inner.next_codepoint() {
+                let prop_next = get_char_property(next);
+                if classify_boundary(prop, prop_next).is_end() {
+                    break;
+                }
+                prop = prop_next;
+                candidate = self.inner.pos();
+            }
+            self.inner.set(candidate);
+            return Some(candidate);
+        }
+        None
+    }
+
+    fn prev_code_boundary(&mut self) -> usize {
+        let mut candidate = self.inner.pos();
+        while let Some(prev) = self.inner.prev_codepoint() {
+            let prop_prev = get_char_property(prev);
+            if prop_prev != CharClassification::Other {
+                break;
+            }
+            candidate = self.inner.pos();
+        }
+        self.inner.set(candidate);
+        candidate
+    }
+
+    fn next_code_boundary(&mut self) -> usize {
+        let mut candidate = self.inner.pos();
+        while let Some(next) = self.inner.next_codepoint() {
+            let prop_next = get_char_property(next);
+            if prop_next != CharClassification::Other {
+                break;
+            }
+            candidate = self.inner.pos();
+        }
+        self.inner.set(candidate);
+        candidate
+    }
+
+    fn select_word(&mut self) -> (usize, usize) {
+        let initial = self.inner.pos();
+        let end = self.next_code_boundary();
+        self.inner.set(initial);
+        let start = self.prev_code_boundary();
+        (start, end)
+    }
+}